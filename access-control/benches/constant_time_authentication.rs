@@ -1,4 +1,8 @@
 //! Test that the authentication functionality is roughtly in constant time to prevent user enumeration
+use access_control::remember::RememberSeries;
+use access_control::session::{SessionInfo, SessionLifetime, SessionMetadata};
+use access_control::throttling::{FailedAttempts, Throttling};
+use access_control::webauthn::Credential;
 use access_control::{AccessControl, Backend, FutureOption, FutureResult, User};
 
 use criterion::async_executor::FuturesExecutor;
@@ -6,6 +10,7 @@ use criterion::black_box;
 use criterion::Criterion;
 use criterion::{criterion_group, criterion_main};
 use futures_util::future::ready;
+use std::time::{Duration, SystemTime};
 
 #[derive(Debug, Clone)]
 struct TestUser;
@@ -47,25 +52,208 @@ impl Backend for TestBackend {
         unimplemented!()
     }
 
-    fn store_session(&self, _user: &TestUser, _session_id: impl AsRef<str>) -> FutureResult<()> {
+    fn update_password_hash(
+        &self,
+        _user: &TestUser,
+        _password_hash: impl AsRef<str>,
+    ) -> FutureResult<()> {
+        unimplemented!()
+    }
+
+    fn store_session(
+        &self,
+        _user: &TestUser,
+        _session_id: impl AsRef<str>,
+        _lifetime: &SessionLifetime,
+        _metadata: &SessionMetadata,
+    ) -> FutureResult<()> {
+        unimplemented!()
+    }
+
+    fn touch_session(
+        &self,
+        _session_id: impl AsRef<str>,
+        _idle_timeout: Duration,
+    ) -> FutureOption<SystemTime> {
+        unimplemented!()
+    }
+
+    fn store_pending_session(
+        &self,
+        _user: &TestUser,
+        _session_id: impl AsRef<str>,
+        _remember: bool,
+    ) -> FutureResult<()> {
+        unimplemented!()
+    }
+
+    fn get_user_from_pending_session(
+        &self,
+        _session_id: impl AsRef<str>,
+    ) -> FutureOption<(TestUser, bool)> {
+        unimplemented!()
+    }
+
+    fn record_pending_attempt(&self, _session_id: impl AsRef<str>) -> FutureOption<u32> {
         unimplemented!()
     }
 
     fn remove_session(&self, _session_id: impl AsRef<str>) -> FutureResult<()> {
         unimplemented!()
     }
+
+    fn get_sessions(
+        &self,
+        _user: &TestUser,
+        _current_session_id: impl AsRef<str>,
+    ) -> FutureResult<Vec<SessionInfo>> {
+        unimplemented!()
+    }
+
+    fn revoke_session(&self, _user: &TestUser, _id: impl AsRef<str>) -> FutureResult<bool> {
+        unimplemented!()
+    }
+
+    fn revoke_other_sessions(
+        &self,
+        _user: &TestUser,
+        _current_session_id: impl AsRef<str>,
+        _remember_selector: Option<&str>,
+    ) -> FutureResult<()> {
+        unimplemented!()
+    }
+
+    fn has_grant(
+        &self,
+        _user: &TestUser,
+        _action: impl AsRef<str>,
+        _resource_type: impl AsRef<str>,
+        _resource_id: impl AsRef<str>,
+    ) -> FutureResult<bool> {
+        unimplemented!()
+    }
+
+    fn store_totp_secret(&self, _user: &TestUser, _secret: Option<&str>) -> FutureResult<()> {
+        unimplemented!()
+    }
+
+    fn use_totp_step(&self, _user: &TestUser, _step: u64) -> FutureResult<bool> {
+        unimplemented!()
+    }
+
+    fn store_recovery_codes(&self, _user: &TestUser, _code_hashes: &[String]) -> FutureResult<()> {
+        unimplemented!()
+    }
+
+    fn get_recovery_codes(&self, _user: &TestUser) -> FutureResult<Vec<String>> {
+        unimplemented!()
+    }
+
+    fn use_recovery_code(
+        &self,
+        _user: &TestUser,
+        _code_hash: impl AsRef<str>,
+    ) -> FutureResult<bool> {
+        unimplemented!()
+    }
+
+    fn store_webauthn_challenge(
+        &self,
+        _user: Option<&TestUser>,
+        _challenge: impl AsRef<str>,
+    ) -> FutureResult<()> {
+        unimplemented!()
+    }
+
+    fn take_webauthn_challenge(
+        &self,
+        _user: Option<&TestUser>,
+        _challenge: impl AsRef<str>,
+    ) -> FutureResult<bool> {
+        unimplemented!()
+    }
+
+    fn store_webauthn_credential(
+        &self,
+        _user: &TestUser,
+        _credential: &Credential,
+    ) -> FutureResult<()> {
+        unimplemented!()
+    }
+
+    fn get_webauthn_credential(
+        &self,
+        _credential_id: &[u8],
+    ) -> FutureOption<(TestUser, Credential)> {
+        unimplemented!()
+    }
+
+    fn update_webauthn_sign_count(
+        &self,
+        _credential_id: &[u8],
+        _sign_count: u32,
+    ) -> FutureResult<bool> {
+        unimplemented!()
+    }
+
+    fn store_remember_token(
+        &self,
+        _user: &Self::User,
+        _selector: impl AsRef<str>,
+        _validator_hash: impl AsRef<str>,
+        _lifetime: Duration,
+    ) -> FutureResult<()> {
+        unimplemented!()
+    }
+
+    fn get_remember_token(
+        &self,
+        _selector: impl AsRef<str>,
+    ) -> FutureOption<(Self::User, RememberSeries)> {
+        unimplemented!()
+    }
+
+    fn rotate_remember_token(
+        &self,
+        _selector: impl AsRef<str>,
+        _old_hash: impl AsRef<str>,
+        _new_hash: impl AsRef<str>,
+        _lifetime: Duration,
+    ) -> FutureResult<bool> {
+        unimplemented!()
+    }
+
+    fn remove_remember_token(&self, _selector: impl AsRef<str>) -> FutureResult<()> {
+        unimplemented!()
+    }
+
+    fn get_failed_attempts(&self, _key: impl AsRef<str>) -> FutureResult<Option<FailedAttempts>> {
+        Box::pin(ready(Ok(None)))
+    }
+
+    fn record_failed_attempt(
+        &self,
+        _key: impl AsRef<str>,
+        _reset_after: Duration,
+    ) -> FutureResult<()> {
+        Box::pin(ready(Ok(())))
+    }
+
+    fn reset_failed_attempts(&self, _key: impl AsRef<str>) -> FutureResult<()> {
+        Box::pin(ready(Ok(())))
+    }
 }
 
 async fn test_authenticate_valid(backend: TestBackend, password: &'static str) {
     assert!(AccessControl::new(backend)
-        .authenticate_creds("testuser", password)
+        .authenticate_creds("testuser", password, None, &Throttling::default())
         .await
         .is_ok())
 }
 
 async fn test_authenticate_invalid(backend: TestBackend, password: &'static str) {
     assert!(AccessControl::new(backend)
-        .authenticate_creds("testuser", password)
+        .authenticate_creds("testuser", password, None, &Throttling::default())
         .await
         .is_err())
 }
@@ -0,0 +1,630 @@
+use crate::User;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The abbreviations of the weekdays, which are used in `weekday` conditions.
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+/// Errors that occur when loading an [`AccessPolicy`].
+#[derive(thiserror::Error, Debug)]
+pub enum PolicyError {
+    /// The policy file could not be read
+    #[error("Could not read the access policy")]
+    Io(#[from] io::Error),
+    /// A line of the policy is invalid
+    #[error("Invalid access policy in line {line}: {reason}")]
+    Parse { line: usize, reason: String },
+}
+
+/// The effect of a rule and the result of a [`Decision`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    Allow,
+    Deny,
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Effect::Allow => write!(f, "allow"),
+            Effect::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+/// The attributes of a request that conditions of an [`AccessPolicy`] can refer to.
+///
+/// The attributes of the user are read from [`User::attribute`] while evaluating the policy.
+#[derive(Debug, Clone)]
+pub struct RequestAttributes {
+    /// The HTTP method in uppercase, e.g. `GET`
+    pub method: String,
+    pub path: String,
+    /// The IP address of the client, if known
+    pub ip: Option<IpAddr>,
+    /// The time of the request, conditions compare it in UTC
+    pub time: SystemTime,
+}
+
+impl RequestAttributes {
+    /// Creates the attributes of a request that happens now.
+    pub fn new(method: impl AsRef<str>, path: impl Into<String>, ip: Option<IpAddr>) -> Self {
+        RequestAttributes {
+            method: method.as_ref().to_uppercase(),
+            path: path.into(),
+            ip,
+            time: SystemTime::now(),
+        }
+    }
+
+    /// Returns the minute of the day and the weekday (0 is monday) of the request in UTC.
+    fn minute_and_weekday(&self) -> (u32, usize) {
+        let seconds = self
+            .time
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let days = seconds / 86400;
+
+        // The 1st of January 1970 was a thursday
+        (((seconds % 86400) / 60) as u32, ((days + 3) % 7) as usize)
+    }
+}
+
+/// The attribute a [`Condition`] refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Attribute {
+    Method,
+    Path,
+    Ip,
+    Time,
+    Weekday,
+    /// `user.username` or any attribute of the user, e.g. `user.department`
+    User(String),
+}
+
+/// A value of a [`Condition`], which has been validated for its attribute.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Text(String),
+    /// A path that ends with `*` matches every path with the prefix
+    PathPrefix(String),
+    /// An IP network in CIDR notation
+    Network(IpAddr, u8),
+    /// A range of minutes of the day, which may wrap around midnight
+    TimeRange(u32, u32),
+    Weekday(usize),
+}
+
+/// A single comparison of an attribute, e.g. `method in GET, HEAD`.
+#[derive(Debug, Clone)]
+struct Condition {
+    attribute: Attribute,
+    negated: bool,
+    values: Vec<Value>,
+    /// The condition as written in the policy, which is used in the trace
+    source: String,
+}
+
+impl Condition {
+    fn parse(source: &str) -> Result<Self, String> {
+        let mut words = source.splitn(2, char::is_whitespace);
+        let attribute = match words.next().unwrap_or_default() {
+            "method" => Attribute::Method,
+            "path" => Attribute::Path,
+            "ip" => Attribute::Ip,
+            "time" => Attribute::Time,
+            "weekday" => Attribute::Weekday,
+            user if user.starts_with("user.") && user.len() > 5 => {
+                Attribute::User(user[5..].to_string())
+            }
+            other => return Err(format!("unknown attribute `{}`", other)),
+        };
+
+        let rest = words.next().unwrap_or_default().trim_start();
+        let (negated, values) = if let Some(values) = rest.strip_prefix("not in ") {
+            (true, values)
+        } else if let Some(values) = rest.strip_prefix("in ") {
+            (false, values)
+        } else if let Some(values) = rest.strip_prefix("!=") {
+            (true, values)
+        } else if let Some(values) = rest.strip_prefix('=') {
+            (false, values)
+        } else {
+            return Err(format!(
+                "expected `=`, `!=`, `in` or `not in` in `{}`",
+                source
+            ));
+        };
+
+        let values = values
+            .split(',')
+            .map(|value| value.trim().trim_matches('"'))
+            .filter(|value| !value.is_empty())
+            .map(|value| Self::parse_value(&attribute, value))
+            .collect::<Result<Vec<_>, _>>()?;
+        if values.is_empty() {
+            return Err(format!("missing value in `{}`", source));
+        }
+
+        Ok(Condition {
+            attribute,
+            negated,
+            values,
+            source: source.to_string(),
+        })
+    }
+
+    fn parse_value(attribute: &Attribute, value: &str) -> Result<Value, String> {
+        let invalid = || format!("invalid value `{}`", value);
+
+        match attribute {
+            Attribute::Method => Ok(Value::Text(value.to_uppercase())),
+            Attribute::Path => match value.strip_suffix('*') {
+                Some(prefix) => Ok(Value::PathPrefix(prefix.to_string())),
+                None => Ok(Value::Text(value.to_string())),
+            },
+            Attribute::Ip => {
+                let mut parts = value.splitn(2, '/');
+                let ip: IpAddr = parts
+                    .next()
+                    .unwrap_or_default()
+                    .parse()
+                    .map_err(|_| invalid())?;
+                let max = if ip.is_ipv4() { 32 } else { 128 };
+                let prefix = match parts.next() {
+                    Some(prefix) => prefix.parse().map_err(|_| invalid())?,
+                    None => max,
+                };
+                if prefix > max {
+                    return Err(invalid());
+                }
+                Ok(Value::Network(ip, prefix))
+            }
+            Attribute::Time => {
+                let minute = |time: &str| -> Option<u32> {
+                    let mut parts = time.trim().splitn(2, ':');
+                    let hours: u32 = parts.next()?.parse().ok()?;
+                    let minutes: u32 = parts.next()?.parse().ok()?;
+                    (hours < 24 && minutes < 60).then(|| hours * 60 + minutes)
+                };
+                let mut parts = value.splitn(2, '-');
+                let start = parts.next().and_then(minute).ok_or_else(invalid)?;
+                let end = parts.next().and_then(minute).ok_or_else(invalid)?;
+                Ok(Value::TimeRange(start, end))
+            }
+            Attribute::Weekday => WEEKDAYS
+                .iter()
+                .position(|day| value.eq_ignore_ascii_case(day))
+                .map(Value::Weekday)
+                .ok_or_else(invalid),
+            Attribute::User(_) => Ok(Value::Text(value.to_string())),
+        }
+    }
+
+    /// Evaluates the condition, a missing attribute matches no value.
+    fn matches(&self, request: &RequestAttributes, user: &impl User) -> bool {
+        let (minute, weekday) = request.minute_and_weekday();
+        let user_attribute = match &self.attribute {
+            Attribute::User(name) if name == "username" => Some(user.username().to_string()),
+            Attribute::User(name) => user.attribute(name),
+            _ => None,
+        };
+
+        let matches = self
+            .values
+            .iter()
+            .any(|value| match (&self.attribute, value) {
+                (Attribute::Method, Value::Text(method)) => &request.method == method,
+                (Attribute::Path, Value::Text(path)) => &request.path == path,
+                (Attribute::Path, Value::PathPrefix(prefix)) => request.path.starts_with(prefix),
+                (Attribute::Ip, Value::Network(network, prefix)) => request
+                    .ip
+                    .is_some_and(|ip| in_network(ip, *network, *prefix)),
+                (Attribute::Time, Value::TimeRange(start, end)) if start <= end => {
+                    (*start..*end).contains(&minute)
+                }
+                (Attribute::Time, Value::TimeRange(start, end)) => {
+                    minute >= *start || minute < *end
+                }
+                (Attribute::Weekday, Value::Weekday(day)) => *day == weekday,
+                (Attribute::User(_), Value::Text(text)) => user_attribute.as_ref() == Some(text),
+                _ => false,
+            });
+
+        matches != self.negated
+    }
+}
+
+/// Returns `true` if the IP address is part of the network.
+fn in_network(ip: IpAddr, network: IpAddr, prefix: u8) -> bool {
+    match (ip, network) {
+        (IpAddr::V4(ip), IpAddr::V4(network)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(network)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+/// Returns the conditions after the `if` keyword, [`None`] if the string does not start with it.
+fn strip_if(s: &str) -> Option<&str> {
+    s.strip_prefix("if")
+        .filter(|rest| rest.is_empty() || rest.starts_with(char::is_whitespace))
+}
+
+/// A rule matches if all of its conditions match.
+#[derive(Debug, Clone)]
+struct Rule {
+    name: String,
+    effect: Effect,
+    conditions: Vec<Condition>,
+}
+
+/// The evaluation of a single rule, which is part of the trace of a [`Decision`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub rule: String,
+    pub effect: Effect,
+    pub matched: bool,
+    /// The first condition that did not match
+    pub failed_condition: Option<String>,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.failed_condition {
+            Some(condition) => write!(
+                f,
+                "{} {}: not matched, `{}` is false",
+                self.effect, self.rule, condition
+            ),
+            None => write!(f, "{} {}: matched", self.effect, self.rule),
+        }
+    }
+}
+
+/// The result of evaluating an [`AccessPolicy`] with the trace of every rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decision {
+    pub effect: Effect,
+    /// The rule that decided, [`None`] if the default was used
+    pub rule: Option<String>,
+    pub trace: Vec<TraceEntry>,
+}
+
+impl Decision {
+    /// Returns `true` if the request is allowed.
+    pub fn is_allowed(&self) -> bool {
+        self.effect == Effect::Allow
+    }
+}
+
+impl fmt::Display for Decision {
+    /// Writes the trace and the decision, one line each.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in &self.trace {
+            writeln!(f, "{}", entry)?;
+        }
+        match &self.rule {
+            Some(rule) => write!(f, "decision: {} by rule {}", self.effect, rule),
+            None => write!(f, "decision: {} by default", self.effect),
+        }
+    }
+}
+
+/// A declarative policy that decides about requests based on their attributes and the attributes of the user.
+///
+/// Every line of a policy is either empty, a comment starting with `#`, the default effect or a rule:
+///
+/// ```text
+/// # Used if no rule matches, `deny` if omitted
+/// default deny
+///
+/// # <allow|deny> [name] [if <condition> [and <condition>]...]
+/// deny external if ip not in 10.0.0.0/8, fd00::/8
+/// deny weekend if weekday in sat, sun
+/// allow reads if method in GET, HEAD
+/// allow finance if user.department = finance and time in 08:00-18:00
+/// ```
+///
+/// A condition compares an attribute with `=`, `!=`, `in` or `not in` to a comma separated list of values:
+///
+/// - `method`: the HTTP method, e.g. `GET`
+/// - `path`: the path of the request, a value ending with `*` matches every path with the prefix
+/// - `ip`: the IP address of the client, values are addresses or networks in CIDR notation
+/// - `time`: the time in UTC, values are ranges like `08:00-18:00` or `22:00-06:00`
+/// - `weekday`: the weekday in UTC, `mon` to `sun`
+/// - `user.<name>`: the attribute of the user returned by [`User::attribute`], `user.username` is the username
+///
+/// If an attribute is unknown, e.g. the IP address, `=` and `in` do not match and `!=` and `not in` do.
+/// Deny rules take precedence: a request is denied if any deny rule matches, otherwise it is allowed if any allow
+/// rule matches, otherwise the default effect applies.
+#[derive(Debug, Clone)]
+pub struct AccessPolicy {
+    default: Effect,
+    rules: Vec<Rule>,
+}
+
+impl AccessPolicy {
+    /// Reads the policy from a file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, PolicyError> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    /// Evaluates every rule of the policy and returns the decision with its trace.
+    pub fn evaluate(&self, request: &RequestAttributes, user: &impl User) -> Decision {
+        let trace: Vec<_> = self
+            .rules
+            .iter()
+            .map(|rule| {
+                let failed_condition = rule
+                    .conditions
+                    .iter()
+                    .find(|condition| !condition.matches(request, user))
+                    .map(|condition| condition.source.clone());
+
+                TraceEntry {
+                    rule: rule.name.clone(),
+                    effect: rule.effect,
+                    matched: failed_condition.is_none(),
+                    failed_condition,
+                }
+            })
+            .collect();
+
+        let decisive = [Effect::Deny, Effect::Allow]
+            .iter()
+            .find_map(|&effect| {
+                trace
+                    .iter()
+                    .find(|entry| entry.matched && entry.effect == effect)
+            })
+            .map(|entry| (entry.effect, Some(entry.rule.clone())));
+        let (effect, rule) = decisive.unwrap_or((self.default, None));
+
+        Decision {
+            effect,
+            rule,
+            trace,
+        }
+    }
+}
+
+impl FromStr for AccessPolicy {
+    type Err = PolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policy = AccessPolicy {
+            default: Effect::Deny,
+            rules: Vec::new(),
+        };
+
+        for (index, line) in s.lines().enumerate() {
+            let error = |reason: String| PolicyError::Parse {
+                line: index + 1,
+                reason,
+            };
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (keyword, rest) = match line.find(char::is_whitespace) {
+                Some(position) => (&line[..position], line[position..].trim()),
+                None => (line, ""),
+            };
+            let effect = match keyword {
+                "default" => {
+                    policy.default = match rest {
+                        "allow" => Effect::Allow,
+                        "deny" => Effect::Deny,
+                        _ => {
+                            return Err(error("expected `default allow` or `default deny`".into()))
+                        }
+                    };
+                    continue;
+                }
+                "allow" => Effect::Allow,
+                "deny" => Effect::Deny,
+                _ => return Err(error(format!("unknown keyword `{}`", keyword))),
+            };
+
+            // The name is optional, unnamed rules are named after their line
+            let (name, conditions) = match strip_if(rest) {
+                Some(conditions) => (None, Some(conditions)),
+                None => {
+                    let mut parts = rest.splitn(2, char::is_whitespace);
+                    let name = parts.next().filter(|name| !name.is_empty());
+                    match parts.next().map(str::trim).unwrap_or_default() {
+                        "" => (name, None),
+                        rest => match strip_if(rest) {
+                            Some(conditions) => (name, Some(conditions)),
+                            None => {
+                                return Err(error(
+                                    "expected `if` after the name of the rule".into(),
+                                ))
+                            }
+                        },
+                    }
+                }
+            };
+            let name = name.map_or_else(|| format!("line {}", index + 1), str::to_string);
+
+            let conditions = match conditions.map(str::trim) {
+                None => Vec::new(),
+                Some("") => return Err(error("missing condition after `if`".into())),
+                Some(conditions) => conditions
+                    .split(" and ")
+                    .map(|condition| Condition::parse(condition.trim()))
+                    .collect::<Result<_, _>>()
+                    .map_err(error)?,
+            };
+
+            policy.rules.push(Rule {
+                name,
+                effect,
+                conditions,
+            });
+        }
+
+        Ok(policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, HashSet};
+    use std::time::Duration;
+
+    struct TestUser {
+        attributes: HashMap<String, String>,
+        capabilities: HashSet<String>,
+    }
+
+    impl User for TestUser {
+        fn username(&self) -> &str {
+            "alice"
+        }
+
+        fn password_hash(&self) -> &str {
+            unimplemented!()
+        }
+
+        fn capabilities(&self) -> &HashSet<String> {
+            &self.capabilities
+        }
+
+        fn attribute(&self, name: &str) -> Option<String> {
+            self.attributes.get(name).cloned()
+        }
+    }
+
+    fn user(department: &str) -> TestUser {
+        TestUser {
+            attributes: vec![("department".to_string(), department.to_string())]
+                .into_iter()
+                .collect(),
+            capabilities: HashSet::new(),
+        }
+    }
+
+    /// Creates a request on monday the 4th of January 2021 at the given time in UTC.
+    fn request(method: &str, ip: &str, hours: u64, minutes: u64) -> RequestAttributes {
+        RequestAttributes {
+            time: UNIX_EPOCH + Duration::from_secs(1_609_718_400 + hours * 3600 + minutes * 60),
+            ..RequestAttributes::new(method, "/information/admin", ip.parse().ok())
+        }
+    }
+
+    const POLICY: &str = "
+        # Only requests from the internal network
+        default deny
+        deny external if ip not in 10.0.0.0/8, fd00::/8
+        allow reads if method in GET, HEAD
+        allow finance if user.department = finance and time in 08:00-18:00 and weekday != sun
+    ";
+
+    #[test]
+    fn deny_precedence() {
+        let policy: AccessPolicy = POLICY.parse().unwrap();
+
+        assert!(policy
+            .evaluate(&request("GET", "10.1.2.3", 12, 0), &user("it"))
+            .is_allowed());
+        let decision = policy.evaluate(&request("GET", "192.168.1.1", 12, 0), &user("it"));
+        assert_eq!(decision.effect, Effect::Deny);
+        assert_eq!(decision.rule.as_deref(), Some("external"));
+        // Without an IP address the negated condition matches
+        assert!(!policy
+            .evaluate(&request("GET", "", 12, 0), &user("it"))
+            .is_allowed());
+        assert!(policy
+            .evaluate(&request("GET", "fd00::1", 12, 0), &user("it"))
+            .is_allowed());
+    }
+
+    #[test]
+    fn user_attributes_and_time() {
+        let policy: AccessPolicy = POLICY.parse().unwrap();
+
+        assert!(policy
+            .evaluate(&request("POST", "10.0.0.1", 9, 30), &user("finance"))
+            .is_allowed());
+        assert!(!policy
+            .evaluate(&request("POST", "10.0.0.1", 18, 0), &user("finance"))
+            .is_allowed());
+        let decision = policy.evaluate(&request("POST", "10.0.0.1", 9, 30), &user("it"));
+        assert_eq!(decision.rule, None);
+        assert_eq!(decision.effect, Effect::Deny);
+    }
+
+    #[test]
+    fn time_range_over_midnight() {
+        let policy: AccessPolicy = "default allow\ndeny night if time in 22:00-06:00"
+            .parse()
+            .unwrap();
+
+        assert!(!policy
+            .evaluate(&request("GET", "", 23, 0), &user("it"))
+            .is_allowed());
+        assert!(!policy
+            .evaluate(&request("GET", "", 5, 59), &user("it"))
+            .is_allowed());
+        assert!(policy
+            .evaluate(&request("GET", "", 6, 0), &user("it"))
+            .is_allowed());
+    }
+
+    #[test]
+    fn trace() {
+        let policy: AccessPolicy = POLICY.parse().unwrap();
+        let decision = policy.evaluate(&request("POST", "10.0.0.1", 20, 0), &user("finance"));
+
+        assert_eq!(
+            decision.to_string(),
+            "deny external: not matched, `ip not in 10.0.0.0/8, fd00::/8` is false
+allow reads: not matched, `method in GET, HEAD` is false
+allow finance: not matched, `time in 08:00-18:00` is false
+decision: deny by default"
+        );
+    }
+
+    #[test]
+    fn unnamed_and_unconditional_rules() {
+        let policy: AccessPolicy = "allow if path = /\ndeny everything".parse().unwrap();
+        let decision = policy.evaluate(&request("GET", "", 12, 0), &user("it"));
+
+        assert_eq!(decision.trace[0].rule, "line 1");
+        assert_eq!(decision.rule.as_deref(), Some("everything"));
+    }
+
+    #[test]
+    fn invalid_policies() {
+        let line = |policy: &str| match policy.parse::<AccessPolicy>() {
+            Err(PolicyError::Parse { line, .. }) => line,
+            other => panic!("unexpected result {:?}", other),
+        };
+
+        assert_eq!(line("permit if method = GET"), 1);
+        assert_eq!(line("# comment\nallow if color = red"), 2);
+        assert_eq!(line("allow if ip in 10.0.0.0/33"), 1);
+        assert_eq!(line("allow if time in 25:00-26:00"), 1);
+        assert_eq!(line("allow if weekday = someday"), 1);
+        assert_eq!(line("allow if method GET"), 1);
+        assert_eq!(line("allow reads method = GET"), 1);
+        assert_eq!(line("allow if"), 1);
+        assert_eq!(line("default maybe"), 1);
+        assert!("allow iffy".parse::<AccessPolicy>().is_ok());
+    }
+}
@@ -0,0 +1,35 @@
+//! Builds a [`BloomFilter`] from a downloaded Have I Been Pwned corpus.
+//!
+//! Usage: `hibp-filter <corpus> <filter> [false-positive-rate]`
+//!
+//! The corpus is either the `pwned-passwords-sha1-ordered-by-hash` file or a directory of range files. The filter
+//! is loaded by [`access_control::breached::BreachedPasswords::open`].
+
+use access_control::breached::{read_hibp, BloomFilter};
+use std::{env, fs::File, io::BufWriter, process};
+
+/// The false positive rate if none is provided, which results in about 1.8 bytes per hash.
+const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.001;
+
+fn main() -> std::io::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 || args.len() > 4 {
+        eprintln!("Usage: {} <corpus> <filter> [false-positive-rate]", args[0]);
+        process::exit(1);
+    }
+    let false_positive_rate = args
+        .get(3)
+        .map(|rate| rate.parse().expect("false-positive-rate is not a number"))
+        .unwrap_or(DEFAULT_FALSE_POSITIVE_RATE);
+
+    // Count the hashes first, so that the corpus does not have to be kept in memory
+    let mut expected = 0;
+    read_hibp(&args[1], |_| expected += 1)?;
+
+    let mut filter = BloomFilter::new(expected, false_positive_rate);
+    read_hibp(&args[1], |hash| filter.insert(&hash))?;
+    filter.write_to(BufWriter::new(File::create(&args[2])?))?;
+
+    println!("Wrote {} hashes to {}", expected, args[2]);
+    Ok(())
+}
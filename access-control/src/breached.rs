@@ -0,0 +1,351 @@
+use crate::password_policy::{PasswordPolicy, Violation};
+use sha1::{Digest, Sha1};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+/// The SHA-1 digest of a password, as used by Have I Been Pwned.
+pub type Sha1Hash = [u8; 20];
+
+/// The magic bytes at the start of a file written by [`BloomFilter::write_to`].
+const FILTER_MAGIC: &[u8; 8] = b"HIBPBLM1";
+
+/// The maximum number of bits of a filter that is read by [`BloomFilter::read_from`], which is 16 GiB.
+///
+/// A filter of the whole corpus with a false positive rate of `0.001` has about 2 GiB.
+const MAX_FILTER_BITS: u64 = 1 << 37;
+
+/// The maximum number of bit indices per hash of a filter that is read by [`BloomFilter::read_from`].
+const MAX_FILTER_HASHES: u32 = 64;
+
+/// The number of words that [`BloomFilter::read_from`] allocates in advance, the rest is allocated as it is read.
+const PREALLOCATED_WORDS: usize = 1 << 20;
+
+/// Calculates the SHA-1 digest of a password.
+pub fn sha1(password: &str) -> Sha1Hash {
+    Sha1::digest(password.as_bytes()).into()
+}
+
+/// Parses a line of a Have I Been Pwned file, e.g. `5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824`.
+///
+/// Lines of range files only contain the last 35 characters of the hash, the 5 characters of the range are passed
+/// as `prefix` in this case. The count after the colon is optional. Returns [`None`] for lines that contain no hash.
+pub fn parse_hibp_line(prefix: &str, line: &str) -> Option<Sha1Hash> {
+    let suffix = line.split(':').next()?.trim();
+    let hex = format!("{}{}", prefix, suffix);
+    // from_str_radix would accept a sign, e.g. "+f"
+    if hex.len() != 40 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let mut hash = [0u8; 20];
+    for (byte, pair) in hash.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(hash)
+}
+
+/// Reads every hash of a Have I Been Pwned corpus and passes it to `f`.
+///
+/// The path is either a single file with full hashes, like the downloadable `pwned-passwords-sha1-ordered-by-hash`
+/// file, or a directory of range files named after their 5 character prefix, as created by the
+/// [PwnedPasswordsDownloader](https://github.com/HaveIBeenPwned/PwnedPasswordsDownloader).
+pub fn read_hibp(path: impl AsRef<Path>, mut f: impl FnMut(Sha1Hash)) -> io::Result<()> {
+    let path = path.as_ref();
+
+    if !path.is_dir() {
+        return read_lines(File::open(path)?, "", &mut f);
+    }
+
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        let prefix = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .filter(|stem| stem.len() == 5)
+            .map(str::to_owned);
+
+        if let Some(prefix) = prefix {
+            read_lines(File::open(&path)?, &prefix, &mut f)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the lines of a single file, see [`read_hibp`].
+fn read_lines(file: File, prefix: &str, f: &mut impl FnMut(Sha1Hash)) -> io::Result<()> {
+    for line in BufReader::new(file).lines() {
+        if let Some(hash) = parse_hibp_line(prefix, &line?) {
+            f(hash);
+        }
+    }
+    Ok(())
+}
+
+/// A Bloom filter of SHA-1 hashes, which is a compact representation of a Have I Been Pwned corpus.
+///
+/// A filter may report a password as breached that is not part of the corpus, the rate of these false positives is
+/// chosen when creating the filter. It never misses a password of the corpus.
+/// The `hibp-filter` binary of this crate builds a filter from a downloaded corpus.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BloomFilter {
+    /// The number of bit indices per hash
+    hashes: u32,
+    /// The number of bits in the filter
+    len: u64,
+    bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    /// Creates an empty filter for the expected number of hashes with the given false positive rate, e.g. `0.001`.
+    pub fn new(expected: u64, false_positive_rate: f64) -> Self {
+        let ln2 = std::f64::consts::LN_2;
+        let len =
+            (-(expected.max(1) as f64) * false_positive_rate.ln() / (ln2 * ln2)).ceil() as u64;
+        let len = len.max(64);
+        let hashes = ((len as f64 / expected.max(1) as f64) * ln2)
+            .round()
+            .max(1.0) as u32;
+
+        BloomFilter {
+            hashes,
+            len,
+            bits: vec![0; len.div_ceil(64) as usize],
+        }
+    }
+
+    /// Adds a hash to the filter.
+    pub fn insert(&mut self, hash: &Sha1Hash) {
+        for index in self.indices(hash).collect::<Vec<_>>() {
+            self.bits[(index / 64) as usize] |= 1 << (index % 64);
+        }
+    }
+
+    /// Returns `true` if the hash is probably part of the filter.
+    pub fn contains(&self, hash: &Sha1Hash) -> bool {
+        self.indices(hash)
+            .all(|index| self.bits[(index / 64) as usize] & (1 << (index % 64)) != 0)
+    }
+
+    /// The bit indices of a hash.
+    ///
+    /// SHA-1 digests are uniformly distributed, so two halves of the digest are used for double hashing instead of
+    /// hashing the digest again.
+    fn indices<'a>(&'a self, hash: &Sha1Hash) -> impl Iterator<Item = u64> + 'a {
+        let mut first = [0u8; 8];
+        let mut second = [0u8; 8];
+        first.copy_from_slice(&hash[..8]);
+        second.copy_from_slice(&hash[8..16]);
+        let first = u64::from_le_bytes(first);
+        let second = u64::from_le_bytes(second) | 1;
+
+        (0..self.hashes as u64).map(move |i| first.wrapping_add(i.wrapping_mul(second)) % self.len)
+    }
+
+    /// Writes the filter in a binary format that is read by [`BloomFilter::read_from`].
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        writer.write_all(FILTER_MAGIC)?;
+        writer.write_all(&self.hashes.to_le_bytes())?;
+        writer.write_all(&self.len.to_le_bytes())?;
+        for word in &self.bits {
+            writer.write_all(&word.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    /// Reads a filter that has been written by [`BloomFilter::write_to`].
+    pub fn read_from(mut reader: impl Read) -> io::Result<Self> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != FILTER_MAGIC {
+            return Err(invalid("not a breached password filter"));
+        }
+
+        let mut hashes = [0u8; 4];
+        let mut len = [0u8; 8];
+        reader.read_exact(&mut hashes)?;
+        reader.read_exact(&mut len)?;
+        let hashes = u32::from_le_bytes(hashes);
+        let len = u64::from_le_bytes(len);
+        if hashes == 0 || len == 0 {
+            return Err(invalid("empty breached password filter"));
+        }
+        if hashes > MAX_FILTER_HASHES || len > MAX_FILTER_BITS {
+            return Err(invalid("breached password filter is too large"));
+        }
+
+        // The header of a corrupt file may claim more words than the file contains, so the words are only allocated
+        // as they are read
+        let words = len.div_ceil(64) as usize;
+        let mut bits = Vec::with_capacity(words.min(PREALLOCATED_WORDS));
+        let mut word = [0u8; 8];
+        for _ in 0..words {
+            reader.read_exact(&mut word)?;
+            bits.push(u64::from_le_bytes(word));
+        }
+
+        Ok(BloomFilter { hashes, len, bits })
+    }
+}
+
+/// The representation of the corpus in memory.
+#[derive(Debug, Clone)]
+enum Corpus {
+    /// Sorted hashes, exact but requires 20 bytes per hash
+    Hashes(Vec<Sha1Hash>),
+    Filter(BloomFilter),
+}
+
+/// Rejects passwords that are part of a local copy of the [Have I Been Pwned](https://haveibeenpwned.com/Passwords)
+/// corpus of breached passwords, without any network access.
+///
+/// Loading the full corpus with [`BreachedPasswords::from_hibp`] requires a lot of memory, build a [`BloomFilter`]
+/// with the `hibp-filter` binary and load it with [`BreachedPasswords::from_filter`] instead.
+///
+/// The corpus is a [`PasswordPolicy`], so it only checks the passwords that the policy is applied to. This is the
+/// registration of [`crate::AccessControl::register`], there is no password change yet, which would have to check the
+/// new password with the same policy.
+#[derive(Debug, Clone)]
+pub struct BreachedPasswords {
+    corpus: Corpus,
+}
+
+impl BreachedPasswords {
+    /// Loads every hash of a corpus into memory, see [`read_hibp`] for the supported formats.
+    pub fn from_hibp(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut hashes = Vec::new();
+        read_hibp(path, |hash| hashes.push(hash))?;
+        Ok(Self::from_hashes(hashes))
+    }
+
+    /// Creates the corpus from a list of hashes.
+    pub fn from_hashes(mut hashes: Vec<Sha1Hash>) -> Self {
+        hashes.sort_unstable();
+        hashes.dedup();
+        BreachedPasswords {
+            corpus: Corpus::Hashes(hashes),
+        }
+    }
+
+    /// Uses a filter that has been built by the `hibp-filter` binary.
+    pub fn from_filter(filter: BloomFilter) -> Self {
+        BreachedPasswords {
+            corpus: Corpus::Filter(filter),
+        }
+    }
+
+    /// Opens a filter or a corpus, depending on the content of the path.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+
+        if path.is_file() {
+            let mut magic = [0u8; 8];
+            let mut file = File::open(path)?;
+            if file.read_exact(&mut magic).is_ok() && &magic == FILTER_MAGIC {
+                let filter = BloomFilter::read_from(BufReader::new(File::open(path)?))?;
+                return Ok(Self::from_filter(filter));
+            }
+        }
+        Self::from_hibp(path)
+    }
+
+    /// Returns `true` if the password has been breached.
+    pub fn contains(&self, password: &str) -> bool {
+        let hash = sha1(password);
+
+        match &self.corpus {
+            Corpus::Hashes(hashes) => hashes.binary_search(&hash).is_ok(),
+            Corpus::Filter(filter) => filter.contains(&hash),
+        }
+    }
+}
+
+impl PasswordPolicy for BreachedPasswords {
+    fn check(&self, _username: &str, password: &str) -> Vec<Violation> {
+        if self.contains(password) {
+            vec![Violation::Breached]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The hash of `password` in the format of the downloadable corpus.
+    const PASSWORD_LINE: &str = "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824";
+
+    #[test]
+    fn parse_lines() {
+        let hash = sha1("password");
+
+        assert_eq!(parse_hibp_line("", PASSWORD_LINE), Some(hash));
+        assert_eq!(
+            parse_hibp_line("5BAA6", "1E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824"),
+            Some(hash)
+        );
+        assert_eq!(
+            parse_hibp_line("", "5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8"),
+            Some(hash)
+        );
+        assert_eq!(parse_hibp_line("", "not a hash"), None);
+        assert_eq!(
+            parse_hibp_line("", "+baa61e4c9b93f3f0682250b6cf8331b7ee68fd8"),
+            None
+        );
+        assert_eq!(parse_hibp_line("", ""), None);
+    }
+
+    #[test]
+    fn hashes() {
+        let breached = BreachedPasswords::from_hashes(vec![sha1("password"), sha1("qwerty")]);
+
+        assert_eq!(
+            breached.check("alice", "password"),
+            vec![Violation::Breached]
+        );
+        assert!(breached
+            .check("alice", "correct horse battery staple")
+            .is_empty());
+    }
+
+    #[test]
+    /// Makes sure a filter survives writing and reading it and finds every inserted hash.
+    fn filter() {
+        let passwords: Vec<_> = (0..1000).map(|i| format!("password{}", i)).collect();
+        let mut filter = BloomFilter::new(passwords.len() as u64, 0.001);
+        passwords.iter().for_each(|p| filter.insert(&sha1(p)));
+
+        let mut written = Vec::new();
+        filter.write_to(&mut written).unwrap();
+        let breached =
+            BreachedPasswords::from_filter(BloomFilter::read_from(&written[..]).unwrap());
+
+        assert!(passwords.iter().all(|p| breached.contains(p)));
+        let false_positives = (0..1000)
+            .filter(|i| breached.contains(&format!("correct horse {}", i)))
+            .count();
+        assert!(false_positives < 10);
+    }
+
+    #[test]
+    fn invalid_filter() {
+        assert!(BloomFilter::read_from(&b"HIBPBLM0"[..]).is_err());
+        assert!(BloomFilter::read_from(&b"HIBPBLM1\x01\x00"[..]).is_err());
+
+        let header = |hashes: u32, len: u64| {
+            let mut header = FILTER_MAGIC.to_vec();
+            header.extend_from_slice(&hashes.to_le_bytes());
+            header.extend_from_slice(&len.to_le_bytes());
+            header
+        };
+        // A huge header must neither allocate the claimed bits nor loop over the claimed hashes
+        assert!(BloomFilter::read_from(&header(1, u64::MAX)[..]).is_err());
+        assert!(BloomFilter::read_from(&header(u32::MAX, 64)[..]).is_err());
+        assert!(BloomFilter::read_from(&header(1, MAX_FILTER_BITS)[..]).is_err());
+    }
+}
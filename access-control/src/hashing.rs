@@ -0,0 +1,404 @@
+use crate::pepper::Peppers;
+use crate::schemes::{self, PasswordScheme};
+use crate::{ARGON2_M_COST, ARGON2_P_COST, ARGON2_T_COST, FAKE_PHC_HASH};
+use argon2::password_hash::SaltString;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::{Params, Version, ARGON2ID_IDENT};
+use futures_channel::oneshot;
+use std::convert::TryFrom;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+
+/// A job that is executed by one of the threads of the [`HashingPool`].
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// The pool that is used by every hashing operation of this crate.
+///
+/// It is started lazily by the first hashing operation or explicitly by calling [`configure`].
+static POOL: OnceLock<HashingPool> = OnceLock::new();
+
+/// The configuration of the hashing pool that is passed to [`configure`].
+///
+/// # Default Values
+/// ```
+/// # use access_control::{hashing::{self, Config}, pepper::Peppers, schemes};
+/// Config {
+///     max_concurrency: hashing::default_max_concurrency(),
+///     legacy_schemes: schemes::default_schemes(),
+///     peppers: Peppers::new(),
+/// };
+/// ```
+pub struct Config {
+    /// The limit of concurrent hashing operations.
+    pub max_concurrency: usize,
+    /// The schemes used to verify hashes that are not Argon2 hashes, e.g. of users imported from other systems.
+    pub legacy_schemes: Vec<Box<dyn PasswordScheme>>,
+    /// The peppers used as Argon2 secret.
+    pub peppers: Peppers,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            max_concurrency: default_max_concurrency(),
+            legacy_schemes: schemes::default_schemes(),
+            peppers: Peppers::new(),
+        }
+    }
+}
+
+/// Error returned by [`configure`] if the pool has already been started.
+#[derive(thiserror::Error, Debug)]
+#[error("The hashing pool has already been started")]
+pub struct AlreadyStarted;
+
+/// A bounded pool of blocking threads that runs the Argon2 operations.
+///
+/// Argon2 is CPU and memory heavy by design. Running it inside an async function would block the thread of the
+/// executor (e.g. an actix worker) and therefore every other request that is handled by this thread.
+/// Instead, the operations are sent to a fixed number of threads. The number of threads limits the amount of
+/// concurrently running hash operations and therefore the memory used by them.
+struct HashingPool {
+    sender: Mutex<Sender<Job>>,
+    legacy_schemes: Vec<Box<dyn PasswordScheme>>,
+    peppers: Peppers,
+}
+
+impl HashingPool {
+    /// Starts `config.max_concurrency` threads that wait for jobs.
+    fn start(config: Config) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for i in 0..config.max_concurrency {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("hashing-{}", i))
+                .spawn(move || Self::work(receiver))
+                .expect("could not spawn hashing thread");
+        }
+
+        Self {
+            sender: Mutex::new(sender),
+            legacy_schemes: config.legacy_schemes,
+            peppers: config.peppers,
+        }
+    }
+
+    /// Executes jobs until every sender has been dropped.
+    fn work(receiver: Arc<Mutex<Receiver<Job>>>) {
+        loop {
+            // The lock is only held while waiting for the next job, not while executing it
+            let job = match receiver.lock() {
+                Ok(receiver) => receiver.recv(),
+                Err(_) => return,
+            };
+
+            match job {
+                // A panicking job must not take down the thread, the waiting future is notified by the dropped sender
+                Ok(job) => {
+                    let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                }
+                Err(_) => return,
+            }
+        }
+    }
+
+    /// Sends the job to the pool and waits for its result without blocking the current thread.
+    ///
+    /// Returns `None` if the job panicked.
+    async fn run<F, R>(&self, job: F) -> Option<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let job: Job = Box::new(move || {
+            let _ = tx.send(job());
+        });
+
+        self.sender
+            .lock()
+            .expect("hashing pool sender poisoned")
+            .send(job)
+            .expect("hashing pool stopped");
+
+        rx.await.ok()
+    }
+}
+
+/// The default limit of concurrent hashing operations, which is the available parallelism of the system.
+pub fn default_max_concurrency() -> usize {
+    thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+/// Starts the hashing pool with the provided [`Config`].
+///
+/// Every hashing operation allocates [`ARGON2_M_COST`] KiB of memory, so `max_concurrency` also bounds the memory used
+/// for hashing. This function has to be called before the first authentication or registration, otherwise the pool is
+/// started with the default [`Config`] and [`AlreadyStarted`] is returned.
+///
+/// # Panics
+/// Panics if `max_concurrency` is zero.
+pub fn configure(config: Config) -> Result<(), AlreadyStarted> {
+    assert!(
+        config.max_concurrency > 0,
+        "max_concurrency must not be zero"
+    );
+
+    let mut config = Some(config);
+    POOL.get_or_init(|| HashingPool::start(config.take().expect("pool is started once")));
+
+    match config {
+        None => Ok(()),
+        Some(_) => Err(AlreadyStarted),
+    }
+}
+
+fn pool() -> &'static HashingPool {
+    POOL.get_or_init(|| HashingPool::start(Config::default()))
+}
+
+/// The parameters every new hash is created with.
+fn current_params() -> Params {
+    Params {
+        m_cost: ARGON2_M_COST,
+        t_cost: ARGON2_T_COST,
+        p_cost: ARGON2_P_COST,
+        output_size: Params::DEFAULT_OUTPUT_SIZE,
+        version: Version::default(),
+    }
+}
+
+/// The name of the PHC parameter that contains the key id of the pepper.
+const KEY_ID_PARAM: &str = "keyid";
+
+fn get_argon2_ctx(secret: Option<&[u8]>) -> Argon2<'_> {
+    let params = current_params();
+    Argon2::new(
+        secret,
+        params.t_cost,
+        params.m_cost,
+        params.p_cost,
+        params.version,
+    )
+    .expect("invalid argon2 parameters")
+}
+
+/// Checks whether the PHC hash differs from the current hashing policy.
+///
+/// A hash needs to be rehashed if it is not an Argon2id hash, if its version or parameters differ from
+/// [`ARGON2_M_COST`], [`ARGON2_T_COST`] and [`ARGON2_P_COST`] or if it was not created with the current pepper of the
+/// [`Config`]. Invalid hashes always need to be rehashed.
+pub fn needs_rehash(password_hash: &str) -> bool {
+    let parsed_hash = match PasswordHash::new(password_hash) {
+        Ok(parsed_hash) => parsed_hash,
+        Err(_) => return true,
+    };
+    let current_key_id = pool().peppers.current().map(|(id, _)| id);
+
+    parsed_hash.algorithm != ARGON2ID_IDENT
+        || parsed_hash.params.get_str(KEY_ID_PARAM) != current_key_id
+        || Params::try_from(&parsed_hash)
+            .map(|params| params != current_params())
+            .unwrap_or(true)
+}
+
+/// Verifies the password against the hash on the hashing pool.
+///
+/// Hashes that are identified by one of the legacy schemes of the [`Config`] are verified by this scheme.
+/// Every other hash is verified as Argon2 PHC hash with the pepper of its key id.
+///
+/// If there is no hash, the hash is not a valid PHC hash or its pepper is unknown, the password is verified against
+/// [`FAKE_PHC_HASH`] and the verification fails. This way the verification takes the same amount of time, whether the
+/// user exists or not. Legacy schemes may be much faster than Argon2, so their hashes are additionally verified against
+/// [`FAKE_PHC_HASH`], which keeps imported users from being told apart by the response time.
+pub(crate) async fn verify_password(password: &str, password_hash: Option<String>) -> bool {
+    let password = password.to_string();
+    let pool = pool();
+
+    pool.run(move || {
+        if let Some(ref hash) = password_hash {
+            if let Some(scheme) = pool.legacy_schemes.iter().find(|s| s.identifies(hash)) {
+                let verified = scheme.verify(password.as_bytes(), hash);
+                verify_argon2(password.as_bytes(), None, &pool.peppers);
+                return verified;
+            }
+        }
+
+        verify_argon2(password.as_bytes(), password_hash.as_deref(), &pool.peppers)
+    })
+    .await
+    .unwrap_or(false)
+}
+
+/// Hashes the password with a random salt and the current pepper on the hashing pool and returns the PHC string.
+pub(crate) async fn hash_password(password: &str) -> String {
+    let password = password.to_string();
+    let pool = pool();
+
+    pool.run(move || hash_argon2(password.as_bytes(), &pool.peppers))
+        .await
+        .expect("hashing the password failed")
+}
+
+/// Verifies the password against the Argon2 PHC hash with the pepper of its key id.
+///
+/// If there is no hash, the hash is not a valid PHC hash or its pepper is unknown, the password is verified against
+/// [`FAKE_PHC_HASH`] and the verification fails.
+/// This function blocks the current thread and must only be called on the hashing pool.
+fn verify_argon2(password: &[u8], password_hash: Option<&str>, peppers: &Peppers) -> bool {
+    let parsed_hash = password_hash.and_then(|hash| PasswordHash::new(hash).ok());
+    // `None` if the hash has no key id and `Some(None)` if the pepper of the key id is unknown
+    let pepper = parsed_hash
+        .as_ref()
+        .and_then(|hash| hash.params.get_str(KEY_ID_PARAM))
+        .map(|key_id| peppers.get(key_id));
+
+    let (parsed_hash, secret, valid) = match (parsed_hash, pepper) {
+        (Some(hash), None) => (hash, None, true),
+        (Some(hash), Some(Some(secret))) => (hash, Some(secret), true),
+        _ => (
+            PasswordHash::new(FAKE_PHC_HASH).expect("fake hash is invalid PHC hash"),
+            None,
+            false,
+        ),
+    };
+
+    let verified = get_argon2_ctx(secret)
+        .verify_password(password, &parsed_hash)
+        .is_ok();
+
+    verified && valid
+}
+
+/// Creates an Argon2id PHC hash of the password with a random salt and the current pepper.
+///
+/// This function blocks the current thread and must only be called on the hashing pool.
+fn hash_argon2(password: &[u8], peppers: &Peppers) -> String {
+    let salt = SaltString::generate(rand::thread_rng());
+    let pepper = peppers.current();
+
+    let mut hash = get_argon2_ctx(pepper.map(|(_, secret)| secret))
+        .hash_password_simple(password, salt.as_ref())
+        .unwrap();
+    if let Some((key_id, _)) = pepper {
+        hash.params
+            .add_str(KEY_ID_PARAM, key_id)
+            .expect("pepper key id is invalid PHC value");
+    }
+
+    hash.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_executor::block_on;
+
+    #[test]
+    /// Hashes a password on the pool and verifies it afterwards.
+    fn hash_and_verify() {
+        let hash = block_on(hash_password("password"));
+
+        assert!(block_on(verify_password("password", Some(hash.clone()))));
+        assert!(!block_on(verify_password("wrongpassword", Some(hash))));
+    }
+
+    #[test]
+    /// Makes sure the verification fails without a hash or with an invalid hash.
+    fn verify_without_valid_hash() {
+        assert!(!block_on(verify_password("password", None)));
+        // The fake hash is derived from an empty password
+        assert!(!block_on(verify_password("", None)));
+        assert!(!block_on(verify_password(
+            "password",
+            Some("invalid".to_string())
+        )));
+    }
+
+    #[test]
+    /// Verifies hashes of the legacy schemes that are part of the default configuration.
+    fn verify_legacy_hash() {
+        let hash = "$apr1$r31uPcKa$pml3rFsVOzq1WUkO7cCVl.".to_string();
+
+        assert!(block_on(verify_password("password", Some(hash.clone()))));
+        assert!(!block_on(verify_password("wrongpassword", Some(hash))));
+    }
+
+    #[test]
+    /// Makes sure a legacy hash is not verified noticeably faster than an unknown user, which pays an Argon2 verify.
+    fn legacy_hash_costs_argon2_verify() {
+        let elapsed = |hash: Option<&str>| {
+            let start = std::time::Instant::now();
+            block_on(verify_password("password", hash.map(str::to_string)));
+            start.elapsed()
+        };
+        // Starts the pool, so that its startup is not measured
+        elapsed(None);
+
+        let unknown = elapsed(None);
+        let legacy = elapsed(Some("$apr1$r31uPcKa$pml3rFsVOzq1WUkO7cCVl."));
+        assert!(legacy >= unknown / 4, "{:?} < {:?} / 4", legacy, unknown);
+    }
+
+    #[test]
+    /// Makes sure only hashes with outdated algorithms or parameters need to be rehashed.
+    fn rehash_outdated_hashes() {
+        let hash = block_on(hash_password("password"));
+
+        assert!(!needs_rehash(&hash));
+        assert!(!needs_rehash(FAKE_PHC_HASH));
+        // Lower memory cost
+        assert!(needs_rehash(
+            "$argon2id$v=19$m=4096,t=2,p=1$saltsaltsaltsalt$1hx6lvjIBIrxykf2XmEdsNUxMAsJ6FBKtP5g4R0UygY"
+        ));
+        // Argon2i instead of Argon2id
+        assert!(needs_rehash(
+            "$argon2i$v=19$m=15360,t=2,p=1$saltsaltsaltsalt$1hx6lvjIBIrxykf2XmEdsNUxMAsJ6FBKtP5g4R0UygY"
+        ));
+        // Version 0x10
+        assert!(needs_rehash(
+            "$argon2id$v=16$m=15360,t=2,p=1$saltsaltsaltsalt$1hx6lvjIBIrxykf2XmEdsNUxMAsJ6FBKtP5g4R0UygY"
+        ));
+        assert!(needs_rehash("invalid"));
+    }
+
+    #[test]
+    /// Hashes a password with the current pepper and verifies it after the pepper has been rotated.
+    fn verify_peppered_hash() {
+        let old = Peppers::new()
+            .add("old", b"old pepper".to_vec())
+            .and_then(|peppers| peppers.with_current("old"))
+            .unwrap();
+        let rotated = old
+            .clone()
+            .add("new", b"new pepper".to_vec())
+            .and_then(|peppers| peppers.with_current("new"))
+            .unwrap();
+
+        let hash = hash_argon2(b"password", &old);
+
+        assert!(hash.contains("keyid=old"));
+        assert!(verify_argon2(b"password", Some(&hash), &old));
+        assert!(verify_argon2(b"password", Some(&hash), &rotated));
+        assert!(!verify_argon2(b"wrongpassword", Some(&hash), &rotated));
+        // Without the pepper of the key id the verification has to fail
+        assert!(!verify_argon2(b"password", Some(&hash), &Peppers::new()));
+        // The pool uses the default configuration without a current pepper
+        assert!(needs_rehash(&hash));
+    }
+
+    #[test]
+    /// Makes sure a panicking job does not stop the pool.
+    fn panicking_job() {
+        let result: Option<()> = block_on(pool().run(|| panic!("job panicked")));
+
+        assert!(result.is_none());
+        assert_eq!(block_on(pool().run(|| 42)), Some(42));
+    }
+}
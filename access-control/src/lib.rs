@@ -1,9 +1,62 @@
-use argon2::password_hash::SaltString;
-use argon2::Params;
-use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+//! The access-control crate implements authentication and authorization on top of a generic [`Backend`].
+//!
+//! The [`AccessControl`] struct uses the typestate pattern to make sure a user is authenticated and authorized.
+//! Users with two-factor authentication must additionally pass [`AccessControl::verify_totp`], see [`totp`], or use one
+//! of their [`recovery`] codes.
+//! Passkeys are an alternative to the password, see [`AccessControl::authenticate_webauthn`] and [`webauthn`].
+//! Users that want to stay logged in get a [`remember`] token, see [`AccessControl::authenticate_remember_token`].
+//!
+//! The [`hashing`] module runs the password hashing on a bounded pool of blocking threads, so that the async
+//! executor is not blocked by Argon2. Hashes of imported users are verified by the [`schemes`] module and the
+//! [`pepper`] module provides the server-side secrets that are added to every new hash.
+//!
+//! The `memory` module of the `test-support` feature contains a [`Backend`] without a database to test routes.
+
+/// Attribute-based access policies with allow and deny rules.
+pub mod access_policy;
+/// Offline check of passwords against a local copy of the Have I Been Pwned corpus.
+pub mod breached;
+/// Password hashing and verification on a bounded pool of blocking threads.
+pub mod hashing;
+/// A backend that keeps its data in memory for tests.
+#[cfg(any(test, feature = "test-support"))]
+pub mod memory;
+/// Composable rules that passwords of new users must match.
+pub mod password_policy;
+/// Server-side secrets for password hashes with support for key rotation.
+pub mod pepper;
+/// Single-use recovery codes as fallback of the second factor.
+pub mod recovery;
+/// Long-lived remember-me tokens that restore the login after the session expired.
+pub mod remember;
+/// Boolean expressions of capabilities that a user must satisfy to be authorized.
+pub mod requirement;
+/// Verification of password hashes in other formats than Argon2, e.g. bcrypt.
+pub mod schemes;
+/// Idle and absolute timeouts of sessions.
+pub mod session;
+/// Exponential backoff and temporary lockout of accounts and clients after failed logins.
+pub mod throttling;
+/// Time-based one-time passwords as second factor.
+pub mod totp;
+/// Canonicalization of usernames and the rules that usernames of new users must match.
+pub mod username_policy;
+/// Registration and authentication ceremonies of WebAuthn credentials like passkeys.
+pub mod webauthn;
+
+use password_policy::{PasswordPolicy, Violation};
+use remember::{RememberSeries, RememberToken};
+use requirement::Requirement;
+use session::{SessionInfo, SessionLifetime, SessionMetadata};
 use std::collections::HashSet;
 use std::future::Future;
+use std::net::IpAddr;
 use std::pin::Pin;
+use std::time::{Duration, SystemTime};
+use throttling::{FailedAttempts, ThrottlePolicy, Throttling};
+use totp::Totp;
+use username_policy::{UsernamePolicy, UsernameViolation};
+use webauthn::{AssertionResponse, Credential, RegistrationResponse, RelyingParty, WebAuthnError};
 
 /// Memory cost of 15 MiB as per
 /// [OWASP](https://cheatsheetseries.owasp.org/cheatsheets/Password_Storage_Cheat_Sheet.html#argon2id)
@@ -51,12 +104,26 @@ pub enum Error {
     /// A error when verifying a users identity is an authentication error.
     #[error("Permission denied")]
     Authorization,
-    /// The error to return when the registration failed because the username is invalid
+    /// The error to return when there were too many failed logins, containing how long the client has to wait
+    ///
+    /// It is returned before the credentials are checked, so it does not reveal whether they are valid.
+    #[error("Too many failed login attempts")]
+    Throttled(Duration),
+    /// The error to return when the one-time password of the second factor is invalid or has already been used
+    #[error("Invalid one-time password")]
+    OneTimePassword,
+    /// The error to return when a recovery code is invalid or has already been used, or new codes could not be stored
+    #[error("Invalid recovery code")]
+    RecoveryCode,
+    /// The error to return when the registration of a WebAuthn credential failed, containing the reason why
+    #[error("WebAuthn registration failed: {0}")]
+    WebAuthn(WebAuthnError),
+    /// The error to return when the registration failed because the username is invalid, containing the reasons why
     #[error("Username does not match the policy")]
-    UsernamePolicy,
-    /// The error to return when the password is insufficient
+    UsernamePolicy(Vec<UsernameViolation>),
+    /// The error to return when the password is insufficient, containing the reasons why
     #[error("Password does not match the policy")]
-    PasswordPolicy,
+    PasswordPolicy(Vec<Violation>),
 }
 
 /// The Backend trait defines the operations of the database layer.
@@ -81,10 +148,208 @@ pub trait Backend {
         username: impl AsRef<str>,
         password_hash: impl AsRef<str>,
     ) -> FutureResult<()>;
+    /// Defines a method that should replace the password hash of a user.
+    ///
+    /// It is used to transparently upgrade a hash after a successful login, if the hashing policy changed.
+    fn update_password_hash(
+        &self,
+        user: &Self::User,
+        password_hash: impl AsRef<str>,
+    ) -> FutureResult<()>;
     /// Defines a method that should store a new session for a provided user and session id into the database.
-    fn store_session(&self, user: &Self::User, session_id: impl AsRef<str>) -> FutureResult<()>;
+    ///
+    /// The session must expire after the idle timeout of the [`SessionLifetime`], it is extended by
+    /// [`Backend::touch_session`], but never beyond the absolute timeout. The [`SessionMetadata`] and the creation
+    /// time are stored as well, so that the user can recognize the session in [`Backend::get_sessions`].
+    ///
+    /// Like every other session operation, it should only use the [`session::hash_session_id`] of the session id, so
+    /// that the database does not contain usable session ids.
+    fn store_session(
+        &self,
+        user: &Self::User,
+        session_id: impl AsRef<str>,
+        lifetime: &SessionLifetime,
+        metadata: &SessionMetadata,
+    ) -> FutureResult<()>;
+    /// Defines a method that should extend a session to expire after the idle timeout from now, but not later than
+    /// its absolute expiration, and record now as the time it has last been seen.
+    ///
+    /// Returns the new expiration date, or `None` if the session does not exist, is pending or has expired.
+    fn touch_session(
+        &self,
+        session_id: impl AsRef<str>,
+        idle_timeout: Duration,
+    ) -> FutureOption<SystemTime>;
+    /// Defines a method that should store a session for a user that still has to pass the second factor.
+    ///
+    /// A pending session must not be returned by [`Backend::get_user_from_session`], it is only used to resume the
+    /// login with [`AccessControl::resume_totp`] and removed with [`Backend::remove_session`] afterwards. `remember`
+    /// is whether the user wants to stay logged in, which is only granted after the second factor.
+    fn store_pending_session(
+        &self,
+        user: &Self::User,
+        session_id: impl AsRef<str>,
+        remember: bool,
+    ) -> FutureResult<()>;
+    /// Defines a method that should retrieve a user and whether they want to stay logged in by the id of a pending
+    /// session from the database.
+    fn get_user_from_pending_session(
+        &self,
+        session_id: impl AsRef<str>,
+    ) -> FutureOption<(Self::User, bool)>;
+    /// Defines a method that should count an attempt of the second factor of a pending session.
+    ///
+    /// Returns the number of attempts including this one, or `None` if the pending session does not exist or has
+    /// expired. The counter must be incremented atomically, so that parallel attempts are all counted.
+    fn record_pending_attempt(&self, session_id: impl AsRef<str>) -> FutureOption<u32>;
     /// Defines a method that should remove an existing session by a provided session id.
     fn remove_session(&self, session_id: impl AsRef<str>) -> FutureResult<()>;
+    /// Defines a method that should retrieve the sessions of a user that have not expired, the most recently seen
+    /// first.
+    ///
+    /// Pending sessions are left out. The session with the provided session id is marked as [`SessionInfo::current`].
+    fn get_sessions(
+        &self,
+        user: &Self::User,
+        current_session_id: impl AsRef<str>,
+    ) -> FutureResult<Vec<SessionInfo>>;
+    /// Defines a method that should remove a session of the user by its [`SessionInfo::id`].
+    ///
+    /// The remember-me series of the [`SessionMetadata::remember_selector`] stored with the session is removed as well,
+    /// as it would otherwise restore the revoked login. Returns `false` if the user has no such session, so that users
+    /// can not revoke the sessions of other users.
+    fn revoke_session(&self, user: &Self::User, id: impl AsRef<str>) -> FutureResult<bool>;
+    /// Defines a method that should remove every session of the user except for the one with the provided session id.
+    ///
+    /// The remember-me series of the user are removed as well, except for the one with the provided selector, as
+    /// they would otherwise restore the revoked logins.
+    fn revoke_other_sessions(
+        &self,
+        user: &Self::User,
+        current_session_id: impl AsRef<str>,
+        remember_selector: Option<&str>,
+    ) -> FutureResult<()>;
+    /// Defines a method that should return whether the user has been granted the action on a single resource.
+    ///
+    /// A grant is identified by the user, the action (e.g. `edit`), the resource type (e.g. `document`) and the id of
+    /// the resource (e.g. `42`). It is used by [`AccessControl::authorize_resource`].
+    fn has_grant(
+        &self,
+        user: &Self::User,
+        action: impl AsRef<str>,
+        resource_type: impl AsRef<str>,
+        resource_id: impl AsRef<str>,
+    ) -> FutureResult<bool>;
+    /// Defines a method that should store the base32 encoded TOTP secret of a user, `None` disables the second factor.
+    ///
+    /// The last used time step must be reset, as it belongs to the previous secret.
+    fn store_totp_secret(&self, user: &Self::User, secret: Option<&str>) -> FutureResult<()>;
+    /// Defines a method that should record the time step of a used one-time password.
+    ///
+    /// Returns `false` if the user already used a one-time password of the same or a later time step, which prevents
+    /// the replay of a code. The check and the update must be atomic, e.g. with a conditional `UPDATE`.
+    fn use_totp_step(&self, user: &Self::User, step: u64) -> FutureResult<bool>;
+    /// Defines a method that should retrieve the failed login attempts of an account or client by its key.
+    ///
+    /// The keys are built by [`throttling::account_key`] and [`throttling::client_ip_key`].
+    fn get_failed_attempts(&self, key: impl AsRef<str>) -> FutureResult<Option<FailedAttempts>>;
+    /// Defines a method that should count a failed login attempt of the key and set its last failure to now.
+    ///
+    /// The count must start again at `1` if the last failure is older than `reset_after`. The increment must be
+    /// atomic, e.g. with an `INSERT ... ON CONFLICT DO UPDATE`, so that concurrent attempts are all counted. As every
+    /// tried username is a key, the failures of keys whose `reset_after` has passed should be removed, so that they do
+    /// not accumulate.
+    fn record_failed_attempt(
+        &self,
+        key: impl AsRef<str>,
+        reset_after: Duration,
+    ) -> FutureResult<()>;
+    /// Defines a method that should remove the failed login attempts of the key.
+    fn reset_failed_attempts(&self, key: impl AsRef<str>) -> FutureResult<()>;
+    /// Defines a method that should replace the recovery codes of a user with a new batch of hashes.
+    ///
+    /// The codes of the old batch must not be usable afterwards, so the replacement should be atomic, e.g. in a
+    /// transaction. The hashes are prefixed with the selector of their code and must be stored unchanged.
+    fn store_recovery_codes(&self, user: &Self::User, code_hashes: &[String]) -> FutureResult<()>;
+    /// Defines a method that should retrieve the hashes of the unused recovery codes of a user.
+    fn get_recovery_codes(&self, user: &Self::User) -> FutureResult<Vec<String>>;
+    /// Defines a method that should remove a recovery code of a user by its hash.
+    ///
+    /// Returns `false` if the code has already been removed, which prevents that a code is used twice concurrently.
+    fn use_recovery_code(
+        &self,
+        user: &Self::User,
+        code_hash: impl AsRef<str>,
+    ) -> FutureResult<bool>;
+    /// Defines a method that should store a WebAuthn challenge until it is used or expired.
+    ///
+    /// Challenges of a registration belong to the user, challenges of a login have no user. They should expire after
+    /// [`webauthn::TIMEOUT`] and be deleted once expired, as anyone can request the challenge of a login.
+    fn store_webauthn_challenge(
+        &self,
+        user: Option<&Self::User>,
+        challenge: impl AsRef<str>,
+    ) -> FutureResult<()>;
+    /// Defines a method that should remove a WebAuthn challenge that has been stored for the same user.
+    ///
+    /// Returns `false` if there is no such challenge or it has expired, which prevents the replay of a response. The
+    /// check and the removal must be atomic, e.g. with a `DELETE` whose affected rows are counted.
+    fn take_webauthn_challenge(
+        &self,
+        user: Option<&Self::User>,
+        challenge: impl AsRef<str>,
+    ) -> FutureResult<bool>;
+    /// Defines a method that should store a new WebAuthn credential of the user.
+    fn store_webauthn_credential(
+        &self,
+        user: &Self::User,
+        credential: &Credential,
+    ) -> FutureResult<()>;
+    /// Defines a method that should retrieve a WebAuthn credential and the user it belongs to by the credential id.
+    fn get_webauthn_credential(
+        &self,
+        credential_id: &[u8],
+    ) -> FutureOption<(Self::User, Credential)>;
+    /// Defines a method that should replace the signature counter of a WebAuthn credential.
+    ///
+    /// Returns `false` if the stored counter is not lower, unless the counter is `0`, as authenticators without a
+    /// counter always return `0`. The check and the update must be atomic, e.g. with a conditional `UPDATE`.
+    fn update_webauthn_sign_count(
+        &self,
+        credential_id: &[u8],
+        sign_count: u32,
+    ) -> FutureResult<bool>;
+    /// Defines a method that should store a new remember-me series of the user, which expires after the lifetime.
+    ///
+    /// The series is identified by the selector of the [`RememberToken`], only the hash of its validator is stored.
+    fn store_remember_token(
+        &self,
+        user: &Self::User,
+        selector: impl AsRef<str>,
+        validator_hash: impl AsRef<str>,
+        lifetime: Duration,
+    ) -> FutureResult<()>;
+    /// Defines a method that should retrieve the user and the stored [`RememberSeries`] by its selector, if the series
+    /// has not expired.
+    fn get_remember_token(
+        &self,
+        selector: impl AsRef<str>,
+    ) -> FutureOption<(Self::User, RememberSeries)>;
+    /// Defines a method that should replace the validator hash of a remember-me series and renew it for the lifetime.
+    ///
+    /// The replaced hash is kept as previous validator hash together with the time of the rotation, see
+    /// [`remember::GRACE_PERIOD`]. Returns `false` if the current hash is not `old_hash` anymore, which prevents that a
+    /// validator is used twice concurrently. The check and the update must be atomic, e.g. with a conditional
+    /// `UPDATE`.
+    fn rotate_remember_token(
+        &self,
+        selector: impl AsRef<str>,
+        old_hash: impl AsRef<str>,
+        new_hash: impl AsRef<str>,
+        lifetime: Duration,
+    ) -> FutureResult<bool>;
+    /// Defines a method that should remove a remember-me series by its selector.
+    fn remove_remember_token(&self, selector: impl AsRef<str>) -> FutureResult<()>;
 }
 
 /// The User trait defines the operations of a User that are necessary to be handled by the middleware.
@@ -99,29 +364,30 @@ pub trait User {
     fn username(&self) -> &str;
     fn password_hash(&self) -> &str;
     fn capabilities(&self) -> &HashSet<String>;
-}
-
-fn get_argon2_ctx() -> Argon2<'static> {
-    let params = Params::default();
-    Argon2::new(
-        None,
-        ARGON2_T_COST,
-        ARGON2_M_COST,
-        ARGON2_P_COST,
-        params.version,
-    )
-    .expect("invalid argon2 parameters")
+    /// Returns an attribute of the user like the department, which [`access_policy::AccessPolicy`] rules can refer to.
+    ///
+    /// Users have no attributes by default.
+    fn attribute(&self, _name: &str) -> Option<String> {
+        None
+    }
+    /// Returns the base32 encoded TOTP secret if the user enabled two-factor authentication.
+    ///
+    /// Users have no second factor by default.
+    fn totp_secret(&self) -> Option<&str> {
+        None
+    }
 }
 
 /// AccessControl defines the behavior of a [`Backend`] and ensures its safety at compile time.
 /// This safety is guaranteed by the implementation of the [typestate pattern](http://cliffle.com/blog/rust-typestate/).
 ///
-/// In the case of the AccessControl struct we use the states [`Start`], [`Authenticated`] and [`Authorized`] to ensure that operations are executed in the correct order.
+/// In the case of the AccessControl struct we use the states [`Start`], [`TotpPending`], [`Authenticated`] and [`Authorized`] to ensure that operations are executed in the correct order.
 /// # Operations
 /// The AccessControl defines multiple operations stretched over multiple states.
-/// 1. **Start** provides the [`AccessControl::new`] and [`AccessControl::authenticate_session`] method
-/// 2. **Authenticated** provides the [`AccessControl::authorize`] method
-/// 3. **Authorized** provides the [`AccessControl::get_user`] method
+/// 1. **Start** provides the [`AccessControl::new`], [`AccessControl::authenticate_creds`], [`AccessControl::authenticate_session`], [`AccessControl::resume_totp`], [`AccessControl::begin_webauthn_login`] and [`AccessControl::authenticate_webauthn`] method
+/// 2. **TotpPending** provides the [`AccessControl::verify_totp`] and [`AccessControl::verify_recovery_code`] method, it is only reached by users with two-factor authentication
+/// 3. **Authenticated** provides the [`AccessControl::authorize`], [`AccessControl::authorize_resource`], [`AccessControl::enable_totp`], [`AccessControl::generate_recovery_codes`], [`AccessControl::begin_webauthn_registration`] and [`AccessControl::register_webauthn`] method
+/// 4. **Authorized** provides the [`AccessControl::get_user`] method
 ///
 /// # Definition
 /// To use the AccessControl an implementation of a Backend and therefore User is necessary.
@@ -131,6 +397,8 @@ where
     S: AccessControlState,
     B: Backend,
 {
+    // The state is mostly used at compile time, only the TotpPending state carries data
+    #[allow(dead_code)]
     state: S,
     backend: B,
     user: Option<B::User>,
@@ -161,31 +429,72 @@ where
     /// The authentication process is implemented by the provided `Backend<impl User>` and its `get_user` method.
     ///
     /// This method may return [`Error::Authentication`] on error, otherwise it returns a AccessControl in the state [`Authenticated`].
+    ///
+    /// If the stored hash was created with an outdated algorithm or outdated parameters (see [`hashing::needs_rehash`]),
+    /// the password is hashed again and stored with [`Backend::update_password_hash`].
+    /// This way users that were imported with a hash of one of the [`schemes`] are upgraded to Argon2id.
+    ///
+    /// The user is looked up by the [canonical form](username_policy::canonicalize) of the username.
+    ///
+    /// If the user enabled two-factor authentication, [`Authentication::TotpRequired`] is returned, which can only
+    /// proceed to the [`Authenticated`] state with [`AccessControl::verify_totp`].
+    ///
+    /// Failed attempts are counted per username and per client IP with [`Backend::record_failed_attempt`]. Once the
+    /// [`Throttling`] limits are exceeded, [`Error::Throttled`] is returned before the credentials are checked. As the
+    /// username is counted whether the user exists or not, the throttling does not reveal registered usernames. If the
+    /// failed attempts can not be read, the login fails with [`Error::Authentication`].
     pub async fn authenticate_creds(
         self,
         username: impl AsRef<str>,
         password: impl AsRef<str>,
-    ) -> Result<AccessControl<Authenticated, B>, Error> {
-        let user = self.backend.get_user(username).await;
+        client_ip: Option<IpAddr>,
+        throttling: &Throttling,
+    ) -> Result<Authentication<B>, Error> {
+        let username = username_policy::canonicalize(username.as_ref());
+        let keys = throttling.keys(Some(throttling::account_key(&username)), client_ip);
+        check_throttling(&self.backend, &keys).await?;
+
+        let user = self.backend.get_user(&username).await;
 
         // We can't do an early return if the user does not exist in the database so
-        // we verify with a fake hash
+        // the hashing module verifies with a fake hash
         // Maybe constant time with https://docs.rs/subtle/2.4.0/subtle/struct.CtOption.html?
-        let fake_parsed_hash =
-            PasswordHash::new(FAKE_PHC_HASH).expect("fake hash is invalid PHC hash");
-        let parsed_hash = match user {
-            Some(ref user) => PasswordHash::new(user.password_hash()).unwrap_or(fake_parsed_hash),
-            None => fake_parsed_hash,
-        };
+        let password_hash = user.as_ref().map(|user| user.password_hash().to_string());
+
+        if hashing::verify_password(password.as_ref(), password_hash).await {
+            // Upgrade outdated hashes while the plain password is available
+            if let Some(ref user) = user {
+                if hashing::needs_rehash(user.password_hash()) {
+                    let password_hash = hashing::hash_password(password.as_ref()).await;
+                    // A failed upgrade must not prevent the login, it is retried on the next login
+                    let _ = self.backend.update_password_hash(user, password_hash).await;
+                }
+            }
 
-        match get_argon2_ctx().verify_password(password.as_ref().as_bytes(), &parsed_hash) {
-            Ok(_) => Ok(AccessControl {
+            // Only the failures of the account are forgotten, otherwise a client could reset its own counter by
+            // logging into an account of its own between guesses
+            let _ = self
+                .backend
+                .reset_failed_attempts(throttling::account_key(&username))
+                .await;
+
+            // If the password verifies, the user is some!
+            if user.as_ref().and_then(User::totp_secret).is_some() {
+                return Ok(Authentication::TotpRequired(AccessControl {
+                    state: TotpPending { remember: false },
+                    backend: self.backend,
+                    user,
+                }));
+            }
+
+            Ok(Authentication::Complete(AccessControl {
                 state: Authenticated,
                 backend: self.backend,
-                // If the password verifies, the user is some!
                 user,
-            }),
-            Err(_) => Err(Error::Authentication),
+            }))
+        } else {
+            record_failed_attempts(&self.backend, &keys).await;
+            Err(Error::Authentication)
         }
     }
 
@@ -206,32 +515,209 @@ where
         })
     }
 
+    /// Resumes the login of a user with two-factor authentication by the id of a pending session.
+    ///
+    /// The pending session has been stored with [`Backend::store_pending_session`] after the password was verified.
+    /// Every resumption is an attempt of the second factor, which is counted with [`Backend::record_pending_attempt`].
+    /// Once [`totp::MAX_ATTEMPTS`] have been made, the pending session is removed and the user has to enter the
+    /// password again.
+    pub async fn resume_totp(
+        self,
+        session_id: impl AsRef<str>,
+    ) -> Result<AccessControl<TotpPending, B>, Error> {
+        let session_id = session_id.as_ref();
+        let (user, remember) = self
+            .backend
+            .get_user_from_pending_session(session_id)
+            .await
+            .ok_or(Error::Authentication)?;
+        let attempts = self
+            .backend
+            .record_pending_attempt(session_id)
+            .await
+            .ok_or(Error::Authentication)?;
+        if attempts > totp::MAX_ATTEMPTS {
+            let _ = self.backend.remove_session(session_id).await;
+            return Err(Error::Authentication);
+        }
+        Ok(AccessControl {
+            state: TotpPending { remember },
+            backend: self.backend,
+            user: Some(user),
+        })
+    }
+
+    /// Starts the login with a passkey by issuing a challenge, which is stored with [`Backend::store_webauthn_challenge`].
+    ///
+    /// Returns the options of `navigator.credentials.get` as JSON, the response is verified by
+    /// [`AccessControl::authenticate_webauthn`].
+    pub async fn begin_webauthn_login(
+        &self,
+        relying_party: &RelyingParty,
+    ) -> Result<String, Error> {
+        let challenge = webauthn::generate_challenge();
+        self.backend
+            .store_webauthn_challenge(None, &challenge)
+            .await
+            .map_err(|_| Error::Authentication)?;
+        Ok(relying_party.request_options(&challenge))
+    }
+
+    /// Authenticate a user with a passkey by providing the response of `navigator.credentials.get`.
+    ///
+    /// The credential is looked up with [`Backend::get_webauthn_credential`] and its signature is verified. The
+    /// challenge is consumed with [`Backend::take_webauthn_challenge`], so every response can only be used once, and the
+    /// signature counter is updated with [`Backend::update_webauthn_sign_count`].
+    ///
+    /// The authenticator verified the user, so users with two-factor authentication do not have to enter a one-time
+    /// password. This method may return [`Error::Authentication`] on error, otherwise it returns a AccessControl in the
+    /// state [`Authenticated`].
+    ///
+    /// Failed attempts are throttled like [`AccessControl::authenticate_creds`], per client IP and, if the passkey is
+    /// known, per account of the passkey.
+    pub async fn authenticate_webauthn(
+        self,
+        relying_party: &RelyingParty,
+        response: &AssertionResponse,
+        client_ip: Option<IpAddr>,
+        throttling: &Throttling,
+    ) -> Result<AccessControl<Authenticated, B>, Error> {
+        let credential = self
+            .backend
+            .get_webauthn_credential(&response.credential_id)
+            .await;
+        let account_key = credential
+            .as_ref()
+            .map(|(user, _)| throttling::account_key(user.username()));
+        let keys = throttling.keys(account_key, client_ip);
+        check_throttling(&self.backend, &keys).await?;
+
+        // The challenge is consumed first, so a replayed response does not update the counter
+        let verified: Result<Option<B::User>, Box<dyn std::error::Error>> = async {
+            let (user, credential) = match credential {
+                Some(credential) => credential,
+                None => return Ok(None),
+            };
+            let (challenge, sign_count) =
+                match relying_party.verify_assertion(response, &credential) {
+                    Ok(verified) => verified,
+                    Err(_) => return Ok(None),
+                };
+            let used = self
+                .backend
+                .take_webauthn_challenge(None, &challenge)
+                .await?
+                && self
+                    .backend
+                    .update_webauthn_sign_count(&credential.id, sign_count)
+                    .await?;
+            Ok(Some(user).filter(|_| used))
+        }
+        .await;
+        match verified {
+            Ok(Some(user)) => {
+                let _ = self
+                    .backend
+                    .reset_failed_attempts(throttling::account_key(user.username()))
+                    .await;
+                Ok(AccessControl {
+                    state: Authenticated,
+                    backend: self.backend,
+                    user: Some(user),
+                })
+            }
+            _ => {
+                record_failed_attempts(&self.backend, &keys).await;
+                Err(Error::Authentication)
+            }
+        }
+    }
+
+    /// Authenticate a user by providing a remember-me token, returns the next token of the series.
+    ///
+    /// The presented token is replaced by the returned one with [`Backend::rotate_remember_token`], so every token can
+    /// only be used once. The previous token of the series is still accepted during the [`remember::GRACE_PERIOD`] after
+    /// its rotation, e.g. for parallel requests of the same browser, in which case no next token is returned. If the
+    /// selector is valid, but the validator is neither of them, a stolen token has been used before, so the whole
+    /// series is removed with [`Backend::remove_remember_token`] and both the attacker and the user have to login
+    /// again. This method may return [`Error::Authentication`] on error, otherwise it returns a AccessControl in the
+    /// state [`Authenticated`].
+    pub async fn authenticate_remember_token(
+        self,
+        token: &RememberToken,
+    ) -> Result<(AccessControl<Authenticated, B>, Option<RememberToken>), Error> {
+        let (user, series) = self
+            .backend
+            .get_remember_token(&token.selector)
+            .await
+            .ok_or(Error::Authentication)?;
+        let authenticated = |backend, user| AccessControl {
+            state: Authenticated,
+            backend,
+            user: Some(user),
+        };
+
+        if !token.verify(&series.validator_hash) {
+            if token.verify_previous(&series, SystemTime::now()) {
+                return Ok((authenticated(self.backend, user), None));
+            }
+            let _ = self.backend.remove_remember_token(&token.selector).await;
+            return Err(Error::Authentication);
+        }
+
+        let next = token.rotate();
+        let rotated = self
+            .backend
+            .rotate_remember_token(
+                &token.selector,
+                &series.validator_hash,
+                next.validator_hash(),
+                remember::LIFETIME,
+            )
+            .await;
+        match rotated {
+            Ok(true) => Ok((authenticated(self.backend, user), Some(next))),
+            // A parallel request rotated the series since it has been read, so the token is the previous one now
+            Ok(false) => {
+                let series = self.backend.get_remember_token(&token.selector).await;
+                match series {
+                    Some((user, series)) if token.verify_previous(&series, SystemTime::now()) => {
+                        Ok((authenticated(self.backend, user), None))
+                    }
+                    _ => Err(Error::Authentication),
+                }
+            }
+            Err(_) => Err(Error::Authentication),
+        }
+    }
+
     /// Register a new user account
     ///
     /// The actual registration with the backend should be constant time. Otherwise an attacker could try to register
     /// already existing usernames and see if the registration takes longer than if the username does not exist.
     /// Furthermore, no error is returned, if the user does already exists, only if the username or password does not
     /// match the policy.
+    ///
+    /// The username is checked by the provided [`UsernamePolicy`] and stored in its canonical form. The password is
+    /// checked by the provided [`PasswordPolicy`]. Every violation is part of the returned [`Error::UsernamePolicy`]
+    /// or [`Error::PasswordPolicy`] respectively.
     pub async fn register(
         self,
         username: impl AsRef<str>,
         password: impl AsRef<str>,
+        username_policy: &UsernamePolicy,
+        password_policy: &dyn PasswordPolicy,
     ) -> Result<(), Error> {
-        let username = username.as_ref().to_lowercase();
+        let username = username_policy
+            .check(username.as_ref())
+            .map_err(Error::UsernamePolicy)?;
 
-        if username.is_empty() || !username.chars().all(|c| char::is_ascii_alphanumeric(&c)) {
-            return Err(Error::UsernamePolicy);
+        let violations = password_policy.check(&username, password.as_ref());
+        if !violations.is_empty() {
+            return Err(Error::PasswordPolicy(violations));
         }
 
-        if password.as_ref().chars().count() < 12 || password.as_ref().chars().count() > 256 {
-            return Err(Error::PasswordPolicy);
-        }
-
-        let salt = SaltString::generate(rand::thread_rng());
-        let password_hash = get_argon2_ctx()
-            .hash_password_simple(password.as_ref().as_bytes(), salt.as_ref())
-            .unwrap()
-            .to_string();
+        let password_hash = hashing::hash_password(password.as_ref()).await;
 
         self.backend
             .register_user(username, password_hash)
@@ -244,25 +730,304 @@ where
     }
 }
 
+impl<B> AccessControl<TotpPending, B>
+where
+    B: Backend,
+{
+    /// Verifies the one-time password of the second factor.
+    ///
+    /// Codes of the current time step and [`totp::DRIFT`] steps around it are accepted. Every time step can only be
+    /// used once, which is recorded with [`Backend::use_totp_step`]. Returns [`Error::OneTimePassword`] if the code is
+    /// invalid or has already been used, otherwise an AccessControl in the state [`Authenticated`].
+    ///
+    /// Failed attempts are counted per account with [`throttling::second_factor_key`] and per client IP, once the
+    /// [`Throttling`] limits are exceeded, [`Error::Throttled`] is returned before the code is checked.
+    pub async fn verify_totp(
+        self,
+        code: impl AsRef<str>,
+        client_ip: Option<IpAddr>,
+        throttling: &Throttling,
+    ) -> Result<AccessControl<Authenticated, B>, Error> {
+        let user = self
+            .user
+            .as_ref()
+            .expect("user is always available in totp pending state");
+        let keys = throttling.keys(
+            Some(throttling::second_factor_key(user.username())),
+            client_ip,
+        );
+        check_throttling(&self.backend, &keys).await?;
+
+        let step = user
+            .totp_secret()
+            .and_then(Totp::from_base32)
+            .and_then(|totp| totp.verify(code.as_ref(), totp::current_step()));
+        let verified = match step {
+            Some(step) => matches!(self.backend.use_totp_step(user, step).await, Ok(true)),
+            None => false,
+        };
+        self.complete_second_factor(verified, &keys)
+            .await
+            .ok_or(Error::OneTimePassword)
+    }
+
+    /// Verifies a recovery code instead of the one-time password, e.g. if the user lost the authenticator app.
+    ///
+    /// The code is consumed with [`Backend::use_recovery_code`], so every code can only be used once. Returns
+    /// [`Error::RecoveryCode`] if the code is invalid or has already been used, otherwise an AccessControl in the state
+    /// [`Authenticated`].
+    ///
+    /// Failed attempts are throttled together with the one-time passwords, see [`AccessControl::verify_totp`].
+    pub async fn verify_recovery_code(
+        self,
+        code: impl AsRef<str>,
+        client_ip: Option<IpAddr>,
+        throttling: &Throttling,
+    ) -> Result<AccessControl<Authenticated, B>, Error> {
+        let user = self
+            .user
+            .as_ref()
+            .expect("user is always available in totp pending state");
+        let keys = throttling.keys(
+            Some(throttling::second_factor_key(user.username())),
+            client_ip,
+        );
+        check_throttling(&self.backend, &keys).await?;
+
+        let code_hashes = self
+            .backend
+            .get_recovery_codes(user)
+            .await
+            .map_err(|_| Error::RecoveryCode)?;
+        let verified = match recovery::find(code.as_ref(), &code_hashes).await {
+            Some(code_hash) => matches!(
+                self.backend.use_recovery_code(user, code_hash).await,
+                Ok(true)
+            ),
+            None => false,
+        };
+        self.complete_second_factor(verified, &keys)
+            .await
+            .ok_or(Error::RecoveryCode)
+    }
+
+    /// Proceeds to the [`Authenticated`] state if the second factor has been verified and forgets its failures,
+    /// otherwise the failure is counted for every key.
+    async fn complete_second_factor(
+        self,
+        verified: bool,
+        keys: &[(String, &ThrottlePolicy)],
+    ) -> Option<AccessControl<Authenticated, B>> {
+        if !verified {
+            record_failed_attempts(&self.backend, keys).await;
+            return None;
+        }
+
+        let user = self
+            .user
+            .expect("user is always available in totp pending state");
+        let _ = self
+            .backend
+            .reset_failed_attempts(throttling::second_factor_key(user.username()))
+            .await;
+        Some(AccessControl {
+            state: Authenticated,
+            backend: self.backend,
+            user: Some(user),
+        })
+    }
+
+    /// Returns the user whose password has been verified, e.g. to store a pending session.
+    ///
+    /// The user has not passed the second factor yet and must not be treated as logged in.
+    pub fn pending_user(&self) -> &B::User {
+        self.user
+            .as_ref()
+            .expect("user is always available in totp pending state")
+    }
+
+    /// Returns whether the user wants to stay logged in, as stored with the pending session, see
+    /// [`AccessControl::remember`].
+    pub fn remember_requested(&self) -> bool {
+        self.state.remember
+    }
+}
+
 impl<B> AccessControl<Authenticated, B>
 where
     B: Backend,
 {
-    /// Authorize a user by passing in a `&HashSet<String>` of capabilities and comparing it to the users capabilities.
+    /// Enables two-factor authentication for the user with a new secret, e.g. from [`Totp::generate`].
+    ///
+    /// The user has to confirm the enrollment with a code of the secret, which makes sure the authenticator app has
+    /// been set up correctly. Secrets shorter than a generated one are rejected, as the secret may have been chosen by
+    /// the client. If the user already enabled two-factor authentication, the secret is only replaced with the
+    /// `current_code` of the current secret, whose failures are throttled like [`AccessControl::verify_totp`]. Returns
+    /// [`Error::OneTimePassword`] if a code is invalid or the secret is too short.
+    pub async fn enable_totp(
+        &self,
+        totp: &Totp,
+        code: impl AsRef<str>,
+        current_code: Option<&str>,
+        client_ip: Option<IpAddr>,
+        throttling: &Throttling,
+    ) -> Result<(), Error> {
+        let user = self
+            .user
+            .as_ref()
+            .expect("user is always available in authenticated state");
+        if !totp.has_recommended_length() {
+            return Err(Error::OneTimePassword);
+        }
+
+        // A stolen session must not be enough to take over the second factor
+        if let Some(secret) = user.totp_secret() {
+            let keys = throttling.keys(
+                Some(throttling::second_factor_key(user.username())),
+                client_ip,
+            );
+            check_throttling(&self.backend, &keys).await?;
+
+            let step = Totp::from_base32(secret)
+                .zip(current_code)
+                .and_then(|(current, code)| current.verify(code, totp::current_step()));
+            let verified = match step {
+                Some(step) => matches!(self.backend.use_totp_step(user, step).await, Ok(true)),
+                None => false,
+            };
+            if !verified {
+                record_failed_attempts(&self.backend, &keys).await;
+                return Err(Error::OneTimePassword);
+            }
+        }
+
+        let step = totp
+            .verify(code.as_ref(), totp::current_step())
+            .ok_or(Error::OneTimePassword)?;
+
+        // The confirmation code must not be usable for a login
+        let secret = totp.to_base32();
+        let stored = async {
+            self.backend.store_totp_secret(user, Some(&secret)).await?;
+            self.backend.use_totp_step(user, step).await
+        };
+        stored.await.map(|_| ()).map_err(|_| Error::OneTimePassword)
+    }
+
+    /// Generates a new batch of [`recovery::COUNT`] recovery codes for the user, which replaces the previous batch.
+    ///
+    /// The codes are stored as Argon2 hashes with [`Backend::store_recovery_codes`], so the returned codes have to be
+    /// shown to the user now and can not be retrieved later. Returns [`Error::RecoveryCode`] if the codes could not be
+    /// stored.
+    pub async fn generate_recovery_codes(&self) -> Result<Vec<String>, Error> {
+        let user = self
+            .user
+            .as_ref()
+            .expect("user is always available in authenticated state");
+        let codes = recovery::generate();
+        let code_hashes = recovery::hash(&codes).await;
+
+        self.backend
+            .store_recovery_codes(user, &code_hashes)
+            .await
+            .map_err(|_| Error::RecoveryCode)?;
+        Ok(codes)
+    }
+
+    /// Issues a remember-me token of a new series for the user, which is stored with
+    /// [`Backend::store_remember_token`].
+    ///
+    /// The token restores the login with [`AccessControl::authenticate_remember_token`] until it has not been used for
+    /// [`remember::LIFETIME`]. Returns [`Error::Authentication`] if the series could not be stored.
+    pub async fn remember(&self) -> Result<RememberToken, Error> {
+        let user = self
+            .user
+            .as_ref()
+            .expect("user is always available in authenticated state");
+        let token = RememberToken::generate();
+
+        self.backend
+            .store_remember_token(
+                user,
+                &token.selector,
+                token.validator_hash(),
+                remember::LIFETIME,
+            )
+            .await
+            .map_err(|_| Error::Authentication)?;
+        Ok(token)
+    }
+
+    /// Starts the registration of a passkey by issuing a challenge for the user, which is stored with
+    /// [`Backend::store_webauthn_challenge`].
+    ///
+    /// Returns the options of `navigator.credentials.create` as JSON, the response is verified by
+    /// [`AccessControl::register_webauthn`].
+    pub async fn begin_webauthn_registration(
+        &self,
+        relying_party: &RelyingParty,
+    ) -> Result<String, Error> {
+        let user = self
+            .user
+            .as_ref()
+            .expect("user is always available in authenticated state");
+        let challenge = webauthn::generate_challenge();
+
+        self.backend
+            .store_webauthn_challenge(Some(user), &challenge)
+            .await
+            .map_err(|_| Error::WebAuthn(WebAuthnError::Backend))?;
+        Ok(relying_party.creation_options(&challenge, user.username()))
+    }
+
+    /// Registers a passkey for the user by providing the response of `navigator.credentials.create`.
+    ///
+    /// The challenge must have been issued to the same user by [`AccessControl::begin_webauthn_registration`]. Returns
+    /// [`Error::WebAuthn`] with the reason if the registration failed.
+    pub async fn register_webauthn(
+        &self,
+        relying_party: &RelyingParty,
+        response: &RegistrationResponse,
+    ) -> Result<(), Error> {
+        let user = self
+            .user
+            .as_ref()
+            .expect("user is always available in authenticated state");
+        let (challenge, credential) = relying_party
+            .verify_registration(response)
+            .map_err(Error::WebAuthn)?;
+
+        match self
+            .backend
+            .take_webauthn_challenge(Some(user), &challenge)
+            .await
+        {
+            Ok(true) => {}
+            Ok(false) => return Err(Error::WebAuthn(WebAuthnError::Challenge)),
+            Err(_) => return Err(Error::WebAuthn(WebAuthnError::Backend)),
+        }
+        self.backend
+            .store_webauthn_credential(user, &credential)
+            .await
+            .map_err(|_| Error::WebAuthn(WebAuthnError::Backend))
+    }
+
+    /// Authorize a user by passing in a [`Requirement`] and evaluating it against the users capabilities.
     ///
-    /// If the users capabilities are a superset of the required_capabilities, the method returns a [`AccessControl`] in the [`Authorized`] state.
+    /// If the users capabilities satisfy the requirement, the method returns a [`AccessControl`] in the [`Authorized`] state.
     /// Otherwise it will return an error of the type [`Error::Authorization`].
+    ///
+    /// A `&HashSet<String>` can be converted into a [`Requirement`] that needs all of its capabilities.
     pub fn authorize(
         self,
-        required_capabilities: &HashSet<String>,
+        requirement: &Requirement,
     ) -> Result<AccessControl<Authorized, B>, Error> {
-        if !self
-            .user
-            .as_ref()
-            .expect("user is always available in authenticated state")
-            .capabilities()
-            .is_superset(required_capabilities)
-        {
+        if !requirement.is_satisfied_by(
+            self.user
+                .as_ref()
+                .expect("user is always available in authenticated state")
+                .capabilities(),
+        ) {
             return Err(Error::Authorization);
         }
 
@@ -272,6 +1037,34 @@ where
             user: self.user,
         })
     }
+
+    /// Authorize a user to execute an action on a single resource, e.g. to `edit` the `document` with the id `42`.
+    ///
+    /// The user must satisfy the requirement like in [`AccessControl::authorize`] and additionally have a grant for
+    /// the resource, which is looked up with [`Backend::has_grant`].
+    /// If the grant does not exist or the lookup fails, an error of the type [`Error::Authorization`] is returned.
+    pub async fn authorize_resource(
+        self,
+        requirement: &Requirement,
+        action: impl AsRef<str>,
+        resource_type: impl AsRef<str>,
+        resource_id: impl AsRef<str>,
+    ) -> Result<AccessControl<Authorized, B>, Error> {
+        let authorized = self.authorize(requirement)?;
+        let user = authorized
+            .user
+            .as_ref()
+            .expect("user is always available in authorized state");
+
+        match authorized
+            .backend
+            .has_grant(user, action, resource_type, resource_id)
+            .await
+        {
+            Ok(true) => Ok(authorized),
+            _ => Err(Error::Authorization),
+        }
+    }
 }
 
 impl<B> AccessControl<Authorized, B>
@@ -287,6 +1080,47 @@ where
     }
 }
 
+/// Returns [`Error::Throttled`] if one of the keys exceeded the limits of its [`ThrottlePolicy`].
+///
+/// The attempt is rejected with [`Error::Authentication`] if the failed attempts can not be read, otherwise an
+/// unavailable backend would lift the limits.
+async fn check_throttling<B: Backend>(
+    backend: &B,
+    keys: &[(String, &ThrottlePolicy)],
+) -> Result<(), Error> {
+    let now = SystemTime::now();
+    for (key, policy) in keys {
+        let attempts = backend
+            .get_failed_attempts(key)
+            .await
+            .map_err(|_| Error::Authentication)?;
+        if let Some(retry_after) = attempts.and_then(|attempts| policy.retry_after(&attempts, now))
+        {
+            return Err(Error::Throttled(retry_after));
+        }
+    }
+    Ok(())
+}
+
+/// Counts a failed attempt for every key.
+async fn record_failed_attempts<B: Backend>(backend: &B, keys: &[(String, &ThrottlePolicy)]) {
+    // A failure to record the attempt must not reveal anything, the attempt fails either way
+    for (key, policy) in keys {
+        let _ = backend.record_failed_attempt(key, policy.reset_after).await;
+    }
+}
+
+/// The result of [`AccessControl::authenticate_creds`], which depends on whether the user has a second factor.
+pub enum Authentication<B>
+where
+    B: Backend,
+{
+    /// The password was sufficient to authenticate the user
+    Complete(AccessControl<Authenticated, B>),
+    /// The user has to pass [`AccessControl::verify_totp`] to be authenticated
+    TotpRequired(AccessControl<TotpPending, B>),
+}
+
 /// The [AccessControlState] trait is
 /// [sealed](https://rust-lang.github.io/api-guidelines/future-proofing.html#sealed-traits-protect-against-downstream-implementations-c-sealed)
 /// and cannot be implemented. It is part of the compile time safety check implemented using the [typestate
@@ -295,6 +1129,13 @@ pub trait AccessControlState: private::Sealed {}
 /// The initial state of the [`AccessControl`] struct when initializing it with [`AccessControl::new`].
 /// For details see: [`AccessControl`]
 pub struct Start;
+/// The state of [`AccessControl`] after the password of a user with two-factor authentication has been verified, but
+/// the one-time password has not.
+/// For details see: [`AccessControl`]
+pub struct TotpPending {
+    /// The user wants to stay logged in after the second factor
+    remember: bool,
+}
 /// The [`AccessControl`] struct after a user has been successfully authenticated by reading them from the database with [`AccessControl::authenticate_session`].
 /// For details see: [`AccessControl`]
 pub struct Authenticated;
@@ -303,6 +1144,7 @@ pub struct Authenticated;
 pub struct Authorized;
 
 impl AccessControlState for Start {}
+impl AccessControlState for TotpPending {}
 impl AccessControlState for Authenticated {}
 impl AccessControlState for Authorized {}
 
@@ -312,6 +1154,234 @@ mod private {
     pub trait Sealed {}
 
     impl Sealed for super::Start {}
+    impl Sealed for super::TotpPending {}
     impl Sealed for super::Authenticated {}
     impl Sealed for super::Authorized {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_executor::block_on;
+    use memory::MemoryBackend;
+    use password_policy::default_policy;
+
+    /// A password that matches the default policy.
+    const PASSWORD: &str = "correct-horse-battery-staple-42";
+
+    fn register(backend: &MemoryBackend, username: &str) {
+        block_on(AccessControl::new(backend.clone()).register(
+            username,
+            PASSWORD,
+            &UsernamePolicy::default(),
+            &default_policy(),
+        ))
+        .unwrap();
+    }
+
+    fn login(
+        backend: &MemoryBackend,
+        password: &str,
+        throttling: &Throttling,
+    ) -> Result<Authentication<MemoryBackend>, Error> {
+        block_on(AccessControl::new(backend.clone()).authenticate_creds(
+            "jean-luc",
+            password,
+            Some("192.0.2.1".parse().unwrap()),
+            throttling,
+        ))
+    }
+
+    /// The default throttling with a delay that outlasts the hashing of the password in the tests.
+    fn throttling() -> Throttling {
+        Throttling {
+            account: ThrottlePolicy {
+                base_delay: Duration::from_secs(60),
+                ..ThrottlePolicy::account()
+            },
+            ..Throttling::default()
+        }
+    }
+
+    /// Registers the user and stores the session `session` for it.
+    fn logged_in(backend: &MemoryBackend) {
+        register(backend, "jean-luc");
+        let user = block_on(backend.get_user("jean-luc")).unwrap();
+        block_on(backend.store_session(
+            &user,
+            "session",
+            &SessionLifetime::default(),
+            &SessionMetadata::default(),
+        ))
+        .unwrap();
+    }
+
+    /// Authenticates the session stored by [`logged_in`].
+    fn authenticated(backend: &MemoryBackend) -> AccessControl<Authenticated, MemoryBackend> {
+        block_on(AccessControl::new(backend.clone()).authenticate_session("session")).unwrap()
+    }
+
+    fn pending(
+        backend: &MemoryBackend,
+        throttling: &Throttling,
+    ) -> AccessControl<TotpPending, MemoryBackend> {
+        match login(backend, PASSWORD, throttling) {
+            Ok(Authentication::TotpRequired(pending)) => pending,
+            _ => panic!("the one-time password must be required"),
+        }
+    }
+
+    #[test]
+    /// Makes sure the correct password is rejected once the failed attempts of the account exceeded the limits.
+    fn throttled_login() {
+        let backend = MemoryBackend::new();
+        let throttling = throttling();
+        register(&backend, "jean-luc");
+
+        assert!(matches!(
+            login(&backend, PASSWORD, &throttling),
+            Ok(Authentication::Complete(_))
+        ));
+        for _ in 0..throttling.account.free_attempts {
+            assert!(matches!(
+                login(&backend, "wrong password", &throttling),
+                Err(Error::Authentication)
+            ));
+        }
+        assert!(matches!(
+            login(&backend, PASSWORD, &throttling),
+            Err(Error::Throttled(_))
+        ));
+    }
+
+    #[test]
+    /// Makes sure the one-time passwords are throttled, even though the login with the password resets the failures
+    /// of the account.
+    fn throttled_second_factor() {
+        let backend = MemoryBackend::new();
+        let throttling = throttling();
+        register(&backend, "jean-luc");
+        let totp = Totp::generate();
+        let user = block_on(backend.get_user("jean-luc")).unwrap();
+        block_on(backend.store_totp_secret(&user, Some(&totp.to_base32()))).unwrap();
+
+        for _ in 0..throttling.account.free_attempts {
+            let code = totp.code(totp::current_step() + 10);
+            assert!(matches!(
+                block_on(pending(&backend, &throttling).verify_totp(code, None, &throttling)),
+                Err(Error::OneTimePassword)
+            ));
+        }
+        for code in ["00000000", &totp.code(totp::current_step())] {
+            assert!(matches!(
+                block_on(pending(&backend, &throttling).verify_recovery_code(
+                    code,
+                    None,
+                    &throttling
+                )),
+                Err(Error::Throttled(_))
+            ));
+            assert!(matches!(
+                block_on(pending(&backend, &throttling).verify_totp(code, None, &throttling)),
+                Err(Error::Throttled(_))
+            ));
+        }
+    }
+
+    #[test]
+    /// Makes sure a pending session is removed after the maximum number of attempts, even if the next code is correct.
+    fn second_factor_attempts() {
+        let backend = MemoryBackend::new();
+        // The throttling of the account must not interfere
+        let throttling = Throttling {
+            account: ThrottlePolicy {
+                free_attempts: 100,
+                lockout_threshold: 100,
+                ..ThrottlePolicy::account()
+            },
+            ..Throttling::default()
+        };
+        register(&backend, "jean-luc");
+        let totp = Totp::generate();
+        let user = block_on(backend.get_user("jean-luc")).unwrap();
+        block_on(backend.store_totp_secret(&user, Some(&totp.to_base32()))).unwrap();
+        block_on(backend.store_pending_session(&user, "pending", false)).unwrap();
+        let verify = |code: &str| {
+            let pending = block_on(AccessControl::new(backend.clone()).resume_totp("pending"))?;
+            block_on(pending.verify_totp(code, None, &throttling))
+        };
+
+        for _ in 0..totp::MAX_ATTEMPTS {
+            assert!(matches!(
+                verify(&totp.code(totp::current_step() + 10)),
+                Err(Error::OneTimePassword)
+            ));
+        }
+        assert!(matches!(
+            verify(&totp.code(totp::current_step())),
+            Err(Error::Authentication)
+        ));
+        assert!(block_on(backend.get_user_from_pending_session("pending")).is_none());
+    }
+
+    #[test]
+    /// Makes sure only secrets of the full length are enabled and a secret is only replaced with a code of it.
+    fn replace_totp_secret() {
+        let backend = MemoryBackend::new();
+        let throttling = throttling();
+        logged_in(&backend);
+        let enable = |totp: &Totp, current_code: Option<&str>| {
+            let code = totp.code(totp::current_step());
+            block_on(authenticated(&backend).enable_totp(
+                totp,
+                code,
+                current_code,
+                None,
+                &throttling,
+            ))
+        };
+
+        let short = Totp::from_base32("JBSWY3DPEHPK3PXP").unwrap();
+        assert!(matches!(enable(&short, None), Err(Error::OneTimePassword)));
+        let first = Totp::generate();
+        assert!(enable(&first, None).is_ok());
+
+        let second = Totp::generate();
+        assert!(matches!(enable(&second, None), Err(Error::OneTimePassword)));
+        let wrong = second.code(totp::current_step() + 1);
+        assert!(matches!(
+            enable(&second, Some(&wrong)),
+            Err(Error::OneTimePassword)
+        ));
+        // The code of the current time step has been used to confirm the first secret
+        let current_code = first.code(totp::current_step() + 1);
+        assert!(enable(&second, Some(&current_code)).is_ok());
+        let user = block_on(backend.get_user("jean-luc")).unwrap();
+        assert_eq!(user.totp_secret, Some(second.to_base32()));
+    }
+
+    #[test]
+    /// Makes sure the previous token of a series is only accepted during the grace period after its rotation and an
+    /// older token revokes the whole series.
+    fn remember_token_theft() {
+        let backend = MemoryBackend::new();
+        logged_in(&backend);
+        let exchange = |token: &RememberToken| {
+            block_on(AccessControl::new(backend.clone()).authenticate_remember_token(token))
+                .map(|(_, next)| next)
+        };
+
+        let first = block_on(authenticated(&backend).remember()).unwrap();
+        let second = exchange(&first)
+            .unwrap()
+            .expect("the series must be rotated");
+        // A parallel request with the previous token is accepted without rotating the series again
+        assert!(matches!(exchange(&first), Ok(None)));
+
+        let third = exchange(&second)
+            .unwrap()
+            .expect("the series must be rotated");
+        assert!(matches!(exchange(&first), Err(Error::Authentication)));
+        assert!(matches!(exchange(&third), Err(Error::Authentication)));
+    }
+}
@@ -0,0 +1,599 @@
+//! Contains the [MemoryBackend], a [Backend] that keeps every user and session in memory.
+//!
+//! It implements every operation like the PostgreSQL backend of this project, including the atomic checks, so it can
+//! be used to test routes and middleware without a database. Everything is lost on a restart.
+
+use crate::remember::RememberSeries;
+use crate::session::{hash_session_id, SessionInfo, SessionLifetime, SessionMetadata};
+use crate::throttling::FailedAttempts;
+use crate::webauthn::{self, Credential};
+use crate::{Backend, FutureOption, FutureResult, User};
+use std::collections::{HashMap, HashSet};
+use std::future::ready;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+/// How long a pending session of the second factor is valid.
+const PENDING_SESSION_LIFETIME: Duration = Duration::from_secs(5 * 60);
+
+/// A user of the [MemoryBackend].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryUser {
+    pub username: String,
+    pub password_hash: String,
+    pub capabilities: HashSet<String>,
+    pub totp_secret: Option<String>,
+}
+
+impl User for MemoryUser {
+    fn username(&self) -> &str {
+        &self.username
+    }
+
+    fn password_hash(&self) -> &str {
+        &self.password_hash
+    }
+
+    fn capabilities(&self) -> &HashSet<String> {
+        &self.capabilities
+    }
+
+    fn totp_secret(&self) -> Option<&str> {
+        self.totp_secret.as_deref()
+    }
+}
+
+/// A session, which is stored by the digest of its id.
+#[derive(Debug, Clone)]
+struct Session {
+    username: String,
+    expiration: SystemTime,
+    absolute_expiration: SystemTime,
+    pending: bool,
+    /// The attempts of the second factor of a pending session
+    attempts: u32,
+    /// The user of a pending session wants to stay logged in
+    remember: bool,
+    created: SystemTime,
+    last_seen: SystemTime,
+    metadata: SessionMetadata,
+}
+
+/// A remember-me series, which is stored by its selector.
+#[derive(Debug, Clone)]
+struct StoredSeries {
+    username: String,
+    series: RememberSeries,
+    expiration: SystemTime,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    users: HashMap<String, MemoryUser>,
+    sessions: HashMap<String, Session>,
+    grants: HashSet<(String, String, String, String)>,
+    totp_steps: HashMap<String, u64>,
+    /// The failed attempts of a key and the end of their reset period
+    failed_attempts: HashMap<String, (FailedAttempts, SystemTime)>,
+    recovery_codes: HashMap<String, Vec<String>>,
+    /// The user of a challenge, `None` for the challenges of a login, and its expiration
+    webauthn_challenges: HashMap<String, (Option<String>, SystemTime)>,
+    webauthn_credentials: HashMap<Vec<u8>, (String, Credential)>,
+    remember_tokens: HashMap<String, StoredSeries>,
+}
+
+impl State {
+    /// Returns the user of a session that has not expired.
+    fn session_user(&self, session_id: &str, pending: bool) -> Option<MemoryUser> {
+        self.sessions
+            .get(&hash_session_id(session_id))
+            .filter(|session| session.pending == pending && session.expiration > SystemTime::now())
+            .and_then(|session| self.users.get(&session.username))
+            .cloned()
+    }
+}
+
+/// A [Backend] that keeps its data in memory, e.g. for tests.
+///
+/// Clones share their data. Users are registered with [`crate::AccessControl::register`] like with any other backend,
+/// their capabilities are granted with [`MemoryBackend::add_capability`].
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBackend {
+    state: Arc<Mutex<State>>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Grants a capability to a registered user.
+    pub fn add_capability(&self, username: &str, capability: impl Into<String>) {
+        if let Some(user) = self.state().users.get_mut(username) {
+            user.capabilities.insert(capability.into());
+        }
+    }
+
+    /// Grants the action on a single resource to a user, see [`Backend::has_grant`].
+    pub fn add_grant(&self, username: &str, action: &str, resource_type: &str, resource_id: &str) {
+        self.state().grants.insert((
+            username.to_string(),
+            action.to_string(),
+            resource_type.to_string(),
+            resource_id.to_string(),
+        ));
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        // A poisoned lock only means that another thread panicked while holding it
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Backend for MemoryBackend {
+    type User = MemoryUser;
+
+    fn get_user(&self, username: impl AsRef<str>) -> FutureOption<MemoryUser> {
+        let user = self.state().users.get(username.as_ref()).cloned();
+        Box::pin(ready(user))
+    }
+
+    fn get_user_from_session(&self, session_id: impl AsRef<str>) -> FutureOption<MemoryUser> {
+        let user = self.state().session_user(session_id.as_ref(), false);
+        Box::pin(ready(user))
+    }
+
+    fn register_user(
+        &self,
+        username: impl AsRef<str>,
+        password_hash: impl AsRef<str>,
+    ) -> FutureResult<()> {
+        let username = username.as_ref().to_string();
+        let user = MemoryUser {
+            username: username.clone(),
+            password_hash: password_hash.as_ref().to_string(),
+            capabilities: HashSet::new(),
+            totp_secret: None,
+        };
+        // Like the unique constraint of a database, an existing user is not replaced
+        self.state().users.entry(username).or_insert(user);
+        Box::pin(ready(Ok(())))
+    }
+
+    fn update_password_hash(
+        &self,
+        user: &MemoryUser,
+        password_hash: impl AsRef<str>,
+    ) -> FutureResult<()> {
+        if let Some(user) = self.state().users.get_mut(&user.username) {
+            user.password_hash = password_hash.as_ref().to_string();
+        }
+        Box::pin(ready(Ok(())))
+    }
+
+    fn store_session(
+        &self,
+        user: &MemoryUser,
+        session_id: impl AsRef<str>,
+        lifetime: &SessionLifetime,
+        metadata: &SessionMetadata,
+    ) -> FutureResult<()> {
+        let now = SystemTime::now();
+        let session = Session {
+            username: user.username.clone(),
+            expiration: now + lifetime.idle_timeout,
+            absolute_expiration: now + lifetime.absolute_timeout,
+            pending: false,
+            attempts: 0,
+            remember: false,
+            created: now,
+            last_seen: now,
+            metadata: metadata.clone(),
+        };
+        self.state()
+            .sessions
+            .insert(hash_session_id(session_id.as_ref()), session);
+        Box::pin(ready(Ok(())))
+    }
+
+    fn touch_session(
+        &self,
+        session_id: impl AsRef<str>,
+        idle_timeout: Duration,
+    ) -> FutureOption<SystemTime> {
+        let now = SystemTime::now();
+        let expiration = self
+            .state()
+            .sessions
+            .get_mut(&hash_session_id(session_id.as_ref()))
+            .filter(|session| !session.pending && session.expiration > now)
+            .map(|session| {
+                session.expiration = (now + idle_timeout).min(session.absolute_expiration);
+                session.last_seen = now;
+                session.expiration
+            });
+        Box::pin(ready(expiration))
+    }
+
+    fn store_pending_session(
+        &self,
+        user: &MemoryUser,
+        session_id: impl AsRef<str>,
+        remember: bool,
+    ) -> FutureResult<()> {
+        let now = SystemTime::now();
+        let session = Session {
+            username: user.username.clone(),
+            expiration: now + PENDING_SESSION_LIFETIME,
+            absolute_expiration: now + PENDING_SESSION_LIFETIME,
+            pending: true,
+            attempts: 0,
+            remember,
+            created: now,
+            last_seen: now,
+            metadata: SessionMetadata::default(),
+        };
+        self.state()
+            .sessions
+            .insert(hash_session_id(session_id.as_ref()), session);
+        Box::pin(ready(Ok(())))
+    }
+
+    fn get_user_from_pending_session(
+        &self,
+        session_id: impl AsRef<str>,
+    ) -> FutureOption<(MemoryUser, bool)> {
+        let state = self.state();
+        let user = state
+            .session_user(session_id.as_ref(), true)
+            .zip(state.sessions.get(&hash_session_id(session_id.as_ref())))
+            .map(|(user, session)| (user, session.remember));
+        Box::pin(ready(user))
+    }
+
+    fn record_pending_attempt(&self, session_id: impl AsRef<str>) -> FutureOption<u32> {
+        let now = SystemTime::now();
+        let attempts = self
+            .state()
+            .sessions
+            .get_mut(&hash_session_id(session_id.as_ref()))
+            .filter(|session| session.pending && session.expiration > now)
+            .map(|session| {
+                session.attempts += 1;
+                session.attempts
+            });
+        Box::pin(ready(attempts))
+    }
+
+    fn remove_session(&self, session_id: impl AsRef<str>) -> FutureResult<()> {
+        self.state()
+            .sessions
+            .remove(&hash_session_id(session_id.as_ref()));
+        Box::pin(ready(Ok(())))
+    }
+
+    fn get_sessions(
+        &self,
+        user: &MemoryUser,
+        current_session_id: impl AsRef<str>,
+    ) -> FutureResult<Vec<SessionInfo>> {
+        let now = SystemTime::now();
+        let current = hash_session_id(current_session_id.as_ref());
+        let mut sessions: Vec<_> = self
+            .state()
+            .sessions
+            .iter()
+            .filter(|(_, session)| {
+                session.username == user.username && !session.pending && session.expiration > now
+            })
+            .map(|(id, session)| SessionInfo {
+                id: id.clone(),
+                created: session.created,
+                last_seen: session.last_seen,
+                ip_address: session.metadata.ip_address,
+                user_agent: session.metadata.user_agent.clone(),
+                current: *id == current,
+            })
+            .collect();
+        sessions.sort_by_key(|session| std::cmp::Reverse(session.last_seen));
+        Box::pin(ready(Ok(sessions)))
+    }
+
+    fn revoke_session(&self, user: &MemoryUser, id: impl AsRef<str>) -> FutureResult<bool> {
+        let mut state = self.state();
+        let owned = state
+            .sessions
+            .get(id.as_ref())
+            .is_some_and(|session| session.username == user.username);
+        if owned {
+            let session = state.sessions.remove(id.as_ref());
+            if let Some(selector) = session.and_then(|session| session.metadata.remember_selector) {
+                state.remember_tokens.remove(&selector);
+            }
+        }
+        Box::pin(ready(Ok(owned)))
+    }
+
+    fn revoke_other_sessions(
+        &self,
+        user: &MemoryUser,
+        current_session_id: impl AsRef<str>,
+        remember_selector: Option<&str>,
+    ) -> FutureResult<()> {
+        let current = hash_session_id(current_session_id.as_ref());
+        let mut state = self.state();
+        state
+            .sessions
+            .retain(|id, session| session.username != user.username || *id == current);
+        state.remember_tokens.retain(|selector, series| {
+            series.username != user.username || Some(selector.as_str()) == remember_selector
+        });
+        Box::pin(ready(Ok(())))
+    }
+
+    fn has_grant(
+        &self,
+        user: &MemoryUser,
+        action: impl AsRef<str>,
+        resource_type: impl AsRef<str>,
+        resource_id: impl AsRef<str>,
+    ) -> FutureResult<bool> {
+        let grant = (
+            user.username.clone(),
+            action.as_ref().to_string(),
+            resource_type.as_ref().to_string(),
+            resource_id.as_ref().to_string(),
+        );
+        let granted = self.state().grants.contains(&grant);
+        Box::pin(ready(Ok(granted)))
+    }
+
+    fn store_totp_secret(&self, user: &MemoryUser, secret: Option<&str>) -> FutureResult<()> {
+        let mut state = self.state();
+        if let Some(user) = state.users.get_mut(&user.username) {
+            user.totp_secret = secret.map(str::to_string);
+        }
+        state.totp_steps.remove(&user.username);
+        Box::pin(ready(Ok(())))
+    }
+
+    fn use_totp_step(&self, user: &MemoryUser, step: u64) -> FutureResult<bool> {
+        let mut state = self.state();
+        let unused = match state.totp_steps.get(&user.username) {
+            Some(&last_step) => step > last_step,
+            None => true,
+        };
+        if unused {
+            state.totp_steps.insert(user.username.clone(), step);
+        }
+        Box::pin(ready(Ok(unused)))
+    }
+
+    fn get_failed_attempts(&self, key: impl AsRef<str>) -> FutureResult<Option<FailedAttempts>> {
+        let attempts = self
+            .state()
+            .failed_attempts
+            .get(key.as_ref())
+            .map(|(attempts, _)| *attempts);
+        Box::pin(ready(Ok(attempts)))
+    }
+
+    fn record_failed_attempt(
+        &self,
+        key: impl AsRef<str>,
+        reset_after: Duration,
+    ) -> FutureResult<()> {
+        let now = SystemTime::now();
+        let mut state = self.state();
+        state
+            .failed_attempts
+            .retain(|_, (_, expiration)| *expiration > now);
+        let (attempts, expiration) = state
+            .failed_attempts
+            .entry(key.as_ref().to_string())
+            .or_insert((
+                FailedAttempts {
+                    count: 0,
+                    last_failure: now,
+                },
+                now,
+            ));
+        attempts.count += 1;
+        attempts.last_failure = now;
+        *expiration = now + reset_after;
+        Box::pin(ready(Ok(())))
+    }
+
+    fn reset_failed_attempts(&self, key: impl AsRef<str>) -> FutureResult<()> {
+        self.state().failed_attempts.remove(key.as_ref());
+        Box::pin(ready(Ok(())))
+    }
+
+    fn store_recovery_codes(&self, user: &MemoryUser, code_hashes: &[String]) -> FutureResult<()> {
+        self.state()
+            .recovery_codes
+            .insert(user.username.clone(), code_hashes.to_vec());
+        Box::pin(ready(Ok(())))
+    }
+
+    fn get_recovery_codes(&self, user: &MemoryUser) -> FutureResult<Vec<String>> {
+        let code_hashes = self
+            .state()
+            .recovery_codes
+            .get(&user.username)
+            .cloned()
+            .unwrap_or_default();
+        Box::pin(ready(Ok(code_hashes)))
+    }
+
+    fn use_recovery_code(
+        &self,
+        user: &MemoryUser,
+        code_hash: impl AsRef<str>,
+    ) -> FutureResult<bool> {
+        let used = self
+            .state()
+            .recovery_codes
+            .get_mut(&user.username)
+            .and_then(|code_hashes| {
+                let index = code_hashes
+                    .iter()
+                    .position(|hash| hash == code_hash.as_ref())?;
+                Some(code_hashes.remove(index))
+            })
+            .is_some();
+        Box::pin(ready(Ok(used)))
+    }
+
+    fn store_webauthn_challenge(
+        &self,
+        user: Option<&MemoryUser>,
+        challenge: impl AsRef<str>,
+    ) -> FutureResult<()> {
+        let now = SystemTime::now();
+        let expiration = now + Duration::from_millis(webauthn::TIMEOUT);
+        let mut state = self.state();
+        state
+            .webauthn_challenges
+            .retain(|_, (_, expiration)| *expiration > now);
+        state.webauthn_challenges.insert(
+            challenge.as_ref().to_string(),
+            (user.map(|user| user.username.clone()), expiration),
+        );
+        Box::pin(ready(Ok(())))
+    }
+
+    fn take_webauthn_challenge(
+        &self,
+        user: Option<&MemoryUser>,
+        challenge: impl AsRef<str>,
+    ) -> FutureResult<bool> {
+        let mut state = self.state();
+        let username = user.map(|user| user.username.clone());
+        let valid = state
+            .webauthn_challenges
+            .get(challenge.as_ref())
+            .is_some_and(|(owner, expiration)| {
+                *owner == username && *expiration > SystemTime::now()
+            });
+        if valid {
+            state.webauthn_challenges.remove(challenge.as_ref());
+        }
+        Box::pin(ready(Ok(valid)))
+    }
+
+    fn store_webauthn_credential(
+        &self,
+        user: &MemoryUser,
+        credential: &Credential,
+    ) -> FutureResult<()> {
+        self.state().webauthn_credentials.insert(
+            credential.id.clone(),
+            (user.username.clone(), credential.clone()),
+        );
+        Box::pin(ready(Ok(())))
+    }
+
+    fn get_webauthn_credential(
+        &self,
+        credential_id: &[u8],
+    ) -> FutureOption<(MemoryUser, Credential)> {
+        let state = self.state();
+        let credential =
+            state
+                .webauthn_credentials
+                .get(credential_id)
+                .and_then(|(username, credential)| {
+                    Some((state.users.get(username)?.clone(), credential.clone()))
+                });
+        Box::pin(ready(credential))
+    }
+
+    fn update_webauthn_sign_count(
+        &self,
+        credential_id: &[u8],
+        sign_count: u32,
+    ) -> FutureResult<bool> {
+        let updated = self
+            .state()
+            .webauthn_credentials
+            .get_mut(credential_id)
+            .filter(|(_, credential)| credential.sign_count < sign_count || sign_count == 0)
+            .map(|(_, credential)| credential.sign_count = sign_count)
+            .is_some();
+        Box::pin(ready(Ok(updated)))
+    }
+
+    fn store_remember_token(
+        &self,
+        user: &MemoryUser,
+        selector: impl AsRef<str>,
+        validator_hash: impl AsRef<str>,
+        lifetime: Duration,
+    ) -> FutureResult<()> {
+        let series = StoredSeries {
+            username: user.username.clone(),
+            series: RememberSeries {
+                validator_hash: validator_hash.as_ref().to_string(),
+                previous_validator_hash: None,
+                rotated: None,
+            },
+            expiration: SystemTime::now() + lifetime,
+        };
+        self.state()
+            .remember_tokens
+            .insert(selector.as_ref().to_string(), series);
+        Box::pin(ready(Ok(())))
+    }
+
+    fn get_remember_token(
+        &self,
+        selector: impl AsRef<str>,
+    ) -> FutureOption<(MemoryUser, RememberSeries)> {
+        let state = self.state();
+        let series = state
+            .remember_tokens
+            .get(selector.as_ref())
+            .filter(|stored| stored.expiration > SystemTime::now())
+            .and_then(|stored| {
+                Some((
+                    state.users.get(&stored.username)?.clone(),
+                    stored.series.clone(),
+                ))
+            });
+        Box::pin(ready(series))
+    }
+
+    fn rotate_remember_token(
+        &self,
+        selector: impl AsRef<str>,
+        old_hash: impl AsRef<str>,
+        new_hash: impl AsRef<str>,
+        lifetime: Duration,
+    ) -> FutureResult<bool> {
+        let now = SystemTime::now();
+        let rotated = self
+            .state()
+            .remember_tokens
+            .get_mut(selector.as_ref())
+            .filter(|stored| {
+                stored.series.validator_hash == old_hash.as_ref() && stored.expiration > now
+            })
+            .map(|stored| {
+                let previous = std::mem::replace(
+                    &mut stored.series.validator_hash,
+                    new_hash.as_ref().to_string(),
+                );
+                stored.series.previous_validator_hash = Some(previous);
+                stored.series.rotated = Some(now);
+                stored.expiration = now + lifetime;
+            })
+            .is_some();
+        Box::pin(ready(Ok(rotated)))
+    }
+
+    fn remove_remember_token(&self, selector: impl AsRef<str>) -> FutureResult<()> {
+        self.state().remember_tokens.remove(selector.as_ref());
+        Box::pin(ready(Ok(())))
+    }
+}
@@ -0,0 +1,439 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, BufRead};
+
+/// The minimum length of a password as per
+/// [OWASP](https://cheatsheetseries.owasp.org/cheatsheets/Authentication_Cheat_Sheet.html#implement-proper-password-strength-controls)
+pub const MIN_PASSWORD_LENGTH: usize = 12;
+
+/// The maximum length of a password to limit the work of the hashing function.
+pub const MAX_PASSWORD_LENGTH: usize = 256;
+
+/// A small list of the most common passwords, which is used by [`Denylist::common`].
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456",
+    "123456789",
+    "12345678",
+    "1234567890",
+    "123456789012",
+    "1234567890123",
+    "password",
+    "password1",
+    "password12",
+    "password123",
+    "password1234",
+    "passwordpassword",
+    "qwerty",
+    "qwertyuiop",
+    "qwertyuiopasdfghjkl",
+    "qwertzuiop",
+    "1q2w3e4r5t6y",
+    "1qaz2wsx3edc",
+    "zaq12wsxcde3",
+    "asdfghjkl",
+    "asdfghjklzxcvbnm",
+    "abcdefghijkl",
+    "abcdefghijklmnop",
+    "abc123abc123",
+    "111111111111",
+    "000000000000",
+    "iloveyou",
+    "iloveyouiloveyou",
+    "letmein",
+    "letmeinletmein",
+    "welcome",
+    "welcome123456",
+    "admin",
+    "administrator",
+    "changeme",
+    "changemeplease",
+    "trustno1",
+    "monkey",
+    "dragon",
+    "football",
+    "baseball",
+    "sunshine",
+    "princess",
+    "superman",
+    "starwars",
+    "whatever",
+    "passw0rd",
+    "p@ssw0rd",
+    "p@ssword",
+    "correcthorsebatterystaple",
+];
+
+/// A reason why a password does not match a [`PasswordPolicy`].
+///
+/// The [`fmt::Display`] implementation returns a message that can be shown to the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Violation {
+    /// The password has less than `min` characters
+    TooShort { min: usize },
+    /// The password has more than `max` characters
+    TooLong { max: usize },
+    /// The password contains characters of less than `min` character classes
+    TooFewCharacterClasses { min: usize },
+    /// The estimated entropy of the password is lower than `min_bits`
+    TooPredictable { min_bits: u32 },
+    /// The password contains the username or vice versa
+    SimilarToUsername,
+    /// The password is part of a list of commonly used passwords
+    Common,
+    /// The password is part of a corpus of breached passwords
+    Breached,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::TooShort { min } => {
+                write!(f, "The password must be at least {} characters long", min)
+            }
+            Violation::TooLong { max } => {
+                write!(f, "The password must be at most {} characters long", max)
+            }
+            Violation::TooFewCharacterClasses { min } => write!(
+                f,
+                "The password must contain at least {} of: lowercase letters, uppercase letters, digits and symbols",
+                min
+            ),
+            Violation::TooPredictable { .. } => write!(f, "The password is too easy to guess"),
+            Violation::SimilarToUsername => {
+                write!(f, "The password must not be similar to the username")
+            }
+            Violation::Common => write!(f, "The password is too common"),
+            Violation::Breached => {
+                write!(f, "The password has appeared in a data breach")
+            }
+        }
+    }
+}
+
+/// The PasswordPolicy trait defines a rule that a password must match when registering a user.
+///
+/// Policies can be combined with [`Rules`], the default policy is returned by [`default_policy`].
+pub trait PasswordPolicy: Send + Sync {
+    /// Checks the password of the user and returns every violation, an empty vector if the password matches.
+    fn check(&self, username: &str, password: &str) -> Vec<Violation>;
+}
+
+/// Returns the policy that only allows passwords with [`MIN_PASSWORD_LENGTH`] to [`MAX_PASSWORD_LENGTH`] characters.
+pub fn default_policy() -> Rules {
+    Rules::new().with(Length::default())
+}
+
+/// A combination of policies that returns the violations of every policy.
+///
+/// # Example usage
+/// ```
+/// # use access_control::password_policy::{Denylist, Length, PasswordPolicy, Rules, UsernameSimilarity};
+/// let policy = Rules::new()
+///     .with(Length::default())
+///     .with(UsernameSimilarity)
+///     .with(Denylist::common());
+///
+/// assert!(policy.check("alice", "correct horse battery staple").is_empty());
+/// assert_eq!(policy.check("alice", "alice").len(), 2);
+/// ```
+#[derive(Default)]
+pub struct Rules {
+    rules: Vec<Box<dyn PasswordPolicy>>,
+}
+
+impl Rules {
+    /// Creates an empty combination, which accepts every password.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a policy to the combination.
+    pub fn with(mut self, rule: impl PasswordPolicy + 'static) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+}
+
+impl PasswordPolicy for Rules {
+    fn check(&self, username: &str, password: &str) -> Vec<Violation> {
+        self.rules
+            .iter()
+            .flat_map(|rule| rule.check(username, password))
+            .collect()
+    }
+}
+
+/// Limits the number of characters of a password.
+///
+/// The default allows [`MIN_PASSWORD_LENGTH`] to [`MAX_PASSWORD_LENGTH`] characters.
+#[derive(Debug, Clone, Copy)]
+pub struct Length {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl Default for Length {
+    fn default() -> Self {
+        Length {
+            min: MIN_PASSWORD_LENGTH,
+            max: MAX_PASSWORD_LENGTH,
+        }
+    }
+}
+
+impl PasswordPolicy for Length {
+    fn check(&self, _username: &str, password: &str) -> Vec<Violation> {
+        let length = password.chars().count();
+
+        if length < self.min {
+            vec![Violation::TooShort { min: self.min }]
+        } else if length > self.max {
+            vec![Violation::TooLong { max: self.max }]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// The classes a character of a password can belong to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum CharacterClass {
+    Lowercase,
+    Uppercase,
+    Digit,
+    Symbol,
+}
+
+impl CharacterClass {
+    fn of(c: char) -> Self {
+        if c.is_lowercase() {
+            CharacterClass::Lowercase
+        } else if c.is_uppercase() {
+            CharacterClass::Uppercase
+        } else if c.is_numeric() {
+            CharacterClass::Digit
+        } else {
+            CharacterClass::Symbol
+        }
+    }
+
+    /// The approximate number of characters in the class, used to estimate the entropy.
+    fn size(self) -> f64 {
+        match self {
+            CharacterClass::Lowercase | CharacterClass::Uppercase => 26.0,
+            CharacterClass::Digit => 10.0,
+            CharacterClass::Symbol => 33.0,
+        }
+    }
+}
+
+/// Requires a password to contain characters of at least `min` of the classes lowercase letters, uppercase
+/// letters, digits and symbols.
+#[derive(Debug, Clone, Copy)]
+pub struct CharacterClasses {
+    pub min: usize,
+}
+
+impl PasswordPolicy for CharacterClasses {
+    fn check(&self, _username: &str, password: &str) -> Vec<Violation> {
+        let classes: HashSet<_> = password.chars().map(CharacterClass::of).collect();
+
+        if classes.len() < self.min {
+            vec![Violation::TooFewCharacterClasses { min: self.min }]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// Requires a minimum estimated entropy in bits.
+///
+/// The estimate is inspired by [zxcvbn](https://github.com/dropbox/zxcvbn), but much simpler: every character adds
+/// the entropy of the character classes used in the password, unless it repeats the previous character or continues a
+/// sequence like `abc` or `321`, which only adds a single bit.
+#[derive(Debug, Clone, Copy)]
+pub struct Entropy {
+    pub min_bits: u32,
+}
+
+impl Entropy {
+    /// Estimates the entropy of the password in bits.
+    pub fn estimate(password: &str) -> f64 {
+        let classes: HashSet<_> = password.chars().map(CharacterClass::of).collect();
+        let bits_per_char = classes.iter().map(|c| c.size()).sum::<f64>().log2();
+
+        password
+            .chars()
+            .zip(std::iter::once(None).chain(password.chars().map(Some)))
+            .map(
+                |(current, previous)| match previous.map(|p| current as i64 - p as i64) {
+                    Some(-1..=1) => 1.0,
+                    _ => bits_per_char,
+                },
+            )
+            .sum()
+    }
+}
+
+impl PasswordPolicy for Entropy {
+    fn check(&self, _username: &str, password: &str) -> Vec<Violation> {
+        if Entropy::estimate(password) < self.min_bits as f64 {
+            vec![Violation::TooPredictable {
+                min_bits: self.min_bits,
+            }]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// Rejects passwords that contain the username or are contained in the username, ignoring the case.
+#[derive(Debug, Clone, Copy)]
+pub struct UsernameSimilarity;
+
+impl PasswordPolicy for UsernameSimilarity {
+    fn check(&self, username: &str, password: &str) -> Vec<Violation> {
+        let username = username.to_lowercase();
+        let password = password.to_lowercase();
+        let reversed: String = password.chars().rev().collect();
+
+        if !username.is_empty()
+            && (password.contains(&username)
+                || reversed.contains(&username)
+                || username.contains(&password))
+        {
+            vec![Violation::SimilarToUsername]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// Rejects passwords that are part of a list, ignoring the case.
+#[derive(Debug, Clone, Default)]
+pub struct Denylist {
+    passwords: HashSet<String>,
+}
+
+impl Denylist {
+    /// Creates a denylist from the provided passwords.
+    pub fn new<I, S>(passwords: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Denylist {
+            passwords: passwords
+                .into_iter()
+                .map(|p| p.as_ref().to_lowercase())
+                .collect(),
+        }
+    }
+
+    /// Creates a denylist with a small bundled list of the most common passwords.
+    pub fn common() -> Self {
+        Self::new(COMMON_PASSWORDS)
+    }
+
+    /// Reads a denylist with one password per line, e.g. from a file of a password list like
+    /// [SecLists](https://github.com/danielmiessler/SecLists/tree/master/Passwords/Common-Credentials).
+    pub fn from_reader(reader: impl BufRead) -> io::Result<Self> {
+        let passwords = reader.lines().collect::<io::Result<Vec<_>>>()?;
+        Ok(Self::new(passwords.iter().map(|p| p.trim())))
+    }
+}
+
+impl PasswordPolicy for Denylist {
+    fn check(&self, _username: &str, password: &str) -> Vec<Violation> {
+        if self.passwords.contains(&password.to_lowercase()) {
+            vec![Violation::Common]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Makes sure passwords are between 12 and 256 characters long, counted in characters instead of bytes.
+    fn length() {
+        let policy = default_policy();
+
+        assert_eq!(
+            policy.check("alice", "12345678901"),
+            vec![Violation::TooShort { min: 12 }]
+        );
+        assert!(policy.check("alice", "123456789012").is_empty());
+        assert!(policy.check("alice", &"a".repeat(256)).is_empty());
+        assert_eq!(
+            policy.check("alice", &"a".repeat(257)),
+            vec![Violation::TooLong { max: 256 }]
+        );
+        // Characters are counted, not bytes
+        assert!(policy.check("alice", &"ä".repeat(12)).is_empty());
+    }
+
+    #[test]
+    /// Makes sure a password has to mix the minimum number of character classes.
+    fn character_classes() {
+        let policy = CharacterClasses { min: 3 };
+
+        assert!(!policy.check("alice", "onlylowercase").is_empty());
+        assert!(policy.check("alice", "Lower and 1").is_empty());
+    }
+
+    #[test]
+    /// Makes sure repeated characters, sequences and digits only do not reach the minimum entropy.
+    fn entropy() {
+        let policy = Entropy { min_bits: 50 };
+
+        assert!(!policy.check("alice", "aaaaaaaaaaaaaaaa").is_empty());
+        assert!(!policy.check("alice", "abcdefghijklmnop").is_empty());
+        assert!(!policy.check("alice", "12345678901234567890").is_empty());
+        assert!(policy.check("alice", "Tr0ub4dor&3 horse").is_empty());
+    }
+
+    #[test]
+    /// Makes sure passwords that contain the username or its reverse are rejected, ignoring the case.
+    fn username_similarity() {
+        assert!(!UsernameSimilarity.check("alice", "Alice1234567").is_empty());
+        assert!(!UsernameSimilarity.check("alice", "ecila").is_empty());
+        assert!(UsernameSimilarity
+            .check("alice", "correct horse")
+            .is_empty());
+    }
+
+    #[test]
+    /// Makes sure listed passwords are rejected regardless of their case, including the bundled common passwords.
+    fn denylist() {
+        let policy = Denylist::from_reader("first password\nsecond password\n".as_bytes()).unwrap();
+
+        assert_eq!(
+            policy.check("alice", "Second Password"),
+            vec![Violation::Common]
+        );
+        assert!(policy.check("alice", "third password").is_empty());
+        assert!(!Denylist::common().check("alice", "password1234").is_empty());
+    }
+
+    #[test]
+    /// Makes sure the violations of every rule are returned.
+    fn rules() {
+        let policy = Rules::new()
+            .with(Length::default())
+            .with(UsernameSimilarity)
+            .with(Denylist::common());
+
+        assert_eq!(
+            policy.check("admin", "admin"),
+            vec![
+                Violation::TooShort { min: 12 },
+                Violation::SimilarToUsername,
+                Violation::Common
+            ]
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/// Errors that occur when building [`Peppers`].
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum PepperError {
+    /// The key id is empty or contains other characters than ASCII letters and digits
+    #[error("Invalid pepper key id")]
+    InvalidId,
+    /// The secret is empty or not valid base64
+    #[error("Invalid pepper secret")]
+    InvalidSecret,
+    /// The key id of the current pepper is not part of the peppers
+    #[error("Unknown pepper key id")]
+    UnknownId,
+}
+
+/// A collection of secrets that are passed to Argon2 as secret value, commonly known as pepper.
+///
+/// Unlike the salt, the pepper is not stored in the database, so that a database dump alone is not enough to crack
+/// the password hashes offline. Every pepper is identified by a key id, which is stored as `keyid` parameter in the
+/// PHC string of a hash. This way multiple peppers can coexist while rotating them:
+///
+/// 1. Add a new pepper and make it the current one, new hashes are created with it
+/// 2. Existing hashes are still verified with the pepper of their key id and rehashed with the current pepper on the
+///    next successful login (see [`crate::hashing::needs_rehash`])
+/// 3. Remove the old pepper once no hash references it anymore
+///
+/// Hashes without a key id are verified without a pepper.
+#[derive(Clone, Default)]
+pub struct Peppers {
+    current: Option<String>,
+    secrets: HashMap<String, Vec<u8>>,
+}
+
+impl Peppers {
+    /// Creates an empty collection, new hashes are created without a pepper.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses peppers from a comma separated list of `id:base64-secret` pairs, e.g. `2021:c2VjcmV0,2022:cGVwcGVy`.
+    ///
+    /// If `current` is provided, the pepper with this key id is used for new hashes.
+    pub fn parse(peppers: &str, current: Option<&str>) -> Result<Self, PepperError> {
+        let mut parsed = peppers
+            .split(',')
+            .map(str::trim)
+            .filter(|pepper| !pepper.is_empty())
+            .try_fold(Self::new(), |parsed, pepper| {
+                let mut parts = pepper.splitn(2, ':');
+                let id = parts.next().unwrap_or_default();
+                let secret = parts.next().ok_or(PepperError::InvalidSecret)?;
+                let secret = base64::decode(secret).map_err(|_| PepperError::InvalidSecret)?;
+                parsed.add(id, secret)
+            })?;
+
+        if let Some(current) = current {
+            parsed = parsed.with_current(current)?;
+        }
+
+        Ok(parsed)
+    }
+
+    /// Adds a pepper with the key id, which may only consist of ASCII letters and digits.
+    pub fn add(mut self, id: impl Into<String>, secret: Vec<u8>) -> Result<Self, PepperError> {
+        let id = id.into();
+
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(PepperError::InvalidId);
+        }
+        if secret.is_empty() {
+            return Err(PepperError::InvalidSecret);
+        }
+
+        self.secrets.insert(id, secret);
+        Ok(self)
+    }
+
+    /// Uses the pepper with the key id for new hashes.
+    pub fn with_current(mut self, id: impl Into<String>) -> Result<Self, PepperError> {
+        let id = id.into();
+
+        if !self.secrets.contains_key(&id) {
+            return Err(PepperError::UnknownId);
+        }
+
+        self.current = Some(id);
+        Ok(self)
+    }
+
+    /// Returns the key id and secret of the pepper that is used for new hashes.
+    pub(crate) fn current(&self) -> Option<(&str, &[u8])> {
+        self.current
+            .as_deref()
+            .and_then(|id| self.get(id).map(|secret| (id, secret)))
+    }
+
+    /// Returns the secret of the pepper with the key id.
+    pub(crate) fn get(&self, id: &str) -> Option<&[u8]> {
+        self.secrets.get(id).map(Vec::as_slice)
+    }
+}
+
+impl fmt::Debug for Peppers {
+    /// Only prints the key ids, the secrets must never end up in a log.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Peppers")
+            .field("current", &self.current)
+            .field("ids", &self.secrets.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let peppers = Peppers::parse("2021:c2VjcmV0, 2022:cGVwcGVy", Some("2022")).unwrap();
+
+        assert_eq!(peppers.get("2021"), Some(&b"secret"[..]));
+        assert_eq!(peppers.current(), Some(("2022", &b"pepper"[..])));
+        assert!(Peppers::parse("", None).unwrap().current().is_none());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!(
+            Peppers::parse("20-21:c2VjcmV0", None).unwrap_err(),
+            PepperError::InvalidId
+        );
+        assert_eq!(
+            Peppers::parse("2021", None).unwrap_err(),
+            PepperError::InvalidSecret
+        );
+        assert_eq!(
+            Peppers::parse("2021:c2VjcmV0", Some("2022")).unwrap_err(),
+            PepperError::UnknownId
+        );
+    }
+
+    #[test]
+    /// Makes sure the secrets are not part of the debug output.
+    fn debug_hides_secrets() {
+        let peppers = Peppers::parse("2021:c2VjcmV0", Some("2021")).unwrap();
+
+        assert!(!format!("{:?}", peppers).contains("secret"));
+    }
+}
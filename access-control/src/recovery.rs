@@ -0,0 +1,148 @@
+use crate::hashing;
+use rand::Rng;
+use std::collections::HashSet;
+
+/// The number of recovery codes in a batch.
+pub const COUNT: usize = 10;
+
+/// The number of characters of the selector, which identifies a code of a batch and is not secret.
+const SELECTOR_LENGTH: usize = 4;
+
+/// The number of secret characters of a recovery code, which results in about 49 bits of entropy.
+const SECRET_LENGTH: usize = 10;
+
+/// The number of characters of a normalized recovery code.
+const LENGTH: usize = SELECTOR_LENGTH + SECRET_LENGTH;
+
+/// The characters of recovery codes, which leaves out characters that are easily confused like `0` and `o`.
+const ALPHABET: &[u8] = b"23456789abcdefghjkmnpqrstuvwxyz";
+
+/// Generates a batch of [`COUNT`] random recovery codes with a CSPRNG, e.g. `a3fw-k7m2x-9qhtr`.
+///
+/// The first part of a code is its selector, which is unique in the batch, so that a code is checked against a single
+/// hash. The codes are shown to the user once and must only be stored as hashes, see [`hash`].
+pub fn generate() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    let mut random = |length: usize| -> String {
+        (0..length)
+            .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char)
+            .collect()
+    };
+
+    let mut selectors = HashSet::with_capacity(COUNT);
+    while selectors.len() < COUNT {
+        selectors.insert(random(SELECTOR_LENGTH));
+    }
+    selectors
+        .into_iter()
+        .map(|selector| {
+            let secret = random(SECRET_LENGTH);
+            format!(
+                "{}-{}-{}",
+                selector,
+                &secret[..SECRET_LENGTH / 2],
+                &secret[SECRET_LENGTH / 2..]
+            )
+        })
+        .collect()
+}
+
+/// Removes whitespace and dashes and lowercases the code, so that users may enter it in any format.
+pub fn normalize(code: &str) -> String {
+    code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect::<String>()
+        .to_lowercase()
+}
+
+/// Hashes the normalized codes with Argon2 like passwords, see [`crate::hashing`].
+///
+/// Every hash is prefixed with the selector of its code, e.g. `a3fw:$argon2id$...`.
+pub(crate) async fn hash(codes: &[String]) -> Vec<String> {
+    let mut hashes = Vec::with_capacity(codes.len());
+    for code in codes {
+        let code = normalize(code);
+        let hash = hashing::hash_password(&code).await;
+        hashes.push(format!("{}:{}", &code[..SELECTOR_LENGTH], hash));
+    }
+    hashes
+}
+
+/// Returns the hash that matches the code, which has to be consumed to use the code.
+///
+/// Only the hash with the selector of the code is verified, so every guess costs a single Argon2 verification. If no
+/// hash has the selector, a fake hash is verified, so that the response time does not reveal the selectors.
+pub(crate) async fn find<'a>(code: &str, hashes: &'a [String]) -> Option<&'a String> {
+    let code = normalize(code);
+    // The length and alphabet are public, so invalid codes do not need to be hashed
+    if code.len() != LENGTH || !code.bytes().all(|c| ALPHABET.contains(&c)) {
+        return None;
+    }
+
+    let selector = &code[..SELECTOR_LENGTH];
+    let found = hashes.iter().find_map(|hash| {
+        let (hash_selector, password_hash) = hash.split_once(':')?;
+        Some((hash, password_hash)).filter(|_| hash_selector == selector)
+    });
+    let password_hash = found.map(|(_, password_hash)| password_hash.to_string());
+
+    if hashing::verify_password(&code, password_hash).await {
+        found.map(|(hash, _)| hash)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_executor::block_on;
+
+    #[test]
+    fn generated_codes() {
+        let codes = generate();
+
+        assert_eq!(codes.len(), COUNT);
+        let selectors: HashSet<_> = codes.iter().map(|code| &code[..SELECTOR_LENGTH]).collect();
+        assert_eq!(selectors.len(), COUNT);
+        for code in &codes {
+            assert_eq!(code.len(), LENGTH + 2);
+            assert_eq!(code.as_bytes()[SELECTOR_LENGTH], b'-');
+            assert_eq!(
+                code.as_bytes()[SELECTOR_LENGTH + 1 + SECRET_LENGTH / 2],
+                b'-'
+            );
+            assert_eq!(normalize(code).len(), LENGTH);
+        }
+    }
+
+    #[test]
+    fn normalization() {
+        assert_eq!(normalize(" A3FW-K7M2X-9qhtr\n"), "a3fwk7m2x9qhtr");
+        assert_eq!(normalize("a3fw k7m2x 9qhtr"), "a3fwk7m2x9qhtr");
+    }
+
+    #[test]
+    /// Makes sure a code only matches its own hash, independent of its format.
+    fn hash_and_find() {
+        let codes = generate()[..2].to_vec();
+        let hashes = block_on(hash(&codes));
+
+        assert!(hashes[0].starts_with(&format!("{}:$argon2id$", &codes[0][..SELECTOR_LENGTH])));
+        assert_eq!(block_on(find(&codes[0], &hashes)), Some(&hashes[0]));
+        assert_eq!(
+            block_on(find(&codes[1].to_uppercase().replace('-', " "), &hashes)),
+            Some(&hashes[1])
+        );
+        // The selector of one code with the secret of another does not match
+        let mixed = format!(
+            "{}{}",
+            &codes[0][..SELECTOR_LENGTH],
+            &codes[1][SELECTOR_LENGTH..]
+        );
+        assert_eq!(block_on(find(&mixed, &hashes)), None);
+        assert_eq!(block_on(find("2222-22222-22222", &hashes)), None);
+        assert_eq!(block_on(find("not a code", &hashes)), None);
+        assert_eq!(block_on(find("", &[])), None);
+    }
+}
@@ -0,0 +1,156 @@
+use rand::RngCore;
+use ring::constant_time::verify_slices_are_equal;
+use ring::digest::{digest, SHA256};
+use std::time::{Duration, SystemTime};
+
+/// The time after which a remember-me series expires if it is not used, every use renews it.
+pub const LIFETIME: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// The time after a rotation in which the previous validator is still accepted.
+///
+/// A browser that restores several tabs at once sends the same token with parallel requests, only the first of them
+/// rotates the series. The others must neither revoke the series nor replace the cookie of the first one.
+pub const GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// The length of the selector and the validator in bytes.
+const LENGTH: usize = 32;
+
+/// A remember-me token, which is split into a public selector and a secret validator.
+///
+/// The selector identifies the series of a login and stays the same, the validator is replaced on every use. The
+/// [`crate::Backend`] only stores the hash of the validator, so a leaked database does not contain usable tokens.
+/// If a validator of the series is presented that is not the current one, the token has been stolen and used by
+/// either the attacker or the user, so the whole series is revoked. Only the previous validator is accepted during the
+/// [`GRACE_PERIOD`] after its rotation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RememberToken {
+    pub selector: String,
+    validator: String,
+}
+
+impl RememberToken {
+    /// Generates a token of a new series with a CSPRNG.
+    pub fn generate() -> Self {
+        RememberToken {
+            selector: random(),
+            validator: random(),
+        }
+    }
+
+    /// Parses the value of the cookie, returns `None` if it is malformed.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (selector, validator) = value.split_once('.')?;
+        let valid = |part: &str| {
+            base64::decode_config(part, base64::URL_SAFE_NO_PAD).is_ok_and(|b| b.len() == LENGTH)
+        };
+        if !valid(selector) || !valid(validator) {
+            return None;
+        }
+
+        Some(RememberToken {
+            selector: selector.to_string(),
+            validator: validator.to_string(),
+        })
+    }
+
+    /// Returns the value of the cookie, which the client has to keep secret.
+    pub fn value(&self) -> String {
+        format!("{}.{}", self.selector, self.validator)
+    }
+
+    /// Returns the SHA-256 hash of the validator, which is stored by the backend.
+    ///
+    /// The validator is random with 256 bits, so a fast hash is sufficient, unlike for passwords.
+    pub fn validator_hash(&self) -> String {
+        base64::encode(digest(&SHA256, self.validator.as_bytes()))
+    }
+
+    /// Returns the next token of the series with a new validator.
+    pub(crate) fn rotate(&self) -> Self {
+        RememberToken {
+            selector: self.selector.clone(),
+            validator: random(),
+        }
+    }
+
+    /// Returns whether the validator matches the stored hash, compared in constant time.
+    pub(crate) fn verify(&self, validator_hash: &str) -> bool {
+        verify_slices_are_equal(self.validator_hash().as_bytes(), validator_hash.as_bytes()).is_ok()
+    }
+
+    /// Returns whether the validator is the previous one of the series, which has been replaced less than the
+    /// [`GRACE_PERIOD`] before `now`.
+    pub(crate) fn verify_previous(&self, series: &RememberSeries, now: SystemTime) -> bool {
+        match (&series.previous_validator_hash, series.rotated) {
+            (Some(previous), Some(rotated)) => {
+                // A rotation in the future, e.g. due to clock skew, is treated as a rotation right now
+                now.duration_since(rotated).unwrap_or_default() < GRACE_PERIOD
+                    && self.verify(previous)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A remember-me series as it is stored by the [`crate::Backend`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RememberSeries {
+    /// The hash of the current validator
+    pub validator_hash: String,
+    /// The hash of the validator that has been replaced by the last rotation
+    pub previous_validator_hash: Option<String>,
+    /// The time of the last rotation
+    pub rotated: Option<SystemTime>,
+}
+
+/// Returns [`LENGTH`] random bytes with base64url encoding.
+fn random() -> String {
+    let mut bytes = [0u8; LENGTH];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Makes sure a token survives the cookie and a rotation only replaces the validator.
+    fn parse_and_rotate() {
+        let token = RememberToken::generate();
+        assert_eq!(RememberToken::parse(&token.value()), Some(token.clone()));
+
+        let rotated = token.rotate();
+        assert_eq!(rotated.selector, token.selector);
+        assert!(rotated.verify(&rotated.validator_hash()));
+        assert!(!rotated.verify(&token.validator_hash()));
+
+        assert_eq!(RememberToken::parse("selector.validator"), None);
+        assert_eq!(RememberToken::parse(&token.selector), None);
+        assert_eq!(RememberToken::parse(""), None);
+    }
+
+    #[test]
+    /// Makes sure the previous validator is only accepted shortly after the rotation.
+    fn grace_period() {
+        let token = RememberToken::generate();
+        let rotated = token.rotate();
+        let now = SystemTime::now();
+        let series = |seconds_ago| RememberSeries {
+            validator_hash: rotated.validator_hash(),
+            previous_validator_hash: Some(token.validator_hash()),
+            rotated: Some(now - Duration::from_secs(seconds_ago)),
+        };
+
+        assert!(token.verify_previous(&series(0), now));
+        assert!(token.verify_previous(&series(9), now));
+        assert!(!token.verify_previous(&series(10), now));
+        assert!(!rotated.verify_previous(&series(0), now));
+        let never_rotated = RememberSeries {
+            validator_hash: token.validator_hash(),
+            previous_validator_hash: None,
+            rotated: None,
+        };
+        assert!(!token.verify_previous(&never_rotated, now));
+    }
+}
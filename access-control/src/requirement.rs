@@ -0,0 +1,470 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+/// The error returned when parsing a [`Requirement`] fails.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+#[error("Invalid requirement at position {position}: {reason}")]
+pub struct ParseError {
+    /// The byte offset in the parsed string
+    pub position: usize,
+    pub reason: &'static str,
+}
+
+/// The separator of the segments of a namespaced capability, e.g. `billing:invoices:read`.
+pub const NAMESPACE_SEPARATOR: char = ':';
+
+/// The segment of a granted capability that matches every segment.
+pub const WILDCARD: &str = "*";
+
+/// Returns `true` if the granted capability satisfies the required capability.
+///
+/// Capabilities are split into segments at [`NAMESPACE_SEPARATOR`] and compared segment by segment:
+///
+/// 1. An equal capability always satisfies, so capabilities without a namespace like `AdminRead` keep working
+/// 2. A [`WILDCARD`] as last segment of the granted capability matches one or more remaining segments, so
+///    `billing:*` satisfies `billing:invoices` and `billing:invoices:read`, but not `billing` itself, and `*`
+///    satisfies every capability
+/// 3. A [`WILDCARD`] in any other position matches exactly one segment, so `documents:*:read` satisfies
+///    `documents:42:read`, but not `documents:42:write`
+/// 4. Every other segment must be equal, the comparison is case-sensitive
+///
+/// Wildcards are only expanded in granted capabilities, a `*` in a required capability is compared literally.
+///
+/// ```
+/// # use access_control::requirement::grants;
+/// assert!(grants("billing:*", "billing:invoices:read"));
+/// assert!(grants("*", "AdminRead"));
+/// assert!(!grants("billing:*", "billing"));
+/// assert!(!grants("billing:invoices:read", "billing:*"));
+/// ```
+pub fn grants(granted: &str, required: &str) -> bool {
+    if granted == required {
+        return true;
+    }
+
+    let mut granted = granted.split(NAMESPACE_SEPARATOR).peekable();
+    let mut required = required.split(NAMESPACE_SEPARATOR);
+
+    while let Some(segment) = granted.next() {
+        let is_last = granted.peek().is_none();
+        match required.next() {
+            Some(_) if segment == WILDCARD && is_last => return true,
+            Some(_) if segment == WILDCARD => continue,
+            Some(required) if segment == required => continue,
+            _ => return false,
+        }
+    }
+
+    // Every segment of the granted capability matched, the required capability must not have any more segments
+    required.next().is_none()
+}
+
+/// A boolean expression of capabilities that a user must satisfy to be authorized.
+///
+/// Requirements are either built with the constructor functions or parsed from a string, e.g. in a config file.
+/// The syntax supports `AND`, `OR`, `NOT` (or `&`, `|`, `!`) and parentheses, `NOT` binds strongest and `OR` weakest:
+///
+/// ```
+/// # use access_control::requirement::Requirement;
+/// # use std::collections::HashSet;
+/// let requirement: Requirement = "AdminRead OR (UserRead AND Auditor)".parse().unwrap();
+/// let capabilities: HashSet<String> = vec!["UserRead".to_string(), "Auditor".to_string()]
+///     .into_iter()
+///     .collect();
+///
+/// assert!(requirement.is_satisfied_by(&capabilities));
+/// assert!(!"NOT Auditor".parse::<Requirement>().unwrap().is_satisfied_by(&capabilities));
+/// ```
+///
+/// A `HashSet<String>` converts into a requirement that needs all of its capabilities.
+///
+/// Capabilities are matched with [`grants`], so a user with a wildcard capability like `billing:*` satisfies
+/// `billing:invoices:read`. Note that this applies to negations as well: a user with the capability `*` never
+/// satisfies `NOT Suspended`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Requirement {
+    /// The user must have the capability
+    Capability(String),
+    /// Every requirement must be satisfied, an empty list is always satisfied
+    All(Vec<Requirement>),
+    /// At least one requirement must be satisfied, an empty list is never satisfied
+    Any(Vec<Requirement>),
+    /// The requirement must not be satisfied
+    Not(Box<Requirement>),
+}
+
+impl Requirement {
+    /// Returns a requirement that every user satisfies.
+    pub fn none() -> Self {
+        Requirement::All(Vec::new())
+    }
+
+    /// Returns a requirement for a single capability.
+    pub fn capability(capability: impl Into<String>) -> Self {
+        Requirement::Capability(capability.into())
+    }
+
+    /// Returns a requirement that is satisfied if every requirement is satisfied.
+    pub fn all(requirements: impl IntoIterator<Item = Requirement>) -> Self {
+        Requirement::All(requirements.into_iter().collect())
+    }
+
+    /// Returns a requirement that is satisfied if at least one requirement is satisfied.
+    pub fn any(requirements: impl IntoIterator<Item = Requirement>) -> Self {
+        Requirement::Any(requirements.into_iter().collect())
+    }
+
+    /// Returns a requirement that is satisfied if the requirement is not satisfied.
+    #[allow(clippy::should_implement_trait)]
+    pub fn not(requirement: Requirement) -> Self {
+        Requirement::Not(Box::new(requirement))
+    }
+
+    /// Evaluates the requirement against the capabilities of a user, see [`grants`] for the matching rules.
+    pub fn is_satisfied_by(&self, capabilities: &HashSet<String>) -> bool {
+        match self {
+            Requirement::Capability(capability) => {
+                capabilities.contains(capability)
+                    || capabilities
+                        .iter()
+                        .any(|granted| grants(granted, capability))
+            }
+            Requirement::All(requirements) => {
+                requirements.iter().all(|r| r.is_satisfied_by(capabilities))
+            }
+            Requirement::Any(requirements) => {
+                requirements.iter().any(|r| r.is_satisfied_by(capabilities))
+            }
+            Requirement::Not(requirement) => !requirement.is_satisfied_by(capabilities),
+        }
+    }
+}
+
+impl Default for Requirement {
+    fn default() -> Self {
+        Requirement::none()
+    }
+}
+
+impl From<HashSet<String>> for Requirement {
+    fn from(capabilities: HashSet<String>) -> Self {
+        Requirement::all(capabilities.into_iter().map(Requirement::Capability))
+    }
+}
+
+impl From<&HashSet<String>> for Requirement {
+    fn from(capabilities: &HashSet<String>) -> Self {
+        Requirement::from(capabilities.clone())
+    }
+}
+
+impl fmt::Display for Requirement {
+    /// Writes the requirement in the syntax that is accepted by [`Requirement::from_str`].
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |f: &mut fmt::Formatter<'_>, requirements: &[Requirement], operator| {
+            for (i, requirement) in requirements.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " {} ", operator)?;
+                }
+                match requirement {
+                    Requirement::All(r) | Requirement::Any(r) if r.len() != 1 => {
+                        write!(f, "({})", requirement)?
+                    }
+                    _ => write!(f, "{}", requirement)?,
+                }
+            }
+            Ok(())
+        };
+
+        match self {
+            Requirement::Capability(capability) => write!(f, "{}", capability),
+            Requirement::All(requirements) => join(f, requirements, "AND"),
+            Requirement::Any(requirements) => join(f, requirements, "OR"),
+            Requirement::Not(requirement) => match requirement.as_ref() {
+                Requirement::Capability(_) | Requirement::Not(_) => {
+                    write!(f, "NOT {}", requirement)
+                }
+                _ => write!(f, "NOT ({})", requirement),
+            },
+        }
+    }
+}
+
+impl FromStr for Requirement {
+    type Err = ParseError;
+
+    /// Parses a requirement, an empty string is parsed as [`Requirement::none`].
+    ///
+    /// Capabilities may consist of every character except whitespace, parentheses, `&`, `|` and `!`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s),
+            position: 0,
+            end: s.len(),
+        };
+
+        if parser.tokens.is_empty() {
+            return Ok(Requirement::none());
+        }
+
+        let requirement = parser.or()?;
+        match parser.tokens.get(parser.position) {
+            Some((position, _)) => Err(ParseError {
+                position: *position,
+                reason: "unexpected token",
+            }),
+            None => Ok(requirement),
+        }
+    }
+}
+
+/// The tokens of the requirement syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+    Capability(String),
+}
+
+/// Splits the string into tokens and their byte offsets.
+fn tokenize(s: &str) -> Vec<(usize, Token)> {
+    let is_delimiter = |c: char| c.is_whitespace() || "()&|!".contains(c);
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+
+    while let Some((position, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '&' => Token::And,
+            '|' => Token::Or,
+            '!' => Token::Not,
+            c => {
+                let mut word = c.to_string();
+                while let Some(&(_, c)) = chars.peek() {
+                    if is_delimiter(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                match word.as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => Token::Capability(word),
+                }
+            }
+        };
+        tokens.push((position, token));
+    }
+
+    tokens
+}
+
+/// A recursive descent parser for the grammar:
+///
+/// ```text
+/// or      = and ("OR" and)*
+/// and     = not ("AND" not)*
+/// not     = "NOT" not | primary
+/// primary = "(" or ")" | capability
+/// ```
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+    /// The length of the parsed string, used as position of errors at the end
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(_, token)| token)
+    }
+
+    fn next(&mut self) -> Result<(usize, Token), ParseError> {
+        let token = self.tokens.get(self.position).cloned().ok_or(ParseError {
+            position: self.end,
+            reason: "unexpected end",
+        })?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn or(&mut self) -> Result<Requirement, ParseError> {
+        let mut requirements = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            requirements.push(self.and()?);
+        }
+
+        Ok(match requirements.len() {
+            1 => requirements.remove(0),
+            _ => Requirement::Any(requirements),
+        })
+    }
+
+    fn and(&mut self) -> Result<Requirement, ParseError> {
+        let mut requirements = vec![self.not()?];
+        while self.peek() == Some(&Token::And) {
+            self.position += 1;
+            requirements.push(self.not()?);
+        }
+
+        Ok(match requirements.len() {
+            1 => requirements.remove(0),
+            _ => Requirement::All(requirements),
+        })
+    }
+
+    fn not(&mut self) -> Result<Requirement, ParseError> {
+        if self.peek() == Some(&Token::Not) {
+            self.position += 1;
+            return Ok(Requirement::not(self.not()?));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Requirement, ParseError> {
+        match self.next()? {
+            (_, Token::Capability(capability)) => Ok(Requirement::Capability(capability)),
+            (position, Token::Open) => {
+                let requirement = self.or()?;
+                match self.next() {
+                    Ok((_, Token::Close)) => Ok(requirement),
+                    _ => Err(ParseError {
+                        position,
+                        reason: "unclosed parenthesis",
+                    }),
+                }
+            }
+            (position, _) => Err(ParseError {
+                position,
+                reason: "expected a capability",
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capabilities(capabilities: &[&str]) -> HashSet<String> {
+        capabilities.iter().map(|c| c.to_string()).collect()
+    }
+
+    fn parse(s: &str) -> Requirement {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(
+            parse("A OR B AND NOT C"),
+            Requirement::any(vec![
+                Requirement::capability("A"),
+                Requirement::all(vec![
+                    Requirement::capability("B"),
+                    Requirement::not(Requirement::capability("C")),
+                ]),
+            ])
+        );
+        assert_eq!(parse("(A | B) & !C"), parse("(A OR B) AND NOT C"));
+    }
+
+    #[test]
+    fn evaluate() {
+        let requirement = parse("AdminRead OR (UserRead AND Auditor)");
+
+        assert!(requirement.is_satisfied_by(&capabilities(&["AdminRead"])));
+        assert!(requirement.is_satisfied_by(&capabilities(&["UserRead", "Auditor"])));
+        assert!(!requirement.is_satisfied_by(&capabilities(&["UserRead"])));
+
+        let requirement = parse("NOT Suspended");
+        assert!(requirement.is_satisfied_by(&capabilities(&[])));
+        assert!(!requirement.is_satisfied_by(&capabilities(&["Suspended"])));
+    }
+
+    #[test]
+    /// Makes sure a set of capabilities behaves like the previous superset check.
+    fn from_hash_set() {
+        let requirement = Requirement::from(capabilities(&["A", "B"]));
+
+        assert!(requirement.is_satisfied_by(&capabilities(&["A", "B", "C"])));
+        assert!(!requirement.is_satisfied_by(&capabilities(&["A"])));
+        assert!(Requirement::from(capabilities(&[])).is_satisfied_by(&capabilities(&[])));
+        assert_eq!(parse("  "), Requirement::none());
+    }
+
+    #[test]
+    fn wildcards() {
+        // Exact capabilities, with and without namespace
+        assert!(grants("AdminRead", "AdminRead"));
+        assert!(grants("documents:read", "documents:read"));
+        assert!(!grants("documents:read", "documents:write"));
+        assert!(!grants("documents", "documents:read"));
+        assert!(!grants("documents:read", "documents"));
+        assert!(!grants("adminread", "AdminRead"));
+
+        // A trailing wildcard matches one or more segments
+        assert!(grants("*", "billing:invoices:read"));
+        assert!(grants("billing:*", "billing:invoices"));
+        assert!(grants("billing:*", "billing:invoices:read"));
+        assert!(!grants("billing:*", "billing"));
+        assert!(!grants("billing:*", "billingx:invoices"));
+
+        // Any other wildcard matches exactly one segment
+        assert!(grants("documents:*:read", "documents:42:read"));
+        assert!(!grants("documents:*:read", "documents:42:write"));
+        assert!(!grants("documents:*:read", "documents:42:read:all"));
+
+        // Wildcards in required capabilities are not expanded
+        assert!(!grants("billing:invoices", "billing:*"));
+        assert!(grants("billing:*", "billing:*"));
+    }
+
+    #[test]
+    fn evaluate_wildcards() {
+        let granted = capabilities(&["billing:*", "documents:read"]);
+
+        assert!(parse("billing:invoices:read AND documents:read").is_satisfied_by(&granted));
+        assert!(!parse("documents:write").is_satisfied_by(&granted));
+        assert!(!parse("NOT billing:invoices").is_satisfied_by(&granted));
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for s in &[
+            "A",
+            "A AND B AND C",
+            "A OR (B AND NOT C)",
+            "NOT (A OR B)",
+            "(A OR B) AND (C OR NOT D)",
+        ] {
+            assert_eq!(parse(s).to_string(), *s);
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        let error = |s: &str| s.parse::<Requirement>().unwrap_err();
+
+        assert_eq!(
+            error("A AND"),
+            ParseError {
+                position: 5,
+                reason: "unexpected end"
+            }
+        );
+        assert_eq!(error("(A OR B").reason, "unclosed parenthesis");
+        assert_eq!(error("A B").position, 2);
+        assert_eq!(error("AND A").reason, "expected a capability");
+        assert_eq!(error("A)").reason, "unexpected token");
+    }
+}
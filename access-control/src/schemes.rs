@@ -0,0 +1,242 @@
+use argon2::password_hash::{PasswordHash, PasswordVerifier};
+use md5::{Digest, Md5};
+
+/// A password hashing scheme that is able to verify hashes in its own format.
+///
+/// Schemes are used to verify the hashes of users that were imported from other systems.
+/// After a successful login, these hashes are replaced by an Argon2id hash (see [`crate::hashing::needs_rehash`]).
+pub trait PasswordScheme: Send + Sync {
+    /// Returns `true` if the hash is in the format of this scheme.
+    fn identifies(&self, password_hash: &str) -> bool;
+    /// Verifies the password against a hash that has been identified by this scheme.
+    fn verify(&self, password: &[u8], password_hash: &str) -> bool;
+}
+
+/// Returns every scheme that is implemented by this module.
+pub fn default_schemes() -> Vec<Box<dyn PasswordScheme>> {
+    vec![
+        Box::new(Bcrypt),
+        Box::new(Scrypt),
+        Box::new(Pbkdf2),
+        Box::new(Apr1),
+    ]
+}
+
+/// Returns `true` if the hash is a PHC string of one of the algorithms.
+fn is_phc_algorithm(password_hash: &str, algorithms: &[&str]) -> bool {
+    PasswordHash::new(password_hash)
+        .map(|hash| algorithms.contains(&hash.algorithm.as_str()))
+        .unwrap_or(false)
+}
+
+/// Verifies bcrypt hashes in the modular crypt format, e.g. `$2b$12$...`.
+///
+/// These hashes are created by PostgreSQL's `crypt(password, gen_salt('bf'))`.
+#[derive(Debug, Clone, Copy)]
+pub struct Bcrypt;
+
+impl PasswordScheme for Bcrypt {
+    fn identifies(&self, password_hash: &str) -> bool {
+        ["$2a$", "$2b$", "$2x$", "$2y$"]
+            .iter()
+            .any(|prefix| password_hash.starts_with(prefix))
+    }
+
+    fn verify(&self, password: &[u8], password_hash: &str) -> bool {
+        bcrypt::verify(password, password_hash).unwrap_or(false)
+    }
+}
+
+/// Verifies scrypt hashes in the PHC string format, e.g. `$scrypt$ln=15,r=8,p=1$...`.
+#[derive(Debug, Clone, Copy)]
+pub struct Scrypt;
+
+impl PasswordScheme for Scrypt {
+    fn identifies(&self, password_hash: &str) -> bool {
+        is_phc_algorithm(password_hash, &["scrypt"])
+    }
+
+    fn verify(&self, password: &[u8], password_hash: &str) -> bool {
+        PasswordHash::new(password_hash)
+            .map(|hash| scrypt::Scrypt.verify_password(password, &hash).is_ok())
+            .unwrap_or(false)
+    }
+}
+
+/// Verifies PBKDF2 hashes in the PHC string format, e.g. `$pbkdf2-sha256$i=10000,l=32$...`.
+#[derive(Debug, Clone, Copy)]
+pub struct Pbkdf2;
+
+impl PasswordScheme for Pbkdf2 {
+    fn identifies(&self, password_hash: &str) -> bool {
+        is_phc_algorithm(password_hash, &["pbkdf2-sha256", "pbkdf2-sha512"])
+    }
+
+    fn verify(&self, password: &[u8], password_hash: &str) -> bool {
+        PasswordHash::new(password_hash)
+            .map(|hash| pbkdf2::Pbkdf2.verify_password(password, &hash).is_ok())
+            .unwrap_or(false)
+    }
+}
+
+/// Verifies the MD5 based hashes used by Apache's htpasswd, e.g. `$apr1$salt$hash`.
+///
+/// This scheme is weak and should only be used to migrate users to Argon2id.
+#[derive(Debug, Clone, Copy)]
+pub struct Apr1;
+
+impl Apr1 {
+    const MAGIC: &'static str = "$apr1$";
+    const ITOA64: &'static [u8] =
+        b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+    /// Creates the hash of the password by implementing the MD5-crypt algorithm with the `$apr1$` magic string.
+    fn hash(password: &[u8], salt: &[u8]) -> String {
+        let mut alternate = Md5::new();
+        alternate.update(password);
+        alternate.update(salt);
+        alternate.update(password);
+        let alternate = alternate.finalize();
+
+        let mut context = Md5::new();
+        context.update(password);
+        context.update(Self::MAGIC);
+        context.update(salt);
+        for chunk in password.chunks(16) {
+            context.update(&alternate[..chunk.len()]);
+        }
+        let mut length = password.len();
+        while length > 0 {
+            if length & 1 == 1 {
+                context.update([0u8]);
+            } else {
+                context.update(&password[..1]);
+            }
+            length >>= 1;
+        }
+        let mut digest = context.finalize();
+
+        // Strengthen the hash by running 1000 additional rounds
+        for round in 0..1000 {
+            let mut context = Md5::new();
+            if round & 1 == 1 {
+                context.update(password);
+            } else {
+                context.update(digest);
+            }
+            if round % 3 != 0 {
+                context.update(salt);
+            }
+            if round % 7 != 0 {
+                context.update(password);
+            }
+            if round & 1 == 1 {
+                context.update(digest);
+            } else {
+                context.update(password);
+            }
+            digest = context.finalize();
+        }
+
+        let mut encoded = String::new();
+        let mut encode = |value: u32, length: usize| {
+            (0..length).fold(value, |value, _| {
+                encoded.push(Self::ITOA64[(value & 0x3f) as usize] as char);
+                value >> 6
+            });
+        };
+        for &(a, b, c) in &[(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+            encode(
+                (digest[a] as u32) << 16 | (digest[b] as u32) << 8 | digest[c] as u32,
+                4,
+            );
+        }
+        encode(digest[11] as u32, 2);
+
+        format!(
+            "{}{}${}",
+            Self::MAGIC,
+            String::from_utf8_lossy(salt),
+            encoded
+        )
+    }
+}
+
+impl PasswordScheme for Apr1 {
+    fn identifies(&self, password_hash: &str) -> bool {
+        password_hash.starts_with(Self::MAGIC)
+    }
+
+    fn verify(&self, password: &[u8], password_hash: &str) -> bool {
+        let salt = match password_hash[Self::MAGIC.len()..].split('$').next() {
+            Some(salt) if salt.len() <= 8 => salt,
+            _ => return false,
+        };
+        let computed = Self::hash(password, salt.as_bytes());
+
+        // Compare without an early return to not leak the length of the matching prefix
+        computed.len() == password_hash.len()
+            && computed
+                .bytes()
+                .zip(password_hash.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Asserts that exactly one of the default schemes identifies the hash and verifies the password with it.
+    fn verify(password: &str, password_hash: &str) -> bool {
+        let schemes: Vec<_> = default_schemes()
+            .into_iter()
+            .filter(|scheme| scheme.identifies(password_hash))
+            .collect();
+
+        assert_eq!(schemes.len(), 1);
+        schemes[0].verify(password.as_bytes(), password_hash)
+    }
+
+    #[test]
+    fn bcrypt() {
+        let hash = "$2a$05$CCCCCCCCCCCCCCCCCCCCC.E5YPO9kmyuRGyh0XouQYb4YMJKvyOeW";
+
+        assert!(verify("U*U", hash));
+        assert!(!verify("wrongpassword", hash));
+    }
+
+    #[test]
+    fn scrypt() {
+        let hash =
+            "$scrypt$ln=10,r=8,p=1$saltsaltsaltsalt$DYXdya3gb92A3rv5xi4yY95ZSeCgi2/02X2vRMgHagg";
+
+        assert!(verify("password", hash));
+        assert!(!verify("wrongpassword", hash));
+    }
+
+    #[test]
+    fn pbkdf2() {
+        let hash = "$pbkdf2-sha256$i=10000,l=32$saltsaltsaltsalt$zAGC44WreZPI4w0Shpr1m8fcvuZWWb0HLlH1mt2i0CU";
+
+        assert!(verify("password", hash));
+        assert!(!verify("wrongpassword", hash));
+    }
+
+    #[test]
+    fn apr1() {
+        let hash = "$apr1$r31uPcKa$pml3rFsVOzq1WUkO7cCVl.";
+
+        assert!(verify("password", hash));
+        assert!(!verify("wrongpassword", hash));
+    }
+
+    #[test]
+    /// Makes sure Argon2 hashes are not identified by any of the legacy schemes.
+    fn argon2_is_not_identified() {
+        assert!(default_schemes()
+            .iter()
+            .all(|scheme| !scheme.identifies(crate::FAKE_PHC_HASH)));
+    }
+}
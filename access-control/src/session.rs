@@ -0,0 +1,117 @@
+use ring::digest::{digest, SHA256};
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+
+/// The maximum number of characters of a stored user agent, longer ones are truncated.
+const MAX_USER_AGENT_LENGTH: usize = 256;
+
+/// The lifetime of sessions.
+///
+/// A session expires after `idle_timeout` without an authenticated request, but at the latest `absolute_timeout`
+/// after the login, no matter how active the user is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionLifetime {
+    pub idle_timeout: Duration,
+    pub absolute_timeout: Duration,
+}
+
+impl SessionLifetime {
+    /// Creates the lifetime, the idle timeout is capped by the absolute timeout.
+    pub fn new(idle_timeout: Duration, absolute_timeout: Duration) -> Self {
+        SessionLifetime {
+            idle_timeout: idle_timeout.min(absolute_timeout),
+            absolute_timeout,
+        }
+    }
+}
+
+impl Default for SessionLifetime {
+    /// Sessions expire after 30 minutes without activity and 12 hours after the login.
+    fn default() -> Self {
+        Self::new(
+            Duration::from_secs(30 * 60),
+            Duration::from_secs(12 * 60 * 60),
+        )
+    }
+}
+
+/// The client that started a session, which is stored with the session by [`crate::Backend::store_session`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionMetadata {
+    pub ip_address: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    /// The selector of the remember-me series that has been issued with or restored the session
+    pub remember_selector: Option<String>,
+}
+
+impl SessionMetadata {
+    /// Creates the metadata, the user agent is sent by the client, so it is truncated to a reasonable length.
+    pub fn new(ip_address: Option<IpAddr>, user_agent: Option<&str>) -> Self {
+        SessionMetadata {
+            ip_address,
+            user_agent: user_agent.map(|agent| agent.chars().take(MAX_USER_AGENT_LENGTH).collect()),
+            remember_selector: None,
+        }
+    }
+}
+
+/// An active session of a user, which is listed by [`crate::Backend::get_sessions`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionInfo {
+    /// The [`hash_session_id`] of the session, which identifies it without revealing the session id
+    pub id: String,
+    pub created: SystemTime,
+    /// The time of the last authenticated request, see [`crate::Backend::touch_session`]
+    pub last_seen: SystemTime,
+    pub ip_address: Option<IpAddr>,
+    pub user_agent: Option<String>,
+    /// Whether it is the session of the request that lists the sessions
+    pub current: bool,
+}
+
+/// Returns the SHA-256 digest of a session id in lowercase hex, which is what a [`crate::Backend`] should store.
+///
+/// The raw session id only exists in the cookie, so read access to the database or a backup does not allow to hijack
+/// sessions. Session ids are random with 256 bits, so a fast hash without salt is sufficient, unlike for passwords.
+pub fn hash_session_id(session_id: &str) -> String {
+    digest(&SHA256, session_id.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn idle_timeout_is_capped() {
+        let lifetime = SessionLifetime::new(Duration::from_secs(60), Duration::from_secs(30));
+        assert_eq!(lifetime.idle_timeout, Duration::from_secs(30));
+        assert_eq!(lifetime.absolute_timeout, Duration::from_secs(30));
+
+        let lifetime = SessionLifetime::default();
+        assert!(lifetime.idle_timeout < lifetime.absolute_timeout);
+    }
+
+    #[test]
+    fn user_agent_is_truncated() {
+        let agent = "ä".repeat(MAX_USER_AGENT_LENGTH + 1);
+        let metadata = SessionMetadata::new(None, Some(&agent));
+        assert_eq!(
+            metadata.user_agent.unwrap().chars().count(),
+            MAX_USER_AGENT_LENGTH
+        );
+        assert_eq!(SessionMetadata::new(None, None), SessionMetadata::default());
+    }
+
+    #[test]
+    fn session_id_digest() {
+        assert_eq!(
+            hash_session_id("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_ne!(hash_session_id("abc"), hash_session_id("abd"));
+    }
+}
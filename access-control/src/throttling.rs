@@ -0,0 +1,199 @@
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+
+/// The failed login attempts of an account or client IP, which are persisted by the [`crate::Backend`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FailedAttempts {
+    /// The number of failed attempts since the counter has been reset
+    pub count: u32,
+    pub last_failure: SystemTime,
+}
+
+/// The limits of failed login attempts of a single account or client IP.
+///
+/// The first `free_attempts` failures are not delayed. Every further failure doubles the delay, starting at
+/// `base_delay`, until `lockout_threshold` failures lock the key out for the `lockout_duration`. The failures are
+/// forgotten after `reset_after` without a failure, or after a successful login of the account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThrottlePolicy {
+    pub free_attempts: u32,
+    pub base_delay: Duration,
+    pub lockout_threshold: u32,
+    pub lockout_duration: Duration,
+    pub reset_after: Duration,
+}
+
+impl ThrottlePolicy {
+    /// The default limits of an account: 3 free attempts and a lockout of 15 minutes after 10 failures.
+    pub fn account() -> Self {
+        ThrottlePolicy {
+            free_attempts: 3,
+            base_delay: Duration::from_secs(1),
+            lockout_threshold: 10,
+            lockout_duration: Duration::from_secs(15 * 60),
+            reset_after: Duration::from_secs(60 * 60),
+        }
+    }
+
+    /// The default limits of a client IP, which are looser, as an IP may be shared by many users behind a NAT.
+    pub fn client_ip() -> Self {
+        ThrottlePolicy {
+            free_attempts: 10,
+            lockout_threshold: 100,
+            ..Self::account()
+        }
+    }
+
+    /// Returns how long the next login attempt has to wait, or `None` if it is allowed now.
+    pub fn retry_after(&self, attempts: &FailedAttempts, now: SystemTime) -> Option<Duration> {
+        let elapsed = now
+            .duration_since(attempts.last_failure)
+            .unwrap_or_default();
+        if elapsed >= self.reset_after || attempts.count < self.free_attempts {
+            return None;
+        }
+
+        let delay = if attempts.count >= self.lockout_threshold {
+            self.lockout_duration
+        } else {
+            let exponent = attempts.count - self.free_attempts;
+            self.base_delay
+                .saturating_mul(2u32.saturating_pow(exponent))
+                .min(self.lockout_duration)
+        };
+        delay.checked_sub(elapsed).filter(|d| !d.is_zero())
+    }
+}
+
+/// The [`ThrottlePolicy`]s of the login, which are applied per account and per client IP.
+///
+/// The account is identified by the submitted username, whether it exists or not, so the throttling does not reveal
+/// which usernames are registered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Throttling {
+    pub account: ThrottlePolicy,
+    pub client_ip: ThrottlePolicy,
+}
+
+impl Throttling {
+    /// Returns the keys of an attempt with the policy that applies to each of them.
+    ///
+    /// The account key is `None` if the attempt can not be attributed to an account, e.g. an unknown passkey.
+    pub fn keys(
+        &self,
+        account_key: Option<String>,
+        client_ip: Option<IpAddr>,
+    ) -> Vec<(String, &ThrottlePolicy)> {
+        account_key
+            .map(|key| (key, &self.account))
+            .into_iter()
+            .chain(client_ip.map(|ip| (client_ip_key(ip), &self.client_ip)))
+            .collect()
+    }
+}
+
+impl Default for Throttling {
+    fn default() -> Self {
+        Throttling {
+            account: ThrottlePolicy::account(),
+            client_ip: ThrottlePolicy::client_ip(),
+        }
+    }
+}
+
+/// Returns the key of the failed attempts of an account by its canonical username.
+pub fn account_key(username: &str) -> String {
+    format!("account:{}", username)
+}
+
+/// Returns the key of the failed second factors of an account by its canonical username.
+///
+/// The second factor is counted separately, as the failures of the password are forgotten after a successful login,
+/// which an attacker that knows the password could use to reset the counter between guesses of one-time passwords.
+pub fn second_factor_key(username: &str) -> String {
+    format!("second-factor:{}", username)
+}
+
+/// Returns the key of the failed attempts of a client IP.
+///
+/// IPv6 addresses are grouped by their /64 prefix, as a single client usually controls the whole prefix.
+pub fn client_ip_key(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => format!("ip:{}", ip),
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            format!(
+                "ip:{:x}:{:x}:{:x}:{:x}::/64",
+                segments[0], segments[1], segments[2], segments[3]
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attempts(count: u32, seconds_ago: u64, now: SystemTime) -> FailedAttempts {
+        FailedAttempts {
+            count,
+            last_failure: now - Duration::from_secs(seconds_ago),
+        }
+    }
+
+    #[test]
+    /// Makes sure the delay doubles with every failure after the free attempts until the key is locked out.
+    fn exponential_backoff_and_lockout() {
+        let policy = ThrottlePolicy::account();
+        let now = SystemTime::now();
+        let retry_after =
+            |count, seconds_ago| policy.retry_after(&attempts(count, seconds_ago, now), now);
+
+        assert_eq!(retry_after(0, 0), None);
+        assert_eq!(retry_after(2, 0), None);
+        assert_eq!(retry_after(3, 0), Some(Duration::from_secs(1)));
+        assert_eq!(retry_after(4, 0), Some(Duration::from_secs(2)));
+        assert_eq!(retry_after(9, 0), Some(Duration::from_secs(64)));
+        assert_eq!(retry_after(9, 60), Some(Duration::from_secs(4)));
+        assert_eq!(retry_after(9, 64), None);
+        assert_eq!(retry_after(10, 0), Some(Duration::from_secs(15 * 60)));
+        assert_eq!(retry_after(1000, 60), Some(Duration::from_secs(14 * 60)));
+        assert_eq!(retry_after(1000, 15 * 60), None);
+    }
+
+    #[test]
+    /// Makes sure old failures are forgotten, even if they locked the key out.
+    fn reset_after_quiet_period() {
+        let policy = ThrottlePolicy {
+            lockout_duration: Duration::from_secs(2 * 60 * 60),
+            ..ThrottlePolicy::account()
+        };
+        let now = SystemTime::now();
+
+        assert!(policy
+            .retry_after(&attempts(10, 59 * 60, now), now)
+            .is_some());
+        assert_eq!(policy.retry_after(&attempts(10, 60 * 60, now), now), None);
+        // A failure in the future, e.g. due to clock skew, is treated as a failure right now
+        assert_eq!(
+            policy.retry_after(&attempts(3, 0, now), now - Duration::from_secs(10)),
+            Some(Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    /// Makes sure the keys are prefixed by their kind and IPv6 addresses share the key of their /64 prefix.
+    fn keys() {
+        assert_eq!(account_key("jean-luc"), "account:jean-luc");
+        assert_eq!(second_factor_key("jean-luc"), "second-factor:jean-luc");
+        assert_eq!(client_ip_key("192.0.2.1".parse().unwrap()), "ip:192.0.2.1");
+        assert_eq!(
+            client_ip_key("2001:db8:1:2:3:4:5:6".parse().unwrap()),
+            client_ip_key("2001:db8:1:2:ffff::1".parse().unwrap())
+        );
+        assert_eq!(
+            client_ip_key("2001:db8:1:2:3:4:5:6".parse().unwrap()),
+            "ip:2001:db8:1:2::/64"
+        );
+    }
+}
@@ -0,0 +1,220 @@
+use hmac::{Hmac, Mac, NewMac};
+use rand::RngCore;
+use sha1::Sha1;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The number of digits of a one-time password.
+pub const DIGITS: u32 = 6;
+
+/// The number of seconds a one-time password is valid, which is the default of authenticator apps.
+pub const STEP: u64 = 30;
+
+/// The number of time steps before and after the current one that are accepted to allow for clock drift.
+pub const DRIFT: u64 = 1;
+
+/// The number of one-time passwords or recovery codes that can be tried with a single login, see
+/// [`crate::AccessControl::resume_totp`].
+pub const MAX_ATTEMPTS: u32 = 5;
+
+/// The length of generated secrets in bytes, 160 bits as recommended by
+/// [RFC 4226](https://tools.ietf.org/html/rfc4226#section-4).
+const SECRET_LENGTH: usize = 20;
+
+/// The base32 alphabet of [RFC 4648](https://tools.ietf.org/html/rfc4648#section-6) without padding, which is
+/// expected by authenticator apps.
+const BASE32: base32::Alphabet = base32::Alphabet::RFC4648 { padding: false };
+
+/// A time-based one-time password generator as per [RFC 6238](https://tools.ietf.org/html/rfc6238).
+///
+/// Uses HMAC-SHA1, [`DIGITS`] digits and a time step of [`STEP`] seconds, which is supported by every common
+/// authenticator app. The secret is shared with the app by the [`Totp::provisioning_uri`], usually as QR code.
+///
+/// # Example usage
+/// ```
+/// # use access_control::totp::{self, Totp};
+/// let totp = Totp::generate();
+/// let step = totp::current_step();
+///
+/// assert_eq!(totp.verify(&totp.code(step), step), Some(step));
+/// assert_eq!(totp.verify("000000x", step), None);
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct Totp {
+    secret: Vec<u8>,
+}
+
+impl Totp {
+    /// Generates a new random secret with a CSPRNG.
+    pub fn generate() -> Self {
+        let mut secret = vec![0; SECRET_LENGTH];
+        rand::thread_rng().fill_bytes(&mut secret);
+        Totp { secret }
+    }
+
+    /// Decodes a base32 encoded secret, whitespace is ignored and the case does not matter.
+    pub fn from_base32(secret: &str) -> Option<Self> {
+        let secret: String = secret
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect::<String>()
+            .to_uppercase();
+        base32::decode(BASE32, &secret)
+            .filter(|secret| !secret.is_empty())
+            .map(|secret| Totp { secret })
+    }
+
+    /// Returns whether the secret has the 160 bits of a [generated](Totp::generate) secret, which are required to
+    /// enable it, see [`crate::AccessControl::enable_totp`].
+    pub fn has_recommended_length(&self) -> bool {
+        self.secret.len() >= SECRET_LENGTH
+    }
+
+    /// Encodes the secret with base32, which is the format that is stored and shown to the user.
+    pub fn to_base32(&self) -> String {
+        base32::encode(BASE32, &self.secret)
+    }
+
+    /// Returns the `otpauth://` URI that authenticator apps use to enroll the secret.
+    ///
+    /// The `issuer` is the name of the service and the `account` usually the username, both are shown in the app.
+    /// See the [Key Uri Format](https://github.com/google/google-authenticator/wiki/Key-Uri-Format).
+    pub fn provisioning_uri(&self, issuer: &str, account: &str) -> String {
+        format!(
+            "otpauth://totp/{issuer}:{account}?secret={secret}&issuer={issuer}&algorithm=SHA1&digits={digits}&period={period}",
+            issuer = encode_uri_component(issuer),
+            account = encode_uri_component(account),
+            secret = self.to_base32(),
+            digits = DIGITS,
+            period = STEP,
+        )
+    }
+
+    /// Returns the one-time password of the time step.
+    pub fn code(&self, step: u64) -> String {
+        hotp(&self.secret, step, DIGITS)
+    }
+
+    /// Verifies the code against the time step and [`DRIFT`] steps before and after it.
+    ///
+    /// Returns the time step that matched, which must be stored to reject the same code if it is used again.
+    pub fn verify(&self, code: &str, step: u64) -> Option<u64> {
+        let code = code.trim();
+        (step.saturating_sub(DRIFT)..=step + DRIFT)
+            .find(|&step| constant_time_eq(code.as_bytes(), self.code(step).as_bytes()))
+    }
+}
+
+impl std::fmt::Debug for Totp {
+    /// Hides the secret, so that it does not end up in logs.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Totp").finish()
+    }
+}
+
+/// Returns the current time step, which is the number of [`STEP`]s since the unix epoch.
+pub fn current_step() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / STEP)
+        .unwrap_or_default()
+}
+
+/// Computes an HMAC-based one-time password as per [RFC 4226](https://tools.ietf.org/html/rfc4226#section-5.3).
+fn hotp(secret: &[u8], counter: u64, digits: u32) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    format!(
+        "{:0width$}",
+        binary % 10u32.pow(digits),
+        width = digits as usize
+    )
+}
+
+/// Compares two byte strings in constant time for strings of equal length.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Percent-encodes everything but unreserved characters as per
+/// [RFC 3986](https://tools.ietf.org/html/rfc3986#section-2.3).
+fn encode_uri_component(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    /// Uses the SHA1 test vectors of [RFC 6238](https://tools.ietf.org/html/rfc6238#appendix-B).
+    fn rfc_6238_test_vectors() {
+        let secret = b"12345678901234567890";
+
+        for (time, code) in &[
+            (59, "94287082"),
+            (1111111109, "07081804"),
+            (1111111111, "14050471"),
+            (1234567890, "89005924"),
+            (2000000000, "69279037"),
+            (20000000000, "65353130"),
+        ] {
+            assert_eq!(hotp(secret, time / STEP, 8), *code);
+        }
+    }
+
+    #[test]
+    fn drift_window() {
+        let totp = Totp::generate();
+        let step = 1000;
+
+        assert_eq!(totp.verify(&totp.code(step - 1), step), Some(step - 1));
+        assert_eq!(totp.verify(&totp.code(step + 1), step), Some(step + 1));
+        assert_eq!(
+            totp.verify(&format!(" {} ", totp.code(step)), step),
+            Some(step)
+        );
+        assert_eq!(totp.verify(&totp.code(step - 2), step), None);
+        assert_eq!(totp.verify(&totp.code(step + 2), step), None);
+        assert_eq!(totp.verify("", step), None);
+    }
+
+    #[test]
+    fn base32_round_trip() {
+        let totp = Totp::generate();
+        let encoded = totp.to_base32();
+
+        assert_eq!(encoded.len(), 32);
+        assert_eq!(Totp::from_base32(&encoded), Some(totp.clone()));
+        assert_eq!(Totp::from_base32(&encoded.to_lowercase()), Some(totp));
+        assert_eq!(Totp::from_base32("not base32!"), None);
+        assert_eq!(Totp::from_base32(""), None);
+    }
+
+    #[test]
+    fn provisioning_uri() {
+        let totp = Totp::from_base32("JBSWY3DPEHPK3PXP").unwrap();
+
+        assert_eq!(
+            totp.provisioning_uri("Rust Auth", "jean-luc"),
+            "otpauth://totp/Rust%20Auth:jean-luc?secret=JBSWY3DPEHPK3PXP&issuer=Rust%20Auth&algorithm=SHA1&digits=6&period=30"
+        );
+    }
+}
@@ -0,0 +1,439 @@
+use std::collections::HashSet;
+use std::fmt;
+use unicode_normalization::UnicodeNormalization;
+
+/// Names that are reserved by default, as they could be mistaken for an official account of the service.
+const RESERVED_USERNAMES: &[&str] = &[
+    "admin",
+    "administrator",
+    "root",
+    "system",
+    "sysadmin",
+    "superuser",
+    "moderator",
+    "support",
+    "security",
+    "help",
+    "info",
+    "noreply",
+    "postmaster",
+    "webmaster",
+    "hostmaster",
+    "abuse",
+    "owner",
+    "staff",
+    "service",
+    "api",
+    "www",
+    "null",
+    "undefined",
+    "anonymous",
+    "guest",
+];
+
+/// Letters of other scripts that look like Latin letters, mapped to the Latin letter, see [`skeleton`].
+///
+/// This is the subset of the [UTS #39](https://www.unicode.org/reports/tr39/#Confusable_Detection) confusables of
+/// lowercase Cyrillic and Greek letters that are indistinguishable from Latin letters in most fonts.
+const CONFUSABLES: &[(char, char)] = &[
+    ('а', 'a'),
+    ('е', 'e'),
+    ('о', 'o'),
+    ('р', 'p'),
+    ('с', 'c'),
+    ('у', 'y'),
+    ('х', 'x'),
+    ('і', 'i'),
+    ('ј', 'j'),
+    ('ѕ', 's'),
+    ('һ', 'h'),
+    ('ӏ', 'l'),
+    ('ԁ', 'd'),
+    ('ԛ', 'q'),
+    ('ԝ', 'w'),
+    ('α', 'a'),
+    ('ι', 'i'),
+    ('ν', 'v'),
+    ('ο', 'o'),
+    ('ρ', 'p'),
+];
+
+/// Returns the canonical form of a username, which is used to store and look up users.
+///
+/// Follows the idea of the PRECIS `UsernameCaseMapped` profile ([RFC 8265](https://tools.ietf.org/html/rfc8265)):
+/// the username is normalized with NFKC, which maps e.g. full-width characters to their regular form, surrounding
+/// whitespace is removed and the case is folded to lowercase. Two usernames belong to the same user if and only if
+/// their canonical forms are equal.
+pub fn canonicalize(username: &str) -> String {
+    // Lowercasing may produce characters that are not normalized, so normalize again
+    username
+        .nfkc()
+        .collect::<String>()
+        .trim()
+        .to_lowercase()
+        .nfkc()
+        .collect()
+}
+
+/// A reason why a username does not match a [`UsernamePolicy`].
+///
+/// The [`fmt::Display`] implementation returns a message that can be shown to the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UsernameViolation {
+    /// The username has less than `min` characters
+    TooShort { min: usize },
+    /// The username has more than `max` characters
+    TooLong { max: usize },
+    /// The username contains a character that is not allowed
+    InvalidCharacter(char),
+    /// The username starts or ends with a separator or contains consecutive separators
+    MisplacedSeparator,
+    /// The username is reserved, e.g. `admin`
+    Reserved,
+    /// The username mixes letters of several scripts, e.g. a Cyrillic `а` in `аdmin`
+    MixedScripts,
+}
+
+impl fmt::Display for UsernameViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UsernameViolation::TooShort { min } => {
+                write!(f, "The username must be at least {} characters long", min)
+            }
+            UsernameViolation::TooLong { max } => {
+                write!(f, "The username must be at most {} characters long", max)
+            }
+            UsernameViolation::InvalidCharacter(c) => {
+                write!(f, "The username must not contain '{}'", c.escape_default())
+            }
+            UsernameViolation::MisplacedSeparator => write!(
+                f,
+                "Separators must be surrounded by letters or digits in the username"
+            ),
+            UsernameViolation::Reserved => write!(f, "The username is reserved"),
+            UsernameViolation::MixedScripts => {
+                write!(f, "The username must not mix letters of different scripts")
+            }
+        }
+    }
+}
+
+/// The UsernamePolicy defines which usernames can be registered.
+///
+/// The username is [canonicalized](canonicalize) before it is checked. The default policy allows 3 to 64 letters and
+/// digits of any script and the separators `-`, `.` and `_` between them, e.g. `jean-luc`, `first.last` or `łukasz`.
+/// [Reserved](UsernamePolicy::with_reserved) names are rejected even if they contain separators, so `ad.min` is
+/// rejected just like `admin`.
+///
+/// Letters of different scripts look alike, so usernames that mix scripts are rejected, following the
+/// [highly restrictive](https://www.unicode.org/reports/tr39/#Restriction_Level_Detection) level of UTS #39: Latin
+/// may only be combined with the Chinese, Japanese and Korean scripts. Reserved names are compared by a confusable
+/// [skeleton](skeleton) as well, so a Cyrillic `аdmin` is rejected twice.
+///
+/// # Example usage
+/// ```
+/// # use access_control::username_policy::{UsernamePolicy, UsernameViolation};
+/// let policy = UsernamePolicy::default().with_reserved(&["operator"]);
+///
+/// assert_eq!(policy.check("Jean-Luc"), Ok("jean-luc".to_string()));
+/// assert_eq!(policy.check("Operator"), Err(vec![UsernameViolation::Reserved]));
+/// ```
+#[derive(Debug, Clone)]
+pub struct UsernamePolicy {
+    /// The minimum number of characters
+    pub min_length: usize,
+    /// The maximum number of characters
+    pub max_length: usize,
+    /// Allows letters and digits of every script if `true`, only ASCII letters and digits otherwise
+    pub allow_unicode: bool,
+    /// Characters that may be used between letters and digits
+    pub separators: Vec<char>,
+    reserved: HashSet<String>,
+}
+
+impl Default for UsernamePolicy {
+    fn default() -> Self {
+        UsernamePolicy {
+            min_length: 3,
+            max_length: 64,
+            allow_unicode: true,
+            separators: vec!['-', '.', '_'],
+            reserved: HashSet::new(),
+        }
+        .with_reserved(RESERVED_USERNAMES)
+    }
+}
+
+impl UsernamePolicy {
+    /// Allows only ASCII letters and digits without any separators, which matches the previous behaviour.
+    pub fn ascii_alphanumeric() -> Self {
+        UsernamePolicy {
+            allow_unicode: false,
+            separators: Vec::new(),
+            ..Default::default()
+        }
+    }
+
+    /// Adds names that can not be registered.
+    pub fn with_reserved<S: AsRef<str>>(mut self, names: &[S]) -> Self {
+        self.reserved.extend(
+            names
+                .iter()
+                .map(|name| skeleton(&canonicalize(name.as_ref()))),
+        );
+        self
+    }
+
+    /// Checks the username and returns its canonical form or every violation.
+    pub fn check(&self, username: &str) -> Result<String, Vec<UsernameViolation>> {
+        let username = canonicalize(username);
+        let mut violations = Vec::new();
+
+        let length = username.chars().count();
+        if length < self.min_length {
+            violations.push(UsernameViolation::TooShort {
+                min: self.min_length,
+            });
+        } else if length > self.max_length {
+            violations.push(UsernameViolation::TooLong {
+                max: self.max_length,
+            });
+        }
+
+        if let Some(c) = username
+            .chars()
+            .find(|&c| !self.is_alphanumeric(c) && !self.separators.contains(&c))
+        {
+            violations.push(UsernameViolation::InvalidCharacter(c));
+        }
+
+        let is_separator = |c: &char| self.separators.contains(c);
+        let chars: Vec<char> = username.chars().collect();
+        if chars.first().is_some_and(is_separator)
+            || chars.last().is_some_and(is_separator)
+            || chars
+                .windows(2)
+                .any(|pair| is_separator(&pair[0]) && is_separator(&pair[1]))
+        {
+            violations.push(UsernameViolation::MisplacedSeparator);
+        }
+
+        if self.reserved.contains(&skeleton(&username)) {
+            violations.push(UsernameViolation::Reserved);
+        }
+
+        if is_mixed_script(&username) {
+            violations.push(UsernameViolation::MixedScripts);
+        }
+
+        if violations.is_empty() {
+            Ok(username)
+        } else {
+            Err(violations)
+        }
+    }
+
+    fn is_alphanumeric(&self, c: char) -> bool {
+        if self.allow_unicode {
+            c.is_alphanumeric()
+        } else {
+            c.is_ascii_alphanumeric()
+        }
+    }
+}
+
+/// Removes everything but letters and digits and maps [confusable](CONFUSABLES) letters to Latin, so that reserved
+/// names can not be registered with separators in between or with lookalike letters.
+fn skeleton(username: &str) -> String {
+    username
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .map(|c| {
+            CONFUSABLES
+                .iter()
+                .find(|(confusable, _)| *confusable == c)
+                .map_or(c, |(_, latin)| *latin)
+        })
+        .collect()
+}
+
+/// The scripts that [`is_mixed_script`] tells apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+    Armenian,
+    Hebrew,
+    Arabic,
+    /// Han, Hiragana, Katakana, Hangul and Bopomofo, which are commonly mixed
+    Cjk,
+    /// Every other script, which are not told apart from each other
+    Other,
+}
+
+/// Returns the script of a letter by its Unicode block, or `None` for digits and other characters that are common to
+/// every script.
+fn script(c: char) -> Option<Script> {
+    if !c.is_alphabetic() {
+        return None;
+    }
+
+    let script = match u32::from(c) {
+        0x0041..=0x024F | 0x0250..=0x02AF | 0x1E00..=0x1EFF | 0x2C60..=0x2C7F | 0xA720..=0xA7FF => {
+            Script::Latin
+        }
+        0x0370..=0x03FF | 0x1F00..=0x1FFF => Script::Greek,
+        0x0400..=0x052F | 0x1C80..=0x1C8F | 0x2DE0..=0x2DFF | 0xA640..=0xA69F => Script::Cyrillic,
+        0x0530..=0x058F => Script::Armenian,
+        0x0590..=0x05FF => Script::Hebrew,
+        0x0600..=0x06FF | 0x0750..=0x077F | 0x08A0..=0x08FF => Script::Arabic,
+        0x1100..=0x11FF
+        | 0x2E80..=0x2FDF
+        | 0x3040..=0x31FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xAC00..=0xD7AF
+        | 0xF900..=0xFAFF
+        | 0x20000..=0x3FFFF => Script::Cjk,
+        _ => Script::Other,
+    };
+    Some(script)
+}
+
+/// Returns `true` if the letters of the username belong to more than one script, Latin combined with the Chinese,
+/// Japanese and Korean scripts is allowed.
+fn is_mixed_script(username: &str) -> bool {
+    let mut scripts: Vec<Script> = Vec::new();
+    for script in username.chars().filter_map(script) {
+        if !scripts.contains(&script) {
+            scripts.push(script);
+        }
+    }
+    if scripts.contains(&Script::Cjk) {
+        scripts.retain(|script| *script != Script::Latin);
+    }
+
+    scripts.len() > 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_form() {
+        assert_eq!(canonicalize("  Alice "), "alice");
+        // Full-width characters and ligatures are mapped by NFKC
+        assert_eq!(canonicalize("ＡＬＩＣＥ"), "alice");
+        assert_eq!(canonicalize("ﬁona"), "fiona");
+        // Composed and decomposed forms are equal
+        assert_eq!(canonicalize("Ze\u{301}phyr"), canonicalize("Zéphyr"));
+    }
+
+    #[test]
+    fn allowed_usernames() {
+        let policy = UsernamePolicy::default();
+
+        for username in &[
+            "jean-luc",
+            "first.last",
+            "snake_case",
+            "łukasz",
+            "さくら",
+            "user42",
+            "дмитрий",
+            "tanaka_たろう",
+        ] {
+            assert_eq!(policy.check(username), Ok(username.to_string()));
+        }
+    }
+
+    #[test]
+    fn invalid_usernames() {
+        let policy = UsernamePolicy::default();
+
+        assert_eq!(
+            policy.check("al"),
+            Err(vec![UsernameViolation::TooShort { min: 3 }])
+        );
+        assert_eq!(
+            policy.check(&"a".repeat(65)),
+            Err(vec![UsernameViolation::TooLong { max: 64 }])
+        );
+        assert_eq!(
+            policy.check("alice bob"),
+            Err(vec![UsernameViolation::InvalidCharacter(' ')])
+        );
+        assert_eq!(
+            policy.check(".alice"),
+            Err(vec![UsernameViolation::MisplacedSeparator])
+        );
+        assert_eq!(
+            policy.check("alice..bob"),
+            Err(vec![UsernameViolation::MisplacedSeparator])
+        );
+    }
+
+    #[test]
+    fn reserved_usernames() {
+        let policy = UsernamePolicy::default();
+
+        assert_eq!(
+            policy.check("Admin"),
+            Err(vec![UsernameViolation::Reserved])
+        );
+        assert_eq!(
+            policy.check("ad.min"),
+            Err(vec![UsernameViolation::Reserved])
+        );
+        assert_eq!(
+            policy.check("ＲＯＯＴ"),
+            Err(vec![UsernameViolation::Reserved])
+        );
+        assert!(policy.check("administration").is_ok());
+    }
+
+    #[test]
+    /// Makes sure lookalike letters of other scripts can not be used to impersonate a user or a reserved name.
+    fn confusable_usernames() {
+        let policy = UsernamePolicy::default();
+
+        // Cyrillic а
+        assert_eq!(
+            policy.check("\u{430}dmin"),
+            Err(vec![
+                UsernameViolation::Reserved,
+                UsernameViolation::MixedScripts
+            ])
+        );
+        // Greek ο
+        assert_eq!(
+            policy.check("r\u{3bf}\u{3bf}t"),
+            Err(vec![
+                UsernameViolation::Reserved,
+                UsernameViolation::MixedScripts
+            ])
+        );
+        assert_eq!(
+            policy.check("p\u{430}ypal"),
+            Err(vec![UsernameViolation::MixedScripts])
+        );
+        assert_eq!(skeleton("\u{440}\u{430}\u{443}\u{440}\u{430}l"), "paypal");
+    }
+
+    #[test]
+    fn ascii_alphanumeric() {
+        let policy = UsernamePolicy::ascii_alphanumeric();
+
+        assert!(policy.check("alice42").is_ok());
+        assert_eq!(
+            policy.check("jean-luc"),
+            Err(vec![UsernameViolation::InvalidCharacter('-')])
+        );
+        assert_eq!(
+            policy.check("łukasz"),
+            Err(vec![UsernameViolation::InvalidCharacter('ł')])
+        );
+    }
+}
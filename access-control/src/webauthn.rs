@@ -0,0 +1,628 @@
+use rand::RngCore;
+use ring::digest::{digest, SHA256};
+use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_ASN1};
+use serde::de::{Deserializer, Error as _};
+use serde::Deserialize;
+use serde_cbor::Value;
+use serde_json::json;
+
+/// The number of milliseconds the browser waits for the authenticator, which matches the lifetime of a challenge.
+pub const TIMEOUT: u64 = 5 * 60 * 1000;
+
+/// The length of generated challenges in bytes, the specification requires at least 16.
+const CHALLENGE_LENGTH: usize = 32;
+
+/// The maximum length of a credential id as per the
+/// [specification](https://www.w3.org/TR/webauthn-2/#credential-id).
+const MAX_CREDENTIAL_ID_LENGTH: usize = 1023;
+
+/// Flag of the authenticator data that is set if the user touched the authenticator.
+const USER_PRESENT: u8 = 0x01;
+/// Flag of the authenticator data that is set if the authenticator verified the user, e.g. by PIN or biometrics.
+const USER_VERIFIED: u8 = 0x04;
+/// Flag of the authenticator data that is set if it contains a new credential.
+const ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+/// The [COSE algorithm](https://www.iana.org/assignments/cose/cose.xhtml#algorithms) ES256, which is ECDSA with
+/// P-256 and SHA-256. It is supported by every common authenticator.
+const COSE_ES256: i128 = -7;
+
+/// The reasons why a WebAuthn ceremony failed.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum WebAuthnError {
+    #[error("Malformed WebAuthn response")]
+    Malformed,
+    #[error("The response belongs to another ceremony")]
+    Ceremony,
+    #[error("The response was created for another origin")]
+    Origin,
+    #[error("The credential is scoped to another relying party")]
+    RelyingParty,
+    #[error("The authenticator did not verify the user")]
+    UserVerification,
+    #[error("The challenge is unknown, expired or has already been used")]
+    Challenge,
+    #[error("Only the attestation format none is supported")]
+    UnsupportedAttestation,
+    #[error("Only ES256 public keys are supported")]
+    UnsupportedAlgorithm,
+    #[error("Invalid signature")]
+    Signature,
+    #[error("The signature counter did not increase, the authenticator may have been cloned")]
+    CounterRegression,
+    #[error("The ceremony could not be stored by the backend")]
+    Backend,
+}
+
+/// A public key credential of a user, which is created by [`RelyingParty::verify_registration`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credential {
+    /// The id that the authenticator chose for the credential
+    pub id: Vec<u8>,
+    /// The ES256 public key as uncompressed point
+    pub public_key: Vec<u8>,
+    /// The last signature counter of the authenticator, which must increase with every assertion
+    pub sign_count: u32,
+}
+
+/// The response of `navigator.credentials.create`, binary fields are base64url encoded in JSON.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RegistrationResponse {
+    #[serde(rename = "clientDataJSON", deserialize_with = "base64url")]
+    pub client_data_json: Vec<u8>,
+    #[serde(rename = "attestationObject", deserialize_with = "base64url")]
+    pub attestation_object: Vec<u8>,
+}
+
+/// The response of `navigator.credentials.get`, binary fields are base64url encoded in JSON.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AssertionResponse {
+    /// The raw id of the credential that signed the challenge
+    #[serde(rename = "id", deserialize_with = "base64url")]
+    pub credential_id: Vec<u8>,
+    #[serde(rename = "clientDataJSON", deserialize_with = "base64url")]
+    pub client_data_json: Vec<u8>,
+    #[serde(rename = "authenticatorData", deserialize_with = "base64url")]
+    pub authenticator_data: Vec<u8>,
+    #[serde(deserialize_with = "base64url")]
+    pub signature: Vec<u8>,
+}
+
+/// The service that users register passkeys with, as per [WebAuthn](https://www.w3.org/TR/webauthn-2/).
+///
+/// Credentials are scoped to the `id`, which is the domain of the service, e.g. `example.com`. Responses are only
+/// accepted if the browser created them on the `origin`, e.g. `https://example.com`, which makes passkeys phishing
+/// resistant. Only the attestation format `none` and ES256 keys are supported, which every common authenticator
+/// provides. The user must be verified by the authenticator, so a passkey is a second factor on its own.
+///
+/// The relying party does not keep any state, the challenges have to be stored and consumed by the caller, see
+/// [`crate::AccessControl::begin_webauthn_login`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelyingParty {
+    pub id: String,
+    pub origin: String,
+    /// The name of the service, which the browser shows during the registration
+    pub name: String,
+}
+
+impl RelyingParty {
+    /// Creates a relying party for the domain, which is served on the origin.
+    pub fn new(id: impl Into<String>, origin: impl Into<String>, name: impl Into<String>) -> Self {
+        RelyingParty {
+            id: id.into(),
+            origin: origin.into(),
+            name: name.into(),
+        }
+    }
+
+    /// Returns the options of `navigator.credentials.create` as JSON.
+    ///
+    /// The user handle is the SHA-256 hash of the username, so that the authenticator does not store the username as
+    /// handle, but a user still gets the same handle for every passkey.
+    pub fn creation_options(&self, challenge: &str, username: &str) -> String {
+        json!({
+            "publicKey": {
+                "rp": { "id": self.id, "name": self.name },
+                "user": {
+                    "id": encode(digest(&SHA256, username.as_bytes()).as_ref()),
+                    "name": username,
+                    "displayName": username,
+                },
+                "challenge": challenge,
+                "pubKeyCredParams": [{ "type": "public-key", "alg": COSE_ES256 as i64 }],
+                "timeout": TIMEOUT,
+                "attestation": "none",
+                "authenticatorSelection": {
+                    "residentKey": "preferred",
+                    "userVerification": "required",
+                },
+            }
+        })
+        .to_string()
+    }
+
+    /// Returns the options of `navigator.credentials.get` as JSON.
+    ///
+    /// No credentials are listed, so the authenticator offers every passkey of the relying party and the user does
+    /// not have to enter a username.
+    pub fn request_options(&self, challenge: &str) -> String {
+        json!({
+            "publicKey": {
+                "rpId": self.id,
+                "challenge": challenge,
+                "timeout": TIMEOUT,
+                "userVerification": "required",
+            }
+        })
+        .to_string()
+    }
+
+    /// Verifies the response of a registration ceremony.
+    ///
+    /// Returns the challenge of the response, which must be checked against the issued challenges, and the new
+    /// credential.
+    pub fn verify_registration(
+        &self,
+        response: &RegistrationResponse,
+    ) -> Result<(String, Credential), WebAuthnError> {
+        let challenge = self.verify_client_data(&response.client_data_json, "webauthn.create")?;
+
+        let attestation = match serde_cbor::from_slice(&response.attestation_object) {
+            Ok(Value::Map(attestation)) => attestation,
+            _ => return Err(WebAuthnError::Malformed),
+        };
+        let field = |name: &str| attestation.get(&Value::Text(name.to_string()));
+        match (field("fmt"), field("attStmt")) {
+            (Some(Value::Text(format)), Some(Value::Map(statement)))
+                if format == "none" && statement.is_empty() => {}
+            _ => return Err(WebAuthnError::UnsupportedAttestation),
+        }
+        let authenticator_data = match field("authData") {
+            Some(Value::Bytes(authenticator_data)) => AuthenticatorData::parse(authenticator_data)?,
+            _ => return Err(WebAuthnError::Malformed),
+        };
+        self.verify_authenticator_data(&authenticator_data)?;
+
+        let (id, public_key) = authenticator_data
+            .attested_credential
+            .ok_or(WebAuthnError::Malformed)?;
+        Ok((
+            challenge,
+            Credential {
+                id,
+                public_key,
+                sign_count: authenticator_data.sign_count,
+            },
+        ))
+    }
+
+    /// Verifies the response of an authentication ceremony with the stored credential.
+    ///
+    /// Returns the challenge of the response, which must be checked against the issued challenges, and the new
+    /// signature counter, which must be stored. Authenticators that do not implement the counter always return `0`,
+    /// otherwise the counter must increase, as a lower counter indicates a cloned authenticator.
+    pub fn verify_assertion(
+        &self,
+        response: &AssertionResponse,
+        credential: &Credential,
+    ) -> Result<(String, u32), WebAuthnError> {
+        let challenge = self.verify_client_data(&response.client_data_json, "webauthn.get")?;
+        let authenticator_data = AuthenticatorData::parse(&response.authenticator_data)?;
+        self.verify_authenticator_data(&authenticator_data)?;
+
+        // The signature covers the authenticator data and the hash of the client data
+        let mut signed = response.authenticator_data.clone();
+        signed.extend_from_slice(digest(&SHA256, &response.client_data_json).as_ref());
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, &credential.public_key)
+            .verify(&signed, &response.signature)
+            .map_err(|_| WebAuthnError::Signature)?;
+
+        let sign_count = authenticator_data.sign_count;
+        if (sign_count != 0 || credential.sign_count != 0) && sign_count <= credential.sign_count {
+            return Err(WebAuthnError::CounterRegression);
+        }
+        Ok((challenge, sign_count))
+    }
+
+    /// Checks the type and origin of the client data and returns its challenge.
+    fn verify_client_data(
+        &self,
+        client_data_json: &[u8],
+        ceremony: &str,
+    ) -> Result<String, WebAuthnError> {
+        let client_data: ClientData =
+            serde_json::from_slice(client_data_json).map_err(|_| WebAuthnError::Malformed)?;
+
+        if client_data.ceremony != ceremony {
+            return Err(WebAuthnError::Ceremony);
+        }
+        if client_data.origin != self.origin {
+            return Err(WebAuthnError::Origin);
+        }
+        Ok(client_data.challenge)
+    }
+
+    /// Checks that the authenticator data belongs to this relying party and the user has been verified.
+    fn verify_authenticator_data(
+        &self,
+        authenticator_data: &AuthenticatorData,
+    ) -> Result<(), WebAuthnError> {
+        if authenticator_data.rp_id_hash != digest(&SHA256, self.id.as_bytes()).as_ref() {
+            return Err(WebAuthnError::RelyingParty);
+        }
+        if authenticator_data.flags & (USER_PRESENT | USER_VERIFIED) != USER_PRESENT | USER_VERIFIED
+        {
+            return Err(WebAuthnError::UserVerification);
+        }
+        Ok(())
+    }
+}
+
+/// Generates a new random challenge with a CSPRNG, which is base64url encoded like in the client data.
+pub fn generate_challenge() -> String {
+    let mut challenge = [0; CHALLENGE_LENGTH];
+    rand::thread_rng().fill_bytes(&mut challenge);
+    encode(&challenge)
+}
+
+/// Encodes bytes with base64url without padding, which is the encoding of binary fields in WebAuthn.
+pub fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+/// Decodes base64url with or without padding.
+pub fn decode(encoded: &str) -> Option<Vec<u8>> {
+    base64::decode_config(encoded.trim_end_matches('='), base64::URL_SAFE_NO_PAD).ok()
+}
+
+/// Deserializes a base64url encoded field.
+fn base64url<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let encoded = String::deserialize(deserializer)?;
+    decode(&encoded).ok_or_else(|| D::Error::custom("invalid base64url"))
+}
+
+/// The fields of the [client data](https://www.w3.org/TR/webauthn-2/#dictionary-client-data) that are verified.
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ceremony: String,
+    challenge: String,
+    origin: String,
+}
+
+/// The [authenticator data](https://www.w3.org/TR/webauthn-2/#sctn-authenticator-data) of a response.
+struct AuthenticatorData {
+    rp_id_hash: Vec<u8>,
+    flags: u8,
+    sign_count: u32,
+    /// The id and public key of a new credential
+    attested_credential: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl AuthenticatorData {
+    /// Parses the authenticator data, extensions after the credential are ignored.
+    fn parse(data: &[u8]) -> Result<Self, WebAuthnError> {
+        if data.len() < 37 {
+            return Err(WebAuthnError::Malformed);
+        }
+        let (rp_id_hash, data) = data.split_at(32);
+        let flags = data[0];
+        let sign_count = u32::from_be_bytes([data[1], data[2], data[3], data[4]]);
+        let data = &data[5..];
+
+        let attested_credential = if flags & ATTESTED_CREDENTIAL_DATA != 0 {
+            // The AAGUID of 16 bytes is followed by the length of the credential id
+            if data.len() < 18 {
+                return Err(WebAuthnError::Malformed);
+            }
+            let length = u16::from_be_bytes([data[16], data[17]]) as usize;
+            let data = &data[18..];
+            if length == 0 || length > MAX_CREDENTIAL_ID_LENGTH || data.len() < length {
+                return Err(WebAuthnError::Malformed);
+            }
+            let (id, public_key) = data.split_at(length);
+
+            // The COSE key is followed by the extensions, so only the first CBOR value is read
+            let mut deserializer = serde_cbor::Deserializer::from_slice(public_key);
+            let public_key =
+                Value::deserialize(&mut deserializer).map_err(|_| WebAuthnError::Malformed)?;
+            Some((id.to_vec(), es256_public_key(&public_key)?))
+        } else {
+            None
+        };
+
+        Ok(AuthenticatorData {
+            rp_id_hash: rp_id_hash.to_vec(),
+            flags,
+            sign_count,
+            attested_credential,
+        })
+    }
+}
+
+/// Converts an ES256 [COSE key](https://tools.ietf.org/html/rfc8152#section-13.1.1) into an uncompressed point.
+fn es256_public_key(key: &Value) -> Result<Vec<u8>, WebAuthnError> {
+    let key = match key {
+        Value::Map(key) => key,
+        _ => return Err(WebAuthnError::Malformed),
+    };
+    let parameter = |label: i128| key.get(&Value::Integer(label));
+
+    // Key type EC2, algorithm ES256 and curve P-256
+    match (parameter(1), parameter(3), parameter(-1)) {
+        (Some(Value::Integer(2)), Some(Value::Integer(COSE_ES256)), Some(Value::Integer(1))) => {}
+        _ => return Err(WebAuthnError::UnsupportedAlgorithm),
+    }
+    match (parameter(-2), parameter(-3)) {
+        (Some(Value::Bytes(x)), Some(Value::Bytes(y))) if x.len() == 32 && y.len() == 32 => {
+            Ok([&[0x04], x.as_slice(), y.as_slice()].concat())
+        }
+        _ => Err(WebAuthnError::Malformed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING};
+    use std::collections::BTreeMap;
+
+    const ORIGIN: &str = "https://example.com";
+
+    /// An authenticator that keeps a single ES256 credential in memory and signs every challenge.
+    struct SoftwareAuthenticator {
+        key_pair: EcdsaKeyPair,
+        credential_id: Vec<u8>,
+        sign_count: u32,
+        /// Whether the signature counter is implemented, otherwise it is always `0`
+        counter: bool,
+        /// The flags of the authenticator data, user presence and verification by default
+        flags: u8,
+    }
+
+    impl SoftwareAuthenticator {
+        fn new() -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 =
+                EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &rng).unwrap();
+
+            SoftwareAuthenticator {
+                key_pair: EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, pkcs8.as_ref())
+                    .unwrap(),
+                credential_id: decode(&generate_challenge()).unwrap(),
+                sign_count: 0,
+                counter: true,
+                flags: USER_PRESENT | USER_VERIFIED,
+            }
+        }
+
+        fn client_data(ceremony: &str, challenge: &str, origin: &str) -> Vec<u8> {
+            json!({ "type": ceremony, "challenge": challenge, "origin": origin, "crossOrigin": false })
+                .to_string()
+                .into_bytes()
+        }
+
+        fn authenticator_data(&self, rp_id: &str, flags: u8) -> Vec<u8> {
+            let mut data = digest(&SHA256, rp_id.as_bytes()).as_ref().to_vec();
+            data.push(flags);
+            data.extend_from_slice(&self.sign_count.to_be_bytes());
+            data
+        }
+
+        fn cose_key(&self) -> Value {
+            let point = self.key_pair.public_key().as_ref();
+            let mut key = BTreeMap::new();
+            key.insert(Value::Integer(1), Value::Integer(2));
+            key.insert(Value::Integer(3), Value::Integer(COSE_ES256));
+            key.insert(Value::Integer(-1), Value::Integer(1));
+            key.insert(Value::Integer(-2), Value::Bytes(point[1..33].to_vec()));
+            key.insert(Value::Integer(-3), Value::Bytes(point[33..].to_vec()));
+            Value::Map(key)
+        }
+
+        /// Creates the credential like `navigator.credentials.create` with the attestation format `none`.
+        fn create(&self, rp_id: &str, origin: &str, challenge: &str) -> RegistrationResponse {
+            let mut data = self.authenticator_data(rp_id, self.flags | ATTESTED_CREDENTIAL_DATA);
+            data.extend_from_slice(&[0; 16]);
+            data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+            data.extend_from_slice(&self.credential_id);
+            data.extend(serde_cbor::to_vec(&self.cose_key()).unwrap());
+
+            let mut attestation = BTreeMap::new();
+            attestation.insert(Value::Text("fmt".into()), Value::Text("none".into()));
+            attestation.insert(Value::Text("attStmt".into()), Value::Map(BTreeMap::new()));
+            attestation.insert(Value::Text("authData".into()), Value::Bytes(data));
+
+            RegistrationResponse {
+                client_data_json: Self::client_data("webauthn.create", challenge, origin),
+                attestation_object: serde_cbor::to_vec(&Value::Map(attestation)).unwrap(),
+            }
+        }
+
+        /// Signs the challenge like `navigator.credentials.get` and increases the signature counter.
+        fn get(&mut self, rp_id: &str, origin: &str, challenge: &str) -> AssertionResponse {
+            if self.counter {
+                self.sign_count += 1;
+            }
+            let client_data_json = Self::client_data("webauthn.get", challenge, origin);
+            let authenticator_data = self.authenticator_data(rp_id, self.flags);
+
+            let mut signed = authenticator_data.clone();
+            signed.extend_from_slice(digest(&SHA256, &client_data_json).as_ref());
+            let signature = self
+                .key_pair
+                .sign(&SystemRandom::new(), &signed)
+                .unwrap()
+                .as_ref()
+                .to_vec();
+
+            AssertionResponse {
+                credential_id: self.credential_id.clone(),
+                client_data_json,
+                authenticator_data,
+                signature,
+            }
+        }
+    }
+
+    fn relying_party() -> RelyingParty {
+        RelyingParty::new("example.com", ORIGIN, "Example")
+    }
+
+    /// Registers the authenticator and returns the stored credential.
+    fn register(authenticator: &SoftwareAuthenticator) -> Credential {
+        let challenge = generate_challenge();
+        let response = authenticator.create("example.com", ORIGIN, &challenge);
+        let (returned, credential) = relying_party().verify_registration(&response).unwrap();
+        assert_eq!(returned, challenge);
+        credential
+    }
+
+    #[test]
+    fn registration_and_assertion() {
+        let mut authenticator = SoftwareAuthenticator::new();
+        let mut credential = register(&authenticator);
+
+        assert_eq!(credential.id, authenticator.credential_id);
+        assert_eq!(
+            credential.public_key,
+            authenticator.key_pair.public_key().as_ref()
+        );
+        assert_eq!(credential.sign_count, 0);
+
+        for sign_count in 1..=2 {
+            let challenge = generate_challenge();
+            let response = authenticator.get("example.com", ORIGIN, &challenge);
+            assert_eq!(
+                relying_party().verify_assertion(&response, &credential),
+                Ok((challenge, sign_count))
+            );
+            credential.sign_count = sign_count;
+        }
+    }
+
+    #[test]
+    fn signature_counter() {
+        let mut authenticator = SoftwareAuthenticator::new();
+        let mut credential = register(&authenticator);
+        let response = authenticator.get("example.com", ORIGIN, &generate_challenge());
+
+        // A cloned authenticator reuses a counter that has already been seen
+        credential.sign_count = 1;
+        assert_eq!(
+            relying_party().verify_assertion(&response, &credential),
+            Err(WebAuthnError::CounterRegression)
+        );
+
+        // Authenticators without a counter always return 0
+        authenticator.counter = false;
+        authenticator.sign_count = 0;
+        let mut response = authenticator.get("example.com", ORIGIN, &generate_challenge());
+        credential.sign_count = 0;
+        assert!(relying_party()
+            .verify_assertion(&response, &credential)
+            .is_ok());
+
+        response.signature[10] ^= 1;
+        assert_eq!(
+            relying_party().verify_assertion(&response, &credential),
+            Err(WebAuthnError::Signature)
+        );
+    }
+
+    #[test]
+    fn phishing_and_misuse() {
+        let mut authenticator = SoftwareAuthenticator::new();
+        let credential = register(&authenticator);
+        let challenge = generate_challenge();
+        let rp = relying_party();
+
+        let response = authenticator.get("example.com", "https://example.org", &challenge);
+        assert_eq!(
+            rp.verify_assertion(&response, &credential),
+            Err(WebAuthnError::Origin)
+        );
+        let response = authenticator.get("example.org", ORIGIN, &challenge);
+        assert_eq!(
+            rp.verify_assertion(&response, &credential),
+            Err(WebAuthnError::RelyingParty)
+        );
+
+        // A registration response can not be used to login and vice versa
+        let registration = authenticator.create("example.com", ORIGIN, &challenge);
+        let mut response = authenticator.get("example.com", ORIGIN, &challenge);
+        response.client_data_json = registration.client_data_json;
+        assert_eq!(
+            rp.verify_assertion(&response, &credential),
+            Err(WebAuthnError::Ceremony)
+        );
+
+        authenticator.flags = USER_PRESENT;
+        let response = authenticator.get("example.com", ORIGIN, &challenge);
+        assert_eq!(
+            rp.verify_assertion(&response, &credential),
+            Err(WebAuthnError::UserVerification)
+        );
+        assert_eq!(
+            rp.verify_registration(&authenticator.create("example.com", ORIGIN, &challenge)),
+            Err(WebAuthnError::UserVerification)
+        );
+    }
+
+    #[test]
+    fn unsupported_attestation() {
+        let authenticator = SoftwareAuthenticator::new();
+        let mut response = authenticator.create("example.com", ORIGIN, &generate_challenge());
+
+        let mut attestation = match serde_cbor::from_slice(&response.attestation_object).unwrap() {
+            Value::Map(attestation) => attestation,
+            _ => unreachable!(),
+        };
+        attestation.insert(Value::Text("fmt".into()), Value::Text("packed".into()));
+        response.attestation_object = serde_cbor::to_vec(&Value::Map(attestation)).unwrap();
+
+        assert_eq!(
+            relying_party().verify_registration(&response),
+            Err(WebAuthnError::UnsupportedAttestation)
+        );
+
+        response.attestation_object = b"not cbor".to_vec();
+        assert_eq!(
+            relying_party().verify_registration(&response),
+            Err(WebAuthnError::Malformed)
+        );
+    }
+
+    #[test]
+    fn options_and_responses() {
+        let options: serde_json::Value =
+            serde_json::from_str(&relying_party().creation_options("challenge", "jean-luc"))
+                .unwrap();
+        assert_eq!(options["publicKey"]["rp"]["id"], "example.com");
+        assert_eq!(options["publicKey"]["challenge"], "challenge");
+        assert_eq!(options["publicKey"]["pubKeyCredParams"][0]["alg"], -7);
+        assert_eq!(options["publicKey"]["user"]["name"], "jean-luc");
+        assert_eq!(
+            decode(options["publicKey"]["user"]["id"].as_str().unwrap())
+                .unwrap()
+                .len(),
+            32
+        );
+
+        let response: AssertionResponse = serde_json::from_str(
+            r#"{"id": "AQI", "clientDataJSON": "e30=", "authenticatorData": "", "signature": "_-8"}"#,
+        )
+        .unwrap();
+        assert_eq!(response.credential_id, vec![1, 2]);
+        assert_eq!(response.client_data_json, b"{}");
+        assert_eq!(response.signature, vec![0xff, 0xef]);
+        assert!(serde_json::from_str::<RegistrationResponse>(
+            r#"{"clientDataJSON": "not base64!", "attestationObject": ""}"#
+        )
+        .is_err());
+    }
+}
@@ -44,7 +44,7 @@ function database_command {
 }
 
 function database_add_user {
-    database_command "INSERT INTO users (username, password, registration_date) VALUES ('$1', crypt('$2', gen_salt('bf')), NOW());"
+    database_command "INSERT INTO users (username, password_hash, registration_date) VALUES ('$1', crypt('$2', gen_salt('bf')), NOW());"
 }
 
 function database_add_session {
@@ -55,13 +55,37 @@ function database_add_capability {
     database_command "INSERT INTO capabilities (user_id, label) VALUES ($1, '$2');"
 }
 
+function database_add_role {
+    database_command "INSERT INTO roles (name) VALUES ('$1');"
+}
+
+function database_add_role_capability {
+    database_command "INSERT INTO role_capabilities (role_id, label) SELECT role_id, '$2' FROM roles WHERE name = '$1';"
+}
+
+function database_add_role_parent {
+    database_command "INSERT INTO role_parents (role_id, parent_id) SELECT role.role_id, parent.role_id FROM roles role, roles parent WHERE role.name = '$1' AND parent.name = '$2';"
+}
+
+function database_add_user_role {
+    database_command "INSERT INTO user_roles (user_id, role_id) SELECT $1, role_id FROM roles WHERE name = '$2';"
+}
+
+function database_add_grant {
+    database_command "INSERT INTO grants (user_id, action, resource_type, resource_id) VALUES ($1, '$2', '$3', '$4');"
+}
+
+function database_add_attribute {
+    database_command "INSERT INTO user_attributes (user_id, name, value) VALUES ($1, '$2', '$3');"
+}
+
 function list_exipired_sessions {
     database_command "select * from sessions WHERE expiration_date < NOW();"
 }
 
 # Normally you would check username AND password
 function select_user_by_password {
-    database_command "select * from users where password = crypt('$1', password);"
+    database_command "select * from users where password_hash = crypt('$1', password_hash);"
 }
 
 if [ $# == 0 ] || [ "$1" == "testenv" ] && [ "$2" == "restart" ]; then
@@ -98,6 +122,30 @@ elif [ "$1" == "insert" ] && [ "$2" == "cap" ] && [ $# == 4 ]; then
     echo "Inserting capability for user with user_id $3"
     database_add_capability "$3" "$4"
 
+elif [ "$1" == "insert" ] && [ "$2" == "role" ] && [ $# == 3 ]; then
+    echo "Inserting role $3"
+    database_add_role "$3"
+
+elif [ "$1" == "insert" ] && [ "$2" == "role-cap" ] && [ $# == 4 ]; then
+    echo "Inserting capability $4 for role $3"
+    database_add_role_capability "$3" "$4"
+
+elif [ "$1" == "insert" ] && [ "$2" == "role-parent" ] && [ $# == 4 ]; then
+    echo "Inserting parent $4 for role $3"
+    database_add_role_parent "$3" "$4"
+
+elif [ "$1" == "insert" ] && [ "$2" == "user-role" ] && [ $# == 4 ]; then
+    echo "Inserting role $4 for user with user_id $3"
+    database_add_user_role "$3" "$4"
+
+elif [ "$1" == "insert" ] && [ "$2" == "grant" ] && [ $# == 6 ]; then
+    echo "Inserting grant to $4 $5 $6 for user with user_id $3"
+    database_add_grant "$3" "$4" "$5" "$6"
+
+elif [ "$1" == "insert" ] && [ "$2" == "attribute" ] && [ $# == 5 ]; then
+    echo "Inserting attribute $4 = $5 for user with user_id $3"
+    database_add_attribute "$3" "$4" "$5"
+
 elif [ "$1" == "list" ] && [ "$2" == "expired" ]; then
     echo "Listing expired sessions"
     list_exipired_sessions
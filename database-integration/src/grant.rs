@@ -0,0 +1,128 @@
+use crate::user::User;
+use sqlx::postgres::PgDone;
+use sqlx::PgPool;
+
+/// The [`SELECT_GRANT`] constant describes the query to check whether a grant exists.
+///
+/// A grant with the `resource_id` `*` applies to every resource of the type.
+const SELECT_GRANT: &str = "SELECT EXISTS (
+    SELECT 1 FROM grants
+    WHERE user_id = $1 AND action = $2 AND resource_type = $3 AND resource_id IN ($4, '*')
+);";
+
+/// The [`INSERT_GRANT`] constant describes the query to grant an action on a resource to a user.
+const INSERT_GRANT: &str =
+    "INSERT INTO grants (user_id, action, resource_type, resource_id) VALUES ($1, $2, $3, $4);";
+
+/// The [`DELETE_GRANT`] constant describes the query to revoke an action on a resource from a user.
+const DELETE_GRANT: &str = "DELETE FROM grants
+    WHERE user_id = $1 AND action = $2 AND resource_type = $3 AND resource_id = $4;";
+
+/// Tries to check whether the user has been granted the action on the resource by running the `SELECT_GRANT` query.
+///
+/// # Table structure
+/// ```sql
+/// CREATE TABLE IF NOT EXISTS grants (
+///   user_id INTEGER NOT NULL,
+///   action TEXT NOT NULL,
+///   resource_type TEXT NOT NULL,
+///   resource_id TEXT NOT NULL,
+///   CONSTRAINT fk_user FOREIGN KEY(user_id) REFERENCES users(user_id),
+///   PRIMARY KEY (user_id, action, resource_type, resource_id)
+/// );
+/// ```
+pub async fn has_grant(
+    connection: &PgPool,
+    user: &User,
+    action: &str,
+    resource_type: &str,
+    resource_id: &str,
+) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar(SELECT_GRANT)
+        .bind(user.user_id)
+        .bind(action)
+        .bind(resource_type)
+        .bind(resource_id)
+        .fetch_one(connection)
+        .await
+}
+
+/// Tries to grant the action on the resource to the user by running the `INSERT_GRANT` query.
+///
+/// Use `*` as `resource_id` to grant the action on every resource of the type.
+/// The query may fail if the connection to postgres is down or the grant already exists.
+pub async fn grant(
+    connection: &PgPool,
+    user: &User,
+    action: &str,
+    resource_type: &str,
+    resource_id: &str,
+) -> Result<PgDone, sqlx::Error> {
+    sqlx::query(INSERT_GRANT)
+        .bind(user.user_id)
+        .bind(action)
+        .bind(resource_type)
+        .bind(resource_id)
+        .execute(connection)
+        .await
+}
+
+/// Tries to revoke the action on the resource from the user by running the `DELETE_GRANT` query.
+pub async fn revoke(
+    connection: &PgPool,
+    user: &User,
+    action: &str,
+    resource_type: &str,
+    resource_id: &str,
+) -> Result<PgDone, sqlx::Error> {
+    sqlx::query(DELETE_GRANT)
+        .bind(user.user_id)
+        .bind(action)
+        .bind(resource_type)
+        .bind(resource_id)
+        .execute(connection)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::create_db_pool;
+    use chrono::Utc;
+
+    #[ignore = "Needs database to run"]
+    #[actix_rt::test]
+    /// Tries to grant, check and revoke an action on a single resource and on every resource of a type.
+    async fn grant_check_revoke() {
+        let username = format!("{}_grants", Utc::now()).replace(" ", "");
+        let pool = create_db_pool().await.unwrap();
+
+        User::register_user(&pool, &username, "password_hash")
+            .await
+            .unwrap();
+        let user = User::look_up_user(&pool, &username).await.unwrap();
+
+        grant(&pool, &user, "edit", "document", "42").await.unwrap();
+        assert!(has_grant(&pool, &user, "edit", "document", "42")
+            .await
+            .unwrap());
+        assert!(!has_grant(&pool, &user, "edit", "document", "43")
+            .await
+            .unwrap());
+        assert!(!has_grant(&pool, &user, "delete", "document", "42")
+            .await
+            .unwrap());
+
+        grant(&pool, &user, "read", "document", "*").await.unwrap();
+        assert!(has_grant(&pool, &user, "read", "document", "43")
+            .await
+            .unwrap());
+
+        revoke(&pool, &user, "edit", "document", "42")
+            .await
+            .unwrap();
+        assert!(!has_grant(&pool, &user, "edit", "document", "42")
+            .await
+            .unwrap());
+    }
+}
@@ -6,20 +6,47 @@
 //! The [`Backend`] trait is designed to take in an implementation of the [`access_control::User`] trait, when being implemented.
 //! The [`access_control::User`] for the [`PostgreSqlBackend`] is provided by [`user::User`].
 //!
+//! Capabilities are either granted to a user directly or bundled into roles, which are managed by the [`role`] module.
+//! The passkeys of users are stored by the [`webauthn`] module.
+//! Failed logins are counted by the [`throttling`] module, so that the throttling survives restarts.
+//! The remember-me series of users are stored by the [`remember`] module.
+//! Users can list and revoke their sessions with the functions of the [`session`] module.
+//!
 //! Additionally, the [`utility`] module provides functions to interact with the `PostgreSql` database in a more general fashion.
 //! Currently there is just the [`utility::create_db_pool`] function which is used to create a database pool.
 //! This function is currently used in most tests in the [`user`] modules as well as in the main function.
 
+/// Grants of actions on single resources, which are used for resource-scoped authorization.
+pub mod grant;
+/// Hashes of the single-use recovery codes of users.
+pub mod recovery;
+/// Remember-me series of users, which only store the hash of their current validator.
+pub mod remember;
+/// Management of roles, which bundle capabilities and can inherit from other roles.
+///
+/// The roles of a user are expanded into capabilities when a [`user::User`] is looked up.
+pub mod role;
+/// Listing and revocation of the sessions of a user, which are stored by [`user::User::store_session`].
+pub mod session;
+/// Failed login attempts of accounts and client IPs, which are used to throttle the login.
+pub mod throttling;
 /// Implementation of the database user, which the `PostgreSqlBackend` uses.
 ///
 /// This includes all of the necessary requests to the PostgreSql database to handle users and their sessions.
 pub mod user;
 /// Utility functions used to work with the PostgreSql database.
 pub mod utility;
+/// WebAuthn challenges and the credentials of users, e.g. passkeys.
+pub mod webauthn;
 
+use access_control::remember::RememberSeries;
+use access_control::session::{SessionInfo, SessionLifetime, SessionMetadata};
+use access_control::throttling::FailedAttempts;
+use access_control::webauthn::Credential;
 use access_control::{Backend, FutureOption, FutureResult};
 use sqlx::PgPool;
 use std::error;
+use std::time::{Duration, SystemTime};
 
 /// PostgreSQL implementation of the [`Backend`] trait for [`user::User`].
 ///
@@ -74,19 +101,106 @@ impl Backend for PostgreSqlBackend {
         })
     }
 
-    fn store_session(&self, user: &user::User, session_id: impl AsRef<str>) -> FutureResult<()> {
+    fn update_password_hash(
+        &self,
+        user: &user::User,
+        password_hash: impl AsRef<str>,
+    ) -> FutureResult<()> {
+        let db = self.db.clone();
+        let user = user.clone();
+        let password_hash = password_hash.as_ref().to_string();
+
+        Box::pin(async move {
+            user::User::update_password_hash(&db, &user, &password_hash)
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn error::Error>)?;
+            Ok(())
+        })
+    }
+
+    fn store_session(
+        &self,
+        user: &user::User,
+        session_id: impl AsRef<str>,
+        lifetime: &SessionLifetime,
+        metadata: &SessionMetadata,
+    ) -> FutureResult<()> {
+        let db = self.db.clone();
+        let user = user.clone();
+        let session_id = session_id.as_ref().to_string();
+        let lifetime = *lifetime;
+        let metadata = metadata.clone();
+
+        Box::pin(async move {
+            user::User::store_session(&db, &user, &session_id, &lifetime, &metadata)
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn error::Error>)?;
+            Ok(())
+        })
+    }
+
+    fn touch_session(
+        &self,
+        session_id: impl AsRef<str>,
+        idle_timeout: Duration,
+    ) -> FutureOption<SystemTime> {
+        let db = self.db.clone();
+        let session_id = session_id.as_ref().to_string();
+
+        Box::pin(async move {
+            user::User::touch_session(&db, &session_id, idle_timeout)
+                .await
+                .ok()
+                .flatten()
+                .map(SystemTime::from)
+        })
+    }
+
+    fn store_pending_session(
+        &self,
+        user: &user::User,
+        session_id: impl AsRef<str>,
+        remember: bool,
+    ) -> FutureResult<()> {
         let db = self.db.clone();
         let user = user.clone();
         let session_id = session_id.as_ref().to_string();
 
         Box::pin(async move {
-            user::User::store_session(&db, &user, &session_id)
+            user::User::store_pending_session(&db, &user, &session_id, remember)
                 .await
                 .map_err(|e| Box::new(e) as Box<dyn error::Error>)?;
             Ok(())
         })
     }
 
+    fn get_user_from_pending_session(
+        &self,
+        session_id: impl AsRef<str>,
+    ) -> FutureOption<(user::User, bool)> {
+        let db = self.db.clone();
+        let session_id = session_id.as_ref().to_string();
+
+        Box::pin(async move {
+            user::User::look_up_user_from_pending_session(&db, &session_id)
+                .await
+                .ok()
+        })
+    }
+
+    fn record_pending_attempt(&self, session_id: impl AsRef<str>) -> FutureOption<u32> {
+        let db = self.db.clone();
+        let session_id = session_id.as_ref().to_string();
+
+        Box::pin(async move {
+            user::User::record_pending_attempt(&db, &session_id)
+                .await
+                .ok()
+                .flatten()
+                .map(|attempts| attempts.max(0) as u32)
+        })
+    }
+
     fn remove_session(&self, session_id: impl AsRef<str>) -> FutureResult<()> {
         let db = self.db.clone();
         let session_id = session_id.as_ref().to_string();
@@ -98,4 +212,313 @@ impl Backend for PostgreSqlBackend {
             Ok(())
         })
     }
+
+    fn get_sessions(
+        &self,
+        user: &user::User,
+        current_session_id: impl AsRef<str>,
+    ) -> FutureResult<Vec<SessionInfo>> {
+        let db = self.db.clone();
+        let user = user.clone();
+        let current_session_id = current_session_id.as_ref().to_string();
+
+        Box::pin(async move {
+            session::sessions(&db, &user, &current_session_id)
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn error::Error>)
+        })
+    }
+
+    fn revoke_session(&self, user: &user::User, id: impl AsRef<str>) -> FutureResult<bool> {
+        let db = self.db.clone();
+        let user = user.clone();
+        let id = id.as_ref().to_string();
+
+        Box::pin(async move {
+            session::revoke_session(&db, &user, &id)
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn error::Error>)
+        })
+    }
+
+    fn revoke_other_sessions(
+        &self,
+        user: &user::User,
+        current_session_id: impl AsRef<str>,
+        remember_selector: Option<&str>,
+    ) -> FutureResult<()> {
+        let db = self.db.clone();
+        let user = user.clone();
+        let current_session_id = current_session_id.as_ref().to_string();
+        let remember_selector = remember_selector.map(str::to_string);
+
+        Box::pin(async move {
+            session::revoke_other_sessions(
+                &db,
+                &user,
+                &current_session_id,
+                remember_selector.as_deref(),
+            )
+            .await
+            .map_err(|e| Box::new(e) as Box<dyn error::Error>)
+        })
+    }
+
+    fn has_grant(
+        &self,
+        user: &user::User,
+        action: impl AsRef<str>,
+        resource_type: impl AsRef<str>,
+        resource_id: impl AsRef<str>,
+    ) -> FutureResult<bool> {
+        let db = self.db.clone();
+        let user = user.clone();
+        let action = action.as_ref().to_string();
+        let resource_type = resource_type.as_ref().to_string();
+        let resource_id = resource_id.as_ref().to_string();
+
+        Box::pin(async move {
+            grant::has_grant(&db, &user, &action, &resource_type, &resource_id)
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn error::Error>)
+        })
+    }
+
+    fn store_totp_secret(&self, user: &user::User, secret: Option<&str>) -> FutureResult<()> {
+        let db = self.db.clone();
+        let user = user.clone();
+        let secret = secret.map(str::to_string);
+
+        Box::pin(async move {
+            user::User::update_totp_secret(&db, &user, secret.as_deref())
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn error::Error>)?;
+            Ok(())
+        })
+    }
+
+    fn use_totp_step(&self, user: &user::User, step: u64) -> FutureResult<bool> {
+        let db = self.db.clone();
+        let user = user.clone();
+
+        Box::pin(async move {
+            user::User::use_totp_step(&db, &user, step)
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn error::Error>)
+        })
+    }
+
+    fn store_recovery_codes(&self, user: &user::User, code_hashes: &[String]) -> FutureResult<()> {
+        let db = self.db.clone();
+        let user = user.clone();
+        let code_hashes = code_hashes.to_vec();
+
+        Box::pin(async move {
+            recovery::replace_recovery_codes(&db, &user, &code_hashes)
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn error::Error>)
+        })
+    }
+
+    fn get_recovery_codes(&self, user: &user::User) -> FutureResult<Vec<String>> {
+        let db = self.db.clone();
+        let user = user.clone();
+
+        Box::pin(async move {
+            recovery::recovery_codes(&db, &user)
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn error::Error>)
+        })
+    }
+
+    fn use_recovery_code(
+        &self,
+        user: &user::User,
+        code_hash: impl AsRef<str>,
+    ) -> FutureResult<bool> {
+        let db = self.db.clone();
+        let user = user.clone();
+        let code_hash = code_hash.as_ref().to_string();
+
+        Box::pin(async move {
+            recovery::use_recovery_code(&db, &user, &code_hash)
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn error::Error>)
+        })
+    }
+
+    fn store_webauthn_challenge(
+        &self,
+        user: Option<&user::User>,
+        challenge: impl AsRef<str>,
+    ) -> FutureResult<()> {
+        let db = self.db.clone();
+        let user = user.cloned();
+        let challenge = challenge.as_ref().to_string();
+
+        Box::pin(async move {
+            webauthn::store_challenge(&db, user.as_ref(), &challenge)
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn error::Error>)?;
+            Ok(())
+        })
+    }
+
+    fn take_webauthn_challenge(
+        &self,
+        user: Option<&user::User>,
+        challenge: impl AsRef<str>,
+    ) -> FutureResult<bool> {
+        let db = self.db.clone();
+        let user = user.cloned();
+        let challenge = challenge.as_ref().to_string();
+
+        Box::pin(async move {
+            webauthn::take_challenge(&db, user.as_ref(), &challenge)
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn error::Error>)
+        })
+    }
+
+    fn store_webauthn_credential(
+        &self,
+        user: &user::User,
+        credential: &Credential,
+    ) -> FutureResult<()> {
+        let db = self.db.clone();
+        let user = user.clone();
+        let credential = credential.clone();
+
+        Box::pin(async move {
+            webauthn::store_credential(&db, &user, &credential)
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn error::Error>)?;
+            Ok(())
+        })
+    }
+
+    fn get_webauthn_credential(
+        &self,
+        credential_id: &[u8],
+    ) -> FutureOption<(user::User, Credential)> {
+        let db = self.db.clone();
+        let credential_id = credential_id.to_vec();
+
+        Box::pin(async move { webauthn::look_up_credential(&db, &credential_id).await.ok() })
+    }
+
+    fn update_webauthn_sign_count(
+        &self,
+        credential_id: &[u8],
+        sign_count: u32,
+    ) -> FutureResult<bool> {
+        let db = self.db.clone();
+        let credential_id = credential_id.to_vec();
+
+        Box::pin(async move {
+            webauthn::update_sign_count(&db, &credential_id, sign_count)
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn error::Error>)
+        })
+    }
+
+    fn store_remember_token(
+        &self,
+        user: &user::User,
+        selector: impl AsRef<str>,
+        validator_hash: impl AsRef<str>,
+        lifetime: Duration,
+    ) -> FutureResult<()> {
+        let db = self.db.clone();
+        let user = user.clone();
+        let selector = selector.as_ref().to_string();
+        let validator_hash = validator_hash.as_ref().to_string();
+
+        Box::pin(async move {
+            remember::store_remember_token(&db, &user, &selector, &validator_hash, lifetime)
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn error::Error>)?;
+            Ok(())
+        })
+    }
+
+    fn get_remember_token(
+        &self,
+        selector: impl AsRef<str>,
+    ) -> FutureOption<(user::User, RememberSeries)> {
+        let db = self.db.clone();
+        let selector = selector.as_ref().to_string();
+
+        Box::pin(async move { remember::look_up_remember_token(&db, &selector).await.ok() })
+    }
+
+    fn rotate_remember_token(
+        &self,
+        selector: impl AsRef<str>,
+        old_hash: impl AsRef<str>,
+        new_hash: impl AsRef<str>,
+        lifetime: Duration,
+    ) -> FutureResult<bool> {
+        let db = self.db.clone();
+        let selector = selector.as_ref().to_string();
+        let old_hash = old_hash.as_ref().to_string();
+        let new_hash = new_hash.as_ref().to_string();
+
+        Box::pin(async move {
+            remember::rotate_remember_token(&db, &selector, &old_hash, &new_hash, lifetime)
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn error::Error>)
+        })
+    }
+
+    fn remove_remember_token(&self, selector: impl AsRef<str>) -> FutureResult<()> {
+        let db = self.db.clone();
+        let selector = selector.as_ref().to_string();
+
+        Box::pin(async move {
+            remember::remove_remember_token(&db, &selector)
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn error::Error>)?;
+            Ok(())
+        })
+    }
+
+    fn get_failed_attempts(&self, key: impl AsRef<str>) -> FutureResult<Option<FailedAttempts>> {
+        let db = self.db.clone();
+        let key = key.as_ref().to_string();
+
+        Box::pin(async move {
+            throttling::failed_attempts(&db, &key)
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn error::Error>)
+        })
+    }
+
+    fn record_failed_attempt(
+        &self,
+        key: impl AsRef<str>,
+        reset_after: Duration,
+    ) -> FutureResult<()> {
+        let db = self.db.clone();
+        let key = key.as_ref().to_string();
+
+        Box::pin(async move {
+            throttling::record_failed_attempt(&db, &key, reset_after)
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn error::Error>)?;
+            Ok(())
+        })
+    }
+
+    fn reset_failed_attempts(&self, key: impl AsRef<str>) -> FutureResult<()> {
+        let db = self.db.clone();
+        let key = key.as_ref().to_string();
+
+        Box::pin(async move {
+            throttling::reset_failed_attempts(&db, &key)
+                .await
+                .map_err(|e| Box::new(e) as Box<dyn error::Error>)?;
+            Ok(())
+        })
+    }
 }
@@ -0,0 +1,106 @@
+use crate::user::User;
+use sqlx::{Done, PgPool};
+
+/// The [`DELETE_RECOVERY_CODES`] constant describes the query to delete every recovery code of a user.
+const DELETE_RECOVERY_CODES: &str = "DELETE FROM recovery_codes WHERE user_id = $1;";
+
+/// The [`INSERT_RECOVERY_CODE`] constant describes the query to insert the hash of a recovery code of a user.
+const INSERT_RECOVERY_CODE: &str =
+    "INSERT INTO recovery_codes (user_id, code_hash) VALUES ($1, $2);";
+
+/// The [`SELECT_RECOVERY_CODES`] constant describes the query to select the hashes of the recovery codes of a user.
+const SELECT_RECOVERY_CODES: &str = "SELECT code_hash FROM recovery_codes WHERE user_id = $1;";
+
+/// The [`DELETE_RECOVERY_CODE`] constant describes the query to delete a used recovery code by its hash.
+const DELETE_RECOVERY_CODE: &str =
+    "DELETE FROM recovery_codes WHERE user_id = $1 AND code_hash = $2;";
+
+/// Tries to replace the recovery codes of a user with the new hashes in a transaction.
+///
+/// Either every old code is deleted and every new code inserted, or nothing changes.
+///
+/// # Table structure
+/// ```sql
+/// CREATE TABLE IF NOT EXISTS recovery_codes (
+///   user_id INTEGER NOT NULL,
+///   code_hash TEXT NOT NULL,
+///   CONSTRAINT fk_user FOREIGN KEY(user_id) REFERENCES users(user_id),
+///   PRIMARY KEY (user_id, code_hash)
+/// );
+/// ```
+pub async fn replace_recovery_codes(
+    connection: &PgPool,
+    user: &User,
+    code_hashes: &[String],
+) -> Result<(), sqlx::Error> {
+    let mut transaction = connection.begin().await?;
+
+    sqlx::query(DELETE_RECOVERY_CODES)
+        .bind(user.user_id)
+        .execute(&mut transaction)
+        .await?;
+    for code_hash in code_hashes {
+        sqlx::query(INSERT_RECOVERY_CODE)
+            .bind(user.user_id)
+            .bind(code_hash)
+            .execute(&mut transaction)
+            .await?;
+    }
+
+    transaction.commit().await
+}
+
+/// Tries to select the hashes of the unused recovery codes of a user by running the `SELECT_RECOVERY_CODES` query.
+pub async fn recovery_codes(connection: &PgPool, user: &User) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar(SELECT_RECOVERY_CODES)
+        .bind(user.user_id)
+        .fetch_all(connection)
+        .await
+}
+
+/// Tries to consume a recovery code by running the `DELETE_RECOVERY_CODE` query.
+///
+/// Returns `false` if the code does not exist, e.g. because it has already been used.
+pub async fn use_recovery_code(
+    connection: &PgPool,
+    user: &User,
+    code_hash: &str,
+) -> Result<bool, sqlx::Error> {
+    let done = sqlx::query(DELETE_RECOVERY_CODE)
+        .bind(user.user_id)
+        .bind(code_hash)
+        .execute(connection)
+        .await?;
+    Ok(done.rows_affected() == 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::create_db_pool;
+    use chrono::Utc;
+
+    #[ignore = "Needs database to run"]
+    #[actix_rt::test]
+    /// Makes sure a recovery code is only used once and a new batch replaces the old one.
+    async fn use_and_replace() {
+        let username = format!("{}_recovery", Utc::now()).replace(" ", "");
+        let pool = create_db_pool().await.unwrap();
+
+        User::register_user(&pool, &username, "password_hash")
+            .await
+            .unwrap();
+        let user = User::look_up_user(&pool, &username).await.unwrap();
+
+        let old = vec!["old_1".to_string(), "old_2".to_string()];
+        replace_recovery_codes(&pool, &user, &old).await.unwrap();
+        assert!(use_recovery_code(&pool, &user, "old_1").await.unwrap());
+        assert!(!use_recovery_code(&pool, &user, "old_1").await.unwrap());
+        assert_eq!(recovery_codes(&pool, &user).await.unwrap(), vec!["old_2"]);
+
+        let new = vec!["new_1".to_string()];
+        replace_recovery_codes(&pool, &user, &new).await.unwrap();
+        assert!(!use_recovery_code(&pool, &user, "old_2").await.unwrap());
+        assert_eq!(recovery_codes(&pool, &user).await.unwrap(), new);
+    }
+}
@@ -0,0 +1,168 @@
+use crate::user::User;
+use access_control::remember::RememberSeries;
+use chrono::{DateTime, Utc};
+use sqlx::postgres::PgDone;
+use sqlx::{Done, FromRow, PgPool};
+use std::time::{Duration, SystemTime};
+
+/// The [`INSERT_REMEMBER_TOKEN`] constant describes the query to insert a remember-me series, which expires after `$4`
+/// seconds.
+const INSERT_REMEMBER_TOKEN: &str =
+    "INSERT INTO remember_tokens (selector, user_id, validator_hash, expiration_date)
+    VALUES ($1, $2, $3, NOW() + $4::FLOAT8 * INTERVAL '1 second');";
+
+/// The [`SELECT_REMEMBER_TOKEN`] constant describes the query to select a [`DbRememberToken`] and the name of its user
+/// by the `selector`, if the series has not expired.
+const SELECT_REMEMBER_TOKEN: &str =
+    "SELECT username, validator_hash, previous_validator_hash, rotation_date
+    FROM remember_tokens JOIN users USING (user_id) WHERE selector = $1 AND expiration_date > NOW();";
+
+/// The [`UPDATE_REMEMBER_TOKEN`] constant describes the query to replace the validator hash of a series and renew it
+/// for `$4` seconds.
+///
+/// The row is only updated if the validator hash is still `$2`. The replaced hash is kept together with the time of
+/// the rotation, so parallel requests with the old token are not mistaken for a theft.
+const UPDATE_REMEMBER_TOKEN: &str = "UPDATE remember_tokens
+    SET previous_validator_hash = validator_hash, validator_hash = $3, rotation_date = NOW(), expiration_date = NOW() + $4::FLOAT8 * INTERVAL '1 second'
+    WHERE selector = $1 AND validator_hash = $2 AND expiration_date > NOW();";
+
+/// The [`DELETE_REMEMBER_TOKEN`] constant describes the query to delete a remember-me series by its `selector`.
+const DELETE_REMEMBER_TOKEN: &str = "DELETE FROM remember_tokens WHERE selector = $1;";
+
+/// The [`DbRememberToken`] struct represents the remember_tokens table in the database.
+///
+/// # Table structure
+/// ```sql
+/// CREATE TABLE IF NOT EXISTS remember_tokens (
+///   selector TEXT PRIMARY KEY,
+///   user_id INTEGER NOT NULL,
+///   validator_hash TEXT NOT NULL,
+///   previous_validator_hash TEXT,
+///   rotation_date TIMESTAMPTZ,
+///   expiration_date TIMESTAMPTZ NOT NULL,
+///   CONSTRAINT fk_user FOREIGN KEY(user_id) REFERENCES users(user_id)
+/// );
+/// ```
+#[derive(Debug, Clone, FromRow)]
+struct DbRememberToken {
+    username: String,
+    validator_hash: String,
+    previous_validator_hash: Option<String>,
+    rotation_date: Option<DateTime<Utc>>,
+}
+
+/// Tries to store a new remember-me series of the user by running the `INSERT_REMEMBER_TOKEN` query.
+pub async fn store_remember_token(
+    connection: &PgPool,
+    user: &User,
+    selector: &str,
+    validator_hash: &str,
+    lifetime: Duration,
+) -> Result<PgDone, sqlx::Error> {
+    sqlx::query(INSERT_REMEMBER_TOKEN)
+        .bind(selector)
+        .bind(user.user_id)
+        .bind(validator_hash)
+        .bind(lifetime.as_secs_f64())
+        .execute(connection)
+        .await
+}
+
+/// Tries to look up the [`User`] and the [`RememberSeries`] by running the `SELECT_REMEMBER_TOKEN` query.
+pub async fn look_up_remember_token(
+    connection: &PgPool,
+    selector: &str,
+) -> Result<(User, RememberSeries), sqlx::Error> {
+    let token = sqlx::query_as::<_, DbRememberToken>(SELECT_REMEMBER_TOKEN)
+        .bind(selector)
+        .fetch_one(connection)
+        .await?;
+    let user = User::look_up_user(connection, &token.username).await?;
+
+    let series = RememberSeries {
+        validator_hash: token.validator_hash,
+        previous_validator_hash: token.previous_validator_hash,
+        rotated: token.rotation_date.map(SystemTime::from),
+    };
+
+    Ok((user, series))
+}
+
+/// Tries to replace the validator hash of a series by running the `UPDATE_REMEMBER_TOKEN` query.
+///
+/// Returns `false` if the validator hash has already been replaced or the series has expired.
+pub async fn rotate_remember_token(
+    connection: &PgPool,
+    selector: &str,
+    old_hash: &str,
+    new_hash: &str,
+    lifetime: Duration,
+) -> Result<bool, sqlx::Error> {
+    let done = sqlx::query(UPDATE_REMEMBER_TOKEN)
+        .bind(selector)
+        .bind(old_hash)
+        .bind(new_hash)
+        .bind(lifetime.as_secs_f64())
+        .execute(connection)
+        .await?;
+    Ok(done.rows_affected() == 1)
+}
+
+/// Tries to remove a remember-me series by running the `DELETE_REMEMBER_TOKEN` query.
+pub async fn remove_remember_token(
+    connection: &PgPool,
+    selector: &str,
+) -> Result<PgDone, sqlx::Error> {
+    sqlx::query(DELETE_REMEMBER_TOKEN)
+        .bind(selector)
+        .execute(connection)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::create_db_pool;
+    use chrono::Utc;
+
+    #[ignore = "Needs database to run"]
+    #[actix_rt::test]
+    /// Makes sure a validator hash is only replaced once, the replaced hash is kept and a removed series can not be looked up.
+    async fn rotate_and_remove() {
+        let username = format!("{}_remember", Utc::now()).replace(" ", "");
+        let selector = format!("{}_selector", username);
+        let pool = create_db_pool().await.unwrap();
+        let lifetime = Duration::from_secs(60);
+
+        User::register_user(&pool, &username, "password_hash")
+            .await
+            .unwrap();
+        let user = User::look_up_user(&pool, &username).await.unwrap();
+
+        store_remember_token(&pool, &user, &selector, "old", lifetime)
+            .await
+            .unwrap();
+        let (remembered, series) = look_up_remember_token(&pool, &selector).await.unwrap();
+        assert_eq!(remembered, user);
+        assert_eq!(series.validator_hash, "old");
+        assert_eq!(series.previous_validator_hash, None);
+
+        assert!(
+            rotate_remember_token(&pool, &selector, "old", "new", lifetime)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !rotate_remember_token(&pool, &selector, "old", "newer", lifetime)
+                .await
+                .unwrap()
+        );
+        let series = look_up_remember_token(&pool, &selector).await.unwrap().1;
+        assert_eq!(series.validator_hash, "new");
+        assert_eq!(series.previous_validator_hash.as_deref(), Some("old"));
+        assert!(series.rotated.is_some());
+
+        remove_remember_token(&pool, &selector).await.unwrap();
+        assert!(look_up_remember_token(&pool, &selector).await.is_err());
+    }
+}
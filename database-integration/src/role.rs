@@ -0,0 +1,201 @@
+use crate::user::User;
+use sqlx::postgres::PgDone;
+use sqlx::PgPool;
+
+/// The [`INSERT_ROLE`] constant describes the query to insert a new role by its name.
+const INSERT_ROLE: &str = "INSERT INTO roles (name) VALUES ($1);";
+
+/// The [`DELETE_ROLE`] constant describes the query to delete a role by its name.
+///
+/// The capabilities, parents and assignments of the role are deleted by the `ON DELETE CASCADE` constraints.
+const DELETE_ROLE: &str = "DELETE FROM roles WHERE name = $1;";
+
+/// The [`INSERT_ROLE_CAPABILITY`] constant describes the query to grant a capability to a role by the roles name.
+const INSERT_ROLE_CAPABILITY: &str =
+    "INSERT INTO role_capabilities (role_id, label) SELECT role_id, $2 FROM roles WHERE name = $1;";
+
+/// The [`DELETE_ROLE_CAPABILITY`] constant describes the query to revoke a capability from a role by the roles name.
+const DELETE_ROLE_CAPABILITY: &str =
+    "DELETE FROM role_capabilities WHERE label = $2 AND role_id = (SELECT role_id FROM roles WHERE name = $1);";
+
+/// The [`INSERT_ROLE_PARENT`] constant describes the query to let a role inherit from another role by their names.
+const INSERT_ROLE_PARENT: &str = "INSERT INTO role_parents (role_id, parent_id)
+    SELECT role.role_id, parent.role_id FROM roles role, roles parent WHERE role.name = $1 AND parent.name = $2;";
+
+/// The [`DELETE_ROLE_PARENT`] constant describes the query to remove the inheritance between two roles by their names.
+const DELETE_ROLE_PARENT: &str = "DELETE FROM role_parents
+    WHERE role_id = (SELECT role_id FROM roles WHERE name = $1)
+    AND parent_id = (SELECT role_id FROM roles WHERE name = $2);";
+
+/// The [`INSERT_USER_ROLE`] constant describes the query to assign a role to a user by the `user_id` and roles name.
+const INSERT_USER_ROLE: &str =
+    "INSERT INTO user_roles (user_id, role_id) SELECT $1, role_id FROM roles WHERE name = $2;";
+
+/// The [`DELETE_USER_ROLE`] constant describes the query to remove a role from a user by the `user_id` and roles name.
+const DELETE_USER_ROLE: &str =
+    "DELETE FROM user_roles WHERE user_id = $1 AND role_id = (SELECT role_id FROM roles WHERE name = $2);";
+
+/// Tries to create a new role by running the `INSERT_ROLE` query.
+///
+/// A role bundles capabilities and may inherit the capabilities of other roles.
+/// The roles of a user are expanded into their capabilities when the [`User`] is looked up.
+///
+/// # Table structure
+/// ```sql
+/// CREATE TABLE IF NOT EXISTS roles (
+///   role_id SERIAL PRIMARY KEY,
+///   name TEXT NOT NULL UNIQUE
+/// );
+/// ```
+///
+/// The query may fail if the connection to postgres is down or the role already exists.
+pub async fn create_role(connection: &PgPool, name: &str) -> Result<PgDone, sqlx::Error> {
+    sqlx::query(INSERT_ROLE)
+        .bind(name)
+        .execute(connection)
+        .await
+}
+
+/// Tries to delete a role and every reference to it by running the `DELETE_ROLE` query.
+pub async fn delete_role(connection: &PgPool, name: &str) -> Result<PgDone, sqlx::Error> {
+    sqlx::query(DELETE_ROLE)
+        .bind(name)
+        .execute(connection)
+        .await
+}
+
+/// Tries to grant a capability to the role by running the `INSERT_ROLE_CAPABILITY` query.
+///
+/// The query may fail if the capability has already been granted.
+/// [`PgDone::rows_affected`] is 0 if the role does not exist.
+pub async fn grant_capability(
+    connection: &PgPool,
+    role: &str,
+    capability: &str,
+) -> Result<PgDone, sqlx::Error> {
+    sqlx::query(INSERT_ROLE_CAPABILITY)
+        .bind(role)
+        .bind(capability)
+        .execute(connection)
+        .await
+}
+
+/// Tries to revoke a capability from the role by running the `DELETE_ROLE_CAPABILITY` query.
+pub async fn revoke_capability(
+    connection: &PgPool,
+    role: &str,
+    capability: &str,
+) -> Result<PgDone, sqlx::Error> {
+    sqlx::query(DELETE_ROLE_CAPABILITY)
+        .bind(role)
+        .bind(capability)
+        .execute(connection)
+        .await
+}
+
+/// Tries to let the role inherit every capability of the parent by running the `INSERT_ROLE_PARENT` query.
+///
+/// The query may fail if the role already inherits from the parent or if both are the same role.
+/// [`PgDone::rows_affected`] is 0 if one of the roles does not exist.
+pub async fn add_parent(
+    connection: &PgPool,
+    role: &str,
+    parent: &str,
+) -> Result<PgDone, sqlx::Error> {
+    sqlx::query(INSERT_ROLE_PARENT)
+        .bind(role)
+        .bind(parent)
+        .execute(connection)
+        .await
+}
+
+/// Tries to remove the inheritance between the role and the parent by running the `DELETE_ROLE_PARENT` query.
+pub async fn remove_parent(
+    connection: &PgPool,
+    role: &str,
+    parent: &str,
+) -> Result<PgDone, sqlx::Error> {
+    sqlx::query(DELETE_ROLE_PARENT)
+        .bind(role)
+        .bind(parent)
+        .execute(connection)
+        .await
+}
+
+/// Tries to assign the role to the user by running the `INSERT_USER_ROLE` query.
+///
+/// The query may fail if the user already has the role.
+/// [`PgDone::rows_affected`] is 0 if the role does not exist.
+pub async fn assign_role(
+    connection: &PgPool,
+    user: &User,
+    role: &str,
+) -> Result<PgDone, sqlx::Error> {
+    sqlx::query(INSERT_USER_ROLE)
+        .bind(user.user_id)
+        .bind(role)
+        .execute(connection)
+        .await
+}
+
+/// Tries to remove the role from the user by running the `DELETE_USER_ROLE` query.
+pub async fn unassign_role(
+    connection: &PgPool,
+    user: &User,
+    role: &str,
+) -> Result<PgDone, sqlx::Error> {
+    sqlx::query(DELETE_USER_ROLE)
+        .bind(user.user_id)
+        .bind(role)
+        .execute(connection)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::create_db_pool;
+    use chrono::Utc;
+    use std::collections::HashSet;
+
+    #[ignore = "Needs database to run"]
+    #[actix_rt::test]
+    /// Makes sure the roles of a user are expanded into the capabilities of the roles and their parents.
+    async fn expand_inherited_roles() {
+        let prefix = format!("{}", Utc::now()).replace(" ", "");
+        let username = format!("{}_roles", prefix);
+        let [reader, admin, auditor] =
+            ["Reader", "Admin", "Auditor"].map(|r| format!("{}_{}", prefix, r));
+        let pool = create_db_pool().await.unwrap();
+
+        User::register_user(&pool, &username, "password_hash")
+            .await
+            .unwrap();
+        for role in &[&reader, &admin, &auditor] {
+            create_role(&pool, role).await.unwrap();
+        }
+        grant_capability(&pool, &reader, "UserRead").await.unwrap();
+        grant_capability(&pool, &admin, "AdminRead").await.unwrap();
+        grant_capability(&pool, &auditor, "Audit").await.unwrap();
+        add_parent(&pool, &admin, &reader).await.unwrap();
+        // A cycle must not prevent the lookup of a user
+        add_parent(&pool, &reader, &admin).await.unwrap();
+
+        let user = User::look_up_user(&pool, &username).await.unwrap();
+        assign_role(&pool, &user, &admin).await.unwrap();
+        let user = User::look_up_user(&pool, &username).await.unwrap();
+
+        let expected: HashSet<String> = ["UserRead", "AdminRead"]
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        assert_eq!(user.capabilities, expected);
+
+        unassign_role(&pool, &user, &admin).await.unwrap();
+        for role in &[&reader, &admin, &auditor] {
+            delete_role(&pool, role).await.unwrap();
+        }
+        let user = User::look_up_user(&pool, &username).await.unwrap();
+        assert!(user.capabilities.is_empty());
+    }
+}
@@ -0,0 +1,179 @@
+use crate::user::User;
+use access_control::session::{hash_session_id, SessionInfo};
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use std::time::SystemTime;
+
+/// The [`SELECT_SESSIONS`] constant describes the query to select every [`DbSession`] of a user that has not expired,
+/// the most recently seen first.
+///
+/// The session with the digest `$2` is marked as the current one.
+const SELECT_SESSIONS: &str =
+    "SELECT session_id, creation_date, last_seen, ip_address, user_agent, session_id = $2 AS current
+    FROM sessions WHERE user_id = $1 AND expiration_date > NOW() AND NOT pending ORDER BY last_seen DESC;";
+
+/// The [`DELETE_USER_SESSION`] constant describes the query to delete a session of a user by its digest, which returns
+/// the selector of its remember-me series.
+const DELETE_USER_SESSION: &str =
+    "DELETE FROM sessions WHERE user_id = $1 AND session_id = $2 RETURNING remember_selector;";
+
+/// The [`DELETE_USER_REMEMBER_TOKEN`] constant describes the query to delete a remember-me series of a user by its
+/// selector.
+const DELETE_USER_REMEMBER_TOKEN: &str =
+    "DELETE FROM remember_tokens WHERE user_id = $1 AND selector = $2;";
+
+/// The [`DELETE_OTHER_SESSIONS`] constant describes the query to delete every session of a user except for the one
+/// with the digest `$2`.
+const DELETE_OTHER_SESSIONS: &str = "DELETE FROM sessions WHERE user_id = $1 AND session_id <> $2;";
+
+/// The [`DELETE_OTHER_REMEMBER_TOKENS`] constant describes the query to delete every remember-me series of a user
+/// except for the one with the selector `$2`, which may be `NULL`.
+const DELETE_OTHER_REMEMBER_TOKENS: &str =
+    "DELETE FROM remember_tokens WHERE user_id = $1 AND selector IS DISTINCT FROM $2;";
+
+/// The [`DbSession`] struct represents a row of the sessions table, see [`User::store_session`] for its structure.
+///
+/// The `session_id` column contains the digest of the session id.
+#[derive(Debug, Clone, FromRow)]
+struct DbSession {
+    session_id: String,
+    creation_date: DateTime<Utc>,
+    last_seen: DateTime<Utc>,
+    ip_address: Option<String>,
+    user_agent: Option<String>,
+    current: bool,
+}
+
+/// Tries to select the active sessions of a user by running the `SELECT_SESSIONS` query.
+pub async fn sessions(
+    connection: &PgPool,
+    user: &User,
+    current_session_id: &str,
+) -> Result<Vec<SessionInfo>, sqlx::Error> {
+    let sessions = sqlx::query_as::<_, DbSession>(SELECT_SESSIONS)
+        .bind(user.user_id)
+        .bind(hash_session_id(current_session_id))
+        .fetch_all(connection)
+        .await?;
+
+    Ok(sessions
+        .into_iter()
+        .map(|session| SessionInfo {
+            id: session.session_id,
+            created: SystemTime::from(session.creation_date),
+            last_seen: SystemTime::from(session.last_seen),
+            ip_address: session.ip_address.and_then(|ip| ip.parse().ok()),
+            user_agent: session.user_agent,
+            current: session.current,
+        })
+        .collect())
+}
+
+/// Tries to delete a session of a user by its digest and its remember-me series in a transaction.
+///
+/// Returns `false` if the user has no such session.
+pub async fn revoke_session(
+    connection: &PgPool,
+    user: &User,
+    id: &str,
+) -> Result<bool, sqlx::Error> {
+    let mut transaction = connection.begin().await?;
+
+    let revoked: Option<Option<String>> = sqlx::query_scalar(DELETE_USER_SESSION)
+        .bind(user.user_id)
+        .bind(id)
+        .fetch_optional(&mut transaction)
+        .await?;
+    if let Some(Some(selector)) = &revoked {
+        sqlx::query(DELETE_USER_REMEMBER_TOKEN)
+            .bind(user.user_id)
+            .bind(selector)
+            .execute(&mut transaction)
+            .await?;
+    }
+
+    transaction.commit().await?;
+    Ok(revoked.is_some())
+}
+
+/// Tries to delete every other session and remember-me series of a user in a transaction.
+pub async fn revoke_other_sessions(
+    connection: &PgPool,
+    user: &User,
+    current_session_id: &str,
+    remember_selector: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let mut transaction = connection.begin().await?;
+
+    sqlx::query(DELETE_OTHER_SESSIONS)
+        .bind(user.user_id)
+        .bind(hash_session_id(current_session_id))
+        .execute(&mut transaction)
+        .await?;
+    sqlx::query(DELETE_OTHER_REMEMBER_TOKENS)
+        .bind(user.user_id)
+        .bind(remember_selector)
+        .execute(&mut transaction)
+        .await?;
+
+    transaction.commit().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remember::{look_up_remember_token, store_remember_token};
+    use crate::utility::create_db_pool;
+    use access_control::session::{SessionLifetime, SessionMetadata};
+    use std::time::Duration;
+
+    #[ignore = "Needs database to run"]
+    #[actix_rt::test]
+    /// Makes sure the sessions of a user are listed with their metadata and can be revoked one by one, together with
+    /// their remember-me series, or all at once.
+    async fn list_and_revoke() {
+        let username = format!("{}_sessions", Utc::now()).replace(" ", "");
+        let pool = create_db_pool().await.unwrap();
+        let lifetime = SessionLifetime::default();
+        let metadata = SessionMetadata::new("192.0.2.1".parse().ok(), Some("Firefox"));
+
+        User::register_user(&pool, &username, "password_hash")
+            .await
+            .unwrap();
+        let user = User::look_up_user(&pool, &username).await.unwrap();
+        let selector = format!("{}_selector", username);
+        store_remember_token(&pool, &user, &selector, "hash", Duration::from_secs(60))
+            .await
+            .unwrap();
+        for session_id in &["current", "other", "another"] {
+            let metadata = SessionMetadata {
+                remember_selector: (*session_id == "other").then(|| selector.clone()),
+                ..metadata.clone()
+            };
+            let session_id = format!("{}_{}", username, session_id);
+            User::store_session(&pool, &user, &session_id, &lifetime, &metadata)
+                .await
+                .unwrap();
+        }
+        let current = format!("{}_current", username);
+
+        let listed = sessions(&pool, &user, &current).await.unwrap();
+        assert_eq!(listed.len(), 3);
+        assert_eq!(listed.iter().filter(|session| session.current).count(), 1);
+        assert_eq!(listed[0].ip_address, metadata.ip_address);
+        assert_eq!(listed[0].user_agent, metadata.user_agent);
+
+        let other = hash_session_id(&format!("{}_other", username));
+        assert!(revoke_session(&pool, &user, &other).await.unwrap());
+        assert!(!revoke_session(&pool, &user, &other).await.unwrap());
+        assert!(look_up_remember_token(&pool, &selector).await.is_err());
+        assert_eq!(sessions(&pool, &user, &current).await.unwrap().len(), 2);
+
+        revoke_other_sessions(&pool, &user, &current, None)
+            .await
+            .unwrap();
+        let listed = sessions(&pool, &user, &current).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert!(listed[0].current);
+    }
+}
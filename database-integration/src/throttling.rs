@@ -0,0 +1,138 @@
+use access_control::throttling::FailedAttempts;
+use chrono::{DateTime, Utc};
+use sqlx::postgres::PgDone;
+use sqlx::{FromRow, PgPool};
+use std::time::{Duration, SystemTime};
+
+/// The [`SELECT_FAILED_ATTEMPTS`] constant describes the query to select the [`DbFailedAttempts`] of a key.
+const SELECT_FAILED_ATTEMPTS: &str =
+    "SELECT count, last_failure FROM failed_logins WHERE throttle_key = $1;";
+
+/// The [`UPSERT_FAILED_ATTEMPT`] constant describes the query to count a failed attempt of a key.
+///
+/// The count starts again at `1` if the last failure is older than `$2` seconds. The failures of other keys whose
+/// reset period has passed are deleted, so that usernames which are only tried once do not accumulate.
+const UPSERT_FAILED_ATTEMPT: &str = "WITH expired AS (
+        DELETE FROM failed_logins WHERE expiration_date < NOW() AND throttle_key <> $1
+    )
+    INSERT INTO failed_logins (throttle_key, count, last_failure, expiration_date)
+    VALUES ($1, 1, NOW(), NOW() + $2::FLOAT8 * INTERVAL '1 second')
+    ON CONFLICT (throttle_key) DO UPDATE SET
+    count = CASE WHEN failed_logins.last_failure < NOW() - $2::FLOAT8 * INTERVAL '1 second'
+        THEN 1 ELSE failed_logins.count + 1 END,
+    last_failure = NOW(),
+    expiration_date = EXCLUDED.expiration_date;";
+
+/// The [`DELETE_FAILED_ATTEMPTS`] constant describes the query to forget the failed attempts of a key.
+const DELETE_FAILED_ATTEMPTS: &str = "DELETE FROM failed_logins WHERE throttle_key = $1;";
+
+/// The [`DbFailedAttempts`] struct represents the failed_logins table in the database.
+///
+/// # Table structure
+/// ```sql
+/// CREATE TABLE IF NOT EXISTS failed_logins (
+///   throttle_key TEXT PRIMARY KEY,
+///   count INTEGER NOT NULL,
+///   last_failure TIMESTAMPTZ NOT NULL,
+///   expiration_date TIMESTAMPTZ NOT NULL
+/// );
+/// ```
+#[derive(Debug, Clone, FromRow)]
+struct DbFailedAttempts {
+    count: i32,
+    last_failure: DateTime<Utc>,
+}
+
+/// Tries to select the failed attempts of a key by running the `SELECT_FAILED_ATTEMPTS` query.
+///
+/// Returns `None` if the key has no failed attempts, a database error is returned as such, so that the caller does not
+/// mistake it for a key without failures.
+pub async fn failed_attempts(
+    connection: &PgPool,
+    key: &str,
+) -> Result<Option<FailedAttempts>, sqlx::Error> {
+    let attempts = sqlx::query_as::<_, DbFailedAttempts>(SELECT_FAILED_ATTEMPTS)
+        .bind(key)
+        .fetch_optional(connection)
+        .await?;
+
+    Ok(attempts.map(|attempts| FailedAttempts {
+        count: attempts.count.max(0) as u32,
+        last_failure: SystemTime::from(attempts.last_failure),
+    }))
+}
+
+/// Tries to count a failed attempt of a key by running the `UPSERT_FAILED_ATTEMPT` query.
+pub async fn record_failed_attempt(
+    connection: &PgPool,
+    key: &str,
+    reset_after: Duration,
+) -> Result<PgDone, sqlx::Error> {
+    sqlx::query(UPSERT_FAILED_ATTEMPT)
+        .bind(key)
+        .bind(reset_after.as_secs_f64())
+        .execute(connection)
+        .await
+}
+
+/// Tries to forget the failed attempts of a key by running the `DELETE_FAILED_ATTEMPTS` query.
+pub async fn reset_failed_attempts(connection: &PgPool, key: &str) -> Result<PgDone, sqlx::Error> {
+    sqlx::query(DELETE_FAILED_ATTEMPTS)
+        .bind(key)
+        .execute(connection)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::create_db_pool;
+
+    #[ignore = "Needs database to run"]
+    #[actix_rt::test]
+    /// Makes sure failed attempts are counted, start again after the reset period and can be forgotten.
+    async fn count_and_reset() {
+        let key = format!("account:{}_throttling", Utc::now()).replace(" ", "");
+        let pool = create_db_pool().await.unwrap();
+        let hour = Duration::from_secs(60 * 60);
+
+        assert_eq!(failed_attempts(&pool, &key).await.unwrap(), None);
+        record_failed_attempt(&pool, &key, hour).await.unwrap();
+        record_failed_attempt(&pool, &key, hour).await.unwrap();
+        assert_eq!(
+            failed_attempts(&pool, &key).await.unwrap().unwrap().count,
+            2
+        );
+
+        record_failed_attempt(&pool, &key, Duration::from_secs(0))
+            .await
+            .unwrap();
+        assert_eq!(
+            failed_attempts(&pool, &key).await.unwrap().unwrap().count,
+            1
+        );
+
+        reset_failed_attempts(&pool, &key).await.unwrap();
+        assert_eq!(failed_attempts(&pool, &key).await.unwrap(), None);
+    }
+
+    #[ignore = "Needs database to run"]
+    #[actix_rt::test]
+    /// Makes sure the failures of a key are deleted by the next failure of another key once their reset period passed.
+    async fn purge_expired() {
+        let key = format!("account:{}_expired", Utc::now()).replace(" ", "");
+        let pool = create_db_pool().await.unwrap();
+
+        record_failed_attempt(&pool, &key, Duration::from_secs(0))
+            .await
+            .unwrap();
+        assert!(failed_attempts(&pool, &key).await.unwrap().is_some());
+
+        let other = format!("{}_other", key);
+        record_failed_attempt(&pool, &other, Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_eq!(failed_attempts(&pool, &key).await.unwrap(), None);
+        assert!(failed_attempts(&pool, &other).await.unwrap().is_some());
+    }
+}
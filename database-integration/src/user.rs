@@ -1,35 +1,107 @@
 use std::cmp::PartialEq;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 
+use access_control::session::{hash_session_id, SessionLifetime, SessionMetadata};
 use access_control::User as UserTrait;
 
 use chrono::{DateTime, Utc};
 use sqlx::postgres::PgDone;
-use sqlx::{FromRow, PgPool};
+use sqlx::{Done, FromRow, PgPool};
 
 /// This constant describes the query to select a [`DbUser`] by their username.
 const SELECT_USER: &str = "SELECT * FROM users WHERE username = $1;";
 
 const SELECT_USER_BY_SESSION_ID: &str =
-    "SELECT * FROM users WHERE user_id = (SELECT user_id FROM sessions WHERE session_id = $1 AND expiration_date > NOW());";
+    "SELECT * FROM users WHERE user_id = (SELECT user_id FROM sessions WHERE session_id = $1 AND expiration_date > NOW() AND NOT pending);";
+
+/// The [`SELECT_USER_BY_PENDING_SESSION_ID`] constant describes the query to select a [`DbUser`] by a session that
+/// waits for the second factor.
+const SELECT_USER_BY_PENDING_SESSION_ID: &str =
+    "SELECT * FROM users WHERE user_id = (SELECT user_id FROM sessions WHERE session_id = $1 AND expiration_date > NOW() AND pending);";
 
 /// This constant describes the query to insert a new [`DbUser`] by their name and password hash.
 /// The registration_date that is part of the [`DbUser`] is set to the current time using postgres' NOW() function.
 /// The password hash comes from the access control library and contains the PHC hash.
-const INSERT_USER: &str =
-    "INSERT INTO users (username, password_hash, registration_date) VALUES ($1, $2, NOW());";
+///
+/// No user is inserted if the canonical form of the username is already taken, e.g. by an account that could not be
+/// canonicalized by the migration.
+const INSERT_USER: &str = "INSERT INTO users (username, password_hash, registration_date)
+    SELECT $1, $2, NOW() WHERE NOT EXISTS
+    (SELECT 1 FROM users WHERE canonical_username(username) = canonical_username($1));";
+
+/// The [`UPDATE_PASSWORD_HASH`] constant describes the query to replace the password hash of a user by their `user_id`.
+const UPDATE_PASSWORD_HASH: &str = "UPDATE users SET password_hash = $1 WHERE user_id = $2;";
 
 /// The [`INSERT_SESSION`] constant describes the query to insert a session by providing a `session_id` and `user_id`.
 ///
-/// The sessions expiration date is set to the current time plus 5 minutes.
-const INSERT_SESSION: &str =
-    "INSERT INTO sessions (session_id, user_id, expiration_date) VALUES ($1, $2, NOW() + INTERVAL '5 minutes');";
+/// The sessions expiration date is set to the current time plus the idle timeout of `$3` seconds, its absolute
+/// expiration date to the current time plus the absolute timeout of `$4` seconds. The client IP address `$5` and user
+/// agent `$6` are stored to list the session, the selector of its remember-me series `$7` to revoke it with the session.
+const INSERT_SESSION: &str = "INSERT INTO sessions
+    (session_id, user_id, expiration_date, absolute_expiration_date, ip_address, user_agent, remember_selector)
+    VALUES ($1, $2, NOW() + $3::FLOAT8 * INTERVAL '1 second', NOW() + $4::FLOAT8 * INTERVAL '1 second', $5, $6, $7);";
+
+/// The [`INSERT_PENDING_SESSION`] constant describes the query to insert a session that waits for the second factor.
+///
+/// A pending session is never extended and expires after 5 minutes. `$3` stores whether the login asked to be
+/// remembered.
+const INSERT_PENDING_SESSION: &str =
+    "INSERT INTO sessions (session_id, user_id, expiration_date, absolute_expiration_date, pending, remember)
+    VALUES ($1, $2, NOW() + INTERVAL '5 minutes', NOW() + INTERVAL '5 minutes', TRUE, $3);";
+
+/// The [`SELECT_PENDING_REMEMBER`] constant describes the query to select whether the login of a pending session asked
+/// to be remembered.
+const SELECT_PENDING_REMEMBER: &str =
+    "SELECT remember FROM sessions WHERE session_id = $1 AND expiration_date > NOW() AND pending;";
+
+/// The [`COUNT_PENDING_ATTEMPT`] constant describes the query to count an attempt of the second factor of a pending
+/// session, which returns the number of attempts.
+const COUNT_PENDING_ATTEMPT: &str = "UPDATE sessions SET attempts = attempts + 1
+    WHERE session_id = $1 AND expiration_date > NOW() AND pending
+    RETURNING attempts;";
+
+/// The [`TOUCH_SESSION`] constant describes the query to extend a valid session by the idle timeout of `$2` seconds,
+/// but not beyond its absolute expiration date, and to record the current time as last seen.
+const TOUCH_SESSION: &str = "UPDATE sessions
+    SET expiration_date = LEAST(NOW() + $2::FLOAT8 * INTERVAL '1 second', absolute_expiration_date),
+    last_seen = NOW()
+    WHERE session_id = $1 AND expiration_date > NOW() AND NOT pending
+    RETURNING expiration_date;";
+
+/// The [`UPDATE_TOTP_SECRET`] constant describes the query to replace the TOTP secret of a user by their `user_id`.
+///
+/// The last used time step belongs to the previous secret and is therefore reset.
+const UPDATE_TOTP_SECRET: &str =
+    "UPDATE users SET totp_secret = $1, totp_last_step = NULL WHERE user_id = $2;";
+
+/// The [`UPDATE_TOTP_LAST_STEP`] constant describes the query to record the time step of a used one-time password.
+///
+/// The row is only updated if the time step is later than the last used one, so a code can not be used twice.
+const UPDATE_TOTP_LAST_STEP: &str = "UPDATE users SET totp_last_step = $1
+    WHERE user_id = $2 AND (totp_last_step IS NULL OR totp_last_step < $1);";
 
 /// The [`DELETE_SESSION`] constant describes the query to delete a session by its `session_id`.
 const DELETE_SESSION: &str = "DELETE FROM sessions WHERE session_id = $1;";
 
-/// The [`SELECT_CAPABILITIES`] constant describes the query to select a new [`DbCapability`] by `user_id`.
-const SELECT_CAPABILITIES: &str = "SELECT * FROM capabilities WHERE user_id = $1;";
+/// The [`SELECT_CAPABILITIES`] constant describes the query to select every [`DbCapability`] of a user by `user_id`.
+///
+/// This includes the capabilities that are granted to the user directly and the capabilities of the users roles.
+/// The roles are expanded recursively by following the `role_parents` table, so a role has the capabilities of its
+/// parents as well. `UNION` removes duplicate rows, which makes sure the recursion ends even if the roles form a cycle.
+const SELECT_CAPABILITIES: &str = "
+    WITH RECURSIVE expanded_roles(role_id) AS (
+        SELECT role_id FROM user_roles WHERE user_id = $1
+        UNION
+        SELECT role_parents.parent_id FROM role_parents
+        JOIN expanded_roles ON role_parents.role_id = expanded_roles.role_id
+    )
+    SELECT label FROM capabilities WHERE user_id = $1
+    UNION
+    SELECT label FROM role_capabilities JOIN expanded_roles USING (role_id);";
+
+/// The [`SELECT_ATTRIBUTES`] constant describes the query to select every [`DbAttribute`] of a user by `user_id`.
+const SELECT_ATTRIBUTES: &str = "SELECT name, value FROM user_attributes WHERE user_id = $1;";
 
 /// The [`User`] struct is provided to the Middleware and combines data from the user and capabilities table.
 ///
@@ -38,11 +110,14 @@ const SELECT_CAPABILITIES: &str = "SELECT * FROM capabilities WHERE user_id = $1
 /// On the contrary, data like the capabilities are not part of the user table, but are move into the [`User`] struct, as they are necessary for authorization.
 #[derive(Debug, Clone, PartialEq)]
 pub struct User {
-    user_id: i32,
+    pub(crate) user_id: i32,
     pub username: String,
     password_hash: String,
     pub registration_date: DateTime<Utc>,
     pub capabilities: HashSet<String>,
+    totp_secret: Option<String>,
+    /// Attributes like the department, which access policies can refer to
+    pub attributes: HashMap<String, String>,
 }
 
 impl UserTrait for User {
@@ -60,6 +135,16 @@ impl UserTrait for User {
     fn capabilities(&self) -> &HashSet<String> {
         &self.capabilities
     }
+
+    /// Returns an attribute from the `user_attributes` table
+    fn attribute(&self, name: &str) -> Option<String> {
+        self.attributes.get(name).cloned()
+    }
+
+    /// Returns the TOTP secret if the user enabled two-factor authentication
+    fn totp_secret(&self) -> Option<&str> {
+        self.totp_secret.as_deref()
+    }
 }
 
 /// The [`DbUser`] struct represents the users table in the database.
@@ -73,8 +158,11 @@ impl UserTrait for User {
 ///   user_id SERIAL PRIMARY KEY,
 ///   username TEXT NOT NULL UNIQUE,
 ///   password_hash TEXT NOT NULL,
-///   registration_date TIMESTAMPTZ NOT NULL
+///   registration_date TIMESTAMPTZ NOT NULL,
+///   totp_secret TEXT,
+///   totp_last_step BIGINT
 /// );
+/// CREATE INDEX IF NOT EXISTS users_canonical_username ON users (canonical_username(username));
 /// ```
 #[derive(Debug, Clone, FromRow)]
 struct DbUser {
@@ -82,11 +170,14 @@ struct DbUser {
     username: String,
     password_hash: String,
     registration_date: DateTime<Utc>,
+    totp_secret: Option<String>,
 }
 
 /// The [`DbCapability`] struct represents the capability table in the database.
 /// It is only used to query the necessary information to build a [`User`] by combining it with a [`DbUser`].
 ///
+/// Capabilities are either granted to a user directly or by a role, see [`crate::role`].
+///
 /// # Table structure
 /// ``` sql
 /// TABLE capabilities (
@@ -98,16 +189,34 @@ struct DbUser {
 /// ```
 #[derive(Debug, Clone, FromRow)]
 struct DbCapability {
-    user_id: i32,
     label: String,
 }
 
+/// The [`DbAttribute`] struct represents the user_attributes table in the database.
+///
+/// # Table structure
+/// ``` sql
+/// TABLE user_attributes (
+///   user_id INTEGER NOT NULL,
+///   name TEXT NOT NULL,
+///   value TEXT NOT NULL,
+///   CONSTRAINT fk_user FOREIGN KEY(user_id) REFERENCES users(user_id),
+///   PRIMARY KEY (user_id, name)
+/// );
+/// ```
+#[derive(Debug, Clone, FromRow)]
+struct DbAttribute {
+    name: String,
+    value: String,
+}
+
 impl User {
     /// Tries to insert a new user into the database by running the `INSERT_USER` query.
     ///
     ///
     /// The query may fail if the connection to postgres is down or the user already exists.
-    /// In this case a [`sqlx::Error`] is returned.
+    /// In this case a [`sqlx::Error`] is returned. If another user has the same canonical username,
+    /// [`sqlx::Error::RowNotFound`] is returned.
     ///
     /// If successful, the functions returns [`sqlx::postgres::PgDone`].
     pub(crate) async fn register_user(
@@ -115,11 +224,15 @@ impl User {
         username: &str,
         password_hash: &str,
     ) -> Result<PgDone, sqlx::Error> {
-        sqlx::query(INSERT_USER)
+        let done = sqlx::query(INSERT_USER)
             .bind(username)
             .bind(password_hash)
             .execute(connection)
-            .await
+            .await?;
+        if done.rows_affected() == 0 {
+            return Err(sqlx::Error::RowNotFound);
+        }
+        Ok(done)
     }
 
     /// Tries to look up a [`User`] by running the `SELECT_USER` and `SELECT_CAPABILITIES` query.
@@ -143,13 +256,8 @@ impl User {
             .bind(username)
             .fetch_one(connection)
             .await?;
-        let user_caps: HashSet<String> = sqlx::query_as::<_, DbCapability>(SELECT_CAPABILITIES)
-            .bind(dbuser.user_id)
-            .fetch_all(connection)
-            .await?
-            .into_iter()
-            .map(|c: DbCapability| c.label)
-            .collect();
+        let user_caps = Self::look_up_capabilities(connection, dbuser.user_id).await?;
+        let attributes = Self::look_up_attributes(connection, dbuser.user_id).await?;
 
         Ok(User {
             user_id: dbuser.user_id,
@@ -157,6 +265,8 @@ impl User {
             password_hash: dbuser.password_hash,
             registration_date: dbuser.registration_date,
             capabilities: user_caps,
+            totp_secret: dbuser.totp_secret,
+            attributes,
         })
     }
 
@@ -173,17 +283,41 @@ impl User {
         connection: &PgPool,
         session_id: &str,
     ) -> Result<User, sqlx::Error> {
-        let dbuser = sqlx::query_as::<_, DbUser>(SELECT_USER_BY_SESSION_ID)
-            .bind(session_id)
+        Self::look_up_user_by_session_query(connection, SELECT_USER_BY_SESSION_ID, session_id).await
+    }
+
+    /// Tries to retrieve a [`User`] by the `session_id` of a session that waits for the second factor, together with
+    /// whether the login asked to be remembered.
+    pub(crate) async fn look_up_user_from_pending_session(
+        connection: &PgPool,
+        session_id: &str,
+    ) -> Result<(User, bool), sqlx::Error> {
+        let user = Self::look_up_user_by_session_query(
+            connection,
+            SELECT_USER_BY_PENDING_SESSION_ID,
+            session_id,
+        )
+        .await?;
+        let remember = sqlx::query_scalar(SELECT_PENDING_REMEMBER)
+            .bind(hash_session_id(session_id))
             .fetch_one(connection)
             .await?;
-        let user_caps: HashSet<String> = sqlx::query_as::<_, DbCapability>(SELECT_CAPABILITIES)
-            .bind(dbuser.user_id)
-            .fetch_all(connection)
-            .await?
-            .into_iter()
-            .map(|c: DbCapability| c.label)
-            .collect();
+
+        Ok((user, remember))
+    }
+
+    /// Runs one of the queries that select a [`DbUser`] by `session_id` and combines it with the capabilities.
+    async fn look_up_user_by_session_query(
+        connection: &PgPool,
+        query: &str,
+        session_id: &str,
+    ) -> Result<User, sqlx::Error> {
+        let dbuser = sqlx::query_as::<_, DbUser>(query)
+            .bind(hash_session_id(session_id))
+            .fetch_one(connection)
+            .await?;
+        let user_caps = Self::look_up_capabilities(connection, dbuser.user_id).await?;
+        let attributes = Self::look_up_attributes(connection, dbuser.user_id).await?;
 
         Ok(User {
             user_id: dbuser.user_id,
@@ -191,20 +325,79 @@ impl User {
             password_hash: dbuser.password_hash,
             registration_date: dbuser.registration_date,
             capabilities: user_caps,
+            totp_secret: dbuser.totp_secret,
+            attributes,
         })
     }
 
+    /// Tries to select the capabilities of a user by running the `SELECT_CAPABILITIES` query, which expands the roles
+    /// of the user into their capabilities.
+    async fn look_up_capabilities(
+        connection: &PgPool,
+        user_id: i32,
+    ) -> Result<HashSet<String>, sqlx::Error> {
+        Ok(sqlx::query_as::<_, DbCapability>(SELECT_CAPABILITIES)
+            .bind(user_id)
+            .fetch_all(connection)
+            .await?
+            .into_iter()
+            .map(|c: DbCapability| c.label)
+            .collect())
+    }
+
+    /// Tries to select the attributes of a user by running the `SELECT_ATTRIBUTES` query.
+    async fn look_up_attributes(
+        connection: &PgPool,
+        user_id: i32,
+    ) -> Result<HashMap<String, String>, sqlx::Error> {
+        Ok(sqlx::query_as::<_, DbAttribute>(SELECT_ATTRIBUTES)
+            .bind(user_id)
+            .fetch_all(connection)
+            .await?
+            .into_iter()
+            .map(|a: DbAttribute| (a.name, a.value))
+            .collect())
+    }
+
+    /// Tries to replace the password hash of a [`User`] by running the `UPDATE_PASSWORD_HASH` query.
+    ///
+    /// The query may fail if the connection to postgres is down.
+    /// In this case a [`sqlx::Error`] is returned.
+    pub(crate) async fn update_password_hash(
+        connection: &PgPool,
+        user: &User,
+        password_hash: &str,
+    ) -> Result<PgDone, sqlx::Error> {
+        sqlx::query(UPDATE_PASSWORD_HASH)
+            .bind(password_hash)
+            .bind(user.user_id)
+            .execute(connection)
+            .await
+    }
+
     /// Tries to insert a new session into the database.
     ///
     /// This query may fail if the selected `session_id` is already in the sessions table.
     /// If successful, the query returns `()`.
     ///
+    /// Every session function only stores and queries the [`hash_session_id`] of the `session_id`, the raw session id
+    /// only exists in the cookie.
+    ///
     /// A session has the following format PostgreSql:
     /// ```sql
     /// TABLE sessions (
     ///   session_id TEXT PRIMARY KEY,
     ///   user_id SERIAL,
     ///   expiration_date TIMESTAMPTZ NOT NULL,
+    ///   absolute_expiration_date TIMESTAMPTZ NOT NULL,
+    ///   pending BOOLEAN NOT NULL DEFAULT FALSE,
+    ///   attempts INTEGER NOT NULL DEFAULT 0,
+    ///   remember BOOLEAN NOT NULL DEFAULT FALSE,
+    ///   creation_date TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ///   last_seen TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ///   ip_address TEXT,
+    ///   user_agent TEXT,
+    ///   remember_selector TEXT,
     ///   CONSTRAINT fk_user FOREIGN KEY(user_id) REFERENCES users(user_id)
     /// );
     /// ```
@@ -212,14 +405,97 @@ impl User {
         connection: &PgPool,
         user: &User,
         session_id: &str,
+        lifetime: &SessionLifetime,
+        metadata: &SessionMetadata,
     ) -> Result<PgDone, sqlx::Error> {
         sqlx::query(INSERT_SESSION)
-            .bind(session_id)
+            .bind(hash_session_id(session_id))
+            .bind(user.user_id)
+            .bind(lifetime.idle_timeout.as_secs_f64())
+            .bind(lifetime.absolute_timeout.as_secs_f64())
+            .bind(metadata.ip_address.map(|ip| ip.to_string()))
+            .bind(&metadata.user_agent)
+            .bind(&metadata.remember_selector)
+            .execute(connection)
+            .await
+    }
+
+    /// Tries to extend a session by running the `TOUCH_SESSION` query.
+    ///
+    /// Returns the new expiration date, or `None` if the session does not exist, is pending or has expired.
+    pub(crate) async fn touch_session(
+        connection: &PgPool,
+        session_id: &str,
+        idle_timeout: Duration,
+    ) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+        sqlx::query_scalar(TOUCH_SESSION)
+            .bind(hash_session_id(session_id))
+            .bind(idle_timeout.as_secs_f64())
+            .fetch_optional(connection)
+            .await
+    }
+
+    /// Tries to insert a session that waits for the second factor into the database.
+    ///
+    /// A pending session is not returned by [`User::look_up_user_from_session`].
+    pub(crate) async fn store_pending_session(
+        connection: &PgPool,
+        user: &User,
+        session_id: &str,
+        remember: bool,
+    ) -> Result<PgDone, sqlx::Error> {
+        sqlx::query(INSERT_PENDING_SESSION)
+            .bind(hash_session_id(session_id))
             .bind(user.user_id)
+            .bind(remember)
             .execute(connection)
             .await
     }
 
+    /// Tries to count an attempt of the second factor by running the `COUNT_PENDING_ATTEMPT` query.
+    ///
+    /// Returns the number of attempts, or `None` if the pending session does not exist or has expired.
+    pub(crate) async fn record_pending_attempt(
+        connection: &PgPool,
+        session_id: &str,
+    ) -> Result<Option<i32>, sqlx::Error> {
+        sqlx::query_scalar(COUNT_PENDING_ATTEMPT)
+            .bind(hash_session_id(session_id))
+            .fetch_optional(connection)
+            .await
+    }
+
+    /// Tries to replace the TOTP secret of a [`User`] by running the `UPDATE_TOTP_SECRET` query.
+    ///
+    /// `None` disables two-factor authentication.
+    pub(crate) async fn update_totp_secret(
+        connection: &PgPool,
+        user: &User,
+        secret: Option<&str>,
+    ) -> Result<PgDone, sqlx::Error> {
+        sqlx::query(UPDATE_TOTP_SECRET)
+            .bind(secret)
+            .bind(user.user_id)
+            .execute(connection)
+            .await
+    }
+
+    /// Tries to record the time step of a used one-time password by running the `UPDATE_TOTP_LAST_STEP` query.
+    ///
+    /// Returns `false` if the time step is not later than the last used one.
+    pub(crate) async fn use_totp_step(
+        connection: &PgPool,
+        user: &User,
+        step: u64,
+    ) -> Result<bool, sqlx::Error> {
+        let done = sqlx::query(UPDATE_TOTP_LAST_STEP)
+            .bind(step as i64)
+            .bind(user.user_id)
+            .execute(connection)
+            .await?;
+        Ok(done.rows_affected() == 1)
+    }
+
     /// Tries to delete a session by its `session_id`.
     ///
     /// This query may fail if the `session_id` does not exist.
@@ -229,7 +505,7 @@ impl User {
         session_id: &str,
     ) -> Result<PgDone, sqlx::Error> {
         sqlx::query(DELETE_SESSION)
-            .bind(session_id)
+            .bind(hash_session_id(session_id))
             .execute(connection)
             .await
     }
@@ -256,6 +532,7 @@ mod tests {
 
         assert_eq!(user_lookup.username, username);
         assert_eq!(user_lookup.capabilities, HashSet::new());
+        assert_eq!(user_lookup.attributes, HashMap::new());
     }
 
     #[ignore = "Needs database to run"]
@@ -275,6 +552,29 @@ mod tests {
             .is_err());
     }
 
+    #[ignore = "Needs database to run"]
+    #[actix_rt::test]
+    /// Makes sure a user cannot register with another form of a username whose canonical form is taken.
+    async fn register_canonical_duplicate() {
+        let username = format!("{}_canonical", Utc::now()).replace(" ", "");
+        let pool = create_db_pool().await.unwrap();
+
+        User::register_user(&pool, &username, "password_hash")
+            .await
+            .unwrap();
+        let full_width: String = format!(" {} ", username.to_uppercase())
+            .chars()
+            .map(|c| match c {
+                'A'..='Z' => char::from_u32(c as u32 - 'A' as u32 + 'Ａ' as u32).unwrap(),
+                c => c,
+            })
+            .collect();
+        assert!(matches!(
+            User::register_user(&pool, &full_width, "password_hash").await,
+            Err(sqlx::Error::RowNotFound)
+        ));
+    }
+
     #[ignore = "Needs database to run"]
     #[actix_rt::test]
     /// Tries to look up a user that does not exist.
@@ -285,6 +585,27 @@ mod tests {
         assert!(User::look_up_user(&pool, &username).await.is_err());
     }
 
+    #[ignore = "Needs database to run"]
+    #[actix_rt::test]
+    /// Tries to replace the password hash of a user by running [`User::update_password_hash`].
+    async fn update_password_hash() {
+        let username = format!("{}_update_password_hash", Utc::now()).replace(" ", "");
+        let password_hash = format!("{}", Utc::now());
+        let pool = create_db_pool().await.unwrap();
+
+        User::register_user(&pool, &username, &password_hash)
+            .await
+            .unwrap();
+        let user = User::look_up_user(&pool, &username).await.unwrap();
+
+        User::update_password_hash(&pool, &user, "new_password_hash")
+            .await
+            .unwrap();
+        let user = User::look_up_user(&pool, &username).await.unwrap();
+
+        assert_eq!(user.password_hash, "new_password_hash");
+    }
+
     #[ignore = "Needs database to run"]
     #[actix_rt::test]
     /// Tries to insert, lookup and delete a session from the database.
@@ -299,9 +620,25 @@ mod tests {
             .unwrap();
         let user = User::look_up_user(&pool, &username).await.unwrap();
 
-        User::store_session(&pool, &user, session_id.as_str())
-            .await
-            .unwrap();
+        let lifetime = SessionLifetime::new(Duration::from_secs(60), Duration::from_secs(90));
+        User::store_session(
+            &pool,
+            &user,
+            session_id.as_str(),
+            &lifetime,
+            &SessionMetadata::default(),
+        )
+        .await
+        .unwrap();
+
+        // Only the digest of the session id is stored
+        let stored: Vec<String> =
+            sqlx::query_scalar("SELECT session_id FROM sessions WHERE user_id = $1;")
+                .bind(user.user_id)
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(stored, vec![hash_session_id(&session_id)]);
 
         let retrieved_user = User::look_up_user_from_session(&pool, session_id.as_str())
             .await
@@ -309,8 +646,85 @@ mod tests {
 
         assert_eq!(user, retrieved_user);
 
+        // The session is extended by the idle timeout, but not beyond the absolute timeout
+        let extended = User::touch_session(&pool, &session_id, Duration::from_secs(60))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(extended > Utc::now() + chrono::Duration::seconds(50));
+        let capped = User::touch_session(&pool, &session_id, Duration::from_secs(3600))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(capped < Utc::now() + chrono::Duration::seconds(91));
+        assert!(
+            User::touch_session(&pool, "unknown", Duration::from_secs(60))
+                .await
+                .unwrap()
+                .is_none()
+        );
+
         User::remove_session(&pool, session_id.as_str())
             .await
             .unwrap();
     }
+
+    #[ignore = "Needs database to run"]
+    #[actix_rt::test]
+    /// Makes sure a pending session only resumes the login and a time step of a one-time password is only used once.
+    async fn pending_session_and_totp_steps() {
+        let username = format!("{}_totp", Utc::now()).replace(" ", "");
+        let pool = create_db_pool().await.unwrap();
+        let session_id = format!("{}_pending_session_id", Utc::now()).replace(" ", "");
+
+        User::register_user(&pool, &username, "password_hash")
+            .await
+            .unwrap();
+        let user = User::look_up_user(&pool, &username).await.unwrap();
+        assert_eq!(user.totp_secret, None);
+
+        User::update_totp_secret(&pool, &user, Some("JBSWY3DPEHPK3PXP"))
+            .await
+            .unwrap();
+        let user = User::look_up_user(&pool, &username).await.unwrap();
+        assert_eq!(user.totp_secret.as_deref(), Some("JBSWY3DPEHPK3PXP"));
+
+        User::store_pending_session(&pool, &user, &session_id, true)
+            .await
+            .unwrap();
+        assert!(User::look_up_user_from_session(&pool, &session_id)
+            .await
+            .is_err());
+        assert_eq!(
+            User::look_up_user_from_pending_session(&pool, &session_id)
+                .await
+                .unwrap(),
+            (user.clone(), true)
+        );
+        assert_eq!(
+            User::record_pending_attempt(&pool, &session_id)
+                .await
+                .unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            User::record_pending_attempt(&pool, &session_id)
+                .await
+                .unwrap(),
+            Some(2)
+        );
+
+        assert!(User::use_totp_step(&pool, &user, 100).await.unwrap());
+        assert!(!User::use_totp_step(&pool, &user, 100).await.unwrap());
+        assert!(!User::use_totp_step(&pool, &user, 99).await.unwrap());
+        assert!(User::use_totp_step(&pool, &user, 101).await.unwrap());
+
+        User::remove_session(&pool, &session_id).await.unwrap();
+        assert_eq!(
+            User::record_pending_attempt(&pool, &session_id)
+                .await
+                .unwrap(),
+            None
+        );
+    }
 }
@@ -0,0 +1,223 @@
+use crate::user::User;
+use access_control::webauthn::Credential;
+use sqlx::postgres::PgDone;
+use sqlx::{Done, FromRow, PgPool};
+
+/// The [`INSERT_CHALLENGE`] constant describes the query to insert a challenge, which expires after 5 minutes.
+///
+/// The `user_id` is `NULL` for challenges of a login. Expired challenges are deleted by the same query, as challenges
+/// of a login can be requested without an account and would otherwise pile up.
+const INSERT_CHALLENGE: &str =
+    "WITH expired AS (DELETE FROM webauthn_challenges WHERE expiration_date <= NOW())
+    INSERT INTO webauthn_challenges (challenge, user_id, expiration_date)
+    VALUES ($1, $2, NOW() + INTERVAL '5 minutes');";
+
+/// The [`INSERT_EXPIRED_CHALLENGE`] constant describes the query to insert a challenge that has already expired.
+#[cfg(test)]
+const INSERT_EXPIRED_CHALLENGE: &str =
+    "INSERT INTO webauthn_challenges (challenge, user_id, expiration_date) VALUES ($1, NULL, NOW());";
+
+/// The [`DELETE_CHALLENGE`] constant describes the query to consume a challenge that has not expired.
+const DELETE_CHALLENGE: &str = "DELETE FROM webauthn_challenges
+    WHERE challenge = $1 AND user_id IS NOT DISTINCT FROM $2 AND expiration_date > NOW();";
+
+/// The [`INSERT_CREDENTIAL`] constant describes the query to insert a new credential of a user.
+const INSERT_CREDENTIAL: &str = "INSERT INTO webauthn_credentials
+    (credential_id, user_id, public_key, sign_count, registration_date) VALUES ($1, $2, $3, $4, NOW());";
+
+/// The [`SELECT_CREDENTIAL`] constant describes the query to select a [`DbCredential`] and the name of its user by the
+/// `credential_id`.
+const SELECT_CREDENTIAL: &str = "SELECT username, public_key, sign_count
+    FROM webauthn_credentials JOIN users USING (user_id) WHERE credential_id = $1;";
+
+/// The [`UPDATE_SIGN_COUNT`] constant describes the query to replace the signature counter of a credential.
+///
+/// The row is only updated if the counter increased or the authenticator does not implement a counter.
+const UPDATE_SIGN_COUNT: &str = "UPDATE webauthn_credentials SET sign_count = $1
+    WHERE credential_id = $2 AND (sign_count < $1 OR $1 = 0);";
+
+/// The [`DbCredential`] struct represents the webauthn_credentials table in the database.
+///
+/// # Table structure
+/// ```sql
+/// CREATE TABLE IF NOT EXISTS webauthn_credentials (
+///   credential_id BYTEA PRIMARY KEY,
+///   user_id INTEGER NOT NULL,
+///   public_key BYTEA NOT NULL,
+///   sign_count BIGINT NOT NULL,
+///   registration_date TIMESTAMPTZ NOT NULL,
+///   CONSTRAINT fk_user FOREIGN KEY(user_id) REFERENCES users(user_id)
+/// );
+/// ```
+#[derive(Debug, Clone, FromRow)]
+struct DbCredential {
+    username: String,
+    public_key: Vec<u8>,
+    sign_count: i64,
+}
+
+/// Tries to store a challenge by running the `INSERT_CHALLENGE` query.
+///
+/// # Table structure
+/// ```sql
+/// CREATE TABLE IF NOT EXISTS webauthn_challenges (
+///   challenge TEXT PRIMARY KEY,
+///   user_id INTEGER,
+///   expiration_date TIMESTAMPTZ NOT NULL,
+///   CONSTRAINT fk_user FOREIGN KEY(user_id) REFERENCES users(user_id)
+/// );
+/// ```
+pub async fn store_challenge(
+    connection: &PgPool,
+    user: Option<&User>,
+    challenge: &str,
+) -> Result<PgDone, sqlx::Error> {
+    sqlx::query(INSERT_CHALLENGE)
+        .bind(challenge)
+        .bind(user.map(|user| user.user_id))
+        .execute(connection)
+        .await
+}
+
+/// Tries to consume a challenge of the user by running the `DELETE_CHALLENGE` query.
+///
+/// Returns `false` if the challenge does not exist, has expired or belongs to another user.
+pub async fn take_challenge(
+    connection: &PgPool,
+    user: Option<&User>,
+    challenge: &str,
+) -> Result<bool, sqlx::Error> {
+    let done = sqlx::query(DELETE_CHALLENGE)
+        .bind(challenge)
+        .bind(user.map(|user| user.user_id))
+        .execute(connection)
+        .await?;
+    Ok(done.rows_affected() == 1)
+}
+
+/// Tries to store a new credential of the user by running the `INSERT_CREDENTIAL` query.
+///
+/// The query may fail if the connection to postgres is down or the credential has already been registered.
+pub async fn store_credential(
+    connection: &PgPool,
+    user: &User,
+    credential: &Credential,
+) -> Result<PgDone, sqlx::Error> {
+    sqlx::query(INSERT_CREDENTIAL)
+        .bind(&credential.id)
+        .bind(user.user_id)
+        .bind(&credential.public_key)
+        .bind(credential.sign_count as i64)
+        .execute(connection)
+        .await
+}
+
+/// Tries to look up a credential and its [`User`] by running the `SELECT_CREDENTIAL` query.
+pub async fn look_up_credential(
+    connection: &PgPool,
+    credential_id: &[u8],
+) -> Result<(User, Credential), sqlx::Error> {
+    let credential = sqlx::query_as::<_, DbCredential>(SELECT_CREDENTIAL)
+        .bind(credential_id)
+        .fetch_one(connection)
+        .await?;
+    let user = User::look_up_user(connection, &credential.username).await?;
+
+    Ok((
+        user,
+        Credential {
+            id: credential_id.to_vec(),
+            public_key: credential.public_key,
+            sign_count: credential.sign_count as u32,
+        },
+    ))
+}
+
+/// Tries to replace the signature counter of a credential by running the `UPDATE_SIGN_COUNT` query.
+///
+/// Returns `false` if the counter did not increase.
+pub async fn update_sign_count(
+    connection: &PgPool,
+    credential_id: &[u8],
+    sign_count: u32,
+) -> Result<bool, sqlx::Error> {
+    let done = sqlx::query(UPDATE_SIGN_COUNT)
+        .bind(sign_count as i64)
+        .bind(credential_id)
+        .execute(connection)
+        .await?;
+    Ok(done.rows_affected() == 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utility::create_db_pool;
+    use chrono::Utc;
+
+    #[ignore = "Needs database to run"]
+    #[actix_rt::test]
+    /// Tries to consume challenges once and to store, look up and update a credential.
+    async fn challenges_and_credentials() {
+        let username = format!("{}_webauthn", Utc::now()).replace(" ", "");
+        let pool = create_db_pool().await.unwrap();
+
+        User::register_user(&pool, &username, "password_hash")
+            .await
+            .unwrap();
+        let user = User::look_up_user(&pool, &username).await.unwrap();
+
+        let challenge = format!("{}_challenge", username);
+        store_challenge(&pool, Some(&user), &challenge)
+            .await
+            .unwrap();
+        assert!(!take_challenge(&pool, None, &challenge).await.unwrap());
+        assert!(take_challenge(&pool, Some(&user), &challenge)
+            .await
+            .unwrap());
+        assert!(!take_challenge(&pool, Some(&user), &challenge)
+            .await
+            .unwrap());
+
+        // Storing a challenge deletes the expired ones
+        let expired = format!("{}_expired", username);
+        sqlx::query(INSERT_EXPIRED_CHALLENGE)
+            .bind(&expired)
+            .execute(&pool)
+            .await
+            .unwrap();
+        store_challenge(&pool, None, &challenge).await.unwrap();
+        let remaining: (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM webauthn_challenges WHERE challenge = $1;")
+                .bind(&expired)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(remaining.0, 0);
+        assert!(take_challenge(&pool, None, &challenge).await.unwrap());
+
+        let credential = Credential {
+            id: username.as_bytes().to_vec(),
+            public_key: vec![4; 65],
+            sign_count: 0,
+        };
+        store_credential(&pool, &user, &credential).await.unwrap();
+        assert!(store_credential(&pool, &user, &credential).await.is_err());
+        assert_eq!(
+            look_up_credential(&pool, &credential.id).await.unwrap(),
+            (user, credential.clone())
+        );
+
+        assert!(update_sign_count(&pool, &credential.id, 5).await.unwrap());
+        assert!(!update_sign_count(&pool, &credential.id, 5).await.unwrap());
+        assert!(update_sign_count(&pool, &credential.id, 6).await.unwrap());
+        assert_eq!(
+            look_up_credential(&pool, &credential.id)
+                .await
+                .unwrap()
+                .1
+                .sign_count,
+            6
+        );
+    }
+}
@@ -0,0 +1,171 @@
+//! Contains the procedural macros of the middleware, which are re-exported by the `middleware` crate.
+//!
+//! The generated code refers to the `middleware` crate, so depend on it instead of using this crate directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::parse::{Parse, ParseStream, Parser};
+use syn::punctuated::Punctuated;
+use syn::{parse_quote, Error, FnArg, Ident, ItemFn, LitStr, Token, Type};
+
+/// The extractors that provide an authenticated user and can therefore be checked for capabilities.
+const USER_EXTRACTORS: &[&str] = &["UserDetails", "ResourceDetails"];
+
+/// Requires the user of a route handler to have every listed capability.
+///
+/// The handler needs a `UserDetails` or `ResourceDetails` argument. Its type is wrapped in `middleware::Authorized`,
+/// an extractor that responds with `403 Forbidden` before the handler is called if the user lacks a capability.
+/// Capabilities are either identifiers or string literals, e.g. for namespaced capabilities like `"billing:read"`.
+///
+/// # Example usage
+/// ```ignore
+/// #[require_capabilities(AdminRead, "billing:read")]
+/// pub async fn retrieve_admin_information(
+///     user_details: UserDetails<PostgreSqlBackend>,
+/// ) -> Result<String> {
+///     Ok(format!("Admin information: {:?}", user_details.user))
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn require_capabilities(args: TokenStream, item: TokenStream) -> TokenStream {
+    expand(args.into(), item.into())
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
+
+/// A capability that is written as identifier or string literal.
+struct Capability(LitStr);
+
+impl Parse for Capability {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let capability = if input.peek(LitStr) {
+            input.parse()?
+        } else {
+            let ident: Ident = input.parse()?;
+            LitStr::new(&ident.to_string(), ident.span())
+        };
+
+        if capability.value().trim().is_empty() {
+            return Err(Error::new(
+                capability.span(),
+                "capabilities must not be empty",
+            ));
+        }
+        Ok(Capability(capability))
+    }
+}
+
+fn expand(args: TokenStream2, item: TokenStream2) -> syn::Result<TokenStream2> {
+    let capabilities: Vec<LitStr> = Punctuated::<Capability, Token![,]>::parse_terminated
+        .parse2(args.clone())?
+        .into_iter()
+        .map(|capability| capability.0)
+        .collect();
+    if capabilities.is_empty() {
+        return Err(Error::new_spanned(args, "expected at least one capability"));
+    }
+
+    let mut function: ItemFn = syn::parse2(item)?;
+    let name = function.sig.ident.clone();
+    let marker = format_ident!("__{}_capabilities", name);
+
+    // Wrap the type of the argument that provides the user, the binding in the pattern stays the same
+    let argument = function
+        .sig
+        .inputs
+        .iter_mut()
+        .find_map(|input| match input {
+            FnArg::Typed(argument) if is_user_extractor(&argument.ty) => Some(argument),
+            _ => None,
+        })
+        .ok_or_else(|| {
+            Error::new(
+                name.span(),
+                "#[require_capabilities] needs a `UserDetails` or `ResourceDetails` argument",
+            )
+        })?;
+    let (pat, ty) = (argument.pat.clone(), argument.ty.clone());
+    *argument.pat = parse_quote!(::middleware::Authorized { inner: #pat, .. });
+    *argument.ty = parse_quote!(::middleware::Authorized<#ty, #marker>);
+
+    let vis = &function.vis;
+    Ok(quote! {
+        #[doc(hidden)]
+        #[allow(non_camel_case_types)]
+        #vis struct #marker;
+
+        impl ::middleware::CapabilityRequirement for #marker {
+            fn requirement() -> ::middleware::Requirement {
+                ::middleware::Requirement::all(vec![
+                    #(::middleware::Requirement::capability(#capabilities)),*
+                ])
+            }
+        }
+
+        #function
+    })
+}
+
+/// Returns `true` if the type is one of the [`USER_EXTRACTORS`], which is compared by the last segment of its path.
+fn is_user_extractor(ty: &Type) -> bool {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| USER_EXTRACTORS.iter().any(|e| segment.ident == e)),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_user_extractor() {
+        let expanded = expand(
+            quote!(AdminRead, "billing:read"),
+            quote! {
+                pub async fn admin(
+                    state: SessionState<B>,
+                    user_details: UserDetails<B>,
+                ) -> Result<String> {
+                    Ok(user_details.user.username)
+                }
+            },
+        )
+        .unwrap()
+        .to_string();
+
+        assert!(expanded.contains("pub struct __admin_capabilities"));
+        assert!(expanded.contains(
+            &quote!(::middleware::Authorized<UserDetails<B>, __admin_capabilities>).to_string()
+        ));
+        assert!(expanded.contains(&quote!(state: SessionState<B>).to_string()));
+        assert!(expanded.contains("\"AdminRead\""));
+        assert!(expanded.contains("\"billing:read\""));
+    }
+
+    #[test]
+    fn invalid_usage() {
+        let error = |args, item| expand(args, item).unwrap_err().to_string();
+        let handler = quote!(
+            async fn admin(user_details: UserDetails<B>) {}
+        );
+
+        assert_eq!(
+            error(quote!(), handler.clone()),
+            "expected at least one capability"
+        );
+        assert_eq!(error(quote!(""), handler), "capabilities must not be empty");
+        assert!(error(
+            quote!(AdminRead),
+            quote!(
+                async fn admin(req: HttpRequest) {}
+            )
+        )
+        .contains("needs a `UserDetails` or `ResourceDetails` argument"));
+    }
+}
@@ -0,0 +1,469 @@
+//! Contains the [Csrf] middleware that protects state-changing requests against cross-site request forgery.
+//!
+//! Every request that is not `GET`, `HEAD`, `OPTIONS` or `TRACE` has to
+//! - come from an allowed origin, if the browser sent an `Origin` or `Referer` header, and
+//! - contain the [CsrfToken] of the client, either in the `csrf_token` field of a form or in the `X-CSRF-Token` header.
+//!
+//! The token is bound to the session of the user. Clients without a session, e.g. on the login and registration
+//! pages, get a pre-session token that is bound to a random `csrf` cookie. As the token is derived from the session id
+//! or the cookie with HMAC, it does not need to be stored. Mismatches are rejected with `403 Forbidden`.
+
+use crate::DynamicFutureReturn;
+use actix_service::{Service, Transform};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{Payload, PayloadStream, ServiceRequest, ServiceResponse};
+use actix_web::error::{ErrorForbidden, ErrorInternalServerError, ErrorPayloadTooLarge};
+use actix_web::http::{header, Method};
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest};
+use futures_util::future::{ok, ready, Ready};
+use futures_util::{stream, StreamExt};
+use rand::RngCore;
+use ring::hmac;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// The name of the form field that contains the token.
+pub const FIELD_NAME: &str = "csrf_token";
+
+/// The name of the header that contains the token, which is used by scripts.
+pub const HEADER_NAME: &str = "x-csrf-token";
+
+/// The name of the cookie that binds the pre-session token to the browser.
+const COOKIE_NAME: &str = "csrf";
+
+/// The largest form that is searched for the token, larger bodies are rejected with `413 Payload Too Large`.
+const MAX_FORM_SIZE: usize = 64 * 1024;
+
+/// The token of a client, which has to be sent back with every state-changing request.
+///
+/// The [Csrf] middleware must wrap the route, otherwise the extractor responds with `500 Internal Server Error`.
+///
+/// # Example usage
+/// ```ignore
+/// // The template contains `{{ csrf_token.hidden_field()|safe }}` inside of the form
+/// pub async fn login_page(csrf_token: CsrfToken) -> impl Responder {
+///     LoginPage::new(csrf_token)
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsrfToken(String);
+
+impl CsrfToken {
+    /// Returns the token, e.g. for the `X-CSRF-Token` header.
+    pub fn value(&self) -> &str {
+        &self.0
+    }
+
+    /// Returns a hidden input that submits the token with a form.
+    ///
+    /// The token is base64url encoded, so it is safe to use the field without escaping.
+    pub fn hidden_field(&self) -> String {
+        format!(
+            r#"<input type="hidden" name="{}" value="{}">"#,
+            FIELD_NAME, self.0
+        )
+    }
+}
+
+impl FromRequest for CsrfToken {
+    type Config = ();
+    type Error = Error;
+    type Future = Ready<Result<CsrfToken, Error>>;
+
+    #[inline]
+    fn from_request(req: &HttpRequest, _: &mut Payload<PayloadStream>) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<CsrfToken>()
+                .cloned()
+                .ok_or_else(|| ErrorInternalServerError("extractor failed")),
+        )
+    }
+}
+
+/// The middleware that rejects cross-site requests, see the [module documentation](self).
+///
+/// Wrap the whole app, so that every page can render forms with a [CsrfToken]. Clones share the key, so a single
+/// instance can be cloned into the workers of the server.
+///
+/// # Example usage
+/// ```ignore
+/// let csrf = Csrf::new(&secret);
+/// HttpServer::new(move || App::new().wrap(csrf.clone()).configure(configuration::website))
+/// ```
+#[derive(Clone)]
+pub struct Csrf {
+    key: hmac::Key,
+    allowed_origins: Arc<HashSet<String>>,
+}
+
+impl Csrf {
+    /// Creates the middleware with a secret of at least 32 bytes, which has to be shared by every instance of the
+    /// service.
+    ///
+    /// Requests are only allowed from the origin of the service itself, use [`Csrf::with_allowed_origin`] to allow
+    /// further origins.
+    pub fn new(secret: &[u8]) -> Self {
+        Csrf {
+            key: hmac::Key::new(hmac::HMAC_SHA256, secret),
+            allowed_origins: Arc::new(HashSet::new()),
+        }
+    }
+
+    /// Creates the middleware with a random secret, so the tokens become invalid with a restart of the service.
+    pub fn random() -> Self {
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        Self::new(&secret)
+    }
+
+    /// Allows requests from another origin, e.g. `https://example.com`.
+    pub fn with_allowed_origin(mut self, origin: impl Into<String>) -> Self {
+        Arc::make_mut(&mut self.allowed_origins).insert(origin.into());
+        self
+    }
+
+    /// Derives the token of a session or of a pre-session cookie.
+    ///
+    /// The purpose is part of the message, so that a session id can never produce the token of a pre-session cookie.
+    fn token(&self, purpose: &str, value: &str) -> String {
+        let message = format!("{}\0{}", purpose, value);
+        base64::encode_config(
+            hmac::sign(&self.key, message.as_bytes()),
+            base64::URL_SAFE_NO_PAD,
+        )
+    }
+
+    /// Returns whether the submitted token is the expected one, compared in constant time.
+    fn verify(&self, purpose: &str, value: &str, submitted: &str) -> bool {
+        let message = format!("{}\0{}", purpose, value);
+        base64::decode_config(submitted, base64::URL_SAFE_NO_PAD)
+            .map(|tag| hmac::verify(&self.key, message.as_bytes(), &tag).is_ok())
+            .unwrap_or(false)
+    }
+
+    /// Returns whether the `Origin` or, if it is missing, the `Referer` of the request is allowed.
+    ///
+    /// Requests without both headers are allowed, as they are not sent by every client, the token is checked anyway.
+    fn origin_allowed(&self, req: &ServiceRequest) -> bool {
+        let origin = match req.headers().get(header::ORIGIN) {
+            Some(origin) => origin.to_str().ok().map(str::to_string),
+            None => match req.headers().get(header::REFERER) {
+                Some(referer) => referer.to_str().ok().and_then(origin_of),
+                None => return true,
+            },
+        };
+        let origin = match origin {
+            Some(origin) => origin,
+            None => return false,
+        };
+
+        let info = req.connection_info();
+        origin == format!("{}://{}", info.scheme(), info.host())
+            || self.allowed_origins.contains(&origin)
+    }
+}
+
+/// Replaces the [CsrfToken] of the request with the token of a session that has been started before the handler, e.g.
+/// by a remember-me token, so that the rendered forms match the session cookie of the response.
+pub(crate) fn bind_to_session(req: &ServiceRequest, session_id: &str) {
+    let csrf = req.extensions().get::<Csrf>().cloned();
+    if let Some(csrf) = csrf {
+        req.extensions_mut()
+            .insert(CsrfToken(csrf.token("session", session_id)));
+    }
+}
+
+/// Returns the origin of a URL, e.g. `https://example.com:8080` of `https://example.com:8080/login?next=/`.
+fn origin_of(url: &str) -> Option<String> {
+    let scheme_end = url.find("://")? + 3;
+    let host_end = url[scheme_end..]
+        .find(['/', '?', '#'])
+        .map_or(url.len(), |end| scheme_end + end);
+    Some(url[..host_end].to_string())
+}
+
+/// Returns whether requests with the method must not change any state.
+fn is_safe(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
+
+/// Reads the token out of an urlencoded form and puts the body back into the request for the handler.
+async fn form_token(req: &mut ServiceRequest) -> Result<Option<String>, Error> {
+    let mut body = BytesMut::new();
+    let mut payload = req.take_payload();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > MAX_FORM_SIZE {
+            return Err(ErrorPayloadTooLarge("form is too large"));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let body = body.freeze();
+
+    let token = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&body)
+        .ok()
+        .and_then(|fields| {
+            fields
+                .into_iter()
+                .find(|(name, _)| name == FIELD_NAME)
+                .map(|(_, value)| value)
+        });
+    let body: PayloadStream = Box::pin(stream::once(ready(Ok::<Bytes, _>(body))));
+    req.set_payload(Payload::Stream(body));
+
+    Ok(token)
+}
+
+impl<S, B> Transform<S> for Csrf
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CsrfMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CsrfMiddleware {
+            csrf: self.clone(),
+            service: Rc::new(RefCell::new(service)),
+        })
+    }
+}
+
+/// Actix Web middleware that is created by [Csrf].
+pub struct CsrfMiddleware<S> {
+    csrf: Csrf,
+    service: Rc<RefCell<S>>,
+}
+
+impl<S, B> Service for CsrfMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = DynamicFutureReturn<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let mut srv = self.service.clone();
+        let csrf = self.csrf.clone();
+
+        Box::pin(async move {
+            // The token of a session takes precedence, a new pre-session cookie is only set if it is needed
+            let (purpose, value, new_cookie) = match (req.cookie("id"), req.cookie(COOKIE_NAME)) {
+                (Some(session), _) => ("session", session.value().to_string(), false),
+                (None, Some(cookie)) => ("pre-session", cookie.value().to_string(), false),
+                (None, None) => {
+                    let mut nonce = [0u8; 32];
+                    rand::thread_rng().fill_bytes(&mut nonce);
+                    let nonce = base64::encode_config(nonce, base64::URL_SAFE_NO_PAD);
+                    ("pre-session", nonce, true)
+                }
+            };
+
+            if !is_safe(req.method()) {
+                if !csrf.origin_allowed(&req) {
+                    return Err(ErrorForbidden("cross-site request"));
+                }
+
+                let header = req
+                    .headers()
+                    .get(HEADER_NAME)
+                    .and_then(|token| token.to_str().ok())
+                    .map(str::to_string);
+                let submitted = match header {
+                    Some(token) => Some(token),
+                    None if req.content_type() == "application/x-www-form-urlencoded" => {
+                        form_token(&mut req).await?
+                    }
+                    None => None,
+                };
+                // A new pre-session cookie can never match, as the client did not know it
+                let valid = !new_cookie
+                    && submitted.is_some_and(|token| csrf.verify(purpose, &value, &token));
+                if !valid {
+                    return Err(ErrorForbidden("invalid CSRF token"));
+                }
+            }
+
+            req.extensions_mut()
+                .insert(CsrfToken(csrf.token(purpose, &value)));
+            req.extensions_mut().insert(csrf);
+            let mut res = srv.call(req).await?;
+
+            if new_cookie {
+                let cookie = Cookie::build(COOKIE_NAME, value)
+                    .secure(true)
+                    .http_only(true)
+                    .same_site(SameSite::Lax)
+                    .path("/")
+                    .finish();
+                res.response_mut().add_cookie(&cookie)?;
+            }
+
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{self, TestRequest};
+    use actix_web::web::{self, Form};
+    use actix_web::{http::StatusCode, App};
+    use std::collections::HashMap;
+
+    #[test]
+    /// Makes sure a token only verifies for the value and purpose it has been derived from.
+    fn tokens() {
+        let csrf = Csrf::new(b"secretsecretsecretsecretsecretse");
+        let token = csrf.token("session", "id");
+
+        assert!(csrf.verify("session", "id", &token));
+        assert!(!csrf.verify("session", "other", &token));
+        assert!(!csrf.verify("pre-session", "id", &token));
+        assert!(!csrf.verify("session", "id", "not base64!"));
+        assert!(!Csrf::random().verify("session", "id", &token));
+    }
+
+    #[test]
+    /// Makes sure only the own host and allowed origins pass the `Origin` or `Referer` check, unless both are missing.
+    fn origins() {
+        let csrf = Csrf::random().with_allowed_origin("https://example.com");
+        let allowed = |name, value| {
+            csrf.origin_allowed(
+                &TestRequest::post()
+                    .header(header::HOST, "localhost:8080")
+                    .header(name, value)
+                    .to_srv_request(),
+            )
+        };
+
+        assert!(allowed(header::ORIGIN, "http://localhost:8080"));
+        assert!(allowed(header::ORIGIN, "https://example.com"));
+        assert!(!allowed(header::ORIGIN, "https://attacker.example"));
+        assert!(!allowed(header::ORIGIN, "null"));
+        assert!(allowed(
+            header::REFERER,
+            "http://localhost:8080/login?next=/"
+        ));
+        assert!(!allowed(
+            header::REFERER,
+            "https://attacker.example/localhost:8080"
+        ));
+        assert!(csrf.origin_allowed(&TestRequest::post().to_srv_request()));
+    }
+
+    #[test]
+    /// Makes sure the origin of a URL keeps the scheme, host and port and URLs without a scheme have none.
+    fn urls() {
+        assert_eq!(
+            origin_of("https://example.com:8080/login?next=/").as_deref(),
+            Some("https://example.com:8080")
+        );
+        assert_eq!(
+            origin_of("https://example.com").as_deref(),
+            Some("https://example.com")
+        );
+        assert_eq!(origin_of("example.com/login"), None);
+    }
+
+    #[actix_rt::test]
+    /// Makes sure forms are only accepted with the token of the client and reach the handler unchanged.
+    async fn middleware() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Csrf::random())
+                .route(
+                    "/",
+                    web::get().to(|token: CsrfToken| async move { token.0 }),
+                )
+                .route(
+                    "/",
+                    web::post().to(|form: Form<HashMap<String, String>>| async move {
+                        form.get("name").cloned().unwrap_or_default()
+                    }),
+                ),
+        )
+        .await;
+
+        let resp = test::call_service(&mut app, TestRequest::get().uri("/").to_request()).await;
+        let cookie = resp
+            .response()
+            .cookies()
+            .find(|c| c.name() == COOKIE_NAME)
+            .unwrap()
+            .into_owned();
+        let token = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+
+        let post = |token: &str| {
+            TestRequest::post()
+                .uri("/")
+                .cookie(cookie.clone())
+                .set_form(&[("name", "jean-luc"), (FIELD_NAME, token)])
+        };
+        let resp = test::call_service(&mut app, post(&token).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(test::read_body(resp).await, "jean-luc");
+
+        let resp = app.call(post("forged").to_request()).await;
+        assert_eq!(status(resp), StatusCode::FORBIDDEN);
+        let resp = app
+            .call(
+                post(&token)
+                    .header(header::ORIGIN, "https://attacker.example")
+                    .to_request(),
+            )
+            .await;
+        assert_eq!(status(resp), StatusCode::FORBIDDEN);
+
+        // Scripts send the token in the header
+        let req = TestRequest::post()
+            .uri("/")
+            .cookie(cookie.clone())
+            .header(HEADER_NAME, token.as_str())
+            .set_form(&[("name", "jean-luc")])
+            .to_request();
+        assert_eq!(
+            test::call_service(&mut app, req).await.status(),
+            StatusCode::OK
+        );
+
+        // The token of the pre-session is not valid for a session
+        let req = TestRequest::post()
+            .uri("/")
+            .cookie(Cookie::new("id", "session"))
+            .header(HEADER_NAME, token.as_str())
+            .to_request();
+        assert_eq!(status(app.call(req).await), StatusCode::FORBIDDEN);
+    }
+
+    /// Returns the status of the response, the errors of the middleware are turned into responses by the server.
+    fn status<B>(result: Result<ServiceResponse<B>, Error>) -> StatusCode {
+        match result {
+            Ok(resp) => resp.status(),
+            Err(err) => err.as_response_error().status_code(),
+        }
+    }
+}
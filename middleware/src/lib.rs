@@ -1,26 +1,56 @@
 //! Contains the middleware that uses a [Backend] implementation to secure routes.
 //!
 //! The [RustAuthMiddleware] struct provides a [`RustAuthMiddleware::new`] function that initializes the middleware.
+//! Handlers can declare the capabilities they require with the [require_capabilities] attribute.
+//! The request rate of resources can be limited with the [`rate_limit::RateLimit`] middleware.
+//! Forms are protected against cross-site request forgery by the [`csrf::Csrf`] middleware.
+//!
+//! Users that log in with `remember` get a long-lived `remember` cookie next to the session cookie `id`. If the session
+//! has expired, the middleware exchanges the remember-me token for a new session before the handler is called.
+
+/// Protection against cross-site request forgery with tokens bound to the session.
+pub mod csrf;
+/// Rate limiting of requests with token buckets per client IP, user or custom key.
+pub mod rate_limit;
+
+pub use access_control::requirement::Requirement;
+pub use middleware_macros::require_capabilities;
 
-use access_control::{AccessControl, Backend};
+use access_control::access_policy::{AccessPolicy, RequestAttributes};
+use access_control::password_policy::{default_policy, PasswordPolicy};
+use access_control::remember::{self, RememberToken};
+use access_control::session::{SessionInfo, SessionLifetime, SessionMetadata};
+use access_control::throttling::Throttling;
+use access_control::totp::Totp;
+use access_control::username_policy::UsernamePolicy;
+use access_control::webauthn::{AssertionResponse, RegistrationResponse, RelyingParty};
+use access_control::{AccessControl, Authenticated, Authentication, Backend, TotpPending, User};
 use actix_service::{Service, Transform};
 use actix_web::cookie::{Cookie, SameSite};
-use actix_web::dev::{Payload, PayloadStream, ServiceRequest, ServiceResponse};
+use actix_web::dev::{Payload, PayloadStream, RequestHead, ServiceRequest, ServiceResponse};
 use actix_web::error::{
-    ErrorBadRequest, ErrorForbidden, ErrorInternalServerError, ErrorUnauthorized,
+    ErrorBadRequest, ErrorForbidden, ErrorInternalServerError, ErrorNotFound, ErrorUnauthorized,
 };
-use actix_web::{Error, FromRequest, HttpMessage, HttpRequest};
+use actix_web::http::{header, Method, StatusCode};
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError};
 use futures_core::Future;
 use futures_util::future::{ok, Ready};
 use rand::RngCore;
 use std::cell::RefCell;
-use std::collections::HashSet;
+use std::collections::HashMap;
+use std::fmt;
 use std::marker::PhantomData;
+use std::net::IpAddr;
 use std::pin::Pin;
 use std::rc::Rc;
+use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::SystemTime;
 use time::{Duration, OffsetDateTime};
 
+/// The name of the cookie that contains the remember-me token.
+const REMEMBER_COOKIE: &str = "remember";
+
 /// A simple type to describe a dynamic Future to make clippy happy.
 type DynamicFutureReturn<R> = Pin<Box<dyn Future<Output = R>>>;
 
@@ -32,21 +62,156 @@ where
     T: Backend,
 {
     pub backend: T,
-    pub required_capabilities: HashSet<String>,
+    pub required_capabilities: Requirement,
+    /// Requirements that replace `required_capabilities` for requests with the method
+    pub method_requirements: HashMap<Method, Requirement>,
+    pub username_policy: Arc<UsernamePolicy>,
+    pub password_policy: Arc<dyn PasswordPolicy>,
+    pub resource_scope: Option<ResourceScope>,
+    pub access_policy: Option<Arc<AccessPolicy>>,
+    pub relying_party: Option<Arc<RelyingParty>>,
+    pub throttling: Arc<Throttling>,
+    pub session_lifetime: SessionLifetime,
 }
 
 impl<T> RustAuthMiddleware<T>
 where
     T: Backend,
 {
-    /// Create a new instance of the [RustAuthMiddleware] by providing a [Backend] implementation and a list of required capabilities.
+    /// Create a new instance of the [RustAuthMiddleware] by providing a [Backend] implementation and the required capabilities.
     ///
     /// The [Backend] is used to extract and set User and Sessions by providing a generic trait.
-    /// The [HashSet] described what capabilities a user needs to have, go access this route.
-    pub fn new(backend: T, required_capabilities: HashSet<String>) -> Self {
+    /// The [Requirement] describes what capabilities a user needs to have, to access this route.
+    /// A [HashSet](std::collections::HashSet) of capabilities can be passed as well, in which case a user needs to
+    /// have all of them.
+    /// New users have to match the default [UsernamePolicy] and the [`default_policy`] for passwords, use
+    /// [`RustAuthMiddleware::with_username_policy`] and [`RustAuthMiddleware::with_password_policy`] to replace them.
+    /// Failed logins are throttled by the default [Throttling], see [`RustAuthMiddleware::with_throttling`].
+    /// Sessions expire after the default [SessionLifetime], see [`RustAuthMiddleware::with_session_lifetime`].
+    pub fn new(backend: T, required_capabilities: impl Into<Requirement>) -> Self {
         Self {
             backend,
-            required_capabilities,
+            required_capabilities: required_capabilities.into(),
+            method_requirements: HashMap::new(),
+            username_policy: Arc::new(UsernamePolicy::default()),
+            password_policy: Arc::new(default_policy()),
+            resource_scope: None,
+            access_policy: None,
+            relying_party: None,
+            throttling: Arc::new(Throttling::default()),
+            session_lifetime: SessionLifetime::default(),
+        }
+    }
+
+    /// Sets the [Requirement] for requests with the HTTP method, which replaces the required capabilities that have
+    /// been passed to [`RustAuthMiddleware::new`].
+    ///
+    /// Methods without a requirement of their own fall back to the required capabilities.
+    ///
+    /// # Example usage
+    /// ```ignore
+    /// // Reading a document needs `DocRead`, deleting it needs `DocAdmin`
+    /// resource("/documents/{id}")
+    ///     .wrap(
+    ///         RustAuthMiddleware::new(backend, Requirement::capability("DocRead"))
+    ///             .with_method_requirement(Method::DELETE, Requirement::capability("DocAdmin")),
+    ///     )
+    ///     .route(get().to(routes::read_document))
+    ///     .route(delete().to(routes::delete_document))
+    /// ```
+    pub fn with_method_requirement(
+        mut self,
+        method: Method,
+        requirement: impl Into<Requirement>,
+    ) -> Self {
+        self.method_requirements.insert(method, requirement.into());
+        self
+    }
+
+    /// Replaces the [UsernamePolicy] that is used to check the usernames of new users.
+    pub fn with_username_policy(mut self, username_policy: Arc<UsernamePolicy>) -> Self {
+        self.username_policy = username_policy;
+        self
+    }
+
+    /// Replaces the [PasswordPolicy] that is used to check the passwords of new users.
+    pub fn with_password_policy(mut self, password_policy: Arc<dyn PasswordPolicy>) -> Self {
+        self.password_policy = password_policy;
+        self
+    }
+
+    /// Sets the [ResourceScope] that is checked by the [ResourceDetails] extractor.
+    pub fn with_resource_scope(mut self, resource_scope: ResourceScope) -> Self {
+        self.resource_scope = Some(resource_scope);
+        self
+    }
+
+    /// Sets an [AccessPolicy] that is evaluated after the user satisfied the required capabilities.
+    ///
+    /// The [UserDetails] and [ResourceDetails] extractors respond with `403 Forbidden` if the policy denies the
+    /// request. The decision trace is logged at the `debug` level.
+    pub fn with_access_policy(mut self, access_policy: Arc<AccessPolicy>) -> Self {
+        self.access_policy = Some(access_policy);
+        self
+    }
+
+    /// Sets the [RelyingParty] that enables the registration and login with passkeys, see
+    /// [`SessionState::webauthn_login`].
+    pub fn with_relying_party(mut self, relying_party: Arc<RelyingParty>) -> Self {
+        self.relying_party = Some(relying_party);
+        self
+    }
+
+    /// Replaces the [Throttling] that delays and locks out failed logins per account and client IP.
+    ///
+    /// The client IP is the peer address of the connection. Behind a reverse proxy, this is the IP of the proxy, so
+    /// the client IP limits should be loosened accordingly.
+    pub fn with_throttling(mut self, throttling: Arc<Throttling>) -> Self {
+        self.throttling = throttling;
+        self
+    }
+
+    /// Replaces the [SessionLifetime] of new sessions.
+    ///
+    /// Every request that is authenticated by the session cookie extends the session by the idle timeout with
+    /// [`Backend::touch_session`], the cookie expires at the same time. Use the same lifetime for every route.
+    pub fn with_session_lifetime(mut self, session_lifetime: SessionLifetime) -> Self {
+        self.session_lifetime = session_lifetime;
+        self
+    }
+}
+
+/// Describes which action on which type of resource a route executes and where to find the id of the resource.
+///
+/// The [ResourceDetails] extractor uses it to check whether the user has a grant for the resource.
+///
+/// # Example usage
+/// ```ignore
+/// // Users need a grant to `edit` the `document` with the id in the path
+/// resource("/documents/{id}").wrap(
+///     RustAuthMiddleware::new(backend, HashSet::new())
+///         .with_resource_scope(ResourceScope::new("edit", "document", "id")),
+/// )
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResourceScope {
+    pub action: String,
+    pub resource_type: String,
+    /// The name of the path segment that contains the id of the resource
+    pub id_param: String,
+}
+
+impl ResourceScope {
+    /// Creates a scope for the action on the resource type, which reads the resource id from the path segment.
+    pub fn new(
+        action: impl Into<String>,
+        resource_type: impl Into<String>,
+        id_param: impl Into<String>,
+    ) -> Self {
+        ResourceScope {
+            action: action.into(),
+            resource_type: resource_type.into(),
+            id_param: id_param.into(),
         }
     }
 }
@@ -69,6 +234,14 @@ where
         ok(AuthorizationMiddleware {
             backend: self.backend.clone(),
             required_capabilities: self.required_capabilities.clone(),
+            method_requirements: self.method_requirements.clone(),
+            username_policy: self.username_policy.clone(),
+            password_policy: self.password_policy.clone(),
+            resource_scope: self.resource_scope.clone(),
+            access_policy: self.access_policy.clone(),
+            relying_party: self.relying_party.clone(),
+            throttling: self.throttling.clone(),
+            session_lifetime: self.session_lifetime,
             service: Rc::new(RefCell::new(service)),
         })
     }
@@ -80,7 +253,15 @@ where
     T: Backend,
 {
     backend: T,
-    required_capabilities: HashSet<String>,
+    required_capabilities: Requirement,
+    method_requirements: HashMap<Method, Requirement>,
+    username_policy: Arc<UsernamePolicy>,
+    password_policy: Arc<dyn PasswordPolicy>,
+    resource_scope: Option<ResourceScope>,
+    access_policy: Option<Arc<AccessPolicy>>,
+    relying_party: Option<Arc<RelyingParty>>,
+    throttling: Arc<Throttling>,
+    session_lifetime: SessionLifetime,
     /// TODO: Check whether the `Rc<RefCell<S>>` structure is properly implemented and safe.
     /// Especially race conditions have not been checked yet.
     service: Rc<RefCell<S>>,
@@ -104,14 +285,51 @@ where
 
     fn call(&mut self, req: ServiceRequest) -> Self::Future {
         let mut srv = self.service.clone();
-        let required_caps = self.required_capabilities.clone();
+        // The extractors only see the requirement that applies to the method of this request
+        let required_caps = self
+            .method_requirements
+            .get(req.method())
+            .unwrap_or(&self.required_capabilities)
+            .clone();
         let backend = self.backend.clone();
+        let username_policy = self.username_policy.clone();
+        let password_policy = self.password_policy.clone();
+        let resource_scope = self.resource_scope.clone();
+        let access_policy = self.access_policy.clone();
+        let relying_party = self.relying_party.clone();
+        let throttling = self.throttling.clone();
+        let session_lifetime = self.session_lifetime;
 
         Box::pin(async move {
+            // Without a session cookie, the session is restored by the remember-me token
+            let mut session_id = req.cookie("id").map(|cookie| cookie.value().to_string());
+            let restored = match (&session_id, req.cookie(REMEMBER_COOKIE)) {
+                (None, Some(cookie)) => {
+                    let metadata = session_metadata(req.head());
+                    let restored =
+                        restore_session(&backend, cookie.value(), &session_lifetime, metadata)
+                            .await;
+                    if let Ok((Some(restored_id), _)) = &restored {
+                        csrf::bind_to_session(&req, restored_id);
+                        session_id = Some(restored_id.clone());
+                    }
+                    Some(restored)
+                }
+                _ => None,
+            };
+
             let item = SessionStateItem {
                 actions: Vec::new(),
+                session_id,
                 backend: backend.clone(),
                 required_caps,
+                username_policy,
+                password_policy,
+                resource_scope,
+                access_policy,
+                relying_party,
+                throttling,
+                session_lifetime,
             };
             req.extensions_mut().insert(item);
 
@@ -127,26 +345,111 @@ where
                 .remove::<SessionStateItem<T>>()
                 .unwrap();
 
+            let replaced = item.actions.iter().any(|action| {
+                matches!(
+                    action,
+                    SessionStateAction::Login(_) | SessionStateAction::Logout
+                )
+            });
+            // The handler either removed the series or started a new one
+            let forgotten = item.actions.iter().any(|action| {
+                matches!(
+                    action,
+                    SessionStateAction::Logout | SessionStateAction::Remember(_)
+                )
+            });
+            let restored_session = matches!(restored, Some(Ok((Some(_), _))));
+            match restored {
+                Some(Ok((restored_id, next))) => {
+                    match restored_id {
+                        // The handler logged the user in or out, so the restored session is not needed
+                        Some(restored_id) if replaced => {
+                            let _ = backend.remove_session(&restored_id).await;
+                        }
+                        Some(restored_id) => {
+                            let cookie = session_cookie(restored_id, session_lifetime.idle_timeout);
+                            res.response_mut().add_cookie(&cookie).unwrap();
+                        }
+                        None => {}
+                    }
+                    // The presented token has been used up, so the client must get the next one. A replay of the
+                    // previous token within the grace period keeps the cookie of the parallel request.
+                    if let (Some(next), false) = (next, forgotten) {
+                        res.response_mut()
+                            .add_cookie(&remember_cookie(&next))
+                            .unwrap();
+                    }
+                }
+                Some(Err(_)) if !forgotten => {
+                    if let Some(cookie) = res.request().cookie(REMEMBER_COOKIE) {
+                        res.response_mut().add_cookie(&expired(cookie)).unwrap();
+                    }
+                }
+                _ => {}
+            }
+
+            // A session is only extended once and not at all if the handler replaced or removed it or it is new
+            let mut touched = replaced || restored_session;
             for action in item.actions {
                 match action {
                     SessionStateAction::Login(session_id) => {
                         // Add cookie
-                        let cookie = Cookie::build("id", session_id)
+                        let cookie = session_cookie(session_id, session_lifetime.idle_timeout);
+                        res.response_mut().add_cookie(&cookie).unwrap();
+
+                        // The login may complete a pending session, which is not needed anymore
+                        if let Some(cookie) = res.request().cookie("totp") {
+                            let _ = backend.remove_session(cookie.value()).await;
+                            res.response_mut().add_cookie(&expired(cookie)).unwrap();
+                        }
+                    }
+                    SessionStateAction::PendingTotp(session_id) => {
+                        let cookie = Cookie::build("totp", session_id)
                             .secure(true)
                             .http_only(true)
-                            .same_site(SameSite::Lax)
+                            .same_site(SameSite::Strict)
                             .path("/")
                             .finish();
                         res.response_mut().add_cookie(&cookie).unwrap();
                     }
                     SessionStateAction::Logout => {
-                        if let Some(mut cookie) = res.request().cookie("id") {
+                        if let Some(cookie) = res.request().cookie("id") {
                             // Remove database session
                             let _ = backend.remove_session(cookie.value()).await;
                             // Delete the cookie
-                            cookie.set_value("");
-                            cookie.set_max_age(Duration::zero());
-                            cookie.set_expires(OffsetDateTime::now_utc() - Duration::days(365));
+                            res.response_mut().add_cookie(&expired(cookie)).unwrap();
+                        }
+                        if let Some(cookie) = res.request().cookie(REMEMBER_COOKIE) {
+                            if let Some(token) = RememberToken::parse(cookie.value()) {
+                                let _ = backend.remove_remember_token(&token.selector).await;
+                            }
+                            res.response_mut().add_cookie(&expired(cookie)).unwrap();
+                        }
+                    }
+                    SessionStateAction::Remember(token) => {
+                        // The new series replaces the previous series of the client
+                        let previous = res
+                            .request()
+                            .cookie(REMEMBER_COOKIE)
+                            .and_then(|cookie| RememberToken::parse(cookie.value()));
+                        if let Some(previous) = previous {
+                            let _ = backend.remove_remember_token(&previous.selector).await;
+                        }
+                        res.response_mut()
+                            .add_cookie(&remember_cookie(&token))
+                            .unwrap();
+                    }
+                    SessionStateAction::Touch(_) if touched => {}
+                    SessionStateAction::Touch(session_id) => {
+                        touched = true;
+                        let expiration = backend
+                            .touch_session(&session_id, session_lifetime.idle_timeout)
+                            .await;
+                        if let Some(expiration) = expiration {
+                            let max_age = expiration
+                                .duration_since(SystemTime::now())
+                                .unwrap_or_default();
+                            let cookie = session_cookie(session_id, max_age);
                             res.response_mut().add_cookie(&cookie).unwrap();
                         }
                     }
@@ -158,25 +461,133 @@ where
     }
 }
 
+/// Returns the session cookie, which the browser deletes after `max_age` like the backend deletes the session.
+fn session_cookie(session_id: String, max_age: std::time::Duration) -> Cookie<'static> {
+    Cookie::build("id", session_id)
+        .secure(true)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .path("/")
+        .max_age(Duration::seconds(max_age.as_secs() as i64))
+        .finish()
+}
+
+/// Returns the cookie of a remember-me token, which the browser keeps for the [`remember::LIFETIME`] of the series.
+fn remember_cookie(token: &RememberToken) -> Cookie<'static> {
+    Cookie::build(REMEMBER_COOKIE, token.value())
+        .secure(true)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .path("/")
+        .max_age(Duration::seconds(remember::LIFETIME.as_secs() as i64))
+        .finish()
+}
+
+/// Exchanges a remember-me token for a new session, returns its id and the next token of the series.
+///
+/// The session id is `None` if the session could not be stored, the next token has to be sent to the client anyway, as
+/// the presented token has been used up. There is no next token if a parallel request already rotated the series, see
+/// [`AccessControl::authenticate_remember_token`]. The selector of the series is stored with the session, so that
+/// revoking the session revokes the series as well.
+async fn restore_session<T>(
+    backend: &T,
+    value: &str,
+    session_lifetime: &SessionLifetime,
+    mut metadata: SessionMetadata,
+) -> Result<(Option<String>, Option<RememberToken>), access_control::Error>
+where
+    T: Backend + Clone,
+{
+    let token = RememberToken::parse(value).ok_or(access_control::Error::Authentication)?;
+    let (authenticated, next) = AccessControl::new(backend.clone())
+        .authenticate_remember_token(&token)
+        .await?;
+    let user = authenticated
+        .authorize(&Requirement::none())
+        .expect("no capabilities required to login")
+        .get_user();
+
+    metadata.remember_selector = Some(token.selector);
+    let session_id = generate_session_id();
+    let stored = backend
+        .store_session(&user, &session_id, session_lifetime, &metadata)
+        .await
+        .is_ok();
+
+    Ok((stored.then_some(session_id), next))
+}
+
+/// Returns the client IP address and user agent of a request, which are stored with a new session.
+///
+/// The client IP is the peer address of the connection, behind a reverse proxy this is the IP of the proxy.
+fn session_metadata(head: &RequestHead) -> SessionMetadata {
+    let user_agent = head
+        .headers()
+        .get(header::USER_AGENT)
+        .and_then(|value| value.to_str().ok());
+    SessionMetadata::new(head.peer_addr.map(|addr| addr.ip()), user_agent)
+}
+
+/// Returns a cookie that makes the browser delete the cookie.
+fn expired(mut cookie: Cookie<'static>) -> Cookie<'static> {
+    cookie.set_value("");
+    cookie.set_max_age(Duration::zero());
+    cookie.set_expires(OffsetDateTime::now_utc() - Duration::days(365));
+    cookie
+}
+
+/// Generates a new session ID.
+fn generate_session_id() -> String {
+    // Use 256 bit length for the session ID. This is double of the minimum required by OWASP.
+    let mut key = [0u8; 32];
+    // ThreadRng uses a CSPRNG as per
+    // https://rust-random.github.io/rand/rand/rngs/index.html#our-generators
+    rand::thread_rng().fill_bytes(&mut key);
+    base64::encode(key)
+}
+
 /// Enum with all of the possible actions that a route can add by calling SessionState::login or SessionState::logout.
 #[derive(Debug, Clone)]
 enum SessionStateAction {
     Login(String),
+    /// The password of a user with two-factor authentication has been verified
+    PendingTotp(String),
     Logout,
+    /// The session has authenticated the request and is extended
+    Touch(String),
+    /// The user wants to stay logged in with a new remember-me series
+    Remember(RememberToken),
+}
+
+/// The result of [`SessionState::login`].
+#[derive(Debug, Clone)]
+pub enum LoginOutcome<U> {
+    /// The user is logged in
+    Complete(U),
+    /// The password is correct, but the user has to enter a one-time password with [`SessionState::verify_totp`]
+    TotpRequired,
 }
 
 /// Provides an action to the middleware.
 ///
 /// Adding a [SessionStateItem] to the actix-web extensions provides the possibility to do some action after the request was handled by the route.
 /// [SessionState::login] and [SessionState::logout]
-#[derive(Debug)]
 struct SessionStateItem<B>
 where
     B: Backend,
 {
     actions: Vec<SessionStateAction>,
+    /// The `id` cookie or the session that has been restored by the remember-me token
+    session_id: Option<String>,
     backend: B,
-    required_caps: HashSet<String>,
+    required_caps: Requirement,
+    username_policy: Arc<UsernamePolicy>,
+    password_policy: Arc<dyn PasswordPolicy>,
+    resource_scope: Option<ResourceScope>,
+    access_policy: Option<Arc<AccessPolicy>>,
+    relying_party: Option<Arc<RelyingParty>>,
+    throttling: Arc<Throttling>,
+    session_lifetime: SessionLifetime,
 }
 
 /// Used to provide access to the users session by using the actix-web extractor.
@@ -194,68 +605,472 @@ where
     B: Backend + Clone + 'static,
 {
     /// Tries to login a user by providing username and password.
+    ///
+    /// If the user enabled two-factor authentication, a pending session is stored in a separate cookie and
+    /// [`LoginOutcome::TotpRequired`] is returned. The login is completed by [`SessionState::verify_totp`].
+    ///
+    /// After too many failed attempts of the username or the client IP, the login responds with
+    /// `429 Too Many Requests` and a `Retry-After` header, see [TooManyRequests].
+    ///
+    /// If `remember` is set, a completed login additionally issues a remember-me token, which restores the login after
+    /// the session expired, e.g. after the browser has been closed.
     pub async fn login(
         &self,
         username: impl AsRef<str>,
         password: impl AsRef<str>,
-    ) -> Result<B::User, Error> {
-        let mut extensions = self.req.extensions_mut();
-        let item = extensions
-            .get_mut::<SessionStateItem<B>>()
-            .ok_or_else(|| ErrorInternalServerError("extractor failed"))?;
+        remember: bool,
+    ) -> Result<LoginOutcome<B::User>, Error> {
+        let backend = self.backend()?;
+        let throttling = self.throttling()?;
+        let client_ip = self.client_ip();
 
+        // The username is canonicalized by AccessControl, so that the case is ignored as per
         // https://cheatsheetseries.owasp.org/cheatsheets/Authentication_Cheat_Sheet.html#user-ids
-        let username = username.as_ref().to_lowercase();
+        let authentication = AccessControl::new(backend.clone())
+            .authenticate_creds(username, password, client_ip, &throttling)
+            .await;
+        let authenticated = match authentication.map_err(login_error)? {
+            Authentication::Complete(authenticated) => authenticated,
+            Authentication::TotpRequired(pending) => {
+                let session_id = generate_session_id();
+                backend
+                    .store_pending_session(pending.pending_user(), &session_id, remember)
+                    .await
+                    .map_err(|_| ErrorInternalServerError("backend unavailable"))?;
+                self.push_action(SessionStateAction::PendingTotp(session_id))?;
+
+                return Ok(LoginOutcome::TotpRequired);
+            }
+        };
+
+        let user = self
+            .complete_login(&backend, authenticated, remember)
+            .await?;
+
+        Ok(LoginOutcome::Complete(user))
+    }
+
+    /// Tries to complete the login of a user with two-factor authentication by providing a one-time password.
+    ///
+    /// Responds with `401 Unauthorized` if there is no pending login or the one-time password is invalid. The pending
+    /// login stays valid for [`totp::MAX_ATTEMPTS`] attempts, so that the user can correct a typo. Failed attempts are
+    /// throttled like [`SessionState::login`].
+    ///
+    /// If the login asked to be remembered, a remember-me token is issued like by [`SessionState::login`].
+    pub async fn verify_totp(&self, code: impl AsRef<str>) -> Result<B::User, Error> {
+        let backend = self.backend()?;
+        let throttling = self.throttling()?;
+
+        let pending = self.resume_totp(&backend).await?;
+        let remember = pending.remember_requested();
+        let authenticated = pending
+            .verify_totp(code, self.client_ip(), &throttling)
+            .await
+            .map_err(login_error)?;
+
+        // A new session ID is used for the completed login, the pending session is removed by the middleware
+        self.complete_login(&backend, authenticated, remember).await
+    }
+
+    /// Tries to complete the login of a user with two-factor authentication by providing a recovery code instead of a
+    /// one-time password.
+    ///
+    /// Every recovery code can only be used once. Responds with `401 Unauthorized` if there is no pending login or the
+    /// recovery code is invalid. Failed attempts are throttled like [`SessionState::login`].
+    pub async fn verify_recovery_code(&self, code: impl AsRef<str>) -> Result<B::User, Error> {
+        let backend = self.backend()?;
+        let throttling = self.throttling()?;
+
+        let pending = self.resume_totp(&backend).await?;
+        let remember = pending.remember_requested();
+        let authenticated = pending
+            .verify_recovery_code(code, self.client_ip(), &throttling)
+            .await
+            .map_err(login_error)?;
+
+        self.complete_login(&backend, authenticated, remember).await
+    }
+
+    /// Resumes the pending login of the `totp` cookie.
+    async fn resume_totp(&self, backend: &B) -> Result<AccessControl<TotpPending, B>, Error> {
+        let pending_id = self
+            .req
+            .cookie("totp")
+            .ok_or_else(|| ErrorUnauthorized(access_control::Error::Authentication))?;
+
+        AccessControl::new(backend.clone())
+            .resume_totp(pending_id.value())
+            .await
+            .map_err(ErrorUnauthorized)
+    }
+
+    /// Generates a new batch of recovery codes for the logged-in user, which replaces the previous batch.
+    ///
+    /// The codes are only stored as hashes, so they have to be shown to the user in the response.
+    pub async fn generate_recovery_codes(&self) -> Result<Vec<String>, Error> {
+        self.authenticate()
+            .await?
+            .generate_recovery_codes()
+            .await
+            .map_err(|_| ErrorInternalServerError("backend unavailable"))
+    }
+
+    /// Tries to enable two-factor authentication for the logged-in user.
+    ///
+    /// The code must be a one-time password of the new secret, which confirms that the user set up the authenticator
+    /// app. Users that already enabled two-factor authentication have to provide the `current_code` of their current
+    /// secret. Responds with `400 Bad Request` if a code is invalid and with `429 Too Many Requests` after too many
+    /// invalid current codes.
+    pub async fn enable_totp(
+        &self,
+        totp: &Totp,
+        code: impl AsRef<str>,
+        current_code: Option<&str>,
+    ) -> Result<(), Error> {
+        let throttling = self.throttling()?;
 
-        let user = AccessControl::new(item.backend.clone())
-            .authenticate_creds(username, password)
+        self.authenticate()
+            .await?
+            .enable_totp(totp, code, current_code, self.client_ip(), &throttling)
             .await
-            .map_err(ErrorUnauthorized)?
-            .authorize(&HashSet::new())
+            .map_err(|err| match err {
+                access_control::Error::Throttled(retry_after) => {
+                    TooManyRequests(retry_after).into()
+                }
+                err => ErrorBadRequest(err),
+            })
+    }
+
+    /// Starts the login with a passkey and returns the options of `navigator.credentials.get` as JSON.
+    ///
+    /// Responds with `404 Not Found` if no [RelyingParty] has been set with [`RustAuthMiddleware::with_relying_party`].
+    pub async fn webauthn_login_options(&self) -> Result<String, Error> {
+        let relying_party = self.relying_party()?;
+
+        AccessControl::new(self.backend()?)
+            .begin_webauthn_login(&relying_party)
+            .await
+            .map_err(|_| ErrorInternalServerError("backend unavailable"))
+    }
+
+    /// Tries to login a user with the response of `navigator.credentials.get`.
+    ///
+    /// The authenticator verified the user, so no one-time password is required. Responds with `401 Unauthorized` if
+    /// the passkey is unknown or the response is invalid. Failed attempts are throttled like [`SessionState::login`].
+    pub async fn webauthn_login(&self, response: &AssertionResponse) -> Result<B::User, Error> {
+        let relying_party = self.relying_party()?;
+        let backend = self.backend()?;
+        let throttling = self.throttling()?;
+
+        let user = AccessControl::new(backend.clone())
+            .authenticate_webauthn(&relying_party, response, self.client_ip(), &throttling)
+            .await
+            .map_err(login_error)?
+            .authorize(&Requirement::none())
             .expect("no capabilities required to login")
             .get_user();
+        self.start_session(&backend, &user, None).await?;
+
+        Ok(user)
+    }
+
+    /// Starts the registration of a passkey for the logged-in user and returns the options of
+    /// `navigator.credentials.create` as JSON.
+    pub async fn webauthn_registration_options(&self) -> Result<String, Error> {
+        let relying_party = self.relying_party()?;
+
+        self.authenticate()
+            .await?
+            .begin_webauthn_registration(&relying_party)
+            .await
+            .map_err(|_| ErrorInternalServerError("backend unavailable"))
+    }
+
+    /// Tries to register a passkey for the logged-in user with the response of `navigator.credentials.create`.
+    ///
+    /// Responds with `400 Bad Request` if the response is invalid.
+    pub async fn register_webauthn(&self, response: &RegistrationResponse) -> Result<(), Error> {
+        let relying_party = self.relying_party()?;
+
+        self.authenticate()
+            .await?
+            .register_webauthn(&relying_party, response)
+            .await
+            .map_err(ErrorBadRequest)
+    }
+
+    /// Lists the active sessions of the logged-in user, the most recently seen first.
+    ///
+    /// The current session is marked with [`SessionInfo::current`].
+    pub async fn sessions(&self) -> Result<Vec<SessionInfo>, Error> {
+        let (user, session_id) = self.session_user().await?;
+
+        self.backend()?
+            .get_sessions(&user, session_id)
+            .await
+            .map_err(|_| ErrorInternalServerError("backend unavailable"))
+    }
 
-        // Use 256 bit length for the session ID. This is double of the minimum required by OWASP.
-        let mut key = [0u8; 32];
-        // ThreadRng uses a CSPRNG as per
-        // https://rust-random.github.io/rand/rand/rngs/index.html#our-generators
-        rand::thread_rng().fill_bytes(&mut key);
-        let session_id = base64::encode(key);
+    /// Revokes a session of the logged-in user by its [`SessionInfo::id`], e.g. of a lost device.
+    ///
+    /// The remember-me series that has been issued with or restored the session is revoked as well. Responds with `404 Not Found` if the user has no such session.
+    pub async fn revoke_session(&self, id: impl AsRef<str>) -> Result<(), Error> {
+        let (user, _) = self.session_user().await?;
 
-        item.backend
-            .store_session(&user, &session_id)
+        let revoked = self
+            .backend()?
+            .revoke_session(&user, id)
             .await
             .map_err(|_| ErrorInternalServerError("backend unavailable"))?;
+        if !revoked {
+            return Err(ErrorNotFound("unknown session"));
+        }
+        Ok(())
+    }
+
+    /// Revokes every session of the logged-in user except for the current one.
+    ///
+    /// The remember-me series of other devices are revoked as well, so that they cannot restore a session.
+    pub async fn revoke_other_sessions(&self) -> Result<(), Error> {
+        let (user, session_id) = self.session_user().await?;
+        let remember_selector = self
+            .req
+            .cookie(REMEMBER_COOKIE)
+            .and_then(|cookie| RememberToken::parse(cookie.value()))
+            .map(|token| token.selector);
+
+        self.backend()?
+            .revoke_other_sessions(&user, session_id, remember_selector.as_deref())
+            .await
+            .map_err(|_| ErrorInternalServerError("backend unavailable"))
+    }
+
+    /// Authenticates the logged-in user by the session cookie and returns it with the session id.
+    async fn session_user(&self) -> Result<(B::User, String), Error> {
+        let user = self
+            .authenticate()
+            .await?
+            .authorize(&Requirement::none())
+            .expect("no capabilities required to list sessions")
+            .get_user();
+        let session_id = session_id::<B>(&self.req)
+            .ok_or_else(|| ErrorUnauthorized(access_control::Error::Authentication))?;
+
+        Ok((user, session_id))
+    }
+
+    /// Authenticates the logged-in user by the session cookie.
+    async fn authenticate(&self) -> Result<AccessControl<Authenticated, B>, Error> {
+        let backend = self.backend()?;
+        let session_id = session_id::<B>(&self.req)
+            .ok_or_else(|| ErrorUnauthorized(access_control::Error::Authentication))?;
+
+        let authenticated = AccessControl::new(backend)
+            .authenticate_session(&session_id)
+            .await
+            .map_err(ErrorUnauthorized)?;
+        self.push_action(SessionStateAction::Touch(session_id))?;
+
+        Ok(authenticated)
+    }
 
-        item.actions.push(SessionStateAction::Login(session_id));
+    /// Stores a new session for the user and sets the session cookie.
+    /// Issues a remember-me token if `remember` is set and starts the session of an authenticated login.
+    async fn complete_login(
+        &self,
+        backend: &B,
+        authenticated: AccessControl<Authenticated, B>,
+        remember: bool,
+    ) -> Result<B::User, Error> {
+        let mut remember_selector = None;
+        if remember {
+            let token = authenticated
+                .remember()
+                .await
+                .map_err(|_| ErrorInternalServerError("backend unavailable"))?;
+            remember_selector = Some(token.selector.clone());
+            self.push_action(SessionStateAction::Remember(token))?;
+        }
+        let user = authenticated
+            .authorize(&Requirement::none())
+            .expect("no capabilities required to login")
+            .get_user();
+        self.start_session(backend, &user, remember_selector)
+            .await?;
 
         Ok(user)
     }
 
+    /// Stores a new session of the user, which records the selector of its remember-me series.
+    async fn start_session(
+        &self,
+        backend: &B,
+        user: &B::User,
+        remember_selector: Option<String>,
+    ) -> Result<(), Error> {
+        let session_id = generate_session_id();
+        let session_lifetime = self
+            .req
+            .extensions()
+            .get::<SessionStateItem<B>>()
+            .map(|item| item.session_lifetime)
+            .ok_or_else(|| ErrorInternalServerError("extractor failed"))?;
+
+        let metadata = SessionMetadata {
+            remember_selector,
+            ..session_metadata(self.req.head())
+        };
+
+        backend
+            .store_session(user, &session_id, &session_lifetime, &metadata)
+            .await
+            .map_err(|_| ErrorInternalServerError("backend unavailable"))?;
+
+        self.push_action(SessionStateAction::Login(session_id))
+    }
+
     /// Tries to logout a user
     pub async fn logout(&self) {
-        if let Some(item) = self.req.extensions_mut().get_mut::<SessionStateItem<B>>() {
-            item.actions.push(SessionStateAction::Logout);
-        }
+        let _ = self.push_action(SessionStateAction::Logout);
     }
 
     // TODO: Think about what is required to register a user. Maybe other appliances want to store additional user
     // details like first or last name ...
     /// Tries to register a new user.
+    ///
+    /// The returned error contains a [RegistrationError], which can be retrieved with [`Error::as_error`] to show the
+    /// reasons why the registration failed.
     pub async fn register(
         &self,
         username: impl AsRef<str>,
-        password_hash: impl AsRef<str>,
+        password: impl AsRef<str>,
     ) -> Result<(), Error> {
-        let mut extensions = self.req.extensions_mut();
-        let item = extensions
-            .get_mut::<SessionStateItem<B>>()
+        let (backend, username_policy, password_policy) = self
+            .req
+            .extensions()
+            .get::<SessionStateItem<B>>()
+            .map(|item| {
+                (
+                    item.backend.clone(),
+                    item.username_policy.clone(),
+                    item.password_policy.clone(),
+                )
+            })
             .ok_or_else(|| ErrorInternalServerError("extractor failed"))?;
 
-        AccessControl::new(item.backend.clone())
-            .register(username, password_hash)
+        AccessControl::new(backend)
+            .register(
+                username,
+                password,
+                username_policy.as_ref(),
+                password_policy.as_ref(),
+            )
             .await
-            .map_err(ErrorBadRequest)
+            .map_err(|err| RegistrationError(err).into())
+    }
+
+    /// Clones the [Backend] out of the requests extensions.
+    ///
+    /// The extensions are only borrowed for the duration of this call, so that no borrow is held across an await point.
+    fn backend(&self) -> Result<B, Error> {
+        self.req
+            .extensions()
+            .get::<SessionStateItem<B>>()
+            .map(|item| item.backend.clone())
+            .ok_or_else(|| ErrorInternalServerError("extractor failed"))
+    }
+
+    /// Clones the [Throttling] out of the requests extensions.
+    fn throttling(&self) -> Result<Arc<Throttling>, Error> {
+        self.req
+            .extensions()
+            .get::<SessionStateItem<B>>()
+            .map(|item| item.throttling.clone())
+            .ok_or_else(|| ErrorInternalServerError("extractor failed"))
+    }
+
+    /// Returns the IP address of the client, by which failed logins are throttled.
+    fn client_ip(&self) -> Option<IpAddr> {
+        self.req.peer_addr().map(|addr| addr.ip())
+    }
+
+    /// Clones the [RelyingParty] out of the requests extensions, passkeys are disabled if there is none.
+    fn relying_party(&self) -> Result<Arc<RelyingParty>, Error> {
+        self.req
+            .extensions()
+            .get::<SessionStateItem<B>>()
+            .ok_or_else(|| ErrorInternalServerError("extractor failed"))?
+            .relying_party
+            .clone()
+            .ok_or_else(|| ErrorNotFound("passkeys are not enabled"))
+    }
+
+    /// Adds a [SessionStateAction] that is executed by the middleware after the route handler finished.
+    fn push_action(&self, action: SessionStateAction) -> Result<(), Error> {
+        self.req
+            .extensions_mut()
+            .get_mut::<SessionStateItem<B>>()
+            .map(|item| item.actions.push(action))
+            .ok_or_else(|| ErrorInternalServerError("extractor failed"))
+    }
+}
+
+/// Turns the error of a failed login into `429 Too Many Requests` if it has been throttled, otherwise into
+/// `401 Unauthorized`.
+fn login_error(err: access_control::Error) -> Error {
+    match err {
+        access_control::Error::Throttled(retry_after) => TooManyRequests(retry_after).into(),
+        err => ErrorUnauthorized(err),
+    }
+}
+
+/// The error returned by [SessionState::register], responds with `400 Bad Request`.
+#[derive(Debug)]
+pub struct RegistrationError(pub access_control::Error);
+
+impl fmt::Display for RegistrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl ResponseError for RegistrationError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+/// The error that responds with `429 Too Many Requests` and a `Retry-After` header, which contains how long the
+/// client has to wait.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooManyRequests(pub std::time::Duration);
+
+impl TooManyRequests {
+    /// Returns the delay in whole seconds, rounded up so that the client does not retry too early.
+    pub fn retry_after_secs(&self) -> u64 {
+        self.0.as_secs() + u64::from(self.0.subsec_nanos() > 0)
+    }
+}
+
+impl fmt::Display for TooManyRequests {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Too many requests, retry after {} seconds",
+            self.retry_after_secs()
+        )
+    }
+}
+
+impl ResponseError for TooManyRequests {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::TOO_MANY_REQUESTS
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code())
+            .header(header::RETRY_AFTER, self.retry_after_secs())
+            .body(self.to_string())
     }
 }
 
@@ -277,6 +1092,9 @@ where
 }
 
 /// Provides a [Backend::User] to a request by extracting it from actix-webs extensions.
+///
+/// Responds with `403 Forbidden` if the user does not satisfy the [Requirement] for the method of the request, see
+/// [`RustAuthMiddleware::with_method_requirement`].
 pub struct UserDetails<B>
 where
     B: Backend,
@@ -298,22 +1116,560 @@ where
         Box::pin(async move {
             let err = || ErrorUnauthorized(access_control::Error::Authentication);
 
-            let cookie = req.cookie("id").ok_or_else(err)?;
-            let mut extensions = req.extensions_mut();
-            let item = extensions
-                .get_mut::<SessionStateItem<B>>()
+            let session_id = session_id::<B>(&req).ok_or_else(err)?;
+            let (backend, required_caps, access_policy) = req
+                .extensions()
+                .get::<SessionStateItem<B>>()
+                .map(|item| {
+                    (
+                        item.backend.clone(),
+                        item.required_caps.clone(),
+                        item.access_policy.clone(),
+                    )
+                })
                 .ok_or_else(err)?;
 
             // Authenticate and authorize with the session ID
-            let user = AccessControl::new(item.backend.clone())
-                .authenticate_session(cookie.value())
+            let authenticated = AccessControl::new(backend)
+                .authenticate_session(&session_id)
                 .await
-                .map_err(ErrorUnauthorized)?
-                .authorize(&item.required_caps)
+                .map_err(ErrorUnauthorized)?;
+            touch_session::<B>(&req, &session_id);
+            let user = authenticated
+                .authorize(&required_caps)
                 .map_err(ErrorForbidden)?
                 .get_user();
+            check_access_policy(&req, access_policy.as_deref(), &user)?;
 
             Ok(UserDetails { user })
         })
     }
 }
+
+/// Provides a [Backend::User] to a request if it has a valid session, for pages that work for logged-in and
+/// logged-out users alike.
+///
+/// Unlike [UserDetails], a missing or expired session yields `None` instead of `401 Unauthorized`. A logged-in user
+/// that does not satisfy the [Requirement] for the method of the request is still rejected with `403 Forbidden`.
+pub struct OptionalUserDetails<B>
+where
+    B: Backend,
+{
+    pub user: Option<B::User>,
+}
+
+impl<B> FromRequest for OptionalUserDetails<B>
+where
+    B: Backend + Clone + 'static,
+{
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Error>>>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload<PayloadStream>) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move {
+            let (backend, required_caps, access_policy) = req
+                .extensions()
+                .get::<SessionStateItem<B>>()
+                .map(|item| {
+                    (
+                        item.backend.clone(),
+                        item.required_caps.clone(),
+                        item.access_policy.clone(),
+                    )
+                })
+                .ok_or_else(|| ErrorInternalServerError("extractor failed"))?;
+
+            let session_id = match session_id::<B>(&req) {
+                Some(session_id) => session_id,
+                None => return Ok(OptionalUserDetails { user: None }),
+            };
+            let access_control = match AccessControl::new(backend)
+                .authenticate_session(&session_id)
+                .await
+            {
+                Ok(access_control) => access_control,
+                Err(_) => return Ok(OptionalUserDetails { user: None }),
+            };
+            touch_session::<B>(&req, &session_id);
+
+            let user = access_control
+                .authorize(&required_caps)
+                .map_err(ErrorForbidden)?
+                .get_user();
+            check_access_policy(&req, access_policy.as_deref(), &user)?;
+
+            Ok(OptionalUserDetails { user: Some(user) })
+        })
+    }
+}
+
+/// Provides a [Backend::User] that has been granted the action of the [ResourceScope] on the resource of the request.
+///
+/// The resource id is read from the path segment that is configured by [`RustAuthMiddleware::with_resource_scope`].
+/// Responds with `403 Forbidden` if the user lacks the grant or the required capabilities for the method of the request.
+pub struct ResourceDetails<B>
+where
+    B: Backend,
+{
+    pub user: B::User,
+    pub resource_id: String,
+}
+
+impl<B> FromRequest for ResourceDetails<B>
+where
+    B: Backend + Clone + 'static,
+{
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Error>>>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload<PayloadStream>) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move {
+            let err = || ErrorUnauthorized(access_control::Error::Authentication);
+
+            let session_id = session_id::<B>(&req).ok_or_else(err)?;
+            let (backend, required_caps, resource_scope, access_policy) = req
+                .extensions()
+                .get::<SessionStateItem<B>>()
+                .map(|item| {
+                    (
+                        item.backend.clone(),
+                        item.required_caps.clone(),
+                        item.resource_scope.clone(),
+                        item.access_policy.clone(),
+                    )
+                })
+                .ok_or_else(err)?;
+            let resource_scope = resource_scope
+                .ok_or_else(|| ErrorInternalServerError("no resource scope configured"))?;
+            let resource_id = req
+                .match_info()
+                .get(&resource_scope.id_param)
+                .ok_or_else(|| ErrorNotFound("resource not found"))?
+                .to_string();
+
+            // Authenticate with the session ID and authorize for the resource
+            let authenticated = AccessControl::new(backend)
+                .authenticate_session(&session_id)
+                .await
+                .map_err(ErrorUnauthorized)?;
+            touch_session::<B>(&req, &session_id);
+            let user = authenticated
+                .authorize_resource(
+                    &required_caps,
+                    &resource_scope.action,
+                    &resource_scope.resource_type,
+                    &resource_id,
+                )
+                .await
+                .map_err(ErrorForbidden)?
+                .get_user();
+            check_access_policy(&req, access_policy.as_deref(), &user)?;
+
+            Ok(ResourceDetails { user, resource_id })
+        })
+    }
+}
+
+/// Returns the session id of the request, which is the `id` cookie or the session that the middleware restored by the
+/// remember-me token.
+fn session_id<B>(req: &HttpRequest) -> Option<String>
+where
+    B: Backend + 'static,
+{
+    req.extensions()
+        .get::<SessionStateItem<B>>()
+        .and_then(|item| item.session_id.clone())
+}
+
+/// Lets the middleware extend the session after the handler, as it authenticated the request.
+fn touch_session<B>(req: &HttpRequest, session_id: &str)
+where
+    B: Backend + 'static,
+{
+    if let Some(item) = req.extensions_mut().get_mut::<SessionStateItem<B>>() {
+        item.actions
+            .push(SessionStateAction::Touch(session_id.to_string()));
+    }
+}
+
+/// Implemented by the extractors that provide an authenticated user, so that [Authorized] can check its capabilities.
+pub trait UserExtractor {
+    type User: User;
+
+    fn user(&self) -> &Self::User;
+}
+
+impl<B> UserExtractor for UserDetails<B>
+where
+    B: Backend,
+{
+    type User = B::User;
+
+    fn user(&self) -> &B::User {
+        &self.user
+    }
+}
+
+impl<B> UserExtractor for ResourceDetails<B>
+where
+    B: Backend,
+{
+    type User = B::User;
+
+    fn user(&self) -> &B::User {
+        &self.user
+    }
+}
+
+/// A [Requirement] that is known at compile time, usually generated by the [require_capabilities] attribute.
+pub trait CapabilityRequirement {
+    fn requirement() -> Requirement;
+}
+
+/// Wraps a [UserExtractor] like [UserDetails] and additionally requires the user to satisfy the requirement `R`.
+///
+/// Responds with `403 Forbidden` if the user does not satisfy it. This extractor is usually not written by hand, but
+/// generated by the [require_capabilities] attribute, which keeps the required capabilities next to the handler.
+pub struct Authorized<T, R> {
+    pub inner: T,
+    requirement: PhantomData<R>,
+}
+
+impl<T, R> FromRequest for Authorized<T, R>
+where
+    T: FromRequest<Error = Error> + UserExtractor + 'static,
+    T::Future: 'static,
+    R: CapabilityRequirement + 'static,
+{
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Error>>>>;
+    type Config = T::Config;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload<PayloadStream>) -> Self::Future {
+        let inner = T::from_request(req, payload);
+
+        Box::pin(async move {
+            let inner = inner.await?;
+            if !R::requirement().is_satisfied_by(inner.user().capabilities()) {
+                return Err(ErrorForbidden(access_control::Error::Authorization));
+            }
+
+            Ok(Authorized {
+                inner,
+                requirement: PhantomData,
+            })
+        })
+    }
+}
+
+/// Evaluates the [AccessPolicy] of the route for the request, if one has been configured.
+fn check_access_policy(
+    req: &HttpRequest,
+    access_policy: Option<&AccessPolicy>,
+    user: &impl User,
+) -> Result<(), Error> {
+    let access_policy = match access_policy {
+        Some(access_policy) => access_policy,
+        None => return Ok(()),
+    };
+
+    let attributes = RequestAttributes::new(
+        req.method().as_str(),
+        req.path(),
+        req.peer_addr().map(|addr| addr.ip()),
+    );
+    let decision = access_policy.evaluate(&attributes, user);
+    log::debug!(
+        "Access policy for {} {} by {}:\n{}",
+        attributes.method,
+        attributes.path,
+        user.username(),
+        decision
+    );
+
+    if decision.is_allowed() {
+        Ok(())
+    } else {
+        Err(ErrorForbidden(access_control::Error::Authorization))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use access_control::memory::MemoryBackend;
+    use access_control::session::hash_session_id;
+    use actix_web::test::{self, TestRequest};
+    use actix_web::{web, App};
+
+    /// A password that matches the default policy.
+    const PASSWORD: &str = "correct-horse-battery-staple-42";
+
+    /// Registers a user and stores a session for it, returns the session cookie.
+    async fn logged_in(backend: &MemoryBackend, username: &str) -> Cookie<'static> {
+        AccessControl::new(backend.clone())
+            .register(
+                username,
+                PASSWORD,
+                &UsernamePolicy::default(),
+                &default_policy(),
+            )
+            .await
+            .unwrap();
+        let user = backend.get_user(username).await.unwrap();
+        let session_id = generate_session_id();
+        backend
+            .store_session(
+                &user,
+                &session_id,
+                &SessionLifetime::default(),
+                &SessionMetadata::default(),
+            )
+            .await
+            .unwrap();
+
+        Cookie::new("id", session_id)
+    }
+
+    /// Issues a remember-me token for the user of the session cookie.
+    async fn remembered(backend: &MemoryBackend, session: &Cookie<'_>) -> RememberToken {
+        AccessControl::new(backend.clone())
+            .authenticate_session(session.value())
+            .await
+            .unwrap()
+            .remember()
+            .await
+            .unwrap()
+    }
+
+    /// Returns the status of the response, the errors of the middleware are turned into responses by the server.
+    fn status<B>(result: Result<ServiceResponse<B>, Error>) -> StatusCode {
+        match result {
+            Ok(resp) => resp.status(),
+            Err(err) => err.as_response_error().status_code(),
+        }
+    }
+
+    #[actix_rt::test]
+    /// Makes sure the requirement of a method replaces the default requirement only for requests with this method.
+    async fn method_requirements() {
+        let backend = MemoryBackend::new();
+        let cookie = logged_in(&backend, "jean-luc").await;
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/documents")
+                    .wrap(
+                        RustAuthMiddleware::new(backend.clone(), Requirement::none())
+                            .with_method_requirement(
+                                Method::DELETE,
+                                Requirement::capability("DocAdmin"),
+                            ),
+                    )
+                    .route(web::get().to(|_: UserDetails<MemoryBackend>| async { "read" }))
+                    .route(web::delete().to(|_: UserDetails<MemoryBackend>| async { "deleted" })),
+            ),
+        )
+        .await;
+        let request = |method: Method| {
+            TestRequest::default()
+                .method(method)
+                .uri("/documents")
+                .cookie(cookie.clone())
+                .to_request()
+        };
+
+        assert_eq!(status(app.call(request(Method::GET)).await), StatusCode::OK);
+        assert_eq!(
+            status(app.call(request(Method::DELETE)).await),
+            StatusCode::FORBIDDEN
+        );
+
+        backend.add_capability("jean-luc", "DocAdmin");
+        assert_eq!(
+            status(app.call(request(Method::DELETE)).await),
+            StatusCode::OK
+        );
+        assert_eq!(status(app.call(request(Method::GET)).await), StatusCode::OK);
+    }
+
+    /// Returns the value of the cookie with the `name` that the response sets.
+    fn response_cookie<B>(resp: &ServiceResponse<B>, name: &str) -> Option<String> {
+        resp.response()
+            .cookies()
+            .find(|cookie| cookie.name() == name)
+            .map(|cookie| cookie.value().to_string())
+    }
+
+    #[actix_rt::test]
+    /// Makes sure the remember-me cookie is exchanged for a session and the next token, a parallel request with the
+    /// previous token is accepted and an older token revokes the series.
+    async fn remember_cookie_exchange() {
+        let backend = MemoryBackend::new();
+        let session = logged_in(&backend, "jean-luc").await;
+        let first = remembered(&backend, &session).await;
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RustAuthMiddleware::new(
+                    backend.clone(),
+                    Requirement::none(),
+                ))
+                .route(
+                    "/",
+                    web::get().to(|_: UserDetails<MemoryBackend>| async { "remembered" }),
+                ),
+        )
+        .await;
+        let request = |value: &str| {
+            TestRequest::get()
+                .uri("/")
+                .cookie(Cookie::new(REMEMBER_COOKIE, value.to_string()))
+                .to_request()
+        };
+
+        let resp = app.call(request(&first.value())).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(response_cookie(&resp, "id").is_some());
+        let second = response_cookie(&resp, REMEMBER_COOKIE).expect("the next token must be sent");
+        assert_ne!(second, first.value());
+
+        let resp = app.call(request(&first.value())).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(response_cookie(&resp, "id").is_some());
+        assert_eq!(response_cookie(&resp, REMEMBER_COOKIE), None);
+
+        let resp = app.call(request(&second)).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let third = response_cookie(&resp, REMEMBER_COOKIE).expect("the next token must be sent");
+
+        assert_eq!(
+            status(app.call(request(&first.value())).await),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(app.call(request(&third)).await),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[actix_rt::test]
+    /// Makes sure a login that asked to be remembered issues a remember-me token after the one-time password.
+    async fn remember_after_totp() {
+        let backend = MemoryBackend::new();
+        logged_in(&backend, "jean-luc").await;
+        let totp = Totp::generate();
+        let user = backend.get_user("jean-luc").await.unwrap();
+        backend
+            .store_totp_secret(&user, Some(&totp.to_base32()))
+            .await
+            .unwrap();
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RustAuthMiddleware::new(
+                    backend.clone(),
+                    Requirement::none(),
+                ))
+                .route(
+                    "/login",
+                    web::get().to(|state: SessionState<MemoryBackend>| async move {
+                        state.login("jean-luc", PASSWORD, true).await.map(|_| "")
+                    }),
+                )
+                .route(
+                    "/totp/{code}",
+                    web::get().to(
+                        |state: SessionState<MemoryBackend>, code: web::Path<String>| async move {
+                            state.verify_totp(code.as_str()).await.map(|_| "")
+                        },
+                    ),
+                ),
+        )
+        .await;
+
+        let resp = app
+            .call(TestRequest::get().uri("/login").to_request())
+            .await
+            .unwrap();
+        assert_eq!(response_cookie(&resp, REMEMBER_COOKIE), None);
+        let pending =
+            response_cookie(&resp, "totp").expect("the one-time password must be required");
+
+        let uri = format!("/totp/{}", totp.code(access_control::totp::current_step()));
+        let resp = app
+            .call(
+                TestRequest::get()
+                    .uri(&uri)
+                    .cookie(Cookie::new("totp", pending))
+                    .to_request(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(response_cookie(&resp, "id").is_some());
+        assert!(response_cookie(&resp, REMEMBER_COOKIE).is_some());
+    }
+
+    #[actix_rt::test]
+    /// Makes sure revoking a session that has been restored by a remember-me token revokes the series as well.
+    async fn revoke_remembered_session() {
+        let backend = MemoryBackend::new();
+        let current = logged_in(&backend, "jean-luc").await;
+        let token = remembered(&backend, &current).await;
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RustAuthMiddleware::new(
+                    backend.clone(),
+                    Requirement::none(),
+                ))
+                .route(
+                    "/",
+                    web::get().to(|_: UserDetails<MemoryBackend>| async { "remembered" }),
+                )
+                .route(
+                    "/revoke/{id}",
+                    web::get().to(
+                        |state: SessionState<MemoryBackend>, id: web::Path<String>| async move {
+                            state.revoke_session(id.as_str()).await.map(|_| "")
+                        },
+                    ),
+                ),
+        )
+        .await;
+
+        let resp = app
+            .call(
+                TestRequest::get()
+                    .uri("/")
+                    .cookie(Cookie::new(REMEMBER_COOKIE, token.value()))
+                    .to_request(),
+            )
+            .await
+            .unwrap();
+        let restored = response_cookie(&resp, "id").expect("the session must be restored");
+        let next = response_cookie(&resp, REMEMBER_COOKIE).expect("the next token must be sent");
+
+        let uri = format!("/revoke/{}", hash_session_id(&restored));
+        let resp = app
+            .call(TestRequest::get().uri(&uri).cookie(current).to_request())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            status(
+                app.call(
+                    TestRequest::get()
+                        .uri("/")
+                        .cookie(Cookie::new(REMEMBER_COOKIE, next))
+                        .to_request()
+                )
+                .await
+            ),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
@@ -0,0 +1,411 @@
+//! Contains the [RateLimit] middleware that limits the request rate of clients with token buckets.
+//!
+//! Unlike the login throttling of [`access_control::throttling`], which only counts failed logins, every request is
+//! counted. The buckets are stored in a [RateLimitStore], which is a [MemoryStore] by default.
+
+use crate::{DynamicFutureReturn, TooManyRequests};
+use access_control::session::hash_session_id;
+use access_control::{throttling, FutureResult};
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::{Error, HttpMessage};
+use futures_util::future::{ok, ready, Ready};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// How often the [MemoryStore] removes buckets that are full again.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The size and refill rate of the token buckets of a [RateLimit].
+///
+/// A client can send `capacity` requests in a burst, afterwards one token is refilled every `refill_interval`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitPolicy {
+    pub capacity: u32,
+    pub refill_interval: Duration,
+}
+
+impl RateLimitPolicy {
+    /// Allows `capacity` requests per `period`, which are refilled evenly over the period.
+    pub fn per(capacity: u32, period: Duration) -> Self {
+        RateLimitPolicy {
+            capacity,
+            refill_interval: period / capacity.max(1),
+        }
+    }
+}
+
+/// A token bucket of a single key.
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// The instant at which the bucket is full again and can be forgotten
+    full_at: Instant,
+}
+
+impl Bucket {
+    /// Creates a full bucket.
+    fn new(policy: &RateLimitPolicy, now: Instant) -> Self {
+        Bucket {
+            tokens: f64::from(policy.capacity),
+            updated: now,
+            full_at: now,
+        }
+    }
+
+    /// Refills the bucket and takes a token, returns how long to wait for the next token if the bucket is empty.
+    fn take(&mut self, policy: &RateLimitPolicy, now: Instant) -> Option<Duration> {
+        let capacity = f64::from(policy.capacity);
+        let refill = policy.refill_interval.as_secs_f64();
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+
+        self.tokens = if refill > 0.0 {
+            (self.tokens + elapsed / refill).min(capacity)
+        } else {
+            capacity
+        };
+        self.updated = now;
+
+        let retry_after = if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(policy.refill_interval.mul_f64(1.0 - self.tokens))
+        };
+        self.full_at = now + policy.refill_interval.mul_f64(capacity - self.tokens);
+        retry_after
+    }
+}
+
+/// Stores the token buckets of a [RateLimit], implement this trait to share the buckets between several instances of
+/// the service.
+pub trait RateLimitStore {
+    /// Defines a method that should take a token from the bucket of the key, whose size and refill rate are described
+    /// by the policy.
+    ///
+    /// Returns `None` if the request is allowed, or how long the client has to wait for the next token. The buckets
+    /// of unknown keys are full.
+    fn acquire(&self, key: &str, policy: &RateLimitPolicy) -> FutureResult<Option<Duration>>;
+}
+
+/// A [RateLimitStore] that keeps the buckets in memory.
+///
+/// Clones share their buckets, so a single store can be cloned into the workers of the server. The buckets are lost
+/// on a restart and are not shared with other instances of the service.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+    buckets: Arc<Mutex<Buckets>>,
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    map: HashMap<String, Bucket>,
+    last_prune: Option<Instant>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes a token of the key at the given instant, see [`RateLimitStore::acquire`].
+    fn acquire_at(&self, key: &str, policy: &RateLimitPolicy, now: Instant) -> Option<Duration> {
+        // A poisoned lock only means that another thread panicked, the buckets are still consistent
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Full buckets behave like unknown keys, so they are removed to bound the memory usage
+        if buckets.last_prune.map_or(true, |last| {
+            now.saturating_duration_since(last) >= PRUNE_INTERVAL
+        }) {
+            buckets.map.retain(|_, bucket| bucket.full_at > now);
+            buckets.last_prune = Some(now);
+        }
+
+        buckets
+            .map
+            .entry(key.to_string())
+            .or_insert_with(|| Bucket::new(policy, now))
+            .take(policy, now)
+    }
+}
+
+impl RateLimitStore for MemoryStore {
+    fn acquire(&self, key: &str, policy: &RateLimitPolicy) -> FutureResult<Option<Duration>> {
+        Box::pin(ready(Ok(self.acquire_at(key, policy, Instant::now()))))
+    }
+}
+
+/// Extracts the key of a request, whose requests share a token bucket.
+type KeyFn = dyn Fn(&ServiceRequest) -> DynamicFutureReturn<Option<String>>;
+
+/// Describes by which key the requests of a [RateLimit] are counted.
+#[derive(Clone)]
+pub struct RateLimitKey(Rc<KeyFn>);
+
+impl RateLimitKey {
+    /// Counts the requests per client IP, IPv6 addresses are grouped by their /64 prefix.
+    ///
+    /// The client IP is the peer address of the connection, behind a reverse proxy this is the IP of the proxy.
+    pub fn client_ip() -> Self {
+        Self::custom(client_ip)
+    }
+
+    /// Counts the requests per session cookie, requests without a session cookie are counted per client IP.
+    ///
+    /// The session is not looked up, the key is the [`hash_session_id`] of the cookie, so the limit does not cost a
+    /// query of the backend. Requests with an invalid session cookie are rejected by the [`crate::RustAuthMiddleware`]
+    /// of the resource.
+    pub fn session() -> Self {
+        Self::custom(|req| match req.cookie("id") {
+            Some(cookie) => Some(format!("session:{}", hash_session_id(cookie.value()))),
+            None => client_ip(req),
+        })
+    }
+
+    /// Counts the requests per key that the function returns, requests without a key are not limited.
+    pub fn custom<F>(key: F) -> Self
+    where
+        F: Fn(&ServiceRequest) -> Option<String> + 'static,
+    {
+        RateLimitKey(Rc::new(move |req: &ServiceRequest| {
+            Box::pin(ready(key(req))) as DynamicFutureReturn<_>
+        }))
+    }
+}
+
+/// Returns the key of the client IP of a request, see [`throttling::client_ip_key`].
+fn client_ip(req: &ServiceRequest) -> Option<String> {
+    req.peer_addr()
+        .map(|addr| throttling::client_ip_key(addr.ip()))
+}
+
+/// The middleware that limits the request rate of a resource.
+///
+/// Requests that exceed the [RateLimitPolicy] are answered with `429 Too Many Requests` and a `Retry-After` header,
+/// see [TooManyRequests]. If the [RateLimitStore] fails, the requests are allowed.
+///
+/// # Example usage
+/// ```ignore
+/// // 10 logins per minute and client IP
+/// resource("/login")
+///     .wrap(RustAuthMiddleware::new(backend, HashSet::new()))
+///     .wrap(RateLimit::new("login", RateLimitPolicy::per(10, Duration::from_secs(60)), store.clone()))
+///     .route(post().to(routes::do_login))
+/// ```
+pub struct RateLimit<St> {
+    /// The name of the limit, which separates its buckets from other limits in the same store
+    name: String,
+    policy: RateLimitPolicy,
+    key: RateLimitKey,
+    store: St,
+}
+
+impl<St> RateLimit<St>
+where
+    St: RateLimitStore,
+{
+    /// Creates a limit that counts the requests per client IP, use [`RateLimit::by`] to count them by another key.
+    pub fn new(name: impl Into<String>, policy: RateLimitPolicy, store: St) -> Self {
+        RateLimit {
+            name: name.into(),
+            policy,
+            key: RateLimitKey::client_ip(),
+            store,
+        }
+    }
+
+    /// Replaces the [RateLimitKey] by which the requests are counted.
+    pub fn by(mut self, key: RateLimitKey) -> Self {
+        self.key = key;
+        self
+    }
+}
+
+impl<S, B, St> Transform<S> for RateLimit<St>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+    St: RateLimitStore + Clone + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S, St>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RateLimitMiddleware {
+            name: self.name.clone(),
+            policy: self.policy,
+            key: self.key.clone(),
+            store: self.store.clone(),
+            service: Rc::new(RefCell::new(service)),
+        })
+    }
+}
+
+/// Actix Web middleware that is created by [RateLimit].
+pub struct RateLimitMiddleware<S, St> {
+    name: String,
+    policy: RateLimitPolicy,
+    key: RateLimitKey,
+    store: St,
+    service: Rc<RefCell<S>>,
+}
+
+impl<S, B, St> Service for RateLimitMiddleware<S, St>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+    St: RateLimitStore + Clone + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = DynamicFutureReturn<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let mut srv = self.service.clone();
+        let name = self.name.clone();
+        let policy = self.policy;
+        let store = self.store.clone();
+        let key = (self.key.0)(&req);
+
+        Box::pin(async move {
+            if let Some(key) = key.await {
+                match store.acquire(&format!("{}:{}", name, key), &policy).await {
+                    Ok(Some(retry_after)) => return Err(TooManyRequests(retry_after).into()),
+                    Ok(None) => {}
+                    Err(err) => log::warn!("rate limit {} is not enforced: {}", name, err),
+                }
+            }
+
+            srv.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::cookie::Cookie;
+    use actix_web::http::{header, StatusCode};
+    use actix_web::test::{self, TestRequest};
+    use actix_web::{web, App};
+
+    #[test]
+    /// Makes sure a burst of `capacity` requests is allowed and the tokens are refilled one by one.
+    fn token_bucket() {
+        let policy = RateLimitPolicy::per(3, Duration::from_secs(3));
+        let store = MemoryStore::new();
+        let now = Instant::now();
+        let acquire =
+            |key, seconds| store.acquire_at(key, &policy, now + Duration::from_secs(seconds));
+
+        assert_eq!(acquire("a", 0), None);
+        assert_eq!(acquire("a", 0), None);
+        assert_eq!(acquire("a", 0), None);
+        assert_eq!(acquire("a", 0), Some(Duration::from_secs(1)));
+        // Other keys have buckets of their own
+        assert_eq!(acquire("b", 0), None);
+
+        assert_eq!(acquire("a", 1), None);
+        assert_eq!(acquire("a", 1), Some(Duration::from_secs(1)));
+        // The bucket does not grow beyond its capacity
+        assert_eq!(acquire("a", 100), None);
+        assert_eq!(acquire("a", 100), None);
+        assert_eq!(acquire("a", 100), None);
+        assert!(acquire("a", 100).is_some());
+    }
+
+    #[test]
+    /// Makes sure buckets are forgotten once they are full again.
+    fn prune_full_buckets() {
+        let policy = RateLimitPolicy::per(1, Duration::from_secs(1));
+        let store = MemoryStore::new();
+        let now = Instant::now();
+
+        store.acquire_at("a", &policy, now);
+        store.acquire_at("b", &policy, now + PRUNE_INTERVAL);
+        assert_eq!(store.buckets.lock().unwrap().map.len(), 1);
+    }
+
+    #[actix_rt::test]
+    /// Makes sure the requests beyond the burst of a client are answered with `429 Too Many Requests` and a
+    /// `Retry-After` header, while other clients are not affected.
+    async fn middleware() {
+        let policy = RateLimitPolicy::per(2, Duration::from_secs(60));
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RateLimit::new("test", policy, MemoryStore::new()))
+                .route("/", web::get().to(|| async { "ok" })),
+        )
+        .await;
+        let request = |ip: &str| {
+            TestRequest::get()
+                .uri("/")
+                .peer_addr(format!("{}:443", ip).parse().unwrap())
+                .to_request()
+        };
+
+        for _ in 0..2 {
+            let resp = app.call(request("192.0.2.1")).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+
+        let resp = app
+            .call(request("192.0.2.1"))
+            .await
+            .expect_err("the third request must be limited")
+            .as_response_error()
+            .error_response();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let retry_after = resp.headers().get(header::RETRY_AFTER).unwrap();
+        assert_eq!(retry_after.to_str().unwrap(), "30");
+
+        let resp = app.call(request("192.0.2.2")).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    /// Makes sure the requests with a session cookie are counted per session and the others per client IP.
+    async fn session_key() {
+        let policy = RateLimitPolicy::per(1, Duration::from_secs(60));
+        let mut app = test::init_service(
+            App::new()
+                .wrap(
+                    RateLimit::new("test", policy, MemoryStore::new()).by(RateLimitKey::session()),
+                )
+                .route("/", web::get().to(|| async { "ok" })),
+        )
+        .await;
+        let request = |session_id: Option<&str>| {
+            let request = TestRequest::get()
+                .uri("/")
+                .peer_addr("192.0.2.1:443".parse().unwrap());
+            match session_id {
+                Some(session_id) => request.cookie(Cookie::new("id", session_id.to_string())),
+                None => request,
+            }
+            .to_request()
+        };
+
+        for session_id in [Some("first"), Some("second"), None] {
+            let resp = app.call(request(session_id)).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert!(app.call(request(session_id)).await.is_err());
+        }
+    }
+}
@@ -1,4 +1,15 @@
+DROP TABLE IF EXISTS remember_tokens;
+DROP TABLE IF EXISTS failed_logins;
+DROP TABLE IF EXISTS webauthn_challenges;
+DROP TABLE IF EXISTS webauthn_credentials;
 DROP TABLE IF EXISTS sessions;
+DROP TABLE IF EXISTS recovery_codes;
+DROP TABLE IF EXISTS user_attributes;
+DROP TABLE IF EXISTS grants;
+DROP TABLE IF EXISTS user_roles;
+DROP TABLE IF EXISTS role_parents;
+DROP TABLE IF EXISTS role_capabilities;
+DROP TABLE IF EXISTS roles;
 DROP TABLE IF EXISTS capabilities;
 DROP TABLE IF EXISTS users;
 DROP EXTENSION IF EXISTS pgcrypto;
@@ -4,9 +4,46 @@ CREATE TABLE IF NOT EXISTS users (
   user_id SERIAL PRIMARY KEY,
   username TEXT NOT NULL UNIQUE,
   password_hash TEXT NOT NULL,
-  registration_date TIMESTAMPTZ NOT NULL
+  registration_date TIMESTAMPTZ NOT NULL,
+  totp_secret TEXT,
+  totp_last_step BIGINT
 );
 
+-- The second factor of the users, which older installations lack
+ALTER TABLE users
+  ADD COLUMN IF NOT EXISTS totp_secret TEXT,
+  ADD COLUMN IF NOT EXISTS totp_last_step BIGINT;
+
+-- The canonical form of a username like `username_policy::canonicalize`: normalized with NFKC, trimmed and lowercased
+CREATE OR REPLACE FUNCTION canonical_username(username TEXT) RETURNS TEXT AS $$
+  SELECT normalize(lower(btrim(normalize(username, NFKC))), NFKC);
+$$ LANGUAGE SQL IMMUTABLE STRICT;
+
+CREATE INDEX IF NOT EXISTS users_canonical_username ON users (canonical_username(username));
+
+-- Users are looked up by the canonical form of their username, which older installations did not store. A username
+-- whose canonical form belongs to another account as well is kept and reported, it has to be renamed manually.
+DO $$
+DECLARE
+  collision RECORD;
+BEGIN
+  FOR collision IN
+    SELECT username FROM users
+      WHERE username <> canonical_username(username)
+      AND EXISTS (SELECT 1 FROM users AS other
+        WHERE other.user_id <> users.user_id
+        AND canonical_username(other.username) = canonical_username(users.username))
+  LOOP
+    RAISE WARNING 'username "%" collides with another account and is not canonicalized', collision.username;
+  END LOOP;
+
+  UPDATE users SET username = canonical_username(username)
+    WHERE username <> canonical_username(username)
+    AND NOT EXISTS (SELECT 1 FROM users AS other
+      WHERE other.user_id <> users.user_id
+      AND canonical_username(other.username) = canonical_username(users.username));
+END $$;
+
 CREATE TABLE IF NOT EXISTS capabilities (
   label TEXT NOT NULL,
   user_id SERIAL,
@@ -14,9 +51,142 @@ CREATE TABLE IF NOT EXISTS capabilities (
   UNIQUE (label, user_id)
 );
 
+CREATE TABLE IF NOT EXISTS roles (
+  role_id SERIAL PRIMARY KEY,
+  name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS role_capabilities (
+  role_id INTEGER NOT NULL,
+  label TEXT NOT NULL,
+  CONSTRAINT fk_role FOREIGN KEY(role_id) REFERENCES roles(role_id) ON DELETE CASCADE,
+  UNIQUE (role_id, label)
+);
+
+CREATE TABLE IF NOT EXISTS role_parents (
+  role_id INTEGER NOT NULL,
+  parent_id INTEGER NOT NULL,
+  CONSTRAINT fk_role FOREIGN KEY(role_id) REFERENCES roles(role_id) ON DELETE CASCADE,
+  CONSTRAINT fk_parent FOREIGN KEY(parent_id) REFERENCES roles(role_id) ON DELETE CASCADE,
+  CHECK (role_id <> parent_id),
+  UNIQUE (role_id, parent_id)
+);
+
+CREATE TABLE IF NOT EXISTS user_roles (
+  user_id INTEGER NOT NULL,
+  role_id INTEGER NOT NULL,
+  CONSTRAINT fk_user FOREIGN KEY(user_id) REFERENCES users(user_id),
+  CONSTRAINT fk_role FOREIGN KEY(role_id) REFERENCES roles(role_id) ON DELETE CASCADE,
+  UNIQUE (user_id, role_id)
+);
+
+CREATE TABLE IF NOT EXISTS grants (
+  user_id INTEGER NOT NULL,
+  action TEXT NOT NULL,
+  resource_type TEXT NOT NULL,
+  resource_id TEXT NOT NULL,
+  CONSTRAINT fk_user FOREIGN KEY(user_id) REFERENCES users(user_id),
+  PRIMARY KEY (user_id, action, resource_type, resource_id)
+);
+
+CREATE TABLE IF NOT EXISTS user_attributes (
+  user_id INTEGER NOT NULL,
+  name TEXT NOT NULL,
+  value TEXT NOT NULL,
+  CONSTRAINT fk_user FOREIGN KEY(user_id) REFERENCES users(user_id),
+  PRIMARY KEY (user_id, name)
+);
+
+CREATE TABLE IF NOT EXISTS recovery_codes (
+  user_id INTEGER NOT NULL,
+  code_hash TEXT NOT NULL,
+  CONSTRAINT fk_user FOREIGN KEY(user_id) REFERENCES users(user_id),
+  PRIMARY KEY (user_id, code_hash)
+);
+
 CREATE TABLE IF NOT EXISTS sessions (
   session_id TEXT PRIMARY KEY,
   user_id SERIAL,
   expiration_date TIMESTAMPTZ NOT NULL,
+  absolute_expiration_date TIMESTAMPTZ NOT NULL,
+  pending BOOLEAN NOT NULL DEFAULT FALSE,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  remember BOOLEAN NOT NULL DEFAULT FALSE,
+  creation_date TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  last_seen TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  ip_address TEXT,
+  user_agent TEXT,
+  remember_selector TEXT,
+  CONSTRAINT fk_user FOREIGN KEY(user_id) REFERENCES users(user_id)
+);
+
+-- Logins that wait for the second factor, their attempts and whether to remember them, which older installations lack
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS pending BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS remember BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- The absolute lifetime of the sessions, which older installations lack. Their sessions expire at the end of their
+-- current idle timeout.
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS absolute_expiration_date TIMESTAMPTZ;
+UPDATE sessions SET absolute_expiration_date = expiration_date WHERE absolute_expiration_date IS NULL;
+ALTER TABLE sessions ALTER COLUMN absolute_expiration_date SET NOT NULL;
+
+-- Session ids are stored as SHA-256 digests in hex, this hashes the plain session ids of older installations
+UPDATE sessions SET session_id = encode(digest(session_id, 'sha256'), 'hex')
+  WHERE session_id !~ '^[0-9a-f]{64}$';
+
+-- The metadata of the sessions, which older installations lack
+ALTER TABLE sessions
+  ADD COLUMN IF NOT EXISTS creation_date TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  ADD COLUMN IF NOT EXISTS last_seen TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+  ADD COLUMN IF NOT EXISTS ip_address TEXT,
+  ADD COLUMN IF NOT EXISTS user_agent TEXT,
+  ADD COLUMN IF NOT EXISTS remember_selector TEXT;
+
+CREATE TABLE IF NOT EXISTS webauthn_credentials (
+  credential_id BYTEA PRIMARY KEY,
+  user_id INTEGER NOT NULL,
+  public_key BYTEA NOT NULL,
+  sign_count BIGINT NOT NULL,
+  registration_date TIMESTAMPTZ NOT NULL,
+  CONSTRAINT fk_user FOREIGN KEY(user_id) REFERENCES users(user_id)
+);
+
+CREATE TABLE IF NOT EXISTS webauthn_challenges (
+  challenge TEXT PRIMARY KEY,
+  user_id INTEGER,
+  expiration_date TIMESTAMPTZ NOT NULL,
   CONSTRAINT fk_user FOREIGN KEY(user_id) REFERENCES users(user_id)
 );
+
+CREATE INDEX IF NOT EXISTS webauthn_challenges_expiration_date ON webauthn_challenges (expiration_date);
+
+CREATE TABLE IF NOT EXISTS failed_logins (
+  throttle_key TEXT PRIMARY KEY,
+  count INTEGER NOT NULL,
+  last_failure TIMESTAMPTZ NOT NULL,
+  expiration_date TIMESTAMPTZ NOT NULL
+);
+
+-- The end of the reset period of the failures, which older installations lack. Their failures expire after the
+-- default reset period of an hour.
+ALTER TABLE failed_logins ADD COLUMN IF NOT EXISTS expiration_date TIMESTAMPTZ;
+UPDATE failed_logins SET expiration_date = last_failure + INTERVAL '1 hour' WHERE expiration_date IS NULL;
+ALTER TABLE failed_logins ALTER COLUMN expiration_date SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS failed_logins_expiration_date ON failed_logins (expiration_date);
+
+CREATE TABLE IF NOT EXISTS remember_tokens (
+  selector TEXT PRIMARY KEY,
+  user_id INTEGER NOT NULL,
+  validator_hash TEXT NOT NULL,
+  previous_validator_hash TEXT,
+  rotation_date TIMESTAMPTZ,
+  expiration_date TIMESTAMPTZ NOT NULL,
+  CONSTRAINT fk_user FOREIGN KEY(user_id) REFERENCES users(user_id)
+);
+
+-- The validator replaced by the last rotation, which older installations lack
+ALTER TABLE remember_tokens
+  ADD COLUMN IF NOT EXISTS previous_validator_hash TEXT,
+  ADD COLUMN IF NOT EXISTS rotation_date TIMESTAMPTZ;
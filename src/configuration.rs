@@ -5,85 +5,333 @@
 //! - [admin_config] provides a admin specific configuration
 
 use crate::routes;
+use access_control::{
+    access_policy::AccessPolicy,
+    breached::BreachedPasswords,
+    password_policy::{Denylist, Length, PasswordPolicy, Rules, UsernameSimilarity},
+    session::SessionLifetime,
+    webauthn::RelyingParty,
+};
 use actix_web::{
     web,
     web::{get, resource},
 };
 use database_integration::PostgreSqlBackend;
-use middleware::RustAuthMiddleware;
+use middleware::{
+    csrf::Csrf,
+    rate_limit::{MemoryStore, RateLimit, RateLimitKey, RateLimitPolicy},
+    ResourceScope, RustAuthMiddleware,
+};
 use sqlx::{Pool, Postgres};
-use std::{collections::HashSet, fmt};
+use std::{collections::HashSet, env, sync::Arc, time::Duration};
+
+/// Builds the policy that the passwords of new users must match.
+///
+/// If the `BREACHED_PASSWORDS` environment variable contains the path to a Have I Been Pwned corpus or a filter built
+/// by `hibp-filter`, breached passwords are rejected as well. Call this function once, as loading the corpus is
+/// expensive.
+pub fn password_policy() -> Arc<dyn PasswordPolicy> {
+    let rules = Rules::new()
+        .with(Length::default())
+        .with(UsernameSimilarity)
+        .with(Denylist::common());
+
+    match env::var("BREACHED_PASSWORDS") {
+        Ok(path) => Arc::new(
+            rules.with(BreachedPasswords::open(&path).expect("could not load BREACHED_PASSWORDS")),
+        ),
+        Err(_) => Arc::new(rules),
+    }
+}
 
-#[derive(Debug)]
-pub enum Capabilities {
-    UserRead,
-    AdminRead,
+/// Loads the access policy of the admin routes from the file in the `ACCESS_POLICY` environment variable.
+///
+/// Returns [`None`] if the variable is not set, in which case the required capabilities are sufficient.
+pub fn access_policy() -> Option<Arc<AccessPolicy>> {
+    let path = env::var("ACCESS_POLICY").ok()?;
+    Some(Arc::new(
+        AccessPolicy::from_file(&path).expect("could not load ACCESS_POLICY"),
+    ))
 }
 
-impl fmt::Display for Capabilities {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        fmt::Debug::fmt(self, f)
+/// Builds the WebAuthn relying party of passkeys from the `WEBAUTHN_RP_ID` and `WEBAUTHN_ORIGIN` environment variables.
+///
+/// Browsers only accept domains as relying party id, so it defaults to `localhost` and the origin defaults to
+/// `https://localhost:<SERVICE_PORT>`.
+pub fn relying_party() -> Arc<RelyingParty> {
+    let id = env::var("WEBAUTHN_RP_ID").unwrap_or_else(|_| "localhost".to_string());
+    let origin = env::var("WEBAUTHN_ORIGIN").unwrap_or_else(|_| {
+        let port = env::var("SERVICE_PORT").unwrap_or_else(|_| "8080".to_string());
+        format!("https://{}:{}", id, port)
+    });
+
+    Arc::new(RelyingParty::new(id, origin, "Rust Auth Service"))
+}
+
+/// Creates the CSRF protection of the forms with the secret in the `CSRF_SECRET` environment variable.
+///
+/// The secret has to be shared by every instance of the service and must be at least 32 characters long. Without it,
+/// a random secret is used, so the forms that are open during a restart have to be reloaded.
+pub fn csrf() -> Csrf {
+    match env::var("CSRF_SECRET") {
+        Ok(secret) if secret.len() >= 32 => Csrf::new(secret.as_bytes()),
+        Ok(_) => panic!("CSRF_SECRET must be at least 32 characters long"),
+        Err(_) => Csrf::random(),
     }
 }
 
-pub fn website(cfg: &mut web::ServiceConfig, pool: &Pool<Postgres>) {
+/// Reads the lifetime of sessions from the `SESSION_IDLE_TIMEOUT` and `SESSION_ABSOLUTE_TIMEOUT` environment
+/// variables in minutes.
+///
+/// Sessions expire after 30 minutes without a request and after 12 hours at the latest by default.
+pub fn session_lifetime() -> SessionLifetime {
+    let minutes = |name: &str, default: u64| match env::var(name) {
+        Ok(value) => value
+            .parse::<u64>()
+            .unwrap_or_else(|_| panic!("{} must be a number of minutes", name)),
+        Err(_) => default,
+    };
+    let default = SessionLifetime::default();
+
+    SessionLifetime::new(
+        Duration::from_secs(
+            60 * minutes("SESSION_IDLE_TIMEOUT", default.idle_timeout.as_secs() / 60),
+        ),
+        Duration::from_secs(
+            60 * minutes(
+                "SESSION_ABSOLUTE_TIMEOUT",
+                default.absolute_timeout.as_secs() / 60,
+            ),
+        ),
+    )
+}
+
+/// Creates the authentication middleware of a resource, whose sessions have the given lifetime.
+fn authentication(
+    backend: &PostgreSqlBackend,
+    session_lifetime: SessionLifetime,
+) -> RustAuthMiddleware<PostgreSqlBackend> {
+    RustAuthMiddleware::new(backend.clone(), HashSet::new()).with_session_lifetime(session_lifetime)
+}
+
+/// Creates the store of the request rate limits.
+///
+/// Create it once outside of the server factory, so that its clones share the buckets between all workers.
+pub fn rate_limits() -> MemoryStore {
+    MemoryStore::new()
+}
+
+pub fn website(
+    cfg: &mut web::ServiceConfig,
+    pool: &Pool<Postgres>,
+    password_policy: &Arc<dyn PasswordPolicy>,
+    relying_party: &Arc<RelyingParty>,
+    rate_limits: &MemoryStore,
+    session_lifetime: SessionLifetime,
+) {
     let backend = PostgreSqlBackend::new(pool.clone());
 
-    // Register
+    // Register, which is limited to 10 requests per hour and client IP
     cfg.service(
         resource("/register")
-            .wrap(RustAuthMiddleware::new(backend.clone(), HashSet::new()))
+            .wrap(
+                authentication(&backend, session_lifetime)
+                    .with_password_policy(password_policy.clone()),
+            )
+            .wrap(RateLimit::new(
+                "register",
+                RateLimitPolicy::per(10, Duration::from_secs(60 * 60)),
+                rate_limits.clone(),
+            ))
             .route(web::get().to(routes::register_page))
             .route(web::post().to(routes::do_register)),
     );
 
-    // Login
+    // Login, which is limited to 20 requests per minute and client IP in addition to the throttling of failed logins
     cfg.service(
         resource("/login")
-            .wrap(RustAuthMiddleware::new(backend.clone(), HashSet::new()))
+            .wrap(authentication(&backend, session_lifetime))
+            .wrap(RateLimit::new(
+                "login",
+                RateLimitPolicy::per(20, Duration::from_secs(60)),
+                rate_limits.clone(),
+            ))
             .route(web::get().to(routes::login_page))
             .route(web::post().to(routes::do_login)),
     );
 
+    // Second factor of the login, which shares a limit of 10 requests per minute and client IP with the recovery codes
+    // in addition to the throttling of failed attempts
+    let second_factor_limit = || {
+        RateLimit::new(
+            "second-factor",
+            RateLimitPolicy::per(10, Duration::from_secs(60)),
+            rate_limits.clone(),
+        )
+    };
+    cfg.service(
+        resource("/login/totp")
+            .wrap(authentication(&backend, session_lifetime))
+            .wrap(second_factor_limit())
+            .route(web::get().to(routes::totp_login_page))
+            .route(web::post().to(routes::do_verify_totp)),
+    );
+
+    // Second factor of the login with a recovery code
+    cfg.service(
+        resource("/login/recovery")
+            .wrap(authentication(&backend, session_lifetime))
+            .wrap(second_factor_limit())
+            .route(web::post().to(routes::do_verify_recovery_code)),
+    );
+
+    // Set up two-factor authentication
+    cfg.service(
+        resource("/totp")
+            .wrap(authentication(&backend, session_lifetime))
+            .route(web::get().to(routes::totp_page))
+            .route(web::post().to(routes::do_enable_totp)),
+    );
+
+    // Replace the recovery codes
+    cfg.service(
+        resource("/recovery-codes")
+            .wrap(authentication(&backend, session_lifetime))
+            .route(web::post().to(routes::do_generate_recovery_codes)),
+    );
+
+    // List and revoke the sessions of the user
+    cfg.service(
+        resource("/sessions")
+            .wrap(authentication(&backend, session_lifetime))
+            .route(web::get().to(routes::sessions_page)),
+    );
+    cfg.service(
+        resource("/sessions/revoke")
+            .wrap(authentication(&backend, session_lifetime))
+            .route(web::post().to(routes::do_revoke_session)),
+    );
+    cfg.service(
+        resource("/sessions/revoke-others")
+            .wrap(authentication(&backend, session_lifetime))
+            .route(web::post().to(routes::do_revoke_other_sessions)),
+    );
+
+    // Script of the passkey pages
+    cfg.service(resource("/webauthn.js").route(web::get().to(routes::webauthn_script)));
+
+    // Login with a passkey, which is limited to 20 requests per minute and client IP, as every request for options
+    // stores a challenge
+    let webauthn_limit = || {
+        RateLimit::new(
+            "webauthn-login",
+            RateLimitPolicy::per(20, Duration::from_secs(60)),
+            rate_limits.clone(),
+        )
+    };
+    cfg.service(
+        resource("/login/webauthn/options")
+            .wrap(
+                authentication(&backend, session_lifetime)
+                    .with_relying_party(relying_party.clone()),
+            )
+            .wrap(webauthn_limit())
+            .route(web::post().to(routes::webauthn_login_options)),
+    );
+    cfg.service(
+        resource("/login/webauthn")
+            .wrap(
+                authentication(&backend, session_lifetime)
+                    .with_relying_party(relying_party.clone()),
+            )
+            .wrap(webauthn_limit())
+            .route(web::post().to(routes::do_webauthn_login)),
+    );
+
+    // Register a passkey
+    cfg.service(
+        resource("/passkeys/options")
+            .wrap(
+                authentication(&backend, session_lifetime)
+                    .with_relying_party(relying_party.clone()),
+            )
+            .route(web::post().to(routes::passkey_registration_options)),
+    );
+    cfg.service(
+        resource("/passkeys")
+            .wrap(
+                authentication(&backend, session_lifetime)
+                    .with_relying_party(relying_party.clone()),
+            )
+            .route(web::get().to(routes::passkeys_page))
+            .route(web::post().to(routes::do_register_passkey)),
+    );
+
     // Logout
     cfg.service(
         resource("/logout")
-            .wrap(RustAuthMiddleware::new(backend.clone(), HashSet::new()))
+            .wrap(authentication(&backend, session_lifetime))
             .route(web::post().to(routes::do_logout)),
     );
 
     // Status
     cfg.service(
         resource("/")
-            .wrap(RustAuthMiddleware::new(backend, HashSet::new()))
+            .wrap(authentication(&backend, session_lifetime))
             .route(web::get().to(routes::status_page)),
     );
 }
 
-pub fn user_config(cfg: &mut web::ServiceConfig, pool: &Pool<Postgres>) {
+/// Provides the routes for users, the required capabilities are declared at the route handlers.
+pub fn user_config(
+    cfg: &mut web::ServiceConfig,
+    pool: &Pool<Postgres>,
+    rate_limits: &MemoryStore,
+    session_lifetime: SessionLifetime,
+) {
+    let backend = PostgreSqlBackend::new(pool.clone());
+
+    // Limited to 120 requests per minute and session
     cfg.service(
         resource("/information/user")
-            .wrap(RustAuthMiddleware::new(
-                PostgreSqlBackend::new(pool.clone()),
-                [Capabilities::UserRead]
-                    .iter()
-                    .map(|c| c.to_string())
-                    .collect(),
-            ))
+            .wrap(authentication(&backend, session_lifetime))
+            .wrap(
+                RateLimit::new(
+                    "information",
+                    RateLimitPolicy::per(120, Duration::from_secs(60)),
+                    rate_limits.clone(),
+                )
+                .by(RateLimitKey::session()),
+            )
             .route(get().to(routes::retrieve_user_information)),
     );
+
+    // Users need a grant to read the document with the id in the path
+    cfg.service(
+        resource("/information/document/{id}")
+            .wrap(
+                authentication(&backend, session_lifetime)
+                    .with_resource_scope(ResourceScope::new("read", "document", "id")),
+            )
+            .route(get().to(routes::retrieve_document_information)),
+    );
 }
 
-pub fn admin_config(cfg: &mut web::ServiceConfig, pool: &Pool<Postgres>) {
+/// Provides the routes for admins, the required capabilities are declared at the route handlers.
+pub fn admin_config(
+    cfg: &mut web::ServiceConfig,
+    pool: &Pool<Postgres>,
+    access_policy: &Option<Arc<AccessPolicy>>,
+    session_lifetime: SessionLifetime,
+) {
+    let mut middleware = authentication(&PostgreSqlBackend::new(pool.clone()), session_lifetime);
+    if let Some(access_policy) = access_policy {
+        middleware = middleware.with_access_policy(access_policy.clone());
+    }
+
     cfg.service(
         resource("/information/admin")
-            .wrap(RustAuthMiddleware::new(
-                PostgreSqlBackend::new(pool.clone()),
-                [Capabilities::AdminRead]
-                    .iter()
-                    .map(|c| c.to_string())
-                    .collect(),
-            ))
+            .wrap(middleware)
             .route(get().to(routes::retrieve_admin_information)),
     );
 }
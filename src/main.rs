@@ -1,5 +1,6 @@
 use std::{env, fs::File, io::BufReader};
 
+use access_control::{hashing, pepper::Peppers};
 use database_integration::utility::create_db_pool;
 
 use actix_web::{
@@ -39,6 +40,29 @@ fn build_address() -> String {
     format!("{}:{}", domain, port)
 }
 
+/// Reads the limit of concurrent password hashing operations from the `HASHING_MAX_CONCURRENCY` environment variable.
+///
+/// Falls back to the available parallelism of the system if the variable is not set.
+fn hashing_max_concurrency() -> usize {
+    env::var("HASHING_MAX_CONCURRENCY")
+        .map(|v| {
+            v.parse()
+                .expect("HASHING_MAX_CONCURRENCY is not a valid number")
+        })
+        .unwrap_or_else(|_| hashing::default_max_concurrency())
+}
+
+/// Reads the peppers for password hashes from the `PASSWORD_PEPPERS` and `PASSWORD_PEPPER_ID` environment variables.
+///
+/// `PASSWORD_PEPPERS` contains a comma separated list of `id:base64-secret` pairs and `PASSWORD_PEPPER_ID` the key id
+/// of the pepper that is used for new hashes. Without these variables, the hashes are created without a pepper.
+fn peppers() -> Peppers {
+    let peppers = env::var("PASSWORD_PEPPERS").unwrap_or_default();
+    let current = env::var("PASSWORD_PEPPER_ID").ok();
+
+    Peppers::parse(&peppers, current.as_deref()).expect("PASSWORD_PEPPERS is invalid")
+}
+
 /// This Service starts the actix-web example application.
 ///
 /// To execute this program with its default values, execute these commands.
@@ -54,10 +78,24 @@ async fn main() -> std::io::Result<()> {
     dotenv::dotenv().ok();
     env_logger::init();
 
+    hashing::configure(hashing::Config {
+        max_concurrency: hashing_max_concurrency(),
+        peppers: peppers(),
+        ..Default::default()
+    })
+    .expect("hashing pool started before configuration");
+
     let pool = create_db_pool()
         .await
         .expect("could not create database pool");
 
+    let password_policy = configuration::password_policy();
+    let relying_party = configuration::relying_party();
+    let access_policy = configuration::access_policy();
+    let rate_limits = configuration::rate_limits();
+    let session_lifetime = configuration::session_lifetime();
+    let csrf = configuration::csrf();
+
     // Load TLS certificates
     let mut config = ServerConfig::new(NoClientAuth::new());
     let cert_file = &mut BufReader::new(File::open("cert.pem").expect(CERT_ERROR_MESSAGE));
@@ -76,10 +114,20 @@ async fn main() -> std::io::Result<()> {
                 ErrorHandlers::new()
                     .handler(http::StatusCode::UNAUTHORIZED, routes::login_redirect),
             )
+            .wrap(csrf.clone())
             .wrap(actix_web::middleware::Logger::default())
-            .configure(|c| configuration::website(c, &pool))
-            .configure(|c| configuration::user_config(c, &pool))
-            .configure(|c| configuration::admin_config(c, &pool))
+            .configure(|c| {
+                configuration::website(
+                    c,
+                    &pool,
+                    &password_policy,
+                    &relying_party,
+                    &rate_limits,
+                    session_lifetime,
+                )
+            })
+            .configure(|c| configuration::user_config(c, &pool, &rate_limits, session_lifetime))
+            .configure(|c| configuration::admin_config(c, &pool, &access_policy, session_lifetime))
     })
     .bind_rustls(build_address().as_str(), config)?
     .run()
@@ -95,7 +143,11 @@ mod tests {
     // These test should be rewritten and either build using a macro or a combination of functions.
     // Time limitation don't allow for this (currently), keep in mind that the tests are very repetitive.
     use super::*;
-    use actix_web::{cookie::Cookie, test, App};
+    use actix_web::{
+        cookie::Cookie,
+        dev::{MessageBody, ServiceResponse},
+        test, App,
+    };
     use rand::{distributions::Alphanumeric, thread_rng, Rng};
     use serde::Serialize;
     use sqlx::{postgres::PgRow, Row};
@@ -104,6 +156,35 @@ mod tests {
     struct Credentials {
         username: String,
         password: String,
+        csrf_token: String,
+    }
+
+    #[derive(Serialize)]
+    struct CsrfForm {
+        csrf_token: String,
+    }
+
+    /// Returns the CSRF token of the forms of a page.
+    fn csrf_token(body: &[u8]) -> String {
+        let body = std::str::from_utf8(body).unwrap();
+        let field = r#"name="csrf_token" value=""#;
+        let start = body.find(field).unwrap() + field.len();
+        let end = start + body[start..].find('"').unwrap();
+        body[start..end].to_string()
+    }
+
+    /// Returns the pre-session cookie and the token of the CSRF protection that are set by a page.
+    async fn pre_session<B>(resp: ServiceResponse<B>) -> (Cookie<'static>, String)
+    where
+        B: MessageBody + Unpin,
+    {
+        let cookie = resp
+            .response()
+            .cookies()
+            .find(|c| c.name() == "csrf")
+            .unwrap()
+            .into_owned();
+        (cookie, csrf_token(&test::read_body(resp).await))
     }
 
     #[ignore = "Database necessary to run these tests"]
@@ -114,17 +195,37 @@ mod tests {
         let pool = create_db_pool()
             .await
             .expect("could not create database pool");
+        let password_policy = configuration::password_policy();
+        let relying_party = configuration::relying_party();
+        let access_policy = configuration::access_policy();
+        let rate_limits = configuration::rate_limits();
+        let session_lifetime = configuration::session_lifetime();
 
         // Create app with standard configuration
         let mut app = test::init_service(
             App::new()
-                .configure(|c| configuration::website(c, &pool))
-                .configure(|c| configuration::user_config(c, &pool))
-                .configure(|c| configuration::admin_config(c, &pool)),
+                .wrap(configuration::csrf())
+                .configure(|c| {
+                    configuration::website(
+                        c,
+                        &pool,
+                        &password_policy,
+                        &relying_party,
+                        &rate_limits,
+                        session_lifetime,
+                    )
+                })
+                .configure(|c| configuration::user_config(c, &pool, &rate_limits, session_lifetime))
+                .configure(|c| {
+                    configuration::admin_config(c, &pool, &access_policy, session_lifetime)
+                }),
         )
         .await;
 
         // Tests start here
+        let register_page = test::TestRequest::get().uri("/register").to_request();
+        let (csrf_cookie, token) =
+            pre_session(test::call_service(&mut app, register_page).await).await;
         let credentials = Credentials {
             username: std::str::from_utf8(
                 &thread_rng()
@@ -136,10 +237,12 @@ mod tests {
             .to_string()
             .to_lowercase(),
             password: "12345678901234567890".to_string(),
+            csrf_token: token,
         };
 
         // register user
         let register_req = test::TestRequest::post()
+            .cookie(csrf_cookie.clone())
             .set_form(&credentials)
             .uri("/register")
             .to_request();
@@ -154,8 +257,22 @@ mod tests {
             .await
             .unwrap();
 
+        // a login without the CSRF token is rejected
+        let login_req = test::TestRequest::post()
+            .cookie(csrf_cookie.clone())
+            .set_form(&Credentials {
+                username: credentials.username.clone(),
+                password: credentials.password.clone(),
+                csrf_token: String::new(),
+            })
+            .uri("/login")
+            .to_request();
+        let resp = test::call_service(&mut app, login_req).await;
+        assert_eq!(resp.status(), http::StatusCode::FORBIDDEN);
+
         // login user
         let login_req = test::TestRequest::post()
+            .cookie(csrf_cookie)
             .set_form(&credentials)
             .uri("/login")
             .to_request();
@@ -164,7 +281,7 @@ mod tests {
 
         // check that the session has been created successfully
         sqlx::query("SELECT * FROM sessions WHERE user_id = $1;")
-            .bind(&user_id)
+            .bind(user_id)
             .fetch_one(&pool)
             .await
             .unwrap();
@@ -175,7 +292,7 @@ mod tests {
             .cookies()
             .filter(|c| c.name() == "id")
             .collect::<Vec<Cookie>>()
-            .get(0)
+            .first()
             .unwrap()
             .to_owned();
 
@@ -186,10 +303,13 @@ mod tests {
             .to_request();
         let resp = test::call_service(&mut app, info_req).await;
         assert!(resp.status().is_success());
+        // the token of the logout form is bound to the session
+        let csrf_token = csrf_token(&test::read_body(resp).await);
 
         // logout user
         let info_req = test::TestRequest::post()
             .cookie(id_cookie)
+            .set_form(&CsrfForm { csrf_token })
             .uri("/logout")
             .to_request();
         let resp = test::call_service(&mut app, info_req).await;
@@ -197,7 +317,7 @@ mod tests {
 
         // check that the session has been deleted successfully
         assert!(sqlx::query("SELECT * FROM sessions WHERE user_id = $1;")
-            .bind(&user_id)
+            .bind(user_id)
             .fetch_one(&pool)
             .await
             .is_err());
@@ -212,17 +332,37 @@ mod tests {
         let pool = create_db_pool()
             .await
             .expect("could not create database pool");
+        let password_policy = configuration::password_policy();
+        let relying_party = configuration::relying_party();
+        let access_policy = configuration::access_policy();
+        let rate_limits = configuration::rate_limits();
+        let session_lifetime = configuration::session_lifetime();
 
         // Create app with standard configuration
         let mut app = test::init_service(
             App::new()
-                .configure(|c| configuration::website(c, &pool))
-                .configure(|c| configuration::user_config(c, &pool))
-                .configure(|c| configuration::admin_config(c, &pool)),
+                .wrap(configuration::csrf())
+                .configure(|c| {
+                    configuration::website(
+                        c,
+                        &pool,
+                        &password_policy,
+                        &relying_party,
+                        &rate_limits,
+                        session_lifetime,
+                    )
+                })
+                .configure(|c| configuration::user_config(c, &pool, &rate_limits, session_lifetime))
+                .configure(|c| {
+                    configuration::admin_config(c, &pool, &access_policy, session_lifetime)
+                }),
         )
         .await;
 
         // Tests start here
+        let register_page = test::TestRequest::get().uri("/register").to_request();
+        let (csrf_cookie, token) =
+            pre_session(test::call_service(&mut app, register_page).await).await;
         let credentials = Credentials {
             username: std::str::from_utf8(
                 &thread_rng()
@@ -242,10 +382,12 @@ mod tests {
             .unwrap()
             .to_string()
             .to_lowercase(),
+            csrf_token: token,
         };
 
         // register user
         let register_req = test::TestRequest::post()
+            .cookie(csrf_cookie.clone())
             .set_form(&credentials)
             .uri("/register")
             .to_request();
@@ -270,17 +412,37 @@ mod tests {
         let pool = create_db_pool()
             .await
             .expect("could not create database pool");
+        let password_policy = configuration::password_policy();
+        let relying_party = configuration::relying_party();
+        let access_policy = configuration::access_policy();
+        let rate_limits = configuration::rate_limits();
+        let session_lifetime = configuration::session_lifetime();
 
         // Create app with standard configuration
         let mut app = test::init_service(
             App::new()
-                .configure(|c| configuration::website(c, &pool))
-                .configure(|c| configuration::user_config(c, &pool))
-                .configure(|c| configuration::admin_config(c, &pool)),
+                .wrap(configuration::csrf())
+                .configure(|c| {
+                    configuration::website(
+                        c,
+                        &pool,
+                        &password_policy,
+                        &relying_party,
+                        &rate_limits,
+                        session_lifetime,
+                    )
+                })
+                .configure(|c| configuration::user_config(c, &pool, &rate_limits, session_lifetime))
+                .configure(|c| {
+                    configuration::admin_config(c, &pool, &access_policy, session_lifetime)
+                }),
         )
         .await;
 
         // Tests start here
+        let register_page = test::TestRequest::get().uri("/register").to_request();
+        let (csrf_cookie, token) =
+            pre_session(test::call_service(&mut app, register_page).await).await;
         let credentials = Credentials {
             username: std::str::from_utf8(
                 &thread_rng()
@@ -300,10 +462,12 @@ mod tests {
             .unwrap()
             .to_string()
             .to_lowercase(),
+            csrf_token: token,
         };
 
         // register user
         let register_req = test::TestRequest::post()
+            .cookie(csrf_cookie.clone())
             .set_form(&credentials)
             .uri("/register")
             .to_request();
@@ -318,4 +482,157 @@ mod tests {
             .await
             .unwrap();
     }
+
+    #[derive(Serialize)]
+    struct SessionRevocation {
+        id: String,
+        csrf_token: String,
+    }
+
+    #[ignore = "Database necessary to run these tests"]
+    #[actix_rt::test]
+    async fn revoke_sessions() {
+        dotenv::dotenv().ok();
+        // create database pool
+        let pool = create_db_pool()
+            .await
+            .expect("could not create database pool");
+        let password_policy = configuration::password_policy();
+        let relying_party = configuration::relying_party();
+        let rate_limits = configuration::rate_limits();
+        let session_lifetime = configuration::session_lifetime();
+
+        // Create app with standard configuration
+        let mut app = test::init_service(App::new().wrap(configuration::csrf()).configure(|c| {
+            configuration::website(
+                c,
+                &pool,
+                &password_policy,
+                &relying_party,
+                &rate_limits,
+                session_lifetime,
+            )
+        }))
+        .await;
+
+        // Register two users, the first one logs in on two devices
+        let username = || {
+            std::str::from_utf8(
+                &thread_rng()
+                    .sample_iter(Alphanumeric)
+                    .take(32)
+                    .collect::<Vec<_>>(),
+            )
+            .unwrap()
+            .to_lowercase()
+        };
+        let usernames = [username(), username()];
+        let password = "12345678901234567890".to_string();
+        let mut sessions = Vec::new();
+        for (uri, username) in [
+            ("/register", &usernames[0]),
+            ("/register", &usernames[1]),
+            ("/login", &usernames[0]),
+            ("/login", &usernames[0]),
+            ("/login", &usernames[1]),
+        ] {
+            let register_page = test::TestRequest::get().uri("/register").to_request();
+            let (csrf_cookie, csrf_token) =
+                pre_session(test::call_service(&mut app, register_page).await).await;
+            let req = test::TestRequest::post()
+                .cookie(csrf_cookie)
+                .set_form(&Credentials {
+                    username: username.clone(),
+                    password: password.clone(),
+                    csrf_token,
+                })
+                .uri(uri)
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            if let Some(cookie) = resp.response().cookies().find(|c| c.name() == "id") {
+                sessions.push(cookie.into_owned());
+            }
+        }
+        let (current, other, foreign) = (&sessions[0], &sessions[1], &sessions[2]);
+
+        // the token of the forms of the sessions page is bound to the session
+        let sessions_req = test::TestRequest::get()
+            .cookie(current.clone())
+            .uri("/sessions")
+            .to_request();
+        let resp = test::call_service(&mut app, sessions_req).await;
+        assert!(resp.status().is_success());
+        let csrf_token = csrf_token(&test::read_body(resp).await);
+
+        // the session of another user can not be revoked
+        let revoke_req = test::TestRequest::post()
+            .cookie(current.clone())
+            .set_form(&SessionRevocation {
+                id: access_control::session::hash_session_id(foreign.value()),
+                csrf_token: csrf_token.clone(),
+            })
+            .uri("/sessions/revoke")
+            .to_request();
+        let resp = test::call_service(&mut app, revoke_req).await;
+        assert_eq!(resp.status(), http::StatusCode::NOT_FOUND);
+
+        // revoke the other session of the user
+        let revoke_req = test::TestRequest::post()
+            .cookie(current.clone())
+            .set_form(&CsrfForm { csrf_token })
+            .uri("/sessions/revoke-others")
+            .to_request();
+        let resp = test::call_service(&mut app, revoke_req).await;
+        assert!(resp.status().is_redirection());
+
+        // only the other session of the user has been revoked
+        for (cookie, active) in [(current, true), (other, false), (foreign, true)] {
+            let sessions_req = test::TestRequest::get()
+                .cookie(cookie.clone())
+                .uri("/sessions")
+                .to_request();
+            let resp = test::call_service(&mut app, sessions_req).await;
+            assert_eq!(resp.status().is_success(), active);
+        }
+    }
+
+    #[ignore = "Database necessary to run these tests"]
+    #[actix_rt::test]
+    async fn status_page_logged_out() {
+        dotenv::dotenv().ok();
+        // create database pool
+        let pool = create_db_pool()
+            .await
+            .expect("could not create database pool");
+        let password_policy = configuration::password_policy();
+        let relying_party = configuration::relying_party();
+        let rate_limits = configuration::rate_limits();
+        let session_lifetime = configuration::session_lifetime();
+
+        // Create app with standard configuration
+        let mut app = test::init_service(App::new().wrap(configuration::csrf()).configure(|c| {
+            configuration::website(
+                c,
+                &pool,
+                &password_policy,
+                &relying_party,
+                &rate_limits,
+                session_lifetime,
+            )
+        }))
+        .await;
+
+        // Without a session the status page is shown instead of redirecting to the login page
+        let status_req = test::TestRequest::get().uri("/").to_request();
+        let resp = test::call_service(&mut app, status_req).await;
+        assert!(resp.status().is_success());
+
+        // An unknown session is treated like a missing one
+        let status_req = test::TestRequest::get()
+            .cookie(Cookie::new("id", "unknown"))
+            .uri("/")
+            .to_request();
+        let resp = test::call_service(&mut app, status_req).await;
+        assert!(resp.status().is_success());
+    }
 }
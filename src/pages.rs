@@ -1,7 +1,17 @@
 //! Provides access to the website temples that are used by the actix-web example application.
 
+use access_control::session::SessionInfo;
+use access_control::totp::Totp;
+use access_control::User as UserTrait;
 use askama::Template;
 use database_integration::user::User;
+use middleware::csrf::CsrfToken;
+use qrcode::{render::svg, QrCode};
+use sqlx::types::chrono::{DateTime, Utc};
+use std::time::SystemTime;
+
+/// The name of the service that authenticator apps show next to the one-time passwords.
+const TOTP_ISSUER: &str = "Rust Auth Service";
 
 /// A collection of the available pages that are displayed in the nav-bar.
 const PAGES: &[Page] = &[
@@ -28,19 +38,22 @@ pub struct Page {
 }
 
 /// The [`StatusPage`] struct represents the default landing page and displays the available user data (if available).
-/// # Traits
-/// The [`StatusPage`] struct implements the [`Default`] trait.
-/// It can therefore be used by only defining the User data or using the default value of `None` if the user is not logged in.
+/// # Construction
+/// Every page contains the logout form, so it needs the [`CsrfToken`] of the request.
+/// [`StatusPage::new`] creates the page without user data, which can be set if the user is logged in.
 ///
 /// Additionally the [`StatusPage`] struct uses the [`askama::Template`] at `template/status.html` and `askama_actix` to build a HTTP response.
 /// # Example usage
 /// ```
 /// # use database_integration::{user::User, PostgreSqlBackend};
 /// # use actix_web::Responder;
-/// pub async fn status_page(user_details: UserDetails<PostgreSqlBackend, User>) -> impl Responder {
+/// pub async fn status_page(
+///     user_details: OptionalUserDetails<PostgreSqlBackend>,
+///     csrf_token: CsrfToken,
+/// ) -> impl Responder {
 ///     StatusPage {
-///         user: Some(user_details.user),
-///         ..Default::default()
+///         user: user_details.user,
+///         ..StatusPage::new(csrf_token)
 ///     }
 /// }
 /// ```
@@ -49,24 +62,27 @@ pub struct Page {
 pub struct StatusPage {
     pub title: &'static str,
     pub pages: &'static [Page],
+    pub csrf_token: CsrfToken,
     pub user: Option<User>,
 }
 
-impl Default for StatusPage {
-    /// Implementation of the [`Default`] trait for the [`StatusPage`] struct.
+impl StatusPage {
+    /// Creates the page of a user that is not logged in.
     ///
-    /// # Default Values
+    /// # Values
     /// ```
     /// StatusPage {
     ///     title: "Status",
     ///     pages: PAGES,
+    ///     csrf_token,
     ///     user: None,
     /// }
     /// ```
-    fn default() -> Self {
+    pub fn new(csrf_token: CsrfToken) -> Self {
         StatusPage {
             title: "Status",
             pages: PAGES,
+            csrf_token,
             user: None,
         }
     }
@@ -77,32 +93,182 @@ impl Default for StatusPage {
 pub struct LoginPage {
     pub title: &'static str,
     pub pages: &'static [Page],
+    pub csrf_token: CsrfToken,
     pub error: bool,
+    /// Asks for the one-time password of the second factor instead of the username and password
+    pub totp: bool,
+    /// The seconds to wait after too many failed logins
+    pub retry_after: Option<u64>,
 }
 
-impl Default for LoginPage {
-    fn default() -> Self {
+impl LoginPage {
+    pub fn new(csrf_token: CsrfToken) -> Self {
         LoginPage {
             title: "Login",
             pages: PAGES,
+            csrf_token,
             error: false,
+            totp: false,
+            retry_after: None,
+        }
+    }
+}
+
+/// The [`TotpPage`] struct represents the page to set up two-factor authentication with an authenticator app.
+///
+/// The secret is part of the form, so that the enrollment can be confirmed with a one-time password of the same
+/// secret. It is only stored after the confirmation succeeded and if it has the length of a generated secret.
+#[derive(Template)]
+#[template(path = "totp.html")]
+pub struct TotpPage {
+    pub title: &'static str,
+    pub pages: &'static [Page],
+    pub csrf_token: CsrfToken,
+    /// The base32 encoded secret for users that can not scan the QR code
+    pub secret: String,
+    /// The provisioning URI as QR code in SVG format
+    pub qr_code: String,
+    /// The user already has a secret, which is only replaced with a one-time password of it
+    pub replace: bool,
+    pub error: bool,
+}
+
+impl TotpPage {
+    /// Creates the page for the secret, which is shown to the user with the account name.
+    pub fn new(totp: &Totp, user: &User, error: bool, csrf_token: CsrfToken) -> Self {
+        let uri = totp.provisioning_uri(TOTP_ISSUER, &user.username);
+        let qr_code = QrCode::new(uri.as_bytes())
+            .map(|code| code.render::<svg::Color>().min_dimensions(200, 200).build())
+            .unwrap_or_default();
+
+        TotpPage {
+            title: "Two-Factor Authentication",
+            pages: PAGES,
+            csrf_token,
+            secret: totp.to_base32(),
+            qr_code,
+            replace: user.totp_secret().is_some(),
+            error,
         }
     }
 }
 
+/// The [`RecoveryCodesPage`] struct represents the page that shows a new batch of recovery codes.
+///
+/// The codes are only stored as hashes, so this page is the only time the user sees them.
+#[derive(Template)]
+#[template(path = "recovery.html")]
+pub struct RecoveryCodesPage {
+    pub title: &'static str,
+    pub pages: &'static [Page],
+    pub csrf_token: CsrfToken,
+    pub codes: Vec<String>,
+}
+
+impl RecoveryCodesPage {
+    /// Creates the page that shows the codes.
+    pub fn new(codes: Vec<String>, csrf_token: CsrfToken) -> Self {
+        RecoveryCodesPage {
+            title: "Recovery Codes",
+            pages: PAGES,
+            csrf_token,
+            codes,
+        }
+    }
+}
+
+/// The [`PasskeysPage`] struct represents the page to register a passkey, the ceremony runs in `webauthn.js`.
+#[derive(Template)]
+#[template(path = "passkeys.html")]
+pub struct PasskeysPage {
+    pub title: &'static str,
+    pub pages: &'static [Page],
+    /// Also sent by the script in the `X-CSRF-Token` header
+    pub csrf_token: CsrfToken,
+}
+
+impl PasskeysPage {
+    pub fn new(csrf_token: CsrfToken) -> Self {
+        PasskeysPage {
+            title: "Passkeys",
+            pages: PAGES,
+            csrf_token,
+        }
+    }
+}
+
+/// The [`SessionsPage`] struct represents the page that lists the active sessions of a user, so that the sessions of
+/// lost or unknown devices can be revoked.
+#[derive(Template)]
+#[template(path = "sessions.html")]
+pub struct SessionsPage {
+    pub title: &'static str,
+    pub pages: &'static [Page],
+    pub csrf_token: CsrfToken,
+    pub sessions: Vec<SessionRow>,
+}
+
+impl SessionsPage {
+    pub fn new(sessions: Vec<SessionInfo>, csrf_token: CsrfToken) -> Self {
+        SessionsPage {
+            title: "Sessions",
+            pages: PAGES,
+            csrf_token,
+            sessions: sessions.into_iter().map(SessionRow::from).collect(),
+        }
+    }
+}
+
+/// A session of the [`SessionsPage`] with its values formatted for display.
+pub struct SessionRow {
+    /// The digest of the session id, which is submitted to revoke the session
+    pub id: String,
+    pub created: String,
+    pub last_seen: String,
+    pub ip_address: String,
+    pub user_agent: String,
+    pub current: bool,
+}
+
+impl From<SessionInfo> for SessionRow {
+    fn from(session: SessionInfo) -> Self {
+        SessionRow {
+            id: session.id,
+            created: format_time(session.created),
+            last_seen: format_time(session.last_seen),
+            ip_address: session
+                .ip_address
+                .map(|ip| ip.to_string())
+                .unwrap_or_else(|| "Unknown".to_string()),
+            user_agent: session.user_agent.unwrap_or_else(|| "Unknown".to_string()),
+            current: session.current,
+        }
+    }
+}
+
+/// Formats a point in time in UTC with minute precision.
+fn format_time(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time)
+        .format("%Y-%m-%d %H:%M UTC")
+        .to_string()
+}
+
 #[derive(Template)]
 #[template(path = "register.html")]
 pub struct RegisterPage {
     pub title: &'static str,
     pub pages: &'static [Page],
-    pub message: Option<Result<(), &'static str>>,
+    pub csrf_token: CsrfToken,
+    /// The result of a registration, containing the reasons if it failed
+    pub message: Option<Result<(), Vec<String>>>,
 }
 
-impl Default for RegisterPage {
-    fn default() -> Self {
+impl RegisterPage {
+    pub fn new(csrf_token: CsrfToken) -> Self {
         RegisterPage {
             title: "Register",
             pages: PAGES,
+            csrf_token,
             message: None,
         }
     }
@@ -1,22 +1,47 @@
 //! Provides all routes used by the actix-web example application.
 
-use crate::pages::{LoginPage, RegisterPage, StatusPage};
+use crate::pages::{
+    LoginPage, PasskeysPage, RecoveryCodesPage, RegisterPage, SessionsPage, StatusPage, TotpPage,
+};
+use access_control::totp::Totp;
+use access_control::webauthn::{AssertionResponse, RegistrationResponse};
 use actix_web::{
     dev::{self, ServiceResponse},
-    http::header,
+    http::header::{self, CacheControl, CacheDirective},
     middleware::errhandlers::ErrorHandlerResponse,
-    web::Form,
+    web::{Form, Json},
     HttpResponse, Responder, Result,
 };
 use askama::Template;
 use database_integration::PostgreSqlBackend;
-use middleware::{SessionState, UserDetails};
+use middleware::{
+    csrf::CsrfToken, require_capabilities, LoginOutcome, OptionalUserDetails, RegistrationError,
+    ResourceDetails, SessionState, TooManyRequests, UserDetails,
+};
 use serde::Deserialize;
 
 #[derive(Deserialize)]
 pub struct Credentials {
     username: String,
     password: String,
+    /// Whether the user wants to stay logged in, which is only sent by the login form
+    #[serde(default)]
+    remember: bool,
+}
+
+/// The one-time password of the second factor.
+#[derive(Deserialize)]
+pub struct OneTimePassword {
+    code: String,
+}
+
+/// A new TOTP secret and a one-time password that confirms it.
+#[derive(Deserialize)]
+pub struct TotpEnrollment {
+    secret: String,
+    code: String,
+    /// The one-time password of the current secret, which is required to replace it
+    current_code: Option<String>,
 }
 
 pub fn login_redirect(res: dev::ServiceResponse) -> Result<ErrorHandlerResponse<dev::Body>> {
@@ -28,38 +53,96 @@ pub fn login_redirect(res: dev::ServiceResponse) -> Result<ErrorHandlerResponse<
     )))
 }
 
-pub async fn register_page() -> impl Responder {
-    RegisterPage::default()
+pub async fn register_page(csrf_token: CsrfToken) -> impl Responder {
+    RegisterPage::new(csrf_token)
 }
 
 pub async fn do_register(
     form: Form<Credentials>,
     session_state: SessionState<PostgreSqlBackend>,
+    csrf_token: CsrfToken,
 ) -> impl Responder {
     let message = session_state
         .register(&form.username, &form.password)
         .await
-        .map_err(|_| "registration failed");
+        .map_err(|err| match err.as_error::<RegistrationError>() {
+            Some(RegistrationError(access_control::Error::UsernamePolicy(violations))) => {
+                violations.iter().map(ToString::to_string).collect()
+            }
+            Some(RegistrationError(access_control::Error::PasswordPolicy(violations))) => {
+                violations.iter().map(ToString::to_string).collect()
+            }
+            _ => vec!["registration failed".to_string()],
+        });
     RegisterPage {
         message: Some(message),
-        ..Default::default()
+        ..RegisterPage::new(csrf_token)
     }
 }
 
-pub async fn login_page() -> impl Responder {
-    LoginPage::default()
+pub async fn login_page(csrf_token: CsrfToken) -> impl Responder {
+    LoginPage::new(csrf_token)
 }
 
 pub async fn do_login(
     form: Form<Credentials>,
     session_state: SessionState<PostgreSqlBackend>,
+    csrf_token: CsrfToken,
 ) -> impl Responder {
-    match session_state.login(&form.username, &form.password).await {
+    match session_state
+        .login(&form.username, &form.password, form.remember)
+        .await
+    {
+        Ok(LoginOutcome::Complete(_)) => {
+            HttpResponse::Found().header(header::LOCATION, "/").finish()
+        }
+        Ok(LoginOutcome::TotpRequired) => HttpResponse::Found()
+            .header(header::LOCATION, "/login/totp")
+            .finish(),
+        // The page tells the user how long to wait, the status and header are kept for non-browser clients
+        Err(err) => match err.as_error::<TooManyRequests>() {
+            Some(throttled) => HttpResponse::TooManyRequests()
+                .header(header::RETRY_AFTER, throttled.retry_after_secs())
+                .body(
+                    LoginPage {
+                        retry_after: Some(throttled.retry_after_secs()),
+                        ..LoginPage::new(csrf_token)
+                    }
+                    .render()
+                    .unwrap(),
+                ),
+            None => HttpResponse::Ok().body(
+                LoginPage {
+                    error: true,
+                    ..LoginPage::new(csrf_token)
+                }
+                .render()
+                .unwrap(),
+            ),
+        },
+    }
+}
+
+/// Asks for the one-time password after the password of a user with two-factor authentication has been verified
+pub async fn totp_login_page(csrf_token: CsrfToken) -> impl Responder {
+    LoginPage {
+        totp: true,
+        ..LoginPage::new(csrf_token)
+    }
+}
+
+pub async fn do_verify_totp(
+    form: Form<OneTimePassword>,
+    session_state: SessionState<PostgreSqlBackend>,
+    csrf_token: CsrfToken,
+) -> impl Responder {
+    match session_state.verify_totp(&form.code).await {
         Ok(_) => HttpResponse::Found().header(header::LOCATION, "/").finish(),
         Err(_) => HttpResponse::Ok().body(
             LoginPage {
                 error: true,
-                ..Default::default()
+                totp: true,
+                ..LoginPage::new(csrf_token)
             }
             .render()
             .unwrap(),
@@ -67,6 +150,170 @@ pub async fn do_login(
     }
 }
 
+/// Completes the login with a recovery code instead of the one-time password
+pub async fn do_verify_recovery_code(
+    form: Form<OneTimePassword>,
+    session_state: SessionState<PostgreSqlBackend>,
+    csrf_token: CsrfToken,
+) -> impl Responder {
+    match session_state.verify_recovery_code(&form.code).await {
+        Ok(_) => HttpResponse::Found().header(header::LOCATION, "/").finish(),
+        Err(_) => HttpResponse::Ok().body(
+            LoginPage {
+                error: true,
+                totp: true,
+                ..LoginPage::new(csrf_token)
+            }
+            .render()
+            .unwrap(),
+        ),
+    }
+}
+
+/// Shows a new TOTP secret, which the user has to confirm to enable two-factor authentication
+pub async fn totp_page(
+    user_details: UserDetails<PostgreSqlBackend>,
+    csrf_token: CsrfToken,
+) -> impl Responder {
+    TotpPage::new(&Totp::generate(), &user_details.user, false, csrf_token)
+}
+
+pub async fn do_enable_totp(
+    form: Form<TotpEnrollment>,
+    session_state: SessionState<PostgreSqlBackend>,
+    user_details: UserDetails<PostgreSqlBackend>,
+    csrf_token: CsrfToken,
+) -> impl Responder {
+    let totp = match Totp::from_base32(&form.secret) {
+        Some(totp) => totp,
+        None => return HttpResponse::BadRequest().finish(),
+    };
+
+    match session_state
+        .enable_totp(&totp, &form.code, form.current_code.as_deref())
+        .await
+    {
+        // The recovery codes are shown once right after the enrollment
+        Ok(_) => recovery_codes(&session_state, csrf_token).await,
+        Err(_) => HttpResponse::Ok().body(
+            TotpPage::new(&totp, &user_details.user, true, csrf_token)
+                .render()
+                .unwrap(),
+        ),
+    }
+}
+
+/// Replaces the recovery codes of the user with a new batch
+pub async fn do_generate_recovery_codes(
+    session_state: SessionState<PostgreSqlBackend>,
+    csrf_token: CsrfToken,
+) -> impl Responder {
+    recovery_codes(&session_state, csrf_token).await
+}
+
+/// Generates a new batch of recovery codes and shows them, the response must not be cached
+async fn recovery_codes(
+    session_state: &SessionState<PostgreSqlBackend>,
+    csrf_token: CsrfToken,
+) -> HttpResponse {
+    match session_state.generate_recovery_codes().await {
+        Ok(codes) => HttpResponse::Ok()
+            .set(CacheControl(vec![CacheDirective::NoStore]))
+            .body(RecoveryCodesPage::new(codes, csrf_token).render().unwrap()),
+        Err(err) => err.into(),
+    }
+}
+
+/// Serves the script of the WebAuthn ceremonies, the Content Security Policy does not allow inline scripts
+pub async fn webauthn_script() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("application/javascript")
+        .body(include_str!("../static/webauthn.js"))
+}
+
+/// Issues the challenge of a login with a passkey
+pub async fn webauthn_login_options(
+    session_state: SessionState<PostgreSqlBackend>,
+) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(session_state.webauthn_login_options().await?))
+}
+
+pub async fn do_webauthn_login(
+    response: Json<AssertionResponse>,
+    session_state: SessionState<PostgreSqlBackend>,
+) -> impl Responder {
+    // The script redirects after the login, a 401 would be redirected to the login page instead
+    match session_state.webauthn_login(&response).await {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(_) => HttpResponse::Forbidden().finish(),
+    }
+}
+
+/// Lets a logged-in user register a passkey
+pub async fn passkeys_page(
+    _user_details: UserDetails<PostgreSqlBackend>,
+    csrf_token: CsrfToken,
+) -> impl Responder {
+    PasskeysPage::new(csrf_token)
+}
+
+/// Issues the challenge of a passkey registration
+pub async fn passkey_registration_options(
+    session_state: SessionState<PostgreSqlBackend>,
+) -> Result<HttpResponse> {
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(session_state.webauthn_registration_options().await?))
+}
+
+pub async fn do_register_passkey(
+    response: Json<RegistrationResponse>,
+    session_state: SessionState<PostgreSqlBackend>,
+) -> Result<HttpResponse> {
+    session_state.register_webauthn(&response).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Deserialize)]
+pub struct SessionRevocation {
+    /// The digest of the session id, as listed on the sessions page
+    id: String,
+}
+
+/// Lists the active sessions of the logged-in user
+pub async fn sessions_page(
+    session_state: SessionState<PostgreSqlBackend>,
+    csrf_token: CsrfToken,
+) -> Result<HttpResponse> {
+    let sessions = session_state.sessions().await?;
+    Ok(HttpResponse::Ok()
+        .set(CacheControl(vec![CacheDirective::NoStore]))
+        .body(SessionsPage::new(sessions, csrf_token).render().unwrap()))
+}
+
+/// Revokes a single session of the logged-in user, e.g. of a lost device
+pub async fn do_revoke_session(
+    form: Form<SessionRevocation>,
+    session_state: SessionState<PostgreSqlBackend>,
+) -> Result<HttpResponse> {
+    session_state.revoke_session(&form.id).await?;
+    Ok(HttpResponse::Found()
+        .header(header::LOCATION, "/sessions")
+        .finish())
+}
+
+/// Revokes every session of the logged-in user except for the current one
+pub async fn do_revoke_other_sessions(
+    session_state: SessionState<PostgreSqlBackend>,
+) -> Result<HttpResponse> {
+    session_state.revoke_other_sessions().await?;
+    Ok(HttpResponse::Found()
+        .header(header::LOCATION, "/sessions")
+        .finish())
+}
+
 pub async fn do_logout(session_state: SessionState<PostgreSqlBackend>) -> impl Responder {
     session_state.logout().await;
     HttpResponse::Found()
@@ -74,21 +321,37 @@ pub async fn do_logout(session_state: SessionState<PostgreSqlBackend>) -> impl R
         .finish()
 }
 
-pub async fn status_page(user_details: UserDetails<PostgreSqlBackend>) -> impl Responder {
+/// Shows the account information of a logged-in user, logged-out users are not redirected to the login page
+pub async fn status_page(
+    user_details: OptionalUserDetails<PostgreSqlBackend>,
+    csrf_token: CsrfToken,
+) -> impl Responder {
     StatusPage {
-        user: Some(user_details.user),
-        ..Default::default()
+        user: user_details.user,
+        ..StatusPage::new(csrf_token)
     }
 }
 
 /// Used to access mocked user-specific information
+#[require_capabilities(UserRead)]
 pub async fn retrieve_user_information(
     user_details: UserDetails<PostgreSqlBackend>,
 ) -> Result<String> {
     Ok(format!("User information: {:?}", user_details.user))
 }
 
+/// Used to access mocked information of a single document, which requires a grant for the document
+pub async fn retrieve_document_information(
+    resource_details: ResourceDetails<PostgreSqlBackend>,
+) -> Result<String> {
+    Ok(format!(
+        "Document {} of {:?}",
+        resource_details.resource_id, resource_details.user
+    ))
+}
+
 /// Used to access mocked admin-specific information
+#[require_capabilities(AdminRead)]
 pub async fn retrieve_admin_information(
     user_details: UserDetails<PostgreSqlBackend>,
 ) -> Result<String> {
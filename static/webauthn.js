@@ -0,0 +1,82 @@
+// Runs the WebAuthn ceremonies of the login and passkeys pages.
+// Binary fields are exchanged with the server as base64url, which is how the server encodes challenges.
+'use strict';
+
+function decode(value) {
+  const base64 = value.replace(/-/g, '+').replace(/_/g, '/');
+  const padded = base64.padEnd(Math.ceil(base64.length / 4) * 4, '=');
+  return Uint8Array.from(atob(padded), (c) => c.charCodeAt(0));
+}
+
+function encode(buffer) {
+  return btoa(String.fromCharCode(...new Uint8Array(buffer)))
+    .replace(/\+/g, '-')
+    .replace(/\//g, '_')
+    .replace(/=+$/, '');
+}
+
+// Every page contains the CSRF token in the hidden field of the logout form
+function csrfToken() {
+  return document.querySelector('input[name="csrf_token"]').value;
+}
+
+async function post(url, body) {
+  const response = await fetch(url, {
+    method: 'POST',
+    credentials: 'same-origin',
+    headers: { 'Content-Type': 'application/json', 'X-CSRF-Token': csrfToken() },
+    body: JSON.stringify(body || {}),
+  });
+  if (!response.ok) {
+    throw new Error(await response.text());
+  }
+  return response;
+}
+
+async function login() {
+  const options = await (await post('/login/webauthn/options')).json();
+  options.publicKey.challenge = decode(options.publicKey.challenge);
+
+  const credential = await navigator.credentials.get(options);
+  await post('/login/webauthn', {
+    id: encode(credential.rawId),
+    clientDataJSON: encode(credential.response.clientDataJSON),
+    authenticatorData: encode(credential.response.authenticatorData),
+    signature: encode(credential.response.signature),
+  });
+  window.location.assign('/');
+}
+
+async function register() {
+  const options = await (await post('/passkeys/options')).json();
+  options.publicKey.challenge = decode(options.publicKey.challenge);
+  options.publicKey.user.id = decode(options.publicKey.user.id);
+
+  const credential = await navigator.credentials.create(options);
+  await post('/passkeys', {
+    clientDataJSON: encode(credential.response.clientDataJSON),
+    attestationObject: encode(credential.response.attestationObject),
+  });
+  document.getElementById('webauthn-success').hidden = false;
+}
+
+// Binds a ceremony to its button, failures are shown in the error alert of the page
+function bind(id, ceremony) {
+  const button = document.getElementById(id);
+  if (!button) {
+    return;
+  }
+  if (!window.PublicKeyCredential) {
+    button.disabled = true;
+    return;
+  }
+  button.addEventListener('click', () => {
+    document.getElementById('webauthn-error').hidden = true;
+    ceremony().catch(() => {
+      document.getElementById('webauthn-error').hidden = false;
+    });
+  });
+}
+
+bind('webauthn-login', login);
+bind('webauthn-register', register);